@@ -0,0 +1,827 @@
+//! ドロップ率・ドロップ理由のアラートルールと評価。
+//!
+//! ルールは `fw drops on eth0 > 100/s for 30s` や `new drop reason`、`ringbuf drops > 0` のような式で書く。
+//! `AlertEngine` は評価間隔ごとに集計したパケット数（`AlertWindow`）からルールを評価し、
+//! 発火・解決したアラートを返す。通知先への配送は呼び出し側が行う。
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::netns;
+use crate::types::{CapturedPacket, PacketResult};
+
+/// `new drop reason` ルールで `for` を省略したときに、アラートを発火したままにする時間
+pub const DEFAULT_NEW_REASON_HOLD: Duration = Duration::from_secs(60);
+/// `/api/alerts` で返す解決済みアラートの件数
+pub const RESOLVED_HISTORY_LEN: usize = 50;
+
+/// しきい値ルールで数える値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertMetric {
+    Packets,
+    Delivered,
+    /// NIC と FW のドロップの合計
+    Drops,
+    NicDrops,
+    FwDrops,
+    /// カーネルがリングバッファに書けなかったイベント（`CaptureStats::transport_dropped` の増分）
+    RingbufDrops,
+}
+
+impl AlertMetric {
+    fn matches(self, result: &PacketResult) -> bool {
+        match self {
+            Self::Packets => true,
+            Self::Delivered => *result == PacketResult::Delivered,
+            Self::Drops => *result != PacketResult::Delivered,
+            Self::NicDrops => *result == PacketResult::NicDrop,
+            Self::FwDrops => *result == PacketResult::FwDrop,
+            Self::RingbufDrops => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Comparison {
+    fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Gt => value > threshold,
+            Self::Ge => value >= threshold,
+            Self::Lt => value < threshold,
+            Self::Le => value <= threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleCondition {
+    /// 評価間隔あたりの件数（`per_second` なら毎秒の割合）としきい値の比較
+    Threshold {
+        metric: AlertMetric,
+        interface: Option<String>,
+        reason: Option<String>,
+        comparison: Comparison,
+        threshold: f64,
+        per_second: bool,
+    },
+    /// これまでに見ていないドロップ理由が現れた
+    NewDropReason { interface: Option<String> },
+}
+
+/// アラートルール。`[NAME=]EXPR` の形で書く（名前を省略すると式を名前にする）。
+///
+/// ```text
+/// EXPR := METRIC [on IFACE] [reason REASON] OP NUMBER[/s] [for DURATION]
+///       | new drop reason [on IFACE] [for DURATION]
+/// METRIC := packets | delivered | drops | nic drops | fw drops | ringbuf drops
+/// OP := > | >= | < | <=
+/// ```
+///
+/// しきい値ルールの `for` は条件が続いてから発火するまでの時間、
+/// `new drop reason` の `for` は発火したままにする時間。
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    pub name: String,
+    pub expr: String,
+    pub condition: RuleCondition,
+    pub for_duration: Duration,
+}
+
+impl FromStr for AlertRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, expr) = match s.split_once('=') {
+            // `>=` / `<=` の `=` は名前の区切りではない
+            Some((name, expr)) if !name.ends_with(['>', '<']) => (name.trim(), expr.trim()),
+            _ => ("", s.trim()),
+        };
+        if expr.is_empty() {
+            return Err("alert rule expression is empty".to_string());
+        }
+        let (condition, for_duration) =
+            parse_expr(expr).map_err(|e| format!("invalid alert rule {:?}: {}", expr, e))?;
+        Ok(Self {
+            name: if name.is_empty() { expr } else { name }.to_string(),
+            expr: expr.to_string(),
+            condition,
+            for_duration,
+        })
+    }
+}
+
+/// キーワードとメトリクスは大文字・小文字を区別しない。
+/// インターフェース名（`on` の値）は区別するため、書いたまま使う。
+fn parse_expr(expr: &str) -> Result<(RuleCondition, Duration), String> {
+    let mut tokens = Tokens(expr.split_whitespace().peekable());
+
+    if tokens.eat("new") {
+        tokens.expect("drop")?;
+        tokens.expect("reason")?;
+        let interface = tokens.option("on")?;
+        let hold = tokens.duration()?.unwrap_or(DEFAULT_NEW_REASON_HOLD);
+        tokens.end()?;
+        return Ok((RuleCondition::NewDropReason { interface }, hold));
+    }
+
+    let metric = match tokens.next()?.to_ascii_lowercase().as_str() {
+        "packets" => AlertMetric::Packets,
+        "delivered" => AlertMetric::Delivered,
+        "drops" => AlertMetric::Drops,
+        "nic" => tokens.expect("drops").map(|_| AlertMetric::NicDrops)?,
+        "fw" => tokens.expect("drops").map(|_| AlertMetric::FwDrops)?,
+        "ringbuf" => tokens.expect("drops").map(|_| AlertMetric::RingbufDrops)?,
+        other => return Err(format!("unknown metric {:?}", other)),
+    };
+    let interface = tokens.option("on")?;
+    let reason = tokens.option("reason")?.map(|r| r.to_ascii_uppercase());
+    if metric == AlertMetric::RingbufDrops && (interface.is_some() || reason.is_some()) {
+        return Err("ringbuf drops cannot be filtered by interface or reason".to_string());
+    }
+    let comparison = match tokens.next()? {
+        ">" => Comparison::Gt,
+        ">=" => Comparison::Ge,
+        "<" => Comparison::Lt,
+        "<=" => Comparison::Le,
+        other => return Err(format!("expected a comparison, found {:?}", other)),
+    };
+    let value = tokens.next()?.to_ascii_lowercase();
+    let (value, per_second) = match value.strip_suffix("/s") {
+        Some(value) => (value, true),
+        None => (value.as_str(), false),
+    };
+    let threshold: f64 = value
+        .parse()
+        .ok()
+        .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+        .ok_or_else(|| format!("invalid threshold {:?}", value))?;
+    let for_duration = tokens.duration()?.unwrap_or_default();
+    tokens.end()?;
+    Ok((
+        RuleCondition::Threshold {
+            metric,
+            interface,
+            reason,
+            comparison,
+            threshold,
+            per_second,
+        },
+        for_duration,
+    ))
+}
+
+struct Tokens<'a>(std::iter::Peekable<std::str::SplitWhitespace<'a>>);
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Result<&'a str, String> {
+        self.0
+            .next()
+            .ok_or_else(|| "unexpected end of expression".to_string())
+    }
+
+    fn eat(&mut self, word: &str) -> bool {
+        self.0
+            .next_if(|token| token.eq_ignore_ascii_case(word))
+            .is_some()
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        match self.next()? {
+            found if found.eq_ignore_ascii_case(word) => Ok(()),
+            found => Err(format!("expected {:?}, found {:?}", word, found)),
+        }
+    }
+
+    /// `KEYWORD VALUE` があれば VALUE を返す
+    fn option(&mut self, keyword: &str) -> Result<Option<String>, String> {
+        if !self.eat(keyword) {
+            return Ok(None);
+        }
+        self.next().map(|value| Some(value.to_string()))
+    }
+
+    fn duration(&mut self) -> Result<Option<Duration>, String> {
+        match self.option("for")? {
+            Some(value) => parse_duration(&value.to_ascii_lowercase()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        match self.0.next() {
+            Some(extra) => Err(format!("unexpected {:?}", extra)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `500ms` / `30s` / `5m` / `1h`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", s))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(value * 60)),
+        "h" => Ok(Duration::from_secs(value * 3600)),
+        _ => Err(format!(
+            "invalid duration unit in {:?} (use ms, s, m or h)",
+            s
+        )),
+    }
+}
+
+/// 1 回の評価間隔に集計したパケット数。
+/// 購読が遅れて読めなかったバッチがあれば、読めたバッチと同じ内訳だったとみなして件数を補う。
+#[derive(Debug, Default)]
+pub struct AlertWindow {
+    /// (インターフェース, 結果, ドロップ理由) ごとの件数
+    counts: HashMap<(String, PacketResult, Option<String>), u64>,
+    batches: u64,
+    skipped_batches: u64,
+}
+
+impl AlertWindow {
+    /// 読めなかったバッチの数を記録する
+    pub fn record_lag(&mut self, skipped_batches: u64) {
+        self.skipped_batches += skipped_batches;
+    }
+
+    /// 読めたバッチが 1 つもなく、件数を見積もれない
+    fn is_unknown(&self) -> bool {
+        self.batches == 0 && self.skipped_batches > 0
+    }
+
+    pub fn record_batch(&mut self, packets: &[CapturedPacket]) {
+        self.batches += 1;
+        for captured in packets {
+            let packet = &captured.packet;
+            let interface = packet
+                .interface
+                .as_deref()
+                .map(|name| netns::qualify_interface(packet.netns.as_deref(), name))
+                .unwrap_or_default();
+            *self
+                .counts
+                .entry((interface, captured.result.clone(), packet.reason.clone()))
+                .or_default() += 1;
+        }
+    }
+
+    /// 条件に合う件数。読めなかったバッチの分を補った推定値になることがある。
+    fn count(&self, metric: AlertMetric, interface: Option<&str>, reason: Option<&str>) -> f64 {
+        let observed: u64 = self
+            .counts
+            .iter()
+            .filter(|((iface, result, r), _)| {
+                metric.matches(result)
+                    && interface.is_none_or(|i| i == iface)
+                    && reason.is_none_or(|reason| r.as_deref() == Some(reason))
+            })
+            .map(|(_, count)| count)
+            .sum();
+        if self.batches == 0 {
+            return observed as f64;
+        }
+        observed as f64 * (self.batches + self.skipped_batches) as f64 / self.batches as f64
+    }
+
+    /// ドロップ理由と、それが見えたインターフェース
+    fn drop_reasons(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.counts
+            .keys()
+            .filter(|(_, result, _)| *result != PacketResult::Delivered)
+            .filter_map(|(iface, _, reason)| Some((iface.as_str(), reason.as_deref()?)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    /// 条件を満たしているが `for` の時間に達していない
+    Pending,
+    Firing,
+    Resolved,
+}
+
+impl fmt::Display for AlertState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pending => "pending",
+            Self::Firing => "firing",
+            Self::Resolved => "resolved",
+        })
+    }
+}
+
+/// ルールごと（`new drop reason` は理由ごと）のアラート
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub rule: String,
+    pub expr: String,
+    pub labels: BTreeMap<String, String>,
+    pub state: AlertState,
+    /// 最後に評価した値（割合のルールは毎秒の値）
+    pub value: f64,
+    /// 条件を満たし始めた時刻（UNIX ミリ秒）
+    pub active_since_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fired_at_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_at_ms: Option<u64>,
+}
+
+/// 評価の入力
+pub struct Evaluation<'a> {
+    pub window: &'a AlertWindow,
+    /// 前回の評価からの経過時間（割合の計算に使う）
+    pub elapsed: Duration,
+    /// `CaptureStats::transport_dropped` の現在値（`ringbuf drops` のルールがあるときだけ必要）
+    pub ringbuf_dropped: Option<u64>,
+    pub now_ms: u64,
+}
+
+/// ルールの評価状態
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    /// (ルールの添字, ラベル) ごとの未解決のアラート
+    active: BTreeMap<(usize, BTreeMap<String, String>), Alert>,
+    resolved: VecDeque<Alert>,
+    /// ルールごとに、そのルールの対象で見たことのあるドロップ理由
+    seen_reasons: Vec<HashSet<String>>,
+    last_ringbuf_dropped: u64,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            seen_reasons: vec![HashSet::new(); rules.len()],
+            rules,
+            active: BTreeMap::new(),
+            resolved: VecDeque::new(),
+            last_ringbuf_dropped: 0,
+        }
+    }
+
+    pub fn rules(&self) -> &[AlertRule] {
+        &self.rules
+    }
+
+    pub fn needs_ringbuf_dropped(&self) -> bool {
+        self.rules.iter().any(|rule| {
+            matches!(
+                rule.condition,
+                RuleCondition::Threshold {
+                    metric: AlertMetric::RingbufDrops,
+                    ..
+                }
+            )
+        })
+    }
+
+    /// 未解決（pending / firing）のアラート
+    pub fn active(&self) -> Vec<Alert> {
+        self.active.values().cloned().collect()
+    }
+
+    /// 最近解決したアラート（新しい順）
+    pub fn resolved(&self) -> Vec<Alert> {
+        self.resolved.iter().rev().cloned().collect()
+    }
+
+    /// ルールを評価し、発火・解決したアラートを返す
+    pub fn evaluate(&mut self, input: &Evaluation<'_>) -> Vec<Alert> {
+        let ringbuf_delta = input.ringbuf_dropped.map(|total| {
+            // 統計のリセット後は現在値をそのまま増分とする
+            let delta = total
+                .checked_sub(self.last_ringbuf_dropped)
+                .unwrap_or(total);
+            self.last_ringbuf_dropped = total;
+            delta
+        });
+        let mut events = Vec::new();
+        for index in 0..self.rules.len() {
+            let rule = &self.rules[index];
+            match &rule.condition {
+                RuleCondition::Threshold {
+                    metric,
+                    interface,
+                    reason,
+                    comparison,
+                    threshold,
+                    per_second,
+                } => {
+                    let count = match metric {
+                        AlertMetric::RingbufDrops => match ringbuf_delta {
+                            Some(delta) => delta as f64,
+                            None => continue,
+                        },
+                        // 値が分からない間隔では状態を変えない
+                        _ if input.window.is_unknown() => continue,
+                        _ => input
+                            .window
+                            .count(*metric, interface.as_deref(), reason.as_deref()),
+                    };
+                    let value = if *per_second {
+                        count / input.elapsed.as_secs_f64().max(f64::EPSILON)
+                    } else {
+                        count
+                    };
+                    let mut labels = BTreeMap::new();
+                    if let Some(interface) = interface {
+                        labels.insert("interface".to_string(), interface.clone());
+                    }
+                    if let Some(reason) = reason {
+                        labels.insert("reason".to_string(), reason.clone());
+                    }
+                    let holds = comparison.holds(value, *threshold);
+                    self.update_threshold(index, labels, holds, value, input.now_ms, &mut events);
+                }
+                RuleCondition::NewDropReason { interface } => {
+                    let hold_ms = rule.for_duration.as_millis() as u64;
+                    let new_reasons: BTreeSet<String> = input
+                        .window
+                        .drop_reasons()
+                        .filter(|(iface, reason)| {
+                            interface.as_ref().is_none_or(|i| i == iface)
+                                && !self.seen_reasons[index].contains(*reason)
+                        })
+                        .map(|(_, reason)| reason.to_string())
+                        .collect();
+                    for reason in new_reasons {
+                        let mut labels = BTreeMap::from([("reason".to_string(), reason.clone())]);
+                        if let Some(interface) = interface {
+                            labels.insert("interface".to_string(), interface.clone());
+                        }
+                        let alert = self.new_alert(index, labels.clone(), 1.0, input.now_ms);
+                        let alert = Alert {
+                            state: AlertState::Firing,
+                            fired_at_ms: Some(input.now_ms),
+                            ..alert
+                        };
+                        events.push(alert.clone());
+                        self.active.insert((index, labels), alert);
+                        self.seen_reasons[index].insert(reason);
+                    }
+                    let expired: Vec<_> = self
+                        .active
+                        .iter()
+                        .filter(|((i, _), alert)| {
+                            *i == index
+                                && alert
+                                    .fired_at_ms
+                                    .is_some_and(|at| input.now_ms.saturating_sub(at) >= hold_ms)
+                        })
+                        .map(|(key, _)| key.clone())
+                        .collect();
+                    for key in expired {
+                        self.resolve(&key, input.now_ms, &mut events);
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    fn new_alert(
+        &self,
+        index: usize,
+        labels: BTreeMap<String, String>,
+        value: f64,
+        now_ms: u64,
+    ) -> Alert {
+        let rule = &self.rules[index];
+        Alert {
+            rule: rule.name.clone(),
+            expr: rule.expr.clone(),
+            labels,
+            state: AlertState::Pending,
+            value,
+            active_since_ms: now_ms,
+            fired_at_ms: None,
+            resolved_at_ms: None,
+        }
+    }
+
+    fn update_threshold(
+        &mut self,
+        index: usize,
+        labels: BTreeMap<String, String>,
+        holds: bool,
+        value: f64,
+        now_ms: u64,
+        events: &mut Vec<Alert>,
+    ) {
+        let key = (index, labels);
+        if !holds {
+            match self.active.get(&key).map(|alert| alert.state) {
+                Some(AlertState::Firing) => self.resolve(&key, now_ms, events),
+                Some(_) => {
+                    self.active.remove(&key);
+                }
+                None => {}
+            }
+            return;
+        }
+        let for_ms = self.rules[index].for_duration.as_millis() as u64;
+        let alert = match self.active.remove(&key) {
+            Some(alert) => alert,
+            None => self.new_alert(index, key.1.clone(), value, now_ms),
+        };
+        let mut alert = Alert { value, ..alert };
+        if alert.state == AlertState::Pending
+            && now_ms.saturating_sub(alert.active_since_ms) >= for_ms
+        {
+            alert.state = AlertState::Firing;
+            alert.fired_at_ms = Some(now_ms);
+            events.push(alert.clone());
+        }
+        self.active.insert(key, alert);
+    }
+
+    fn resolve(
+        &mut self,
+        key: &(usize, BTreeMap<String, String>),
+        now_ms: u64,
+        events: &mut Vec<Alert>,
+    ) {
+        let Some(alert) = self.active.remove(key) else {
+            return;
+        };
+        let alert = Alert {
+            state: AlertState::Resolved,
+            resolved_at_ms: Some(now_ms),
+            ..alert
+        };
+        events.push(alert.clone());
+        if self.resolved.len() == RESOLVED_HISTORY_LEN {
+            self.resolved.pop_front();
+        }
+        self.resolved.push_back(alert);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AnimatingPacket;
+
+    fn captured(interface: &str, result: PacketResult, reason: Option<&str>) -> CapturedPacket {
+        let mut packet = AnimatingPacket::generate("abc123", 0);
+        packet.interface = Some(interface.to_string());
+        packet.reason = reason.map(str::to_string);
+        CapturedPacket { packet, result }
+    }
+
+    fn window(packets: &[CapturedPacket]) -> AlertWindow {
+        let mut window = AlertWindow::default();
+        window.record_batch(packets);
+        window
+    }
+
+    fn evaluate(engine: &mut AlertEngine, window: &AlertWindow, now_ms: u64) -> Vec<Alert> {
+        engine.evaluate(&Evaluation {
+            window,
+            elapsed: Duration::from_secs(1),
+            ringbuf_dropped: None,
+            now_ms,
+        })
+    }
+
+    #[test]
+    fn parses_rule_expressions() {
+        let rule: AlertRule = "eth0-fw=fw drops on eth0 > 100/s for 30s".parse().unwrap();
+        assert_eq!(rule.name, "eth0-fw");
+        assert_eq!(rule.expr, "fw drops on eth0 > 100/s for 30s");
+        assert_eq!(rule.for_duration, Duration::from_secs(30));
+        assert_eq!(
+            rule.condition,
+            RuleCondition::Threshold {
+                metric: AlertMetric::FwDrops,
+                interface: Some("eth0".to_string()),
+                reason: None,
+                comparison: Comparison::Gt,
+                threshold: 100.0,
+                per_second: true,
+            }
+        );
+
+        let rule: AlertRule = "drops reason netfilter_drop >= 5".parse().unwrap();
+        assert_eq!(rule.name, "drops reason netfilter_drop >= 5");
+        assert!(matches!(
+            rule.condition,
+            RuleCondition::Threshold {
+                reason: Some(ref r),
+                comparison: Comparison::Ge,
+                per_second: false,
+                ..
+            } if r == "NETFILTER_DROP"
+        ));
+
+        // インターフェース名は大文字・小文字を区別する
+        let rule: AlertRule = "FW Drops ON ns-Blue/eth0 > 10/S FOR 5S".parse().unwrap();
+        assert!(matches!(
+            rule.condition,
+            RuleCondition::Threshold {
+                metric: AlertMetric::FwDrops,
+                interface: Some(ref i),
+                per_second: true,
+                ..
+            } if i == "ns-Blue/eth0"
+        ));
+        assert_eq!(rule.for_duration, Duration::from_secs(5));
+
+        let rule: AlertRule = "New drop reason".parse().unwrap();
+        assert_eq!(
+            rule.condition,
+            RuleCondition::NewDropReason { interface: None }
+        );
+        assert_eq!(rule.for_duration, DEFAULT_NEW_REASON_HOLD);
+
+        assert!("ringbuf drops > 0".parse::<AlertRule>().is_ok());
+        assert!("ringbuf drops on eth0 > 0".parse::<AlertRule>().is_err());
+        assert!("fw drops > lots".parse::<AlertRule>().is_err());
+        assert!("fw drops > 1 for 3d".parse::<AlertRule>().is_err());
+        assert!("fw drops > 1 please".parse::<AlertRule>().is_err());
+        assert!("bytes > 1".parse::<AlertRule>().is_err());
+        assert!("name=".parse::<AlertRule>().is_err());
+    }
+
+    #[test]
+    fn threshold_rule_fires_after_for_duration_and_resolves() {
+        let rule = "fw drops on eth0 > 1/s for 2s".parse().unwrap();
+        let mut engine = AlertEngine::new(vec![rule]);
+        let busy = window(&[
+            captured("eth0", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            captured("eth0", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            // 他のインターフェースは数えない
+            captured("eth1", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+        ]);
+
+        assert!(evaluate(&mut engine, &busy, 1_000).is_empty());
+        assert_eq!(engine.active()[0].state, AlertState::Pending);
+        assert!(evaluate(&mut engine, &busy, 2_000).is_empty());
+
+        let events = evaluate(&mut engine, &busy, 3_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Firing);
+        assert_eq!(events[0].value, 2.0);
+        assert_eq!(events[0].active_since_ms, 1_000);
+        assert_eq!(events[0].labels["interface"], "eth0");
+        // 発火中は通知を繰り返さない
+        assert!(evaluate(&mut engine, &busy, 4_000).is_empty());
+
+        let events = evaluate(&mut engine, &AlertWindow::default(), 5_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Resolved);
+        assert_eq!(events[0].resolved_at_ms, Some(5_000));
+        assert!(engine.active().is_empty());
+        assert_eq!(engine.resolved().len(), 1);
+    }
+
+    #[test]
+    fn threshold_rule_estimates_skipped_batches() {
+        let rule = "fw drops > 3".parse().unwrap();
+        let mut engine = AlertEngine::new(vec![rule]);
+        let mut lagged = window(&[captured(
+            "eth0",
+            PacketResult::FwDrop,
+            Some("NETFILTER_DROP"),
+        )]);
+        lagged.record_batch(&[captured("eth0", PacketResult::Delivered, None)]);
+        // 読めた 2 バッチに 1 件なので、読めなかった 6 バッチを足すと 4 件とみなす
+        lagged.record_lag(6);
+        let events = evaluate(&mut engine, &lagged, 1_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].value, 4.0);
+
+        // 1 つも読めなかった間隔では、解決もしない
+        let mut unknown = AlertWindow::default();
+        unknown.record_lag(3);
+        assert!(evaluate(&mut engine, &unknown, 2_000).is_empty());
+        assert_eq!(engine.active()[0].state, AlertState::Firing);
+        assert_eq!(
+            evaluate(&mut engine, &AlertWindow::default(), 3_000)[0].state,
+            AlertState::Resolved
+        );
+    }
+
+    #[test]
+    fn pending_alert_clears_without_notification() {
+        let rule = "drops > 0 for 10s".parse().unwrap();
+        let mut engine = AlertEngine::new(vec![rule]);
+        let dropped = window(&[captured("eth0", PacketResult::NicDrop, Some("NO_SOCKET"))]);
+        assert!(evaluate(&mut engine, &dropped, 1_000).is_empty());
+        assert!(evaluate(&mut engine, &AlertWindow::default(), 2_000).is_empty());
+        assert!(engine.active().is_empty());
+        assert!(engine.resolved().is_empty());
+    }
+
+    #[test]
+    fn new_drop_reason_fires_once_per_reason() {
+        let rule = "new drop reason for 5s".parse().unwrap();
+        let mut engine = AlertEngine::new(vec![rule]);
+        let first = window(&[
+            captured("eth0", PacketResult::Delivered, None),
+            captured("eth0", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+        ]);
+
+        let events = evaluate(&mut engine, &first, 1_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Firing);
+        assert_eq!(events[0].labels["reason"], "NETFILTER_DROP");
+
+        // 同じ理由は再び発火しない
+        assert!(evaluate(&mut engine, &first, 2_000).is_empty());
+        let second = window(&[captured("eth1", PacketResult::NicDrop, Some("NO_SOCKET"))]);
+        let events = evaluate(&mut engine, &second, 3_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].labels["reason"], "NO_SOCKET");
+
+        // 保持時間を過ぎると解決する
+        let events = evaluate(&mut engine, &AlertWindow::default(), 6_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Resolved);
+        assert_eq!(events[0].labels["reason"], "NETFILTER_DROP");
+    }
+
+    #[test]
+    fn new_drop_reason_is_tracked_per_rule_interface() {
+        let rules = vec![
+            "new drop reason".parse().unwrap(),
+            "new drop reason on eth1".parse().unwrap(),
+        ];
+        let mut engine = AlertEngine::new(rules);
+        // 同じ理由が複数のインターフェースで見えても 1 回だけ発火する
+        let both = window(&[
+            captured("eth0", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            captured("eth2", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+        ]);
+        let events = evaluate(&mut engine, &both, 1_000);
+        assert_eq!(events.len(), 1);
+        assert!(!events[0].labels.contains_key("interface"));
+
+        // eth0 で見えた理由でも、eth1 で初めて見えれば eth1 のルールは発火する
+        let eth1 = window(&[captured(
+            "eth1",
+            PacketResult::FwDrop,
+            Some("NETFILTER_DROP"),
+        )]);
+        let events = evaluate(&mut engine, &eth1, 2_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].labels["interface"], "eth1");
+        assert_eq!(events[0].labels["reason"], "NETFILTER_DROP");
+        assert!(evaluate(&mut engine, &eth1, 3_000).is_empty());
+    }
+
+    #[test]
+    fn ringbuf_rule_uses_counter_increase() {
+        let rule = "ringbuf drops > 0".parse().unwrap();
+        let mut engine = AlertEngine::new(vec![rule]);
+        assert!(engine.needs_ringbuf_dropped());
+        let empty = AlertWindow::default();
+        let mut run = |total, now_ms| {
+            engine.evaluate(&Evaluation {
+                window: &empty,
+                elapsed: Duration::from_secs(1),
+                ringbuf_dropped: Some(total),
+                now_ms,
+            })
+        };
+
+        assert!(run(0, 1_000).is_empty());
+        let events = run(3, 2_000);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].state, AlertState::Firing);
+        assert_eq!(events[0].value, 3.0);
+        let events = run(3, 3_000);
+        assert_eq!(events[0].state, AlertState::Resolved);
+        // 統計のリセット後の値も増分として扱う
+        assert_eq!(run(1, 4_000)[0].state, AlertState::Firing);
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("10").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! ストリーム購読クライアントの一覧と、接続ごとの送信・遅延の統計。
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// 接続ごとのカウンタ
+#[derive(Debug, Default)]
+pub struct ClientMetrics {
+    sent_batches: AtomicU64,
+    sent_packets: AtomicU64,
+    suppressed_packets: AtomicU64,
+    lag_events: AtomicU64,
+    skipped_batches: AtomicU64,
+    approx_skipped_packets: AtomicU64,
+    payload_bytes: AtomicU64,
+    wire_bytes: AtomicU64,
+}
+
+impl ClientMetrics {
+    pub fn record_sent(&self, packets: u64) {
+        self.sent_batches.fetch_add(1, Ordering::Relaxed);
+        self.sent_packets.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    pub fn record_suppressed(&self, packets: u64) {
+        self.suppressed_packets
+            .fetch_add(packets, Ordering::Relaxed);
+    }
+
+    /// 送信したフレームの圧縮前と送信時のバイト数を記録する
+    pub fn record_bytes(&self, payload_bytes: u64, wire_bytes: u64) {
+        self.payload_bytes
+            .fetch_add(payload_bytes, Ordering::Relaxed);
+        self.wire_bytes.fetch_add(wire_bytes, Ordering::Relaxed);
+    }
+
+    /// broadcast から取りこぼしたバッチを記録する
+    pub fn record_lag(&self, skipped_batches: u64, approx_packets: u64) {
+        self.lag_events.fetch_add(1, Ordering::Relaxed);
+        self.skipped_batches
+            .fetch_add(skipped_batches, Ordering::Relaxed);
+        self.approx_skipped_packets
+            .fetch_add(approx_packets, Ordering::Relaxed);
+    }
+}
+
+/// 取りこぼしたパケット数の推定に使う、受信バッチサイズの指数移動平均
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchSizeEstimator {
+    avg: Option<f64>,
+}
+
+impl BatchSizeEstimator {
+    /// 移動平均の重み
+    const ALPHA: f64 = 0.2;
+
+    /// 受信したバッチのパケット数（フィルタや間引きの前）を記録する
+    pub fn record(&mut self, packets: usize) {
+        let size = packets as f64;
+        self.avg = Some(match self.avg {
+            Some(avg) => avg + Self::ALPHA * (size - avg),
+            None => size,
+        });
+    }
+
+    /// `skipped_batches` 個のバッチに含まれていたおおよそのパケット数
+    pub fn approx_packets(&self, skipped_batches: u64) -> u64 {
+        (self.avg.unwrap_or(0.0) * skipped_batches as f64).round() as u64
+    }
+}
+
+/// `/api/capture/status` で返す接続ごとの統計
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientStats {
+    pub id: u64,
+    pub kind: String,
+    pub connected_at_ms: u64,
+    pub sent_batches: u64,
+    pub sent_packets: u64,
+    pub suppressed_packets: u64,
+    pub lag_events: u64,
+    pub skipped_batches: u64,
+    pub approx_skipped_packets: u64,
+    /// 圧縮前のバイト数
+    pub payload_bytes: u64,
+    /// 実際に送ったバイト数（圧縮しない接続では `payload_bytes` と同じ）
+    pub wire_bytes: u64,
+}
+
+struct ClientEntry {
+    kind: String,
+    connected_at_ms: u64,
+    metrics: Arc<ClientMetrics>,
+}
+
+/// 接続中のクライアントの一覧
+#[derive(Default)]
+pub struct ClientRegistry {
+    next_id: AtomicU64,
+    clients: Mutex<BTreeMap<u64, ClientEntry>>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// クライアントを登録する。返したハンドルを drop すると登録が解除される。
+    pub fn register(self: &Arc<Self>, kind: &str) -> ClientHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let metrics = Arc::new(ClientMetrics::default());
+        let connected_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.clients.lock().unwrap().insert(
+            id,
+            ClientEntry {
+                kind: kind.to_string(),
+                connected_at_ms,
+                metrics: Arc::clone(&metrics),
+            },
+        );
+        ClientHandle {
+            id,
+            metrics,
+            registry: Arc::clone(self),
+        }
+    }
+
+    /// 接続順（ID 順）のスナップショット
+    pub fn snapshot(&self) -> Vec<ClientStats> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, entry)| {
+                let m = &entry.metrics;
+                ClientStats {
+                    id,
+                    kind: entry.kind.clone(),
+                    connected_at_ms: entry.connected_at_ms,
+                    sent_batches: m.sent_batches.load(Ordering::Relaxed),
+                    sent_packets: m.sent_packets.load(Ordering::Relaxed),
+                    suppressed_packets: m.suppressed_packets.load(Ordering::Relaxed),
+                    lag_events: m.lag_events.load(Ordering::Relaxed),
+                    skipped_batches: m.skipped_batches.load(Ordering::Relaxed),
+                    approx_skipped_packets: m.approx_skipped_packets.load(Ordering::Relaxed),
+                    payload_bytes: m.payload_bytes.load(Ordering::Relaxed),
+                    wire_bytes: m.wire_bytes.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+}
+
+/// 登録中のクライアント。drop で登録解除する。
+pub struct ClientHandle {
+    id: u64,
+    metrics: Arc<ClientMetrics>,
+    registry: Arc<ClientRegistry>,
+}
+
+impl ClientHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn metrics(&self) -> &ClientMetrics {
+        &self.metrics
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        self.registry.clients.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_size_estimator_smooths_batch_sizes() {
+        let mut estimator = BatchSizeEstimator::default();
+        assert_eq!(estimator.approx_packets(3), 0);
+        estimator.record(10);
+        assert_eq!(estimator.approx_packets(3), 30);
+        // 1 つだけ大きなバッチが来ても推定は急に跳ねない
+        estimator.record(60);
+        assert_eq!(estimator.approx_packets(1), 20);
+    }
+
+    #[test]
+    fn handles_register_and_deregister_on_drop() {
+        let registry = Arc::new(ClientRegistry::new());
+        let first = registry.register("websocket");
+        let second = registry.register("websocket");
+        assert_ne!(first.id(), second.id());
+
+        first.metrics().record_sent(3);
+        first.metrics().record_lag(4, 40);
+        first.metrics().record_lag(1, 10);
+        second.metrics().record_suppressed(7);
+        second.metrics().record_bytes(1000, 300);
+        second.metrics().record_bytes(50, 51);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot[0].id, first.id());
+        assert_eq!(snapshot[0].sent_batches, 1);
+        assert_eq!(snapshot[0].sent_packets, 3);
+        assert_eq!(snapshot[0].lag_events, 2);
+        assert_eq!(snapshot[0].skipped_batches, 5);
+        assert_eq!(snapshot[0].approx_skipped_packets, 50);
+        assert_eq!(snapshot[1].suppressed_packets, 7);
+        assert_eq!(snapshot[1].payload_bytes, 1050);
+        assert_eq!(snapshot[1].wire_bytes, 351);
+
+        drop(first);
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].id, second.id());
+    }
+}
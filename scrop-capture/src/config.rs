@@ -0,0 +1,131 @@
+//! キャプチャのタイミング・容量の設定。
+//!
+//! 既定値はこれまでの定数（`BATCH_FLUSH_INTERVAL_MS` など）と同じ。
+//! サーバーは設定ファイル・環境変数・コマンドライン引数から組み立てて `AppState::with_config` に渡す。
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BATCH_FLUSH_INTERVAL_MS, BATCH_MAX_SIZE, EVENT_CHANNEL_CAPACITY};
+
+pub const DEFAULT_CORRELATION_TIMEOUT_MS: u64 = 50;
+/// eBPF の相関のタイマーホイール（5ms × 64 スロット）に収まる最大値
+pub const MAX_CORRELATION_TIMEOUT_MS: u64 = 300;
+pub const DEFAULT_RING_BUFFER_BYTES: u32 = 16 * 1024 * 1024;
+const PAGE_SIZE: u32 = 4096;
+/// 設定ファイル導入前から使われている、旧方式の相関を有効にする環境変数
+pub const SHADOW_CORRELATOR_ENV: &str = "SCROP_SHADOW_CORRELATOR";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// XDP を通過したパケットを、ドロップが来なければ配送済みとみなすまでの時間
+    pub correlation_timeout_ms: u64,
+    /// 相関済みのパケットをバッチにして送る間隔
+    pub batch_flush_interval_ms: u64,
+    /// 1 バッチの最大パケット数（達したら間隔を待たずに送る）
+    pub batch_max_size: usize,
+    /// `event_tx` のバッチ数。これを超えて遅れた購読者は取りこぼす
+    pub event_channel_capacity: usize,
+    /// eBPF のリングバッファ（`EVENTS`）のサイズ。ページサイズの 2 のべき乗倍
+    pub ring_buffer_bytes: u32,
+    /// 旧方式の相関を並行して動かし、結果を比較する（移行時の診断用）
+    pub shadow_correlator: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            correlation_timeout_ms: DEFAULT_CORRELATION_TIMEOUT_MS,
+            batch_flush_interval_ms: BATCH_FLUSH_INTERVAL_MS,
+            batch_max_size: BATCH_MAX_SIZE,
+            event_channel_capacity: EVENT_CHANNEL_CAPACITY,
+            ring_buffer_bytes: DEFAULT_RING_BUFFER_BYTES,
+            shadow_correlator: false,
+        }
+    }
+}
+
+impl CaptureConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_CORRELATION_TIMEOUT_MS).contains(&self.correlation_timeout_ms) {
+            return Err(format!(
+                "correlation_timeout_ms must be between 1 and {}",
+                MAX_CORRELATION_TIMEOUT_MS
+            ));
+        }
+        if self.batch_flush_interval_ms == 0 {
+            return Err("batch_flush_interval_ms must be greater than 0".to_string());
+        }
+        if self.batch_max_size == 0 {
+            return Err("batch_max_size must be greater than 0".to_string());
+        }
+        if self.event_channel_capacity == 0 {
+            return Err("event_channel_capacity must be greater than 0".to_string());
+        }
+        if self.ring_buffer_bytes < PAGE_SIZE || !self.ring_buffer_bytes.is_power_of_two() {
+            return Err(format!(
+                "ring_buffer_bytes must be a power of two of at least {}",
+                PAGE_SIZE
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 環境変数などのオン・オフの値（`1`/`true`/`yes`/`on` と `0`/`false`/`no`/`off`）
+pub fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" | "" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_matches_constants_and_is_valid() {
+        let config = CaptureConfig::default();
+        assert_eq!(config.batch_max_size, BATCH_MAX_SIZE);
+        assert_eq!(config.event_channel_capacity, EVENT_CHANNEL_CAPACITY);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_values() {
+        let invalid = [
+            CaptureConfig {
+                correlation_timeout_ms: 0,
+                ..Default::default()
+            },
+            CaptureConfig {
+                correlation_timeout_ms: MAX_CORRELATION_TIMEOUT_MS + 1,
+                ..Default::default()
+            },
+            CaptureConfig {
+                batch_max_size: 0,
+                ..Default::default()
+            },
+            CaptureConfig {
+                ring_buffer_bytes: 3 * PAGE_SIZE,
+                ..Default::default()
+            },
+            CaptureConfig {
+                ring_buffer_bytes: 1024,
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+    }
+
+    #[test]
+    fn parse_flag_values() {
+        assert_eq!(parse_flag(" Yes "), Some(true));
+        assert_eq!(parse_flag("0"), Some(false));
+        assert_eq!(parse_flag("maybe"), None);
+    }
+}
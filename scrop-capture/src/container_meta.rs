@@ -0,0 +1,646 @@
+//! IP アドレス・受信インターフェース・netns からコンテナ / Pod / cgroup を引くメタデータ段。
+//!
+//! 取得元（[`MetadataSource`]）から作った索引をスナップショットとして保持し、
+//! 一定間隔ごとに別スレッドで作り直す。送信経路ではハッシュ引きのみ行う。
+
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::enrich::Enricher;
+use crate::netns;
+use crate::types::{CapturedPacket, EndpointMetadata};
+
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// containerd（CRI プラグイン）と CRI-O が OCI bundle を置く場所。`{id}` はコンテナ ID。
+const CRI_BUNDLE_TEMPLATES: &[&str] = &[
+    "/run/containerd/io.containerd.runtime.v2.task/k8s.io/{id}/config.json",
+    "/run/containers/storage/overlay-containers/{id}/userdata/config.json",
+];
+
+/// メタデータの索引
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EndpointIndex {
+    /// IPv4 アドレス（文字列表現）→ メタデータ
+    pub by_ip: HashMap<String, EndpointMetadata>,
+    /// 受信インターフェースの修飾名（`"<netns>/<ifname>"` またはホストの ifname）→ メタデータ
+    pub by_interface: HashMap<String, EndpointMetadata>,
+    /// netns ID → メタデータ
+    pub by_netns: HashMap<String, EndpointMetadata>,
+}
+
+/// 索引を作る取得元
+pub trait MetadataSource: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+    fn load(&self) -> Result<EndpointIndex, String>;
+}
+
+/// [`MetadataSource`] の索引を使ってパケットの送信元・宛先にメタデータを付ける段。
+///
+/// 宛先は IP で見つからなければ、受信インターフェース・netns で引く
+/// （netns 内で受信したパケットはその netns 宛てとみなす）。
+pub struct MetadataEnricher<S> {
+    source: Arc<S>,
+    index: Arc<RwLock<Arc<EndpointIndex>>>,
+    refresh_interval: Duration,
+    last_refresh: Mutex<Instant>,
+    refreshing: Arc<AtomicBool>,
+}
+
+impl<S: MetadataSource> MetadataEnricher<S> {
+    pub fn new(source: S) -> Self {
+        Self::with_refresh_interval(source, DEFAULT_REFRESH_INTERVAL)
+    }
+
+    /// 初回の索引は呼び出しスレッドで同期的に作る
+    pub fn with_refresh_interval(source: S, refresh_interval: Duration) -> Self {
+        let index = source.load().unwrap_or_else(|e| {
+            warn!(source = source.name(), error = %e, "failed to load endpoint metadata");
+            EndpointIndex::default()
+        });
+        Self {
+            source: Arc::new(source),
+            index: Arc::new(RwLock::new(Arc::new(index))),
+            refresh_interval,
+            last_refresh: Mutex::new(Instant::now()),
+            refreshing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<EndpointIndex> {
+        Arc::clone(&self.index.read().unwrap())
+    }
+
+    /// 期限切れなら別スレッドで索引を作り直す（送信経路はブロックしない）
+    fn maybe_refresh(&self) {
+        {
+            let mut last = self.last_refresh.lock().unwrap();
+            if last.elapsed() < self.refresh_interval {
+                return;
+            }
+            *last = Instant::now();
+        }
+        if self.refreshing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let source = Arc::clone(&self.source);
+        let index = Arc::clone(&self.index);
+        let refreshing = Arc::clone(&self.refreshing);
+        std::thread::spawn(move || {
+            match source.load() {
+                Ok(next) => *index.write().unwrap() = Arc::new(next),
+                Err(e) => {
+                    warn!(source = source.name(), error = %e, "failed to refresh endpoint metadata")
+                }
+            }
+            refreshing.store(false, Ordering::Release);
+        });
+    }
+}
+
+impl<S: MetadataSource> Enricher for MetadataEnricher<S> {
+    fn name(&self) -> &'static str {
+        self.source.name()
+    }
+
+    fn enrich(&self, packets: &mut [CapturedPacket]) {
+        self.maybe_refresh();
+        let index = self.snapshot();
+        for captured in packets {
+            let packet = &mut captured.packet;
+            if packet.source_meta.is_none() {
+                packet.source_meta = index.by_ip.get(&packet.source).cloned();
+            }
+            if packet.destination_meta.is_none() {
+                packet.destination_meta = index
+                    .by_ip
+                    .get(&packet.destination)
+                    .or_else(|| {
+                        let interface = packet.interface.as_deref()?;
+                        let key = netns::qualify_interface(packet.netns.as_deref(), interface);
+                        index.by_interface.get(&key)
+                    })
+                    .or_else(|| index.by_netns.get(packet.netns.as_deref()?))
+                    .cloned();
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// ファイル
+// ---------------------------------------------------------------------------
+
+/// JSON ファイルの対応表を読み込む取得元。
+///
+/// ```json
+/// {
+///   "ips": { "10.244.1.17": { "pod": "web-0", "namespace": "default" } },
+///   "interfaces": { "veth1a2b": { "container": "web" } },
+///   "netns": { "cni-1234": { "pod": "web-0" } }
+/// }
+/// ```
+pub struct FileSource {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct FileIndex {
+    #[serde(default)]
+    ips: HashMap<String, EndpointMetadata>,
+    #[serde(default)]
+    interfaces: HashMap<String, EndpointMetadata>,
+    #[serde(default)]
+    netns: HashMap<String, EndpointMetadata>,
+}
+
+impl FileSource {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl MetadataSource for FileSource {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn load(&self) -> Result<EndpointIndex, String> {
+        let text = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let file: FileIndex =
+            serde_json::from_str(&text).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        Ok(EndpointIndex {
+            by_ip: file.ips,
+            by_interface: file.interfaces,
+            by_netns: file.netns,
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// /proc + cgroupfs (+ CRI ランタイム)
+// ---------------------------------------------------------------------------
+
+/// `/proc` を走査して netns ごとのローカル IP・cgroup・コンテナ ID を集める取得元。
+///
+/// `cri` を有効にすると、コンテナ ID に対応する OCI bundle（CRI ランタイムの状態ディレクトリ）の
+/// annotations から Pod 名・namespace・コンテナ名を補う。CRI の gRPC ソケットには接続しない。
+pub struct ProcSource {
+    proc_root: PathBuf,
+    netns_dir: PathBuf,
+    bundle_templates: Vec<String>,
+}
+
+impl ProcSource {
+    pub fn host(cri: bool) -> Self {
+        Self {
+            proc_root: PathBuf::from("/proc"),
+            netns_dir: PathBuf::from("/var/run/netns"),
+            bundle_templates: if cri {
+                CRI_BUNDLE_TEMPLATES.iter().map(|s| s.to_string()).collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    pub fn with_roots(
+        proc_root: PathBuf,
+        netns_dir: PathBuf,
+        bundle_templates: Vec<String>,
+    ) -> Self {
+        Self {
+            proc_root,
+            netns_dir,
+            bundle_templates,
+        }
+    }
+
+    fn pid_metadata(&self, pid: u32) -> Option<(EndpointMetadata, bool)> {
+        let cgroup = std::fs::read_to_string(self.proc_root.join(pid.to_string()).join("cgroup"))
+            .ok()
+            .and_then(|text| parse_cgroup_path(&text))?;
+        let container_id = container_id_from_cgroup(&cgroup);
+        let mut meta = EndpointMetadata {
+            pod_uid: pod_uid_from_cgroup(&cgroup),
+            cgroup: Some(cgroup),
+            container_id: container_id.clone(),
+            ..EndpointMetadata::default()
+        };
+        let mut is_sandbox = false;
+        if let Some(id) = container_id.as_deref() {
+            if let Some(annotations) = self.bundle_annotations(id) {
+                is_sandbox = apply_cri_annotations(&mut meta, &annotations);
+            }
+        }
+        Some((meta, is_sandbox))
+    }
+
+    fn bundle_annotations(&self, container_id: &str) -> Option<BTreeMap<String, String>> {
+        #[derive(Deserialize)]
+        struct OciConfig {
+            #[serde(default)]
+            annotations: BTreeMap<String, String>,
+        }
+        self.bundle_templates.iter().find_map(|template| {
+            let path = template.replace("{id}", container_id);
+            let text = std::fs::read_to_string(path).ok()?;
+            serde_json::from_str::<OciConfig>(&text)
+                .ok()
+                .map(|config| config.annotations)
+        })
+    }
+}
+
+impl MetadataSource for ProcSource {
+    fn name(&self) -> &'static str {
+        if self.bundle_templates.is_empty() {
+            "proc"
+        } else {
+            "cri"
+        }
+    }
+
+    fn load(&self) -> Result<EndpointIndex, String> {
+        let host_inode = inode_of(&self.proc_root.join("1/ns/net"));
+        let entries = std::fs::read_dir(&self.proc_root)
+            .map_err(|e| format!("{}: {}", self.proc_root.display(), e))?;
+        let mut pids: Vec<u32> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().to_str()?.parse().ok())
+            .collect();
+        pids.sort_unstable();
+
+        // netns inode → (代表 PID, メタデータ)。サンドボックス（pause）よりアプリコンテナを優先する。
+        let mut by_inode: HashMap<u64, (u32, EndpointMetadata, bool)> = HashMap::new();
+        for pid in pids {
+            let Some(inode) = inode_of(&self.proc_root.join(pid.to_string()).join("ns/net")) else {
+                continue;
+            };
+            if Some(inode) == host_inode {
+                continue;
+            }
+            let Some((meta, is_sandbox)) = self.pid_metadata(pid) else {
+                continue;
+            };
+            match by_inode.get_mut(&inode) {
+                Some(entry) => {
+                    if entry.2 && !is_sandbox {
+                        let pid = entry.0;
+                        *entry = (pid, merge_metadata(meta, &entry.1), false);
+                    }
+                }
+                None => {
+                    by_inode.insert(inode, (pid, meta, is_sandbox));
+                }
+            }
+        }
+
+        let netns_ids: HashMap<u64, String> = netns::discover_in(&self.netns_dir, &self.proc_root)
+            .into_iter()
+            .map(|ns| (ns.inode, ns.id))
+            .collect();
+
+        let mut index = EndpointIndex::default();
+        for (inode, (pid, meta, _)) in by_inode {
+            let fib_trie = self.proc_root.join(pid.to_string()).join("net/fib_trie");
+            if let Ok(text) = std::fs::read_to_string(fib_trie) {
+                for ip in parse_local_addresses(&text) {
+                    index.by_ip.insert(ip, meta.clone());
+                }
+            }
+            if let Some(id) = netns_ids.get(&inode) {
+                index.by_netns.insert(id.clone(), meta);
+            }
+        }
+        Ok(index)
+    }
+}
+
+fn inode_of(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|m| m.ino())
+}
+
+/// `/proc/<pid>/cgroup` から cgroup パスを取り出す（v2 の `0::` 行を優先）
+fn parse_cgroup_path(text: &str) -> Option<String> {
+    let mut fallback = None;
+    for line in text.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (Some(hierarchy), Some(_), Some(path)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if hierarchy == "0" {
+            return Some(path.to_string());
+        }
+        if fallback.is_none() && path != "/" {
+            fallback = Some(path.to_string());
+        }
+    }
+    fallback
+}
+
+/// cgroup パスに含まれる 64 桁 16 進のコンテナ ID を取り出す
+/// （`docker-<id>.scope`、`cri-containerd-<id>.scope`、`crio-<id>.scope`、`/docker/<id>` など）
+fn container_id_from_cgroup(path: &str) -> Option<String> {
+    path.rsplit('/').find_map(|segment| {
+        let segment = segment.strip_suffix(".scope").unwrap_or(segment);
+        let candidate = segment.rsplit('-').next().unwrap_or(segment);
+        (candidate.len() == 64 && candidate.bytes().all(|b| b.is_ascii_hexdigit()))
+            .then(|| candidate.to_string())
+    })
+}
+
+/// kubepods の cgroup パスから Pod UID を取り出す（`pod<uid>`、systemd では `_` 区切り）
+fn pod_uid_from_cgroup(path: &str) -> Option<String> {
+    path.split('/').find_map(|segment| {
+        let segment = segment.strip_suffix(".slice").unwrap_or(segment);
+        let uid = segment.rsplit('-').next()?.strip_prefix("pod")?;
+        (uid.len() >= 32).then(|| uid.replace('_', "-"))
+    })
+}
+
+/// containerd / CRI-O の annotations を反映する。サンドボックスコンテナなら true を返す。
+fn apply_cri_annotations(
+    meta: &mut EndpointMetadata,
+    annotations: &BTreeMap<String, String>,
+) -> bool {
+    let get = |keys: &[&str]| keys.iter().find_map(|k| annotations.get(*k).cloned());
+    meta.pod = get(&["io.kubernetes.cri.sandbox-name", "io.kubernetes.pod.name"]);
+    meta.namespace = get(&[
+        "io.kubernetes.cri.sandbox-namespace",
+        "io.kubernetes.pod.namespace",
+    ]);
+    if let Some(uid) = get(&["io.kubernetes.cri.sandbox-uid", "io.kubernetes.pod.uid"]) {
+        meta.pod_uid = Some(uid);
+    }
+    meta.container = get(&[
+        "io.kubernetes.cri.container-name",
+        "io.kubernetes.container.name",
+    ]);
+    if let Some(labels) = annotations
+        .get("io.kubernetes.cri-o.Labels")
+        .and_then(|raw| serde_json::from_str::<BTreeMap<String, String>>(raw).ok())
+    {
+        meta.labels = labels;
+    }
+    get(&[
+        "io.kubernetes.cri.container-type",
+        "io.kubernetes.cri-o.ContainerType",
+    ])
+    .is_some_and(|kind| kind == "sandbox")
+        || meta.container.as_deref() == Some("POD")
+}
+
+/// アプリコンテナの情報を優先しつつ、欠けている項目をサンドボックス側で補う
+fn merge_metadata(primary: EndpointMetadata, fallback: &EndpointMetadata) -> EndpointMetadata {
+    EndpointMetadata {
+        container: primary.container.or_else(|| fallback.container.clone()),
+        container_id: primary
+            .container_id
+            .or_else(|| fallback.container_id.clone()),
+        pod: primary.pod.or_else(|| fallback.pod.clone()),
+        pod_uid: primary.pod_uid.or_else(|| fallback.pod_uid.clone()),
+        namespace: primary.namespace.or_else(|| fallback.namespace.clone()),
+        cgroup: primary.cgroup.or_else(|| fallback.cgroup.clone()),
+        labels: if primary.labels.is_empty() {
+            fallback.labels.clone()
+        } else {
+            primary.labels
+        },
+    }
+}
+
+/// `/proc/<pid>/net/fib_trie` からローカル（`/32 host LOCAL`）の IPv4 アドレスを取り出す
+fn parse_local_addresses(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut last_addr: Option<&str> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if let Some(addr) = trimmed.strip_prefix("|-- ") {
+            last_addr = Some(addr);
+        } else if trimmed == "/32 host LOCAL" {
+            if let Some(addr) = last_addr {
+                if !addr.starts_with("127.") && !out.iter().any(|a| a == addr) {
+                    out.push(addr.to_string());
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AnimatingPacket, PacketResult};
+
+    const CONTAINER_ID: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    const SANDBOX_ID: &str = "fedcba9876543210fedcba9876543210fedcba9876543210fedcba9876543210";
+    const POD_UID: &str = "2a3b4c5d-1111-2222-3333-444455556666";
+
+    const FIB_TRIE: &str = "\
+Main:
+  +-- 0.0.0.0/0 3 0 5
+     |-- 0.0.0.0
+        /0 universe UNICAST
+     +-- 10.244.1.0/24 2 0 2
+        |-- 10.244.1.0
+           /24 link UNICAST
+        |-- 10.244.1.17
+           /32 host LOCAL
+Local:
+  +-- 127.0.0.0/8 2 0 2
+     |-- 127.0.0.1
+        /32 host LOCAL
+     |-- 10.244.1.17
+        /32 host LOCAL
+";
+
+    fn captured(source: &str, destination: &str) -> CapturedPacket {
+        let mut packet = AnimatingPacket::generate("abc123", 0);
+        packet.source = source.to_string();
+        packet.destination = destination.to_string();
+        CapturedPacket {
+            packet,
+            result: PacketResult::Delivered,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "scrop-container-meta-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn parses_local_addresses_from_fib_trie() {
+        assert_eq!(parse_local_addresses(FIB_TRIE), vec!["10.244.1.17"]);
+    }
+
+    #[test]
+    fn parses_cgroup_container_and_pod_ids() {
+        let v2 = format!(
+            "0::/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod{}.slice/cri-containerd-{}.scope\n",
+            POD_UID.replace('-', "_"),
+            CONTAINER_ID
+        );
+        let path = parse_cgroup_path(&v2).unwrap();
+        assert_eq!(
+            container_id_from_cgroup(&path).as_deref(),
+            Some(CONTAINER_ID)
+        );
+        assert_eq!(pod_uid_from_cgroup(&path).as_deref(), Some(POD_UID));
+
+        let v1 = format!(
+            "12:pids:/docker/{}\n1:name=systemd:/docker/{}\n",
+            CONTAINER_ID, CONTAINER_ID
+        );
+        let path = parse_cgroup_path(&v1).unwrap();
+        assert_eq!(
+            container_id_from_cgroup(&path).as_deref(),
+            Some(CONTAINER_ID)
+        );
+        assert_eq!(pod_uid_from_cgroup(&path), None);
+
+        assert_eq!(
+            container_id_from_cgroup("/user.slice/session-1.scope"),
+            None
+        );
+    }
+
+    #[test]
+    fn file_source_enriches_by_ip_then_interface_then_netns() {
+        let dir = temp_dir("file");
+        let path = dir.join("endpoints.json");
+        write(
+            &path,
+            r#"{
+                "ips": { "10.244.1.17": { "pod": "web-0", "namespace": "default", "labels": { "app": "web" } } },
+                "interfaces": { "cni-1/eth0": { "container": "sidecar" } },
+                "netns": { "cni-2": { "container": "db" } }
+            }"#,
+        );
+        let enricher = MetadataEnricher::new(FileSource::new(path));
+
+        let mut packets = vec![
+            captured("10.244.1.17", "10.0.0.1"),
+            captured("192.168.0.1", "192.168.0.2"),
+            captured("192.168.0.1", "192.168.0.3"),
+        ];
+        packets[1].packet.interface = Some("eth0".to_string());
+        packets[1].packet.netns = Some("cni-1".to_string());
+        packets[2].packet.interface = Some("eth0".to_string());
+        packets[2].packet.netns = Some("cni-2".to_string());
+        enricher.enrich(&mut packets);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let source = packets[0].packet.source_meta.as_ref().expect("source meta");
+        assert_eq!(source.pod.as_deref(), Some("web-0"));
+        assert_eq!(source.labels.get("app").map(String::as_str), Some("web"));
+        assert!(packets[0].packet.destination_meta.is_none());
+
+        let dest = packets[1]
+            .packet
+            .destination_meta
+            .as_ref()
+            .expect("by interface");
+        assert_eq!(dest.container.as_deref(), Some("sidecar"));
+        let dest = packets[2]
+            .packet
+            .destination_meta
+            .as_ref()
+            .expect("by netns");
+        assert_eq!(dest.container.as_deref(), Some("db"));
+    }
+
+    #[test]
+    fn missing_file_yields_empty_index() {
+        let enricher = MetadataEnricher::new(FileSource::new(PathBuf::from(
+            "/nonexistent/scrop-endpoints.json",
+        )));
+        assert_eq!(*enricher.snapshot(), EndpointIndex::default());
+    }
+
+    #[test]
+    fn proc_source_maps_container_ips_with_cri_annotations() {
+        let root = temp_dir("proc");
+        let proc_root = root.join("proc");
+        // PID 1 はホストの netns
+        write(&proc_root.join("1/ns/net"), "");
+        write(&proc_root.join("1/cgroup"), "0::/init.scope\n");
+
+        // PID 100: サンドボックス（pause）、PID 101: アプリコンテナ。同じ netns を共有する。
+        write(&proc_root.join("100/ns/net"), "");
+        std::fs::create_dir_all(proc_root.join("101/ns")).unwrap();
+        std::fs::hard_link(proc_root.join("100/ns/net"), proc_root.join("101/ns/net")).unwrap();
+        let pod_slice = format!(
+            "/kubepods.slice/kubepods-pod{}.slice",
+            POD_UID.replace('-', "_")
+        );
+        write(
+            &proc_root.join("100/cgroup"),
+            &format!("0::{}/cri-containerd-{}.scope\n", pod_slice, SANDBOX_ID),
+        );
+        write(
+            &proc_root.join("101/cgroup"),
+            &format!("0::{}/cri-containerd-{}.scope\n", pod_slice, CONTAINER_ID),
+        );
+        write(&proc_root.join("100/net/fib_trie"), FIB_TRIE);
+
+        let bundles = root.join("bundles");
+        write(
+            &bundles.join(SANDBOX_ID).join("config.json"),
+            r#"{"annotations": {
+                "io.kubernetes.cri.container-type": "sandbox",
+                "io.kubernetes.cri.sandbox-name": "web-0",
+                "io.kubernetes.cri.sandbox-namespace": "default"
+            }}"#,
+        );
+        write(
+            &bundles.join(CONTAINER_ID).join("config.json"),
+            r#"{"annotations": {
+                "io.kubernetes.cri.container-type": "container",
+                "io.kubernetes.cri.container-name": "nginx",
+                "io.kubernetes.cri.sandbox-name": "web-0",
+                "io.kubernetes.cri.sandbox-namespace": "default"
+            }}"#,
+        );
+
+        let source = ProcSource::with_roots(
+            proc_root,
+            root.join("netns"),
+            vec![format!("{}/{{id}}/config.json", bundles.display())],
+        );
+        assert_eq!(source.name(), "cri");
+        let index = source.load().unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(index.by_ip.len(), 1);
+        let meta = &index.by_ip["10.244.1.17"];
+        assert_eq!(meta.container.as_deref(), Some("nginx"));
+        assert_eq!(meta.container_id.as_deref(), Some(CONTAINER_ID));
+        assert_eq!(meta.pod.as_deref(), Some("web-0"));
+        assert_eq!(meta.namespace.as_deref(), Some("default"));
+        assert_eq!(meta.pod_uid.as_deref(), Some(POD_UID));
+        assert!(meta.cgroup.as_deref().unwrap().ends_with(".scope"));
+    }
+}
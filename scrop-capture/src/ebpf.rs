@@ -1,6 +1,7 @@
 use std::borrow::Borrow;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::Ipv4Addr;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -9,18 +10,28 @@ use tokio::sync::{broadcast, mpsc, oneshot};
 
 use aya::maps::{HashMap as AyaHashMap, MapData, PerCpuArray, RingBuf};
 use aya::programs::xdp::XdpLinkId;
-use aya::programs::{TracePoint, Xdp, XdpFlags};
+use aya::programs::{KProbe, TracePoint, Xdp, XdpFlags};
 use aya::{Btf, EbpfLoader};
 use tracing::{error, info, warn};
 
 use crate::types::{
-    build_packet_id, generate_session_id, AnimatingPacket, CaptureStats, CapturedPacket,
-    CapturedPacketEnvelope, PacketResult, Protocol,
+    build_packet_id, generate_session_id, AnimatingPacket, CaptureStateEvent, CaptureStateKind,
+    CaptureStats, CapturedPacket, CapturedPacketEnvelope, InterfaceEvent, InterfaceEventKind,
+    PacketResult, ProcessInfo, Protocol,
+};
+use scrop_common::{
+    monitored_if_key, PacketEvent, ACTION_KFREE_SKB, ACTION_SOCK_RCV, ACTION_XDP_PASS,
 };
-use scrop_common::{PacketEvent, ACTION_KFREE_SKB, ACTION_XDP_PASS};
 
+use crate::config::{CaptureConfig, MAX_CORRELATION_TIMEOUT_MS};
 use crate::drop_reason::DropReasonResolver;
-use crate::{CaptureError, BATCH_FLUSH_INTERVAL_MS, BATCH_MAX_SIZE};
+use crate::enrich::{self, EnrichmentPipeline, SharedPipeline};
+use crate::metrics::CaptureMetrics;
+use crate::netlink::{LinkChange, LinkEvent, LinkMonitor};
+use crate::{
+    detect_all_interfaces, glob, netlink, netns, CaptureError, INTERFACE_EVENT_CHANNEL_CAPACITY,
+    STATE_EVENT_CHANNEL_CAPACITY,
+};
 
 // ELF64 ヘッダは 8-byte アラインメントが必要だが、include_bytes! は 1-byte しか保証しない。
 // object クレートがアラインメントを検証するため、明示的に 8-byte 境界に配置する。
@@ -36,11 +47,21 @@ const RINGBUF_DROPS_MAP_KEY: u32 = 0;
 const RINGBUF_DROP_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
 const RINGBUF_DRAIN_LIMIT: usize = 1024;
 const CORRELATION_BUCKET_MS: u64 = 5;
-const CORRELATION_TIMEOUT_MS: u64 = 50;
 const SEARCH_BUCKET_RADIUS: u64 = 1;
 const WHEEL_SLOTS: usize = 64;
-const SHADOW_CORRELATOR_ENV: &str = "SCROP_SHADOW_CORRELATOR";
+// 設定できる相関のタイムアウトの上限がタイマーホイールに収まること
+const _: () = assert!(
+    MAX_CORRELATION_TIMEOUT_MS / CORRELATION_BUCKET_MS + SEARCH_BUCKET_RADIUS < WHEEL_SLOTS as u64
+);
 const CORRELATION_BATCH_CHANNEL_CAPACITY: usize = 64;
+/// プロセス帰属用の kprobe（プログラム名, カーネル関数名）
+const SOCKET_KPROBES: &[(&str, &str)] = &[
+    ("scrop_sock_recvmsg", "sock_recvmsg"),
+    ("scrop_sock_sendmsg", "sock_sendmsg"),
+    ("scrop_inet_csk_accept", "inet_csk_accept"),
+    ("scrop_tcp_v4_do_rcv", "tcp_v4_do_rcv"),
+    ("scrop_udp_enqueue", "__udp_enqueue_schedule_skb"),
+];
 
 #[derive(Clone, Copy)]
 enum ResultClass {
@@ -254,6 +275,8 @@ pub enum EbpfCommand {
         interface: String,
         reply: oneshot::Sender<Result<(), String>>,
     },
+    /// 自動アタッチルールの変更などを受けて、実際のインターフェースと突き合わせ直す
+    Resync,
 }
 
 pub struct EbpfCapture {
@@ -262,6 +285,25 @@ pub struct EbpfCapture {
     stats: Arc<std::sync::Mutex<CaptureStats>>,
     diag: Arc<DiagCounters>,
     command_tx: std::sync::Mutex<Option<mpsc::Sender<EbpfCommand>>>,
+    /// アタッチ対象として要求されたインターフェース。停止中も保持し、start 時に再適用する。
+    desired_interfaces: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// 出現時に自動アタッチするインターフェース名の glob ルール
+    auto_attach_rules: Arc<std::sync::Mutex<Vec<String>>>,
+    interface_tx: broadcast::Sender<InterfaceEvent>,
+    state_tx: broadcast::Sender<CaptureStateEvent>,
+    enrichment: SharedPipeline,
+    metrics: Arc<CaptureMetrics>,
+    /// ソケット受信フックでパケットを受信プロセスに結び付ける（次回 start から有効）
+    process_attribution: AtomicBool,
+    config: CaptureConfig,
+    /// 実行中のキャプチャタスク。`shutdown` でデタッチと最後のバッチの送出を待つ
+    task: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+impl Default for EbpfCapture {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl EbpfCapture {
@@ -272,9 +314,49 @@ impl EbpfCapture {
             stats: Arc::new(std::sync::Mutex::new(CaptureStats::default())),
             diag: Arc::new(DiagCounters::default()),
             command_tx: std::sync::Mutex::new(None),
+            desired_interfaces: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            auto_attach_rules: Arc::new(std::sync::Mutex::new(Vec::new())),
+            interface_tx: broadcast::channel(INTERFACE_EVENT_CHANNEL_CAPACITY).0,
+            state_tx: broadcast::channel(STATE_EVENT_CHANNEL_CAPACITY).0,
+            enrichment: SharedPipeline::default(),
+            metrics: Arc::new(CaptureMetrics::new()),
+            process_attribution: AtomicBool::new(false),
+            config: CaptureConfig::default(),
+            task: std::sync::Mutex::new(None),
         }
     }
 
+    /// インターフェース変化の通知先を差し替える
+    pub fn with_interface_events(
+        mut self,
+        interface_tx: broadcast::Sender<InterfaceEvent>,
+    ) -> Self {
+        self.interface_tx = interface_tx;
+        self
+    }
+
+    /// キャプチャ状態の変化の通知先を差し替える
+    pub fn with_state_events(mut self, state_tx: broadcast::Sender<CaptureStateEvent>) -> Self {
+        self.state_tx = state_tx;
+        self
+    }
+
+    /// ラベル別の件数とヒストグラムの記録先を差し替える
+    pub fn with_metrics(mut self, metrics: Arc<CaptureMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// 相関・バッチ・リングバッファの設定を差し替える（次回 start から有効）
+    pub fn with_config(mut self, config: CaptureConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    fn notify_state(&self, kind: CaptureStateKind) {
+        let _ = self.state_tx.send(CaptureStateEvent { kind, error: None });
+    }
+
     pub fn is_running(&self) -> bool {
         self.is_running.load(Ordering::SeqCst)
     }
@@ -282,6 +364,7 @@ impl EbpfCapture {
     pub fn get_stats(&self) -> CaptureStats {
         let mut stats = self.stats.lock().unwrap().clone();
         self.diag.write_into_stats(&mut stats);
+        self.enrichment.read().unwrap().write_stats(&mut stats);
         stats
     }
 
@@ -299,44 +382,76 @@ impl EbpfCapture {
         let diag = Arc::clone(&self.diag);
         let session_id = generate_session_id();
 
-        tokio::spawn(async move {
-            if let Err(e) = run_ebpf_capture(
-                event_tx,
-                cmd_rx,
-                is_running.clone(),
-                packet_counter,
-                stats,
-                diag,
-                session_id,
-            )
-            .await
-            {
+        let ctx = CaptureRunContext {
+            event_tx,
+            cmd_rx,
+            is_running: Arc::clone(&is_running),
+            packet_counter,
+            stats,
+            diag,
+            session_id,
+            desired_interfaces: Arc::clone(&self.desired_interfaces),
+            auto_attach_rules: Arc::clone(&self.auto_attach_rules),
+            interface_tx: self.interface_tx.clone(),
+            enrichment: Arc::clone(&self.enrichment),
+            metrics: Arc::clone(&self.metrics),
+            process_attribution: self.process_attribution.load(Ordering::SeqCst),
+            config: self.config.clone(),
+        };
+
+        let state_tx = self.state_tx.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = run_ebpf_capture(ctx).await {
                 error!(error = %e, "fatal eBPF capture error");
                 is_running.store(false, Ordering::SeqCst);
+                let _ = state_tx.send(CaptureStateEvent {
+                    kind: CaptureStateKind::Failed,
+                    error: Some(e.to_string()),
+                });
                 std::process::exit(1);
             }
         });
+        *self.task.lock().unwrap() = Some(task);
+        self.notify_state(CaptureStateKind::Started);
     }
 
     pub fn stop(&self) {
-        self.is_running.store(false, Ordering::SeqCst);
+        let was_running = self.is_running.swap(false, Ordering::SeqCst);
         // チャネルを閉じてeBPFタスクに通知
         *self.command_tx.lock().unwrap() = None;
+        if was_running {
+            self.notify_state(CaptureStateKind::Stopped);
+        }
+    }
+
+    /// 停止して、XDP のデタッチと相関済みパケットの送出が終わるまで待つ
+    pub async fn shutdown(&self) {
+        self.stop();
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
     }
 
     pub fn reset(&self) {
         self.packet_counter.store(0, Ordering::SeqCst);
         *self.stats.lock().unwrap() = CaptureStats::default();
         self.diag.reset();
+        self.metrics.reset();
+        self.enrichment.read().unwrap().reset_stats();
+        self.notify_state(CaptureStateKind::Reset);
     }
 
     pub async fn attach_interface(&self, name: &str) -> Result<(), CaptureError> {
-        let tx = self
-            .command_tx
-            .lock()
-            .unwrap()
-            .clone()
-            .ok_or_else(|| CaptureError::InvalidState("Capture is not running".to_string()))?;
+        let Some(tx) = self.command_tx.lock().unwrap().clone() else {
+            // 停止中は存在確認のみ行い、次回 start 時にアタッチする
+            resolve_target(name).map_err(|msg| classify_ebpf_error(&msg))?;
+            self.desired_interfaces
+                .lock()
+                .unwrap()
+                .insert(name.to_string());
+            return Ok(());
+        };
         let (reply_tx, reply_rx) = oneshot::channel();
         tx.send(EbpfCommand::Attach {
             interface: name.to_string(),
@@ -347,16 +462,25 @@ impl EbpfCapture {
         reply_rx
             .await
             .map_err(|_| CaptureError::Other("Failed to receive attach reply".to_string()))?
-            .map_err(|msg| classify_ebpf_error(&msg))
+            .map_err(|msg| classify_ebpf_error(&msg))?;
+        self.desired_interfaces
+            .lock()
+            .unwrap()
+            .insert(name.to_string());
+        Ok(())
     }
 
     pub async fn detach_interface(&self, name: &str) -> Result<(), CaptureError> {
-        let tx = self
-            .command_tx
-            .lock()
-            .unwrap()
-            .clone()
-            .ok_or_else(|| CaptureError::InvalidState("Capture is not running".to_string()))?;
+        let Some(tx) = self.command_tx.lock().unwrap().clone() else {
+            if !self.desired_interfaces.lock().unwrap().remove(name) {
+                return Err(CaptureError::InvalidState(format!(
+                    "Interface {} is not attached",
+                    name
+                )));
+            }
+            return Ok(());
+        };
+        let was_desired = self.desired_interfaces.lock().unwrap().contains(name);
         let (reply_tx, reply_rx) = oneshot::channel();
         tx.send(EbpfCommand::Detach {
             interface: name.to_string(),
@@ -364,10 +488,50 @@ impl EbpfCapture {
         })
         .await
         .map_err(|_| CaptureError::Other("Failed to send detach command".to_string()))?;
-        reply_rx
+        let result = reply_rx
             .await
             .map_err(|_| CaptureError::Other("Failed to receive detach reply".to_string()))?
-            .map_err(|msg| classify_ebpf_error(&msg))
+            .map_err(|msg| classify_ebpf_error(&msg));
+        match result {
+            // start 時の再アタッチに失敗していたインターフェースは要求の取り消しだけで成功とする
+            Ok(()) | Err(CaptureError::InvalidState(_)) if was_desired => {
+                self.desired_interfaces.lock().unwrap().remove(name);
+                Ok(())
+            }
+            // 外せなかった場合は XDP が残っているため、次の start でも対象のままにする
+            other => other,
+        }
+    }
+
+    pub fn attached_interfaces(&self) -> HashSet<String> {
+        self.desired_interfaces.lock().unwrap().clone()
+    }
+
+    pub fn auto_attach_rules(&self) -> Vec<String> {
+        self.auto_attach_rules.lock().unwrap().clone()
+    }
+
+    pub fn set_enrichment(&self, pipeline: EnrichmentPipeline) {
+        *self.enrichment.write().unwrap() = Arc::new(pipeline);
+    }
+
+    pub fn process_attribution(&self) -> bool {
+        self.process_attribution.load(Ordering::SeqCst)
+    }
+
+    pub fn set_process_attribution(&self, enabled: bool) {
+        self.process_attribution.store(enabled, Ordering::SeqCst);
+    }
+
+    pub async fn set_auto_attach_rules(&self, rules: Vec<String>) -> Result<(), CaptureError> {
+        *self.auto_attach_rules.lock().unwrap() = rules;
+        let tx = self.command_tx.lock().unwrap().clone();
+        if let Some(tx) = tx {
+            tx.send(EbpfCommand::Resync)
+                .await
+                .map_err(|_| CaptureError::Other("Failed to send resync command".to_string()))?;
+        }
+        Ok(())
     }
 }
 
@@ -381,12 +545,38 @@ fn classify_ebpf_error(msg: &str) -> CaptureError {
     }
 }
 
-/// インターフェース名から ifindex を取得
-fn get_ifindex(iface: &str) -> Option<u32> {
-    let path = format!("/sys/class/net/{}/ifindex", iface);
-    std::fs::read_to_string(&path)
-        .ok()
-        .and_then(|s| s.trim().parse::<u32>().ok())
+/// アタッチ対象のインターフェース（`"<netns>/<ifname>"` の修飾名を解決したもの）
+struct AttachTarget {
+    name: String,
+    netns: Option<String>,
+    netns_path: Option<PathBuf>,
+    netns_ino: u32,
+    ifindex: u32,
+    mac: Option<[u8; 6]>,
+}
+
+/// 修飾名から netns・ifindex・MAC アドレスを解決する
+fn resolve_target(interface: &str) -> Result<AttachTarget, String> {
+    let (netns_id, name) = netns::split_qualified(interface);
+    let netns_path = match netns_id {
+        Some(id) => netns::resolve_path(id).map_err(|e| e.to_string())?,
+        None => None,
+    };
+    let (ifindex, mac) = netns::run_in(netns_path.as_deref(), || {
+        (netns::ifindex(name), netns::hw_addr(name))
+    })
+    .map_err(|e| format!("Failed to enter network namespace of {}: {}", interface, e))?;
+    let ifindex = ifindex.ok_or_else(|| format!("Interface {} not found", interface))?;
+    let netns_ino = netns::inode(netns_path.as_deref())
+        .ok_or_else(|| format!("Network namespace of {} not found", interface))?;
+    Ok(AttachTarget {
+        name: name.to_string(),
+        netns: netns_id.map(str::to_string),
+        netns_path,
+        netns_ino,
+        ifindex,
+        mac,
+    })
 }
 
 fn clock_gettime_ns(clock_id: libc::clockid_t) -> Result<u64, String> {
@@ -406,16 +596,6 @@ fn clock_gettime_ns(clock_id: libc::clockid_t) -> Result<u64, String> {
         .saturating_add(ts.tv_nsec as u64))
 }
 
-fn env_flag_enabled(name: &str) -> bool {
-    match std::env::var(name) {
-        Ok(value) => {
-            let normalized = value.trim().to_ascii_lowercase();
-            matches!(normalized.as_str(), "1" | "true" | "yes" | "on")
-        }
-        Err(_) => false,
-    }
-}
-
 fn calculate_epoch_offset_ms() -> Result<f64, String> {
     let realtime_ns = clock_gettime_ns(libc::CLOCK_REALTIME)?;
     let monotonic_ns = clock_gettime_ns(libc::CLOCK_MONOTONIC)?;
@@ -483,6 +663,8 @@ struct PendingPacket {
     event: PacketEvent,
     counter: u64,
     received_mono_ns: u64,
+    /// ソケット受信イベントで判明した受信プロセス
+    process: Option<ProcessInfo>,
 }
 
 #[derive(Default)]
@@ -494,13 +676,16 @@ struct BucketSlot {
 struct Correlator {
     wheel: Vec<BucketSlot>,
     diag: Arc<DiagCounters>,
+    /// 配送済みとみなすまでのバケット数
+    timeout_buckets: u64,
 }
 
 impl Correlator {
-    fn new(diag: Arc<DiagCounters>) -> Self {
+    fn new(diag: Arc<DiagCounters>, timeout_ms: u64) -> Self {
         Self {
             wheel: (0..WHEEL_SLOTS).map(|_| BucketSlot::default()).collect(),
             diag,
+            timeout_buckets: timeout_ms / CORRELATION_BUCKET_MS,
         }
     }
 
@@ -524,9 +709,53 @@ impl Correlator {
                 event,
                 counter,
                 received_mono_ns,
+                process: None,
             });
     }
 
+    /// ソケット受信イベントの受信プロセスを、同じ flow+size でまだ帰属していない最も古い pending に記録する。
+    /// flow 内の配送は順序どおりなので、受信イベントも到着順（FIFO）に割り当てる。
+    /// パケットは pending に残し、結果（配送 / ドロップ）の確定は従来どおり行う。
+    fn attribute_process(&mut self, event: &PacketEvent) -> bool {
+        let Some(process) = process_from_event(event) else {
+            return false;
+        };
+        let key = FlowSizeKey::from_event(event);
+        let now_mono_ns = event.ktime_ns;
+
+        let mut best: Option<(u64, usize, u64)> = None;
+        for bucket in Self::search_buckets(Self::bucket_of(now_mono_ns)) {
+            let Some(queue) = self
+                .slot_for_epoch(bucket)
+                .and_then(|slot| slot.by_key.get(&key))
+            else {
+                continue;
+            };
+            let Some((index, candidate)) =
+                queue.iter().enumerate().find(|(_, p)| p.process.is_none())
+            else {
+                continue;
+            };
+            let received = candidate.received_mono_ns;
+            if best.is_none_or(|(_, _, oldest)| received < oldest) {
+                best = Some((bucket, index, received));
+            }
+        }
+
+        let Some((bucket, index, _)) = best else {
+            return false;
+        };
+        let Some(pending) = self
+            .slot_for_epoch_mut(bucket)
+            .and_then(|slot| slot.by_key.get_mut(&key))
+            .and_then(|queue| queue.get_mut(index))
+        else {
+            return false;
+        };
+        pending.process = Some(process);
+        true
+    }
+
     fn match_kfree(&mut self, event: &PacketEvent) -> Option<PendingPacket> {
         self.match_kfree_at(event, event.ktime_ns)
     }
@@ -586,8 +815,7 @@ impl Correlator {
 
     fn drain_expired(&mut self, now_mono_ns: u64) -> Vec<PendingPacket> {
         let now_bucket = Self::bucket_of(now_mono_ns);
-        let timeout_buckets = CORRELATION_TIMEOUT_MS / CORRELATION_BUCKET_MS;
-        let expire_bucket = now_bucket.saturating_sub(timeout_buckets);
+        let expire_bucket = now_bucket.saturating_sub(self.timeout_buckets);
         let mut drained = Vec::new();
 
         for slot in &mut self.wheel {
@@ -785,19 +1013,47 @@ fn attach_xdp(program: &mut Xdp, iface: &str) -> Result<XdpLinkId, String> {
 // メインキャプチャループ
 // ---------------------------------------------------------------------------
 
-async fn run_ebpf_capture(
+/// キャプチャタスク 1 回分の起動パラメータ
+struct CaptureRunContext {
     event_tx: broadcast::Sender<CapturedPacketEnvelope>,
-    mut cmd_rx: mpsc::Receiver<EbpfCommand>,
+    cmd_rx: mpsc::Receiver<EbpfCommand>,
     is_running: Arc<AtomicBool>,
     packet_counter: Arc<AtomicU64>,
     stats: Arc<std::sync::Mutex<CaptureStats>>,
     diag: Arc<DiagCounters>,
     session_id: String,
-) -> Result<(), CaptureError> {
-    let resolver = Arc::new(DropReasonResolver::new().map_err(|e| CaptureError::Other(e))?);
+    /// start 時にアタッチするインターフェース（停止中に要求されたものを含む）
+    desired_interfaces: Arc<std::sync::Mutex<HashSet<String>>>,
+    auto_attach_rules: Arc<std::sync::Mutex<Vec<String>>>,
+    interface_tx: broadcast::Sender<InterfaceEvent>,
+    enrichment: SharedPipeline,
+    metrics: Arc<CaptureMetrics>,
+    process_attribution: bool,
+    config: CaptureConfig,
+}
+
+async fn run_ebpf_capture(ctx: CaptureRunContext) -> Result<(), CaptureError> {
+    let CaptureRunContext {
+        event_tx,
+        mut cmd_rx,
+        is_running,
+        packet_counter,
+        stats,
+        diag,
+        session_id,
+        desired_interfaces,
+        auto_attach_rules,
+        interface_tx,
+        enrichment,
+        metrics,
+        process_attribution,
+        config,
+    } = ctx;
+    let resolver = Arc::new(DropReasonResolver::new().map_err(CaptureError::Other)?);
 
     let mut ebpf = EbpfLoader::new()
         .btf(Btf::from_sys_fs().ok().as_ref())
+        .set_max_entries("EVENTS", config.ring_buffer_bytes)
         .load(EBPF_ELF)
         .map_err(|e| CaptureError::EbpfLoadFailed(e.to_string()))?;
 
@@ -812,8 +1068,15 @@ async fn run_ebpf_capture(
         .load()
         .map_err(|e| CaptureError::EbpfLoadFailed(format!("XDP load: {}", e)))?;
 
-    // 動的リンク管理テーブル（コマンドで操作）
-    let mut attached: HashMap<String, (XdpLinkId, u32)> = HashMap::new();
+    // 動的リンク管理テーブル（コマンドとリンクイベントで操作）
+    let labels: AttachmentLabels = Arc::default();
+    let mut tracker = InterfaceTracker {
+        attached: HashMap::new(),
+        desired: desired_interfaces,
+        rules: auto_attach_rules,
+        interface_tx,
+        labels: Arc::clone(&labels),
+    };
 
     // kfree_skb トレースポイントのロード・アタッチ
     let tp: &mut TracePoint = ebpf
@@ -832,6 +1095,16 @@ async fn run_ebpf_capture(
 
     info!("kfree_skb tracepoint attached");
 
+    // プロセス帰属用 kprobe（任意機能のため失敗してもキャプチャは続行する）
+    if process_attribution {
+        match attach_socket_kprobes(&mut ebpf) {
+            Ok(()) => info!("socket kprobes attached; process attribution enabled"),
+            Err(e) => {
+                warn!(error = %e, "failed to attach socket kprobes; process attribution may be incomplete")
+            }
+        }
+    }
+
     // ring buffer のセットアップ
     let ring_buf: RingBuf<_> = ebpf
         .take_map("EVENTS")
@@ -935,33 +1208,35 @@ async fn run_ebpf_capture(
     let correlation_resolver = Arc::clone(&resolver);
     let correlation_session_id = session_id;
     let correlation_diag = Arc::clone(&diag);
-    let shadow_compare_enabled = env_flag_enabled(SHADOW_CORRELATOR_ENV);
+    let correlation_labels = labels;
+    let shadow_compare_enabled = config.shadow_correlator;
     correlation_diag.set_shadow_compare_enabled(shadow_compare_enabled);
     if shadow_compare_enabled {
-        info!(
-            env = SHADOW_CORRELATOR_ENV,
-            "shadow legacy correlator enabled for transition diagnostics"
-        );
+        info!("shadow legacy correlator enabled for transition diagnostics");
     }
+    let correlation_timeout_ms = config.correlation_timeout_ms;
+    let batch_flush_interval_ms = config.batch_flush_interval_ms;
+    let batch_max_size = config.batch_max_size;
 
-    tokio::spawn(async move {
+    let correlation_task = tokio::spawn(async move {
         let mut offset_cache = initial_offset_cache;
-        let mut correlator = Correlator::new(Arc::clone(&correlation_diag));
-        let mut shadow_correlator =
-            shadow_compare_enabled.then(|| Correlator::new(Arc::clone(&correlation_diag)));
+        let mut correlator = Correlator::new(Arc::clone(&correlation_diag), correlation_timeout_ms);
+        let mut shadow_correlator = shadow_compare_enabled
+            .then(|| Correlator::new(Arc::clone(&correlation_diag), correlation_timeout_ms));
         let mut shadow_tracker = shadow_compare_enabled
             .then(|| ShadowTransitionTracker::new(Arc::clone(&correlation_diag)));
         let mut events_closed = false;
         let mut timeout_interval = tokio::time::interval(tokio::time::Duration::from_millis(10));
         let mut batch_flush_interval =
-            tokio::time::interval(tokio::time::Duration::from_millis(BATCH_FLUSH_INTERVAL_MS));
-        let mut out_batch: Vec<CapturedPacket> = Vec::with_capacity(BATCH_MAX_SIZE);
+            tokio::time::interval(tokio::time::Duration::from_millis(batch_flush_interval_ms));
+        let mut out_batch: Vec<CapturedPacket> = Vec::with_capacity(batch_max_size);
 
         loop {
             tokio::select! {
                 recv = rx.recv(), if !events_closed => {
                     match recv {
                         Some(events) => {
+                            let labels = Arc::clone(&correlation_labels.read().unwrap());
                             for (event, counter) in events {
                                 if event.action == ACTION_XDP_PASS {
                                     // XDP PASS → pending に格納
@@ -978,24 +1253,32 @@ async fn run_ebpf_capture(
                                     let result_class = ResultClass::from_packet_result(&result);
 
                                     if let Some(p) = correlator.match_kfree(&event) {
+                                        metrics.observe_correlation_latency(
+                                            event.ktime_ns.saturating_sub(p.received_mono_ns),
+                                        );
                                         // XDP で見たパケットがドロップされた
-                                        let captured = convert_event(
+                                        let mut captured = convert_event(
                                             &p.event,
                                             &correlation_session_id,
                                             p.counter,
                                             result,
                                             Some(reason),
+                                            resolve_label(&labels, &p.event),
                                         );
+                                        captured.packet.process =
+                                            p.process.or_else(|| process_from_event(&event));
                                         update_stats(&correlation_stats, &captured.result);
                                         out_batch.push(captured);
                                         if let Some(tracker) = shadow_tracker.as_mut() {
                                             tracker.observe_ktime(p.counter, result_class);
                                         }
-                                        if out_batch.len() >= BATCH_MAX_SIZE {
+                                        if out_batch.len() >= batch_max_size {
                                             flush_captured_batch(
                                                 &correlation_event_tx,
                                                 &mut out_batch,
                                                 offset_cache.current_offset_ms(),
+                                                &enrichment,
+                                                &metrics,
                                             );
                                         }
                                     }
@@ -1010,6 +1293,9 @@ async fn run_ebpf_capture(
                                         }
                                     }
                                     // pending にマッチしない kfree_skb イベントは破棄する
+                                } else if event.action == ACTION_SOCK_RCV {
+                                    // ソケット到達 → pending に受信プロセスを記録（結果は確定しない）
+                                    correlator.attribute_process(&event);
                                 }
                             }
                         }
@@ -1020,7 +1306,7 @@ async fn run_ebpf_capture(
                     }
                 }
                 _ = timeout_interval.tick() => {
-                    let shadow_is_empty = shadow_correlator.as_ref().map_or(true, Correlator::is_empty);
+                    let shadow_is_empty = shadow_correlator.as_ref().is_none_or(Correlator::is_empty);
                     if (!correlation_is_running.load(Ordering::SeqCst) || events_closed)
                         && correlator.is_empty()
                         && shadow_is_empty
@@ -1035,24 +1321,32 @@ async fn run_ebpf_capture(
                     };
                     let expired_packets = correlator.drain_expired(now_mono_ns);
                     correlation_diag.record_timeout_drain(expired_packets.len() as u64);
+                    let labels = Arc::clone(&correlation_labels.read().unwrap());
                     for p in expired_packets {
-                        let captured = convert_event(
+                        metrics.observe_correlation_latency(
+                            now_mono_ns.saturating_sub(p.received_mono_ns),
+                        );
+                        let mut captured = convert_event(
                             &p.event,
                             &correlation_session_id,
                             p.counter,
                             PacketResult::Delivered,
                             None,
+                            resolve_label(&labels, &p.event),
                         );
+                        captured.packet.process = p.process;
                         update_stats(&correlation_stats, &captured.result);
                         if let Some(tracker) = shadow_tracker.as_mut() {
                             tracker.observe_ktime(p.counter, ResultClass::Delivered);
                         }
                         out_batch.push(captured);
-                        if out_batch.len() >= BATCH_MAX_SIZE {
+                        if out_batch.len() >= batch_max_size {
                             flush_captured_batch(
                                 &correlation_event_tx,
                                 &mut out_batch,
                                 offset_cache.current_offset_ms(),
+                                &enrichment,
+                                &metrics,
                             );
                         }
                     }
@@ -1070,6 +1364,8 @@ async fn run_ebpf_capture(
                         &correlation_event_tx,
                         &mut out_batch,
                         offset_cache.current_offset_ms(),
+                        &enrichment,
+                        &metrics,
                     );
                 }
             }
@@ -1082,9 +1378,23 @@ async fn run_ebpf_capture(
             &correlation_event_tx,
             &mut out_batch,
             offset_cache.current_offset_ms(),
+            &enrichment,
+            &metrics,
         );
     });
 
+    // 停止中に要求されたインターフェースと自動アタッチルールを適用する
+    tracker.resync(&mut ebpf);
+    tracker.publish_labels();
+
+    let mut link_monitor = match LinkMonitor::new() {
+        Ok(monitor) => Some(monitor),
+        Err(e) => {
+            warn!(error = %e, "failed to open netlink link monitor; hotplug tracking disabled");
+            None
+        }
+    };
+
     let mut ringbuf_drop_refresh = tokio::time::interval(RINGBUF_DROP_REFRESH_INTERVAL);
     ringbuf_drop_refresh.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
 
@@ -1094,44 +1404,157 @@ async fn run_ebpf_capture(
             cmd = cmd_rx.recv() => {
                 match cmd {
                     Some(EbpfCommand::Attach { interface, reply }) => {
-                        let result = handle_attach(&mut ebpf, &interface, &mut attached);
+                        let already_attached = tracker.attached.contains_key(&interface);
+                        let result = handle_attach(&mut ebpf, &interface, &mut tracker.attached);
+                        if result.is_ok() && !already_attached {
+                            tracker.notify(InterfaceEventKind::Attached, &interface, None, None);
+                        }
                         let _ = reply.send(result);
                     }
                     Some(EbpfCommand::Detach { interface, reply }) => {
-                        let result = handle_detach(&mut ebpf, &interface, &mut attached);
+                        let result = handle_detach(&mut ebpf, &interface, &mut tracker.attached);
+                        if result.is_ok() {
+                            tracker.notify(InterfaceEventKind::Detached, &interface, None, None);
+                        }
                         let _ = reply.send(result);
                     }
+                    Some(EbpfCommand::Resync) => tracker.resync(&mut ebpf),
                     None => break, // チャネル閉鎖 = stop
                 }
             }
+            events = next_link_events(&mut link_monitor) => {
+                match events {
+                    Ok(events) => {
+                        for event in &events {
+                            tracker.handle_link_event(&mut ebpf, event);
+                        }
+                    }
+                    Err(e) if netlink::is_overrun(&e) => {
+                        warn!("netlink link monitor overrun; resyncing interfaces");
+                        tracker.resync(&mut ebpf);
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "netlink link monitor failed; hotplug tracking disabled");
+                        link_monitor = None;
+                    }
+                }
+            }
             _ = ringbuf_drop_refresh.tick() => {
                 refresh_transport_dropped_stats(&stats, &ringbuf_drops);
+                continue;
             }
             _ = tokio::time::sleep(tokio::time::Duration::from_millis(100)) => {
                 // 定期的に is_running をチェック
+                continue;
             }
         }
+        tracker.publish_labels();
     }
 
     refresh_transport_dropped_stats(&stats, &ringbuf_drops);
 
-    // ebpfがdropされるとXDPプログラム・トレースポイントは自動的にデタッチされる
-    let iface_names: Vec<&str> = attached.keys().map(|s| s.as_str()).collect();
+    // 別の netns のリンクは drop では外れないことがあるため、それぞれの netns で明示的に外す。
+    // トレースポイントは ebpf の drop で自動的にデタッチされる。
+    let iface_names: Vec<String> = tracker.attached.keys().cloned().collect();
+    for interface in &iface_names {
+        if let Err(e) = handle_detach(&mut ebpf, interface, &mut tracker.attached) {
+            warn!(interface, error = %e, "failed to detach XDP program on stop");
+        }
+    }
     info!(interfaces = ?iface_names, "XDP program and tracepoint detached");
+
+    // 未確定のパケットがタイムアウトで確定し、最後のバッチが送られるまで待つ
+    let _ = correlation_task.await;
+    Ok(())
+}
+
+/// XDP アタッチ 1 件分の状態
+struct Attachment {
+    link_id: XdpLinkId,
+    /// ホスト以外の netns にある場合、その nsfs パス（デタッチも同じ netns で行う）
+    netns_path: Option<PathBuf>,
+    label: AttachmentLabel,
+}
+
+impl Attachment {
+    /// kfree_skb 側で照合する MONITORED_IFS のキー。
+    /// `skb->dev` が既に外れていると netns が 0 になるため、ホストのインターフェースは 0 でも登録する。
+    fn monitored_keys(&self) -> Vec<u64> {
+        let mut keys = vec![monitored_if_key(self.label.netns_ino, self.label.ifindex)];
+        if self.netns_path.is_none() {
+            keys.push(monitored_if_key(0, self.label.ifindex));
+        }
+        keys
+    }
+}
+
+/// パケットに付与する受信インターフェースの情報。相関タスクと共有する。
+#[derive(Debug, Clone, PartialEq)]
+struct AttachmentLabel {
+    ifindex: u32,
+    netns_ino: u32,
+    mac: Option<[u8; 6]>,
+    interface: String,
+    netns: Option<String>,
+}
+
+type AttachmentLabels = Arc<std::sync::RwLock<Arc<Vec<AttachmentLabel>>>>;
+
+/// イベントの受信インターフェースを特定する。
+/// XDP では netns が取れないため、同じ ifindex が複数の netns にあれば宛先 MAC で絞り込み、
+/// それでも決まらなければホスト側を優先する。
+fn resolve_label<'a>(
+    labels: &'a [AttachmentLabel],
+    event: &PacketEvent,
+) -> Option<&'a AttachmentLabel> {
+    let mut candidates = labels.iter().filter(|l| {
+        l.ifindex == event.ifindex && (event.netns_ino == 0 || l.netns_ino == event.netns_ino)
+    });
+    let first = candidates.next()?;
+    let rest: Vec<&AttachmentLabel> = candidates.collect();
+    if rest.is_empty() {
+        return Some(first);
+    }
+    let all = std::iter::once(first).chain(rest);
+    let mut fallback = first;
+    for label in all {
+        if label.mac == Some(event.dst_mac) {
+            return Some(label);
+        }
+        if label.netns.is_none() {
+            fallback = label;
+        }
+    }
+    Some(fallback)
+}
+
+fn attach_socket_kprobes(ebpf: &mut aya::Ebpf) -> Result<(), String> {
+    for &(name, function) in SOCKET_KPROBES {
+        let program: &mut KProbe = ebpf
+            .program_mut(name)
+            .ok_or_else(|| format!("kprobe '{}' not found", name))?
+            .try_into()
+            .map_err(|e: aya::programs::ProgramError| e.to_string())?;
+        program
+            .load()
+            .map_err(|e| format!("kprobe {} load: {}", name, e))?;
+        program
+            .attach(function, 0)
+            .map_err(|e| format!("kprobe {} attach: {}", function, e))?;
+    }
     Ok(())
 }
 
 fn handle_attach(
     ebpf: &mut aya::Ebpf,
     interface: &str,
-    attached: &mut HashMap<String, (XdpLinkId, u32)>,
+    attached: &mut HashMap<String, Attachment>,
 ) -> Result<(), String> {
     if attached.contains_key(interface) {
         return Ok(());
     }
 
-    let ifindex =
-        get_ifindex(interface).ok_or_else(|| format!("Interface {} not found", interface))?;
+    let target = resolve_target(interface)?;
 
     let program: &mut Xdp = ebpf
         .program_mut("scrop_xdp")
@@ -1139,33 +1562,58 @@ fn handle_attach(
         .try_into()
         .map_err(|e: aya::programs::ProgramError| e.to_string())?;
 
-    let link_id = attach_xdp(program, interface)?;
+    // aya はインターフェース名を呼び出しスレッドの netns で解決するため、対象 netns に入って実行する
+    let link_id = netns::run_in(target.netns_path.as_deref(), || {
+        attach_xdp(program, &target.name)
+    })
+    .map_err(|e| format!("Failed to enter network namespace of {}: {}", interface, e))??;
     info!(interface, "XDP program attached");
 
-    // MONITORED_IFS に ifindex を登録
-    let mut monitored_ifs: AyaHashMap<_, u32, u32> = AyaHashMap::try_from(
+    let attachment = Attachment {
+        link_id,
+        netns_path: target.netns_path,
+        label: AttachmentLabel {
+            ifindex: target.ifindex,
+            netns_ino: target.netns_ino,
+            mac: target.mac,
+            interface: target.name,
+            netns: target.netns,
+        },
+    };
+
+    // MONITORED_IFS に (netns, ifindex) を登録
+    let mut monitored_ifs: AyaHashMap<_, u64, u32> = AyaHashMap::try_from(
         ebpf.map_mut("MONITORED_IFS")
             .ok_or_else(|| "MONITORED_IFS map not found".to_string())?,
     )
     .map_err(|e: aya::maps::MapError| format!("MONITORED_IFS map: {}", e))?;
 
-    monitored_ifs
-        .insert(&ifindex, &1, 0)
-        .map_err(|e| format!("MONITORED_IFS insert: {}", e))?;
-    info!(ifindex, interface, "registered ifindex in MONITORED_IFS");
-
-    attached.insert(interface.to_string(), (link_id, ifindex));
+    for key in attachment.monitored_keys() {
+        monitored_ifs
+            .insert(key, 1, 0)
+            .map_err(|e| format!("MONITORED_IFS insert: {}", e))?;
+    }
+    info!(
+        ifindex = attachment.label.ifindex,
+        netns_ino = attachment.label.netns_ino,
+        interface,
+        "registered ifindex in MONITORED_IFS"
+    );
+
+    attached.insert(interface.to_string(), attachment);
     Ok(())
 }
 
 fn handle_detach(
     ebpf: &mut aya::Ebpf,
     interface: &str,
-    attached: &mut HashMap<String, (XdpLinkId, u32)>,
+    attached: &mut HashMap<String, Attachment>,
 ) -> Result<(), String> {
-    let (link_id, ifindex) = attached
+    let attachment = attached
         .remove(interface)
         .ok_or_else(|| format!("Interface {} is not attached", interface))?;
+    let keys = attachment.monitored_keys();
+    let ifindex = attachment.label.ifindex;
 
     let program: &mut Xdp = ebpf
         .program_mut("scrop_xdp")
@@ -1173,25 +1621,253 @@ fn handle_detach(
         .try_into()
         .map_err(|e: aya::programs::ProgramError| e.to_string())?;
 
-    program
-        .detach(link_id)
-        .map_err(|e| format!("Failed to detach XDP from {}: {}", interface, e))?;
+    netns::run_in(attachment.netns_path.as_deref(), || {
+        program.detach(attachment.link_id)
+    })
+    .map_err(|e| format!("Failed to enter network namespace of {}: {}", interface, e))?
+    .map_err(|e| format!("Failed to detach XDP from {}: {}", interface, e))?;
 
     info!(interface, "XDP program detached");
 
-    // MONITORED_IFS から ifindex を削除
-    let mut monitored_ifs: AyaHashMap<_, u32, u32> = AyaHashMap::try_from(
+    // MONITORED_IFS から (netns, ifindex) を削除
+    let mut monitored_ifs: AyaHashMap<_, u64, u32> = AyaHashMap::try_from(
         ebpf.map_mut("MONITORED_IFS")
             .ok_or_else(|| "MONITORED_IFS map not found".to_string())?,
     )
     .map_err(|e: aya::maps::MapError| format!("MONITORED_IFS map: {}", e))?;
 
-    let _ = monitored_ifs.remove(&ifindex);
+    for key in keys {
+        let _ = monitored_ifs.remove(&key);
+    }
     info!(ifindex, interface, "removed ifindex from MONITORED_IFS");
 
     Ok(())
 }
 
+/// アタッチ済みの XDP を解除して MONITORED_IFS から外す。
+/// インターフェースや netns が既に消えている場合もあるため、解除の失敗は無視する。
+fn forget_xdp_link(ebpf: &mut aya::Ebpf, interface: &str, attachment: Attachment) {
+    let keys = attachment.monitored_keys();
+    let ifindex = attachment.label.ifindex;
+    if let Some(program) = ebpf
+        .program_mut("scrop_xdp")
+        .and_then(|p| <&mut Xdp>::try_from(p).ok())
+    {
+        let result = netns::run_in(attachment.netns_path.as_deref(), || {
+            program
+                .detach(attachment.link_id)
+                .map_err(|e| e.to_string())
+        })
+        .map_err(|e| e.to_string())
+        .and_then(|r| r);
+        if let Err(e) = result {
+            info!(interface, error = %e, "XDP link already gone");
+        }
+    }
+    if let Some(map) = ebpf.map_mut("MONITORED_IFS") {
+        if let Ok(mut monitored_ifs) = AyaHashMap::<_, u64, u32>::try_from(map) {
+            for key in keys {
+                let _ = monitored_ifs.remove(&key);
+            }
+        }
+    }
+    info!(
+        ifindex,
+        interface, "removed stale ifindex from MONITORED_IFS"
+    );
+}
+
+async fn next_link_events(monitor: &mut Option<LinkMonitor>) -> std::io::Result<Vec<LinkEvent>> {
+    match monitor {
+        Some(monitor) => monitor.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// ホットプラグ対応のインターフェース管理
+// ---------------------------------------------------------------------------
+
+struct InterfaceTracker {
+    /// インターフェース名（netns 内は `"<netns>/<ifname>"`） → アタッチ状態
+    attached: HashMap<String, Attachment>,
+    desired: Arc<std::sync::Mutex<HashSet<String>>>,
+    rules: Arc<std::sync::Mutex<Vec<String>>>,
+    interface_tx: broadcast::Sender<InterfaceEvent>,
+    labels: AttachmentLabels,
+}
+
+impl InterfaceTracker {
+    /// アタッチ状態の変更を相関タスクに反映する
+    fn publish_labels(&self) {
+        let labels: Vec<AttachmentLabel> =
+            self.attached.values().map(|a| a.label.clone()).collect();
+        *self.labels.write().unwrap() = Arc::new(labels);
+    }
+
+    fn notify(
+        &self,
+        kind: InterfaceEventKind,
+        interface: &str,
+        previous_name: Option<String>,
+        error: Option<String>,
+    ) {
+        let mut attached: Vec<String> = self.attached.keys().cloned().collect();
+        attached.sort();
+        let _ = self.interface_tx.send(InterfaceEvent {
+            kind,
+            interface: interface.to_string(),
+            previous_name,
+            error,
+            interfaces: detect_all_interfaces(),
+            attached,
+        });
+    }
+
+    fn is_wanted(&self, name: &str) -> bool {
+        self.desired.lock().unwrap().contains(name)
+            || glob::matches_any(&self.rules.lock().unwrap(), name)
+    }
+
+    fn try_attach(&mut self, ebpf: &mut aya::Ebpf, name: &str) {
+        match handle_attach(ebpf, name, &mut self.attached) {
+            Ok(()) => {
+                self.desired.lock().unwrap().insert(name.to_string());
+                self.notify(InterfaceEventKind::Attached, name, None, None);
+            }
+            Err(e) => {
+                warn!(interface = name, error = %e, "failed to attach interface");
+                self.notify(InterfaceEventKind::AttachFailed, name, None, Some(e));
+            }
+        }
+    }
+
+    /// アタッチが消えたインターフェースを記録から外す。
+    /// ルールで自動アタッチされたものは要求からも外し、明示的に要求されたものは再出現を待つ。
+    fn drop_attachment(&mut self, ebpf: &mut aya::Ebpf, name: &str) {
+        let Some(attachment) = self.attached.remove(name) else {
+            return;
+        };
+        forget_xdp_link(ebpf, name, attachment);
+        if glob::matches_any(&self.rules.lock().unwrap(), name) {
+            self.desired.lock().unwrap().remove(name);
+        }
+        self.notify(InterfaceEventKind::Detached, name, None, None);
+    }
+
+    /// 実際のインターフェース一覧と突き合わせ、消えたものを外して要求・ルールにマッチするものをアタッチする
+    fn resync(&mut self, ebpf: &mut aya::Ebpf) {
+        self.drop_stale(ebpf, false);
+
+        // ルールはホストの名前にのみ適用し、netns 内は明示的に要求されたものだけを対象にする
+        let netns_requested: Vec<String> = self
+            .desired
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|name| netns::split_qualified(name).0.is_some())
+            .cloned()
+            .collect();
+        let mut candidates: Vec<String> = detect_all_interfaces()
+            .into_iter()
+            .filter(|name| self.is_wanted(name))
+            .chain(
+                netns_requested
+                    .into_iter()
+                    .filter(|name| resolve_target(name).is_ok()),
+            )
+            .filter(|name| !self.attached.contains_key(name))
+            .collect();
+        candidates.sort();
+        for name in candidates {
+            self.try_attach(ebpf, &name);
+        }
+    }
+
+    /// 実体が消えた（または ifindex が変わった）アタッチを外す
+    fn drop_stale(&mut self, ebpf: &mut aya::Ebpf, netns_only: bool) {
+        let stale: Vec<String> = self
+            .attached
+            .iter()
+            .filter(|(_, a)| !netns_only || a.netns_path.is_some())
+            .filter(|(name, a)| {
+                resolve_target(name)
+                    .map(|t| (t.ifindex, t.netns_ino) != (a.label.ifindex, a.label.netns_ino))
+                    .unwrap_or(true)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in stale {
+            self.drop_attachment(ebpf, &name);
+        }
+    }
+
+    /// ホスト netns のリンクイベントを反映する（netlink モニタはホストのみを購読している）
+    fn handle_link_event(&mut self, ebpf: &mut aya::Ebpf, event: &LinkEvent) {
+        let by_ifindex = self
+            .attached
+            .iter()
+            .find(|(_, a)| a.netns_path.is_none() && a.label.ifindex == event.ifindex)
+            .map(|(name, _)| name.clone());
+
+        match event.change {
+            LinkChange::Removed => {
+                if let Some(name) = by_ifindex {
+                    self.drop_attachment(ebpf, &name);
+                }
+                // コンテナ削除時はホスト側の veth が消えるので、netns 側のアタッチも確認する
+                self.drop_stale(ebpf, true);
+                self.notify(InterfaceEventKind::Removed, &event.name, None, None);
+            }
+            _ => {
+                // 同じ ifindex で名前が変わった: XDP は ifindex に付いたままなので記録だけ追従する
+                if let Some(previous) = by_ifindex.filter(|name| *name != event.name) {
+                    if let Some(mut entry) = self.attached.remove(&previous) {
+                        entry.label.interface = event.name.clone();
+                        self.attached.insert(event.name.clone(), entry);
+                    }
+                    {
+                        let mut desired = self.desired.lock().unwrap();
+                        if desired.remove(&previous) {
+                            desired.insert(event.name.clone());
+                        }
+                    }
+                    info!(from = %previous, to = %event.name, "attached interface renamed");
+                    self.notify(
+                        InterfaceEventKind::Renamed,
+                        &event.name,
+                        Some(previous),
+                        None,
+                    );
+                    return;
+                }
+
+                // 同名で ifindex が変わった（削除→再作成）: 古いアタッチを捨てて付け直す
+                if let Some(ifindex) = self.attached.get(&event.name).map(|a| a.label.ifindex) {
+                    if ifindex != event.ifindex {
+                        self.drop_attachment(ebpf, &event.name);
+                    }
+                }
+
+                match event.change {
+                    LinkChange::Added => {
+                        self.notify(InterfaceEventKind::Added, &event.name, None, None)
+                    }
+                    LinkChange::Up => self.notify(InterfaceEventKind::Up, &event.name, None, None),
+                    LinkChange::Down => {
+                        self.notify(InterfaceEventKind::Down, &event.name, None, None)
+                    }
+                    LinkChange::Changed | LinkChange::Removed => {}
+                }
+
+                if !self.attached.contains_key(&event.name) && self.is_wanted(&event.name) {
+                    self.try_attach(ebpf, &event.name);
+                }
+            }
+        }
+    }
+}
+
 fn update_stats(stats: &std::sync::Mutex<CaptureStats>, result: &PacketResult) {
     let mut s = stats.lock().unwrap();
     s.total_packets += 1;
@@ -1221,23 +1897,45 @@ fn flush_captured_batch(
     tx: &broadcast::Sender<CapturedPacketEnvelope>,
     out_batch: &mut Vec<CapturedPacket>,
     epoch_offset_ms: f64,
+    enrichment: &SharedPipeline,
+    metrics: &CaptureMetrics,
 ) {
     if out_batch.is_empty() {
         return;
     }
-    let packets = std::mem::take(out_batch);
+    let mut packets = std::mem::take(out_batch);
+    enrich::apply_shared(enrichment, &mut packets);
+    metrics.record_batch(&packets);
     let _ = tx.send(CapturedPacketEnvelope {
         packets,
         epoch_offset_ms,
     });
 }
 
+/// ソケット受信フックが記録した所有プロセスを取り出す（不明な場合は `None`）
+fn process_from_event(event: &PacketEvent) -> Option<ProcessInfo> {
+    if event.pid == 0 && event.cgroup_id == 0 {
+        return None;
+    }
+    let len = event
+        .comm
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(event.comm.len());
+    Some(ProcessInfo {
+        pid: event.pid,
+        comm: String::from_utf8_lossy(&event.comm[..len]).into_owned(),
+        cgroup_id: event.cgroup_id,
+    })
+}
+
 fn convert_event(
     event: &PacketEvent,
     session_id: &str,
     counter: u64,
     result: PacketResult,
     reason: Option<String>,
+    label: Option<&AttachmentLabel>,
 ) -> CapturedPacket {
     let id = build_packet_id(session_id, counter);
 
@@ -1261,6 +1959,16 @@ fn convert_event(
         target_port: None,
         capture_mono_ns: event.ktime_ns,
         reason,
+        interface: label.map(|l| l.interface.clone()),
+        netns: label.and_then(|l| l.netns.clone()),
+        source_meta: None,
+        destination_meta: None,
+        process: None,
+        source_name: None,
+        destination_name: None,
+        service: None,
+        source_geo: None,
+        destination_geo: None,
     };
 
     CapturedPacket { packet, result }
@@ -1281,7 +1989,29 @@ mod tests {
             pkt_len,
             action,
             drop_reason: 0,
+            ifindex: 2,
             ktime_ns,
+            netns_ino: 0,
+            dst_mac: [0; 6],
+            _padding2: [0; 6],
+            cgroup_id: 0,
+            pid: 0,
+            comm: [0; 16],
+            _padding3: [0; 4],
+        }
+    }
+
+    fn sample_label(ifindex: u32, netns: Option<&str>, mac: [u8; 6]) -> AttachmentLabel {
+        AttachmentLabel {
+            ifindex,
+            netns_ino: if netns.is_some() {
+                4026532000
+            } else {
+                4026531840
+            },
+            mac: Some(mac),
+            interface: "eth0".to_string(),
+            netns: netns.map(str::to_string),
         }
     }
 
@@ -1290,7 +2020,38 @@ mod tests {
     }
 
     fn new_correlator() -> Correlator {
-        Correlator::new(Arc::new(DiagCounters::default()))
+        Correlator::new(
+            Arc::new(DiagCounters::default()),
+            crate::config::DEFAULT_CORRELATION_TIMEOUT_MS,
+        )
+    }
+
+    #[tokio::test]
+    async fn attach_while_stopped_is_remembered_for_next_start() {
+        let capture = EbpfCapture::new();
+        assert!(capture.attach_interface("lo").await.is_ok());
+        assert!(capture.attach_interface("lo").await.is_ok());
+        assert_eq!(capture.attached_interfaces().len(), 1);
+        assert!(capture.attached_interfaces().contains("lo"));
+    }
+
+    #[tokio::test]
+    async fn attach_unknown_interface_while_stopped_returns_error() {
+        let capture = EbpfCapture::new();
+        let result = capture.attach_interface("scrop-nonexistent0").await;
+        assert!(matches!(result, Err(CaptureError::InterfaceNotFound(_))));
+        assert!(capture.attached_interfaces().is_empty());
+    }
+
+    #[tokio::test]
+    async fn detach_while_stopped_forgets_interface() {
+        let capture = EbpfCapture::new();
+        capture.attach_interface("lo").await.unwrap();
+        assert!(capture.detach_interface("lo").await.is_ok());
+        assert!(capture.attached_interfaces().is_empty());
+
+        let result = capture.detach_interface("lo").await;
+        assert!(matches!(result, Err(CaptureError::InvalidState(_))));
     }
 
     #[test]
@@ -1320,10 +2081,129 @@ mod tests {
             pkt_len: 128,
             action: ACTION_XDP_PASS,
             drop_reason: 0,
+            ifindex: 2,
             ktime_ns: 42,
+            netns_ino: 0,
+            dst_mac: [0; 6],
+            _padding2: [0; 6],
+            cgroup_id: 0,
+            pid: 0,
+            comm: [0; 16],
+            _padding3: [0; 4],
         };
-        let captured = convert_event(&event, "sess01", 7, PacketResult::Delivered, None);
+        let captured = convert_event(&event, "sess01", 7, PacketResult::Delivered, None, None);
         assert_eq!(captured.packet.capture_mono_ns, 42);
+        assert!(captured.packet.interface.is_none());
+        assert!(captured.packet.netns.is_none());
+    }
+
+    #[test]
+    fn convert_event_tags_interface_and_netns_from_label() {
+        let event = sample_event(128, ACTION_XDP_PASS, 42);
+        let label = sample_label(2, Some("cni-1"), [0; 6]);
+        let captured = convert_event(
+            &event,
+            "sess01",
+            7,
+            PacketResult::Delivered,
+            None,
+            Some(&label),
+        );
+        assert_eq!(captured.packet.interface.as_deref(), Some("eth0"));
+        assert_eq!(captured.packet.netns.as_deref(), Some("cni-1"));
+    }
+
+    #[test]
+    fn resolve_label_uses_dst_mac_when_ifindex_collides_across_netns() {
+        let labels = vec![
+            sample_label(2, None, [0x02, 0, 0, 0, 0, 0x01]),
+            sample_label(2, Some("cni-1"), [0x02, 0, 0, 0, 0, 0x0a]),
+            sample_label(2, Some("cni-2"), [0x02, 0, 0, 0, 0, 0x0b]),
+            sample_label(7, Some("cni-3"), [0x02, 0, 0, 0, 0, 0x0c]),
+        ];
+
+        let mut event = sample_event(128, ACTION_XDP_PASS, 0);
+        event.dst_mac = [0x02, 0, 0, 0, 0, 0x0b];
+        let label = resolve_label(&labels, &event).expect("label");
+        assert_eq!(label.netns.as_deref(), Some("cni-2"));
+
+        // broadcast などで MAC が一致しない場合はホスト側を優先する
+        event.dst_mac = [0xff; 6];
+        let label = resolve_label(&labels, &event).expect("label");
+        assert!(label.netns.is_none());
+
+        // ifindex が一意なら MAC は見ない
+        event.ifindex = 7;
+        let label = resolve_label(&labels, &event).expect("label");
+        assert_eq!(label.netns.as_deref(), Some("cni-3"));
+
+        event.ifindex = 99;
+        assert!(resolve_label(&labels, &event).is_none());
+    }
+
+    #[test]
+    fn resolve_label_prefers_exact_netns_when_known() {
+        let labels = vec![
+            sample_label(2, None, [0; 6]),
+            sample_label(2, Some("cni-1"), [0; 6]),
+        ];
+        let mut event = sample_event(128, ACTION_KFREE_SKB, 0);
+        event.netns_ino = 4026532000;
+        let label = resolve_label(&labels, &event).expect("label");
+        assert_eq!(label.netns.as_deref(), Some("cni-1"));
+    }
+
+    #[tokio::test]
+    async fn attach_to_unknown_netns_while_stopped_returns_error() {
+        let capture = EbpfCapture::new();
+        let result = capture.attach_interface("scrop-missing-netns/eth0").await;
+        assert!(matches!(result, Err(CaptureError::InterfaceNotFound(_))));
+        assert!(capture.attached_interfaces().is_empty());
+    }
+
+    fn sock_rcv_event(pkt_len: u32, ktime_ns: u64, pid: u32, comm: &str) -> PacketEvent {
+        let mut event = sample_event(pkt_len, ACTION_SOCK_RCV, ktime_ns);
+        event.pid = pid;
+        event.cgroup_id = 4242;
+        event.comm[..comm.len()].copy_from_slice(comm.as_bytes());
+        event
+    }
+
+    #[test]
+    fn process_from_event_trims_comm_and_ignores_unknown_owner() {
+        let event = sock_rcv_event(128, 0, 1234, "nginx");
+        assert_eq!(
+            process_from_event(&event),
+            Some(ProcessInfo {
+                pid: 1234,
+                comm: "nginx".to_string(),
+                cgroup_id: 4242,
+            })
+        );
+        assert!(process_from_event(&sample_event(128, ACTION_KFREE_SKB, 0)).is_none());
+    }
+
+    #[test]
+    fn correlator_attributes_process_without_resolving_packet() {
+        let base_ns = 1_000_000_000;
+        let mut correlator = new_correlator();
+        correlator.register_pass(sample_event(128, ACTION_XDP_PASS, at_ms(base_ns, 1)), 1);
+        correlator.register_pass(sample_event(128, ACTION_XDP_PASS, at_ms(base_ns, 2)), 2);
+
+        assert!(correlator.attribute_process(&sock_rcv_event(128, at_ms(base_ns, 3), 10, "a")));
+        assert!(correlator.attribute_process(&sock_rcv_event(128, at_ms(base_ns, 3), 11, "b")));
+        // 同じ flow+size の pending がすべて帰属済みなら記録しない
+        assert!(!correlator.attribute_process(&sock_rcv_event(128, at_ms(base_ns, 3), 12, "c")));
+        assert!(!correlator.attribute_process(&sock_rcv_event(256, at_ms(base_ns, 3), 13, "d")));
+        assert_eq!(correlator.pending_len(), 2);
+
+        let mut expired = correlator.drain_expired(at_ms(base_ns, 100));
+        expired.sort_by_key(|p| p.counter);
+        let pids: Vec<Option<u32>> = expired
+            .iter()
+            .map(|p| p.process.as_ref().map(|proc| proc.pid))
+            .collect();
+        assert_eq!(pids, vec![Some(10), Some(11)]);
     }
 
     #[test]
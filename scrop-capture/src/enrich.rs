@@ -0,0 +1,180 @@
+//! 相関済みパケットに付加情報を載せるエンリッチメント段。
+//!
+//! 相関タスクが確定させたバッチを送信する直前（`flush_captured_batch`）に、
+//! 登録順に各 [`Enricher`] を適用する。先に値を設定した段が優先される。
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use crate::container_meta::{FileSource, MetadataEnricher, ProcSource};
+use crate::types::{CaptureStats, CapturedPacket};
+
+/// バッチ単位でパケットに情報を付加する段
+pub trait Enricher: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// バッチ内のパケットに情報を書き込む。送信経路上で呼ばれるためブロックしないこと。
+    fn enrich(&self, packets: &mut [CapturedPacket]);
+
+    /// 段が独自に集計している値を統計に書き込む
+    fn write_stats(&self, _stats: &mut CaptureStats) {}
+
+    /// 集計値を破棄する（キャプチャのリセット時）
+    fn reset_stats(&self) {}
+}
+
+/// 登録された [`Enricher`] を順番に適用するパイプライン
+#[derive(Clone, Default)]
+pub struct EnrichmentPipeline {
+    stages: Vec<Arc<dyn Enricher>>,
+}
+
+impl EnrichmentPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_stage(mut self, stage: Arc<dyn Enricher>) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn push(&mut self, stage: Arc<dyn Enricher>) {
+        self.stages.push(stage);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    pub fn apply(&self, packets: &mut [CapturedPacket]) {
+        if packets.is_empty() {
+            return;
+        }
+        for stage in &self.stages {
+            stage.enrich(packets);
+        }
+    }
+
+    pub fn write_stats(&self, stats: &mut CaptureStats) {
+        for stage in &self.stages {
+            stage.write_stats(stats);
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        for stage in &self.stages {
+            stage.reset_stats();
+        }
+    }
+}
+
+/// キャプチャタスクと共有するパイプライン。キャプチャ中でも差し替えられる。
+pub type SharedPipeline = Arc<RwLock<Arc<EnrichmentPipeline>>>;
+
+/// 共有パイプラインの現在値をバッチに適用する
+pub fn apply_shared(pipeline: &SharedPipeline, packets: &mut [CapturedPacket]) {
+    let pipeline = Arc::clone(&pipeline.read().unwrap());
+    pipeline.apply(packets);
+}
+
+/// コマンドラインなどで指定するメタデータの取得元
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnrichSource {
+    /// `/proc` と cgroupfs から netns ごとの IP・cgroup・コンテナ ID を引く
+    Proc,
+    /// `proc` に加え、CRI ランタイムの状態ディレクトリから Pod / コンテナ名を引く
+    Cri,
+    /// JSON ファイルで与えた固定の対応表（テストや外部ツールとの連携用）
+    File(PathBuf),
+}
+
+impl FromStr for EnrichSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "proc" => Ok(Self::Proc),
+            "cri" => Ok(Self::Cri),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Self::File(PathBuf::from(path))),
+                _ => Err(format!(
+                    "unknown enrichment source: {} (expected proc, cri or file:<path>)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// 指定された取得元からパイプラインを組み立てる
+pub fn build_pipeline(sources: &[EnrichSource]) -> EnrichmentPipeline {
+    let mut pipeline = EnrichmentPipeline::new();
+    for source in sources {
+        let stage: Arc<dyn Enricher> = match source {
+            EnrichSource::Proc => Arc::new(MetadataEnricher::new(ProcSource::host(false))),
+            EnrichSource::Cri => Arc::new(MetadataEnricher::new(ProcSource::host(true))),
+            EnrichSource::File(path) => {
+                Arc::new(MetadataEnricher::new(FileSource::new(path.clone())))
+            }
+        };
+        pipeline.push(stage);
+    }
+    pipeline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AnimatingPacket, PacketResult};
+
+    struct Tag(&'static str);
+
+    impl Enricher for Tag {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn enrich(&self, packets: &mut [CapturedPacket]) {
+            for p in packets {
+                if p.packet.reason.is_none() {
+                    p.packet.reason = Some(self.0.to_string());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pipeline_applies_stages_in_order() {
+        let pipeline = EnrichmentPipeline::new()
+            .with_stage(Arc::new(Tag("first")))
+            .with_stage(Arc::new(Tag("second")));
+        assert_eq!(pipeline.stage_names(), vec!["first", "second"]);
+
+        let mut packets = vec![CapturedPacket {
+            packet: AnimatingPacket::generate("abc123", 0),
+            result: PacketResult::Delivered,
+        }];
+        pipeline.apply(&mut packets);
+        assert_eq!(packets[0].packet.reason.as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn enrich_source_parses_known_forms() {
+        assert_eq!("proc".parse::<EnrichSource>(), Ok(EnrichSource::Proc));
+        assert_eq!("cri".parse::<EnrichSource>(), Ok(EnrichSource::Cri));
+        assert_eq!(
+            "file:/etc/scrop/endpoints.json".parse::<EnrichSource>(),
+            Ok(EnrichSource::File(PathBuf::from(
+                "/etc/scrop/endpoints.json"
+            )))
+        );
+        assert!("file:".parse::<EnrichSource>().is_err());
+        assert!("k8s-api".parse::<EnrichSource>().is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//! 配信するパケットの絞り込み条件。
+
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::netns;
+use crate::types::{
+    AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult, Protocol,
+};
+
+/// IPv4 の CIDR（`10.0.0.0/8`）。プレフィックス長を省略すると /32 とみなす。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    network: u32,
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    fn mask(&self) -> u32 {
+        u32::MAX
+            .checked_shl(32 - u32::from(self.prefix_len))
+            .unwrap_or(0)
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & self.mask() == self.network
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, len.parse::<u8>().map_err(|_| invalid_cidr(s))?),
+            None => (s, 32),
+        };
+        if prefix_len > 32 {
+            return Err(invalid_cidr(s));
+        }
+        let addr: Ipv4Addr = addr.parse().map_err(|_| invalid_cidr(s))?;
+        let mut cidr = Self {
+            network: 0,
+            prefix_len,
+        };
+        cidr.network = u32::from(addr) & cidr.mask();
+        Ok(cidr)
+    }
+}
+
+fn invalid_cidr(s: &str) -> String {
+    format!("invalid CIDR: {}", s)
+}
+
+/// クライアントごとの購読フィルタ。空の条件は「すべて」を表し、指定した条件はすべて満たす必要がある。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PacketFilter {
+    pub results: Vec<PacketResult>,
+    pub protocols: Vec<Protocol>,
+    /// 送信元・宛先のどちらかが一致すればよい
+    pub ports: Vec<u16>,
+    /// 送信元・宛先のどちらかが含まれればよい
+    pub cidrs: Vec<Ipv4Cidr>,
+    /// 修飾インターフェース名（`"<netns>/<ifname>"` またはホストの ifname）
+    pub interfaces: Vec<String>,
+    pub process: ProcessFilter,
+}
+
+impl PacketFilter {
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+            && self.protocols.is_empty()
+            && self.ports.is_empty()
+            && self.cidrs.is_empty()
+            && self.interfaces.is_empty()
+            && self.process.is_empty()
+    }
+
+    pub fn matches(&self, captured: &CapturedPacket) -> bool {
+        let packet = &captured.packet;
+        (self.results.is_empty() || self.results.contains(&captured.result))
+            && (self.protocols.is_empty() || self.protocols.contains(&packet.protocol))
+            && (self.ports.is_empty()
+                || self.ports.contains(&packet.src_port)
+                || self.ports.contains(&packet.dest_port))
+            && (self.cidrs.is_empty() || self.matches_cidr(packet))
+            && (self.interfaces.is_empty() || self.matches_interface(packet))
+            && self.process.matches(packet)
+    }
+
+    fn matches_cidr(&self, packet: &AnimatingPacket) -> bool {
+        [&packet.source, &packet.destination]
+            .into_iter()
+            .filter_map(|addr| addr.parse::<Ipv4Addr>().ok())
+            .any(|addr| self.cidrs.iter().any(|cidr| cidr.contains(addr)))
+    }
+
+    fn matches_interface(&self, packet: &AnimatingPacket) -> bool {
+        let Some(interface) = packet.interface.as_deref() else {
+            return false;
+        };
+        let qualified = netns::qualify_interface(packet.netns.as_deref(), interface);
+        self.interfaces.contains(&qualified)
+    }
+
+    /// 条件に合うパケットだけを残したバッチを返す。1 つも残らない場合は `None`。
+    pub fn apply(&self, batch: &CapturedPacketEnvelope) -> Option<CapturedPacketEnvelope> {
+        let packets: Vec<_> = batch
+            .packets
+            .iter()
+            .filter(|captured| self.matches(captured))
+            .cloned()
+            .collect();
+        if packets.is_empty() {
+            return None;
+        }
+        Some(CapturedPacketEnvelope {
+            packets,
+            epoch_offset_ms: batch.epoch_offset_ms,
+        })
+    }
+}
+
+/// 受信プロセスによる絞り込み。指定した条件をすべて満たすパケットだけを通す。
+///
+/// 条件を 1 つでも指定すると、受信プロセスが不明なパケットは除外される。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// comm の完全一致
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comm: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup_id: Option<u64>,
+}
+
+impl ProcessFilter {
+    pub fn is_empty(&self) -> bool {
+        self.pid.is_none() && self.comm.is_none() && self.cgroup_id.is_none()
+    }
+
+    pub fn matches(&self, packet: &AnimatingPacket) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let Some(process) = &packet.process else {
+            return false;
+        };
+        self.pid.is_none_or(|pid| process.pid == pid)
+            && self.comm.as_deref().is_none_or(|comm| process.comm == comm)
+            && self.cgroup_id.is_none_or(|id| process.cgroup_id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ProcessInfo;
+
+    fn packet_with_process(process: Option<(u32, &str)>) -> CapturedPacket {
+        let mut packet = AnimatingPacket::generate("abc123", 0);
+        packet.process = process.map(|(pid, comm)| ProcessInfo {
+            pid,
+            comm: comm.to_string(),
+            cgroup_id: 7,
+        });
+        CapturedPacket {
+            packet,
+            result: PacketResult::Delivered,
+        }
+    }
+
+    #[test]
+    fn empty_filter_passes_everything() {
+        let filter = ProcessFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.matches(&packet_with_process(None).packet));
+    }
+
+    #[test]
+    fn filter_requires_all_given_fields() {
+        let filter = ProcessFilter {
+            pid: Some(10),
+            comm: Some("nginx".to_string()),
+            cgroup_id: None,
+        };
+        assert!(filter.matches(&packet_with_process(Some((10, "nginx"))).packet));
+        assert!(!filter.matches(&packet_with_process(Some((11, "nginx"))).packet));
+        assert!(!filter.matches(&packet_with_process(Some((10, "sshd"))).packet));
+        assert!(!filter.matches(&packet_with_process(None).packet));
+    }
+
+    #[test]
+    fn apply_drops_batches_without_matches() {
+        let filter = PacketFilter {
+            process: ProcessFilter {
+                comm: Some("nginx".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let batch = CapturedPacketEnvelope {
+            packets: vec![
+                packet_with_process(Some((10, "nginx"))),
+                packet_with_process(Some((11, "sshd"))),
+                packet_with_process(None),
+            ],
+            epoch_offset_ms: 1.5,
+        };
+        let filtered = filter.apply(&batch).expect("matching packets");
+        assert_eq!(filtered.packets.len(), 1);
+        assert_eq!(filtered.epoch_offset_ms, 1.5);
+
+        let filter = PacketFilter {
+            process: ProcessFilter {
+                pid: Some(99),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(filter.apply(&batch).is_none());
+    }
+
+    fn packet_between(
+        source: &str,
+        src_port: u16,
+        destination: &str,
+        dest_port: u16,
+    ) -> CapturedPacket {
+        let mut captured = packet_with_process(None);
+        captured.packet.source = source.to_string();
+        captured.packet.src_port = src_port;
+        captured.packet.destination = destination.to_string();
+        captured.packet.dest_port = dest_port;
+        captured.packet.protocol = Protocol::Tcp;
+        captured
+    }
+
+    #[test]
+    fn cidr_parses_and_masks_network() {
+        let cidr: Ipv4Cidr = "10.1.2.3/8".parse().unwrap();
+        assert!(cidr.contains("10.200.0.1".parse().unwrap()));
+        assert!(!cidr.contains("11.0.0.1".parse().unwrap()));
+
+        let host: Ipv4Cidr = "192.168.1.5".parse().unwrap();
+        assert!(host.contains("192.168.1.5".parse().unwrap()));
+        assert!(!host.contains("192.168.1.6".parse().unwrap()));
+
+        let any: Ipv4Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains("203.0.113.9".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Ipv4Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Ipv4Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Ipv4Cidr>().is_err());
+    }
+
+    #[test]
+    fn packet_filter_matches_ports_and_cidrs_in_either_direction() {
+        let packet = packet_between("192.168.1.10", 51000, "10.0.0.5", 443);
+
+        let by_port = PacketFilter {
+            ports: vec![443],
+            ..Default::default()
+        };
+        assert!(by_port.matches(&packet));
+        let by_src_port = PacketFilter {
+            ports: vec![51000],
+            ..Default::default()
+        };
+        assert!(by_src_port.matches(&packet));
+
+        let by_cidr = PacketFilter {
+            cidrs: vec!["192.168.0.0/16".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(by_cidr.matches(&packet));
+        let other_cidr = PacketFilter {
+            cidrs: vec!["172.16.0.0/12".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(!other_cidr.matches(&packet));
+    }
+
+    #[test]
+    fn packet_filter_requires_every_condition() {
+        let mut packet = packet_between("192.168.1.10", 51000, "10.0.0.5", 443);
+        packet.result = PacketResult::FwDrop;
+        packet.packet.interface = Some("eth0".to_string());
+        packet.packet.netns = Some("cni-1".to_string());
+
+        let filter = PacketFilter {
+            results: vec![PacketResult::FwDrop, PacketResult::NicDrop],
+            protocols: vec![Protocol::Tcp],
+            ports: vec![443],
+            interfaces: vec!["cni-1/eth0".to_string()],
+            ..Default::default()
+        };
+        assert!(!filter.is_empty());
+        assert!(filter.matches(&packet));
+
+        let wrong_result = PacketFilter {
+            results: vec![PacketResult::Delivered],
+            ..filter.clone()
+        };
+        assert!(!wrong_result.matches(&packet));
+
+        let host_interface = PacketFilter {
+            interfaces: vec!["eth0".to_string()],
+            ..filter.clone()
+        };
+        assert!(!host_interface.matches(&packet));
+
+        packet.packet.interface = None;
+        assert!(!filter.matches(&packet));
+    }
+}
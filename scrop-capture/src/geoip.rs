@@ -0,0 +1,365 @@
+//! ローカルの MaxMind 形式（MMDB）データベースから国・AS を引くエンリッチメント段。
+//!
+//! データベースは起動時にメモリへ読み込み、ネットワークからは何も取得しない。
+//! パケットに付けるほか、送信元（不明なら宛先）の国・AS ごとのパケット数を集計して
+//! 統計 API に載せる。
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use tracing::warn;
+
+use crate::enrich::Enricher;
+use crate::types::{CaptureStats, CapturedPacket, GeoInfo};
+
+#[derive(Default)]
+struct GeoCounts {
+    by_country: BTreeMap<String, u64>,
+    by_asn: BTreeMap<u32, u64>,
+}
+
+/// 国（GeoIP2 / GeoLite2 Country・City）と AS（GeoLite2 ASN）を付ける段
+pub struct GeoIpEnricher {
+    country_db: Option<Reader<Vec<u8>>>,
+    asn_db: Option<Reader<Vec<u8>>>,
+    counts: Mutex<GeoCounts>,
+}
+
+impl GeoIpEnricher {
+    /// 指定されたデータベースを開く。どちらも省略できるが、少なくとも一方は必要。
+    pub fn open(country_db: Option<&Path>, asn_db: Option<&Path>) -> Result<Self, String> {
+        if country_db.is_none() && asn_db.is_none() {
+            return Err("no GeoIP database given".to_string());
+        }
+        Ok(Self {
+            country_db: country_db.map(open_db).transpose()?,
+            asn_db: asn_db.map(open_db).transpose()?,
+            counts: Mutex::default(),
+        })
+    }
+
+    pub fn lookup(&self, addr: &str) -> Option<GeoInfo> {
+        let addr: IpAddr = addr.parse().ok()?;
+        let mut info = GeoInfo::default();
+        if let Some(record) = self
+            .country_db
+            .as_ref()
+            .and_then(|db| found(db.lookup::<geoip2::Country>(addr)))
+        {
+            info.country = record
+                .country
+                .or(record.registered_country)
+                .and_then(|country| country.iso_code)
+                .map(str::to_string);
+        }
+        if let Some(record) = self
+            .asn_db
+            .as_ref()
+            .and_then(|db| found(db.lookup::<geoip2::Asn>(addr)))
+        {
+            info.asn = record.autonomous_system_number;
+            info.as_org = record.autonomous_system_organization.map(str::to_string);
+        }
+        (info != GeoInfo::default()).then_some(info)
+    }
+}
+
+fn open_db(path: &Path) -> Result<Reader<Vec<u8>>, String> {
+    Reader::open_readfile(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// 見つからないアドレスは `None`、それ以外のエラーは警告して `None`
+fn found<T>(result: Result<T, MaxMindDBError>) -> Option<T> {
+    match result {
+        Ok(record) => Some(record),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => None,
+        Err(e) => {
+            warn!(error = %e, "GeoIP lookup failed");
+            None
+        }
+    }
+}
+
+impl Enricher for GeoIpEnricher {
+    fn name(&self) -> &'static str {
+        "geoip"
+    }
+
+    fn enrich(&self, packets: &mut [CapturedPacket]) {
+        let mut counts = self.counts.lock().unwrap();
+        for captured in packets {
+            let packet = &mut captured.packet;
+            if packet.source_geo.is_none() {
+                packet.source_geo = self.lookup(&packet.source);
+            }
+            if packet.destination_geo.is_none() {
+                packet.destination_geo = self.lookup(&packet.destination);
+            }
+
+            let Some(geo) = packet
+                .source_geo
+                .as_ref()
+                .or(packet.destination_geo.as_ref())
+            else {
+                continue;
+            };
+            if let Some(country) = &geo.country {
+                *counts.by_country.entry(country.clone()).or_default() += 1;
+            }
+            if let Some(asn) = geo.asn {
+                *counts.by_asn.entry(asn).or_default() += 1;
+            }
+        }
+    }
+
+    fn write_stats(&self, stats: &mut CaptureStats) {
+        let counts = self.counts.lock().unwrap();
+        for (country, count) in &counts.by_country {
+            *stats.packets_by_country.entry(country.clone()).or_default() += count;
+        }
+        for (asn, count) in &counts.by_asn {
+            *stats.packets_by_asn.entry(*asn).or_default() += count;
+        }
+    }
+
+    fn reset_stats(&self) {
+        *self.counts.lock().unwrap() = GeoCounts::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AnimatingPacket, PacketResult};
+    use std::path::PathBuf;
+
+    /// テスト用の最小限の MMDB（IPv4 ツリー、24 bit レコード）を書き出す
+    mod fixture {
+        use std::net::Ipv4Addr;
+
+        pub enum Value {
+            Str(&'static str),
+            U32(u32),
+            Map(Vec<(&'static str, Value)>),
+        }
+
+        fn encode(value: &Value, out: &mut Vec<u8>) {
+            match value {
+                Value::Str(s) => {
+                    // 29 バイト以上は長さを後続バイトに持つ（テストでは 284 バイト未満のみ）
+                    if s.len() < 29 {
+                        out.push((2 << 5) | s.len() as u8);
+                    } else {
+                        out.extend_from_slice(&[(2 << 5) | 29, (s.len() - 29) as u8]);
+                    }
+                    out.extend_from_slice(s.as_bytes());
+                }
+                Value::U32(n) => {
+                    let bytes = n.to_be_bytes();
+                    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+                    out.push((6 << 5) | (4 - skip) as u8);
+                    out.extend_from_slice(&bytes[skip..]);
+                }
+                Value::Map(entries) => {
+                    out.push((7 << 5) | entries.len() as u8);
+                    for (key, value) in entries {
+                        encode(&Value::Str(key), out);
+                        encode(value, out);
+                    }
+                }
+            }
+        }
+
+        fn encode_u16(n: u16, out: &mut Vec<u8>) {
+            out.push((5 << 5) | 2);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+
+        #[derive(Clone, Copy)]
+        enum Record {
+            Empty,
+            Node(usize),
+            Data(usize),
+        }
+
+        /// `networks` の各 (アドレス, プレフィックス長, 値) を持つ DB を作る
+        pub fn build(database_type: &'static str, networks: Vec<(Ipv4Addr, u8, Value)>) -> Vec<u8> {
+            let mut nodes: Vec<[Record; 2]> = vec![[Record::Empty; 2]];
+            let mut data = Vec::new();
+            for (addr, prefix_len, value) in networks {
+                let offset = data.len();
+                encode(&value, &mut data);
+                let bits = u32::from(addr);
+                let mut node = 0;
+                for i in 0..prefix_len {
+                    let bit = ((bits >> (31 - i)) & 1) as usize;
+                    if i + 1 == prefix_len {
+                        nodes[node][bit] = Record::Data(offset);
+                    } else {
+                        node = match nodes[node][bit] {
+                            Record::Node(next) => next,
+                            _ => {
+                                nodes.push([Record::Empty; 2]);
+                                nodes[node][bit] = Record::Node(nodes.len() - 1);
+                                nodes.len() - 1
+                            }
+                        };
+                    }
+                }
+            }
+
+            let node_count = nodes.len();
+            let mut out = Vec::new();
+            for node in &nodes {
+                for record in node {
+                    let value = match *record {
+                        Record::Empty => node_count,
+                        Record::Node(next) => next,
+                        Record::Data(offset) => node_count + 16 + offset,
+                    } as u32;
+                    out.extend_from_slice(&value.to_be_bytes()[1..]);
+                }
+            }
+            out.extend_from_slice(&[0; 16]);
+            out.extend_from_slice(&data);
+
+            out.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+            out.push((7 << 5) | 9);
+            for (key, value) in [
+                ("binary_format_major_version", 2),
+                ("binary_format_minor_version", 0),
+                ("ip_version", 4),
+                ("record_size", 24),
+            ] {
+                encode(&Value::Str(key), &mut out);
+                encode_u16(value, &mut out);
+            }
+            encode(&Value::Str("node_count"), &mut out);
+            encode(&Value::U32(node_count as u32), &mut out);
+            encode(&Value::Str("build_epoch"), &mut out);
+            out.extend_from_slice(&[0x01, 0x02, 0x00]); // uint64 0
+            encode(&Value::Str("database_type"), &mut out);
+            encode(&Value::Str(database_type), &mut out);
+            encode(&Value::Str("description"), &mut out);
+            encode(&Value::Map(Vec::new()), &mut out);
+            encode(&Value::Str("languages"), &mut out);
+            out.extend_from_slice(&[0x00, 0x04]); // empty array
+            out
+        }
+    }
+
+    use fixture::Value;
+    use std::net::Ipv4Addr;
+
+    fn write_fixtures(dir: &Path) -> (PathBuf, PathBuf) {
+        let country = |code| {
+            Value::Map(vec![(
+                "country",
+                Value::Map(vec![("iso_code", Value::Str(code))]),
+            )])
+        };
+        let country_db = fixture::build(
+            "GeoLite2-Country",
+            vec![
+                (Ipv4Addr::new(1, 1, 1, 0), 24, country("AU")),
+                (Ipv4Addr::new(8, 8, 8, 0), 24, country("US")),
+            ],
+        );
+        let asn_db = fixture::build(
+            "GeoLite2-ASN",
+            vec![(
+                Ipv4Addr::new(8, 8, 8, 0),
+                24,
+                Value::Map(vec![
+                    ("autonomous_system_number", Value::U32(15169)),
+                    ("autonomous_system_organization", Value::Str("GOOGLE")),
+                ]),
+            )],
+        );
+        std::fs::create_dir_all(dir).unwrap();
+        let country_path = dir.join("country.mmdb");
+        let asn_path = dir.join("asn.mmdb");
+        std::fs::write(&country_path, country_db).unwrap();
+        std::fs::write(&asn_path, asn_db).unwrap();
+        (country_path, asn_path)
+    }
+
+    fn open_fixtures(name: &str) -> GeoIpEnricher {
+        let dir = std::env::temp_dir().join(format!("scrop-geoip-{}-{}", name, std::process::id()));
+        let (country, asn) = write_fixtures(&dir);
+        let enricher = GeoIpEnricher::open(Some(&country), Some(&asn)).expect("open fixtures");
+        std::fs::remove_dir_all(&dir).unwrap();
+        enricher
+    }
+
+    fn captured(source: &str, destination: &str) -> CapturedPacket {
+        let mut packet = AnimatingPacket::generate("abc123", 0);
+        packet.source = source.to_string();
+        packet.destination = destination.to_string();
+        CapturedPacket {
+            packet,
+            result: PacketResult::Delivered,
+        }
+    }
+
+    #[test]
+    fn lookup_merges_country_and_asn() {
+        let enricher = open_fixtures("lookup");
+        assert_eq!(
+            enricher.lookup("8.8.8.8"),
+            Some(GeoInfo {
+                country: Some("US".to_string()),
+                asn: Some(15169),
+                as_org: Some("GOOGLE".to_string()),
+            })
+        );
+        assert_eq!(
+            enricher.lookup("1.1.1.1").and_then(|geo| geo.country),
+            Some("AU".to_string())
+        );
+        assert_eq!(enricher.lookup("10.0.0.1"), None);
+        assert_eq!(enricher.lookup("not-an-ip"), None);
+    }
+
+    #[test]
+    fn enrich_tags_packets_and_aggregates_by_remote_endpoint() {
+        let enricher = open_fixtures("aggregate");
+        let mut packets = vec![
+            captured("8.8.8.8", "10.0.0.1"),
+            captured("8.8.8.8", "10.0.0.2"),
+            captured("10.0.0.1", "1.1.1.1"),
+            captured("10.0.0.1", "10.0.0.2"),
+        ];
+        enricher.enrich(&mut packets);
+
+        assert_eq!(
+            packets[0].packet.source_geo.as_ref().and_then(|g| g.asn),
+            Some(15169)
+        );
+        assert!(packets[0].packet.destination_geo.is_none());
+        assert!(packets[3].packet.source_geo.is_none());
+
+        let mut stats = CaptureStats::default();
+        enricher.write_stats(&mut stats);
+        assert_eq!(stats.packets_by_country.get("US"), Some(&2));
+        assert_eq!(stats.packets_by_country.get("AU"), Some(&1));
+        assert_eq!(stats.packets_by_asn.get(&15169), Some(&2));
+
+        enricher.reset_stats();
+        let mut stats = CaptureStats::default();
+        enricher.write_stats(&mut stats);
+        assert!(stats.packets_by_country.is_empty());
+    }
+
+    #[test]
+    fn open_requires_a_readable_database() {
+        assert!(GeoIpEnricher::open(None, None).is_err());
+        let err = GeoIpEnricher::open(Some(Path::new("/nonexistent/GeoLite2-Country.mmdb")), None)
+            .err()
+            .expect("missing database");
+        assert!(err.contains("/nonexistent/GeoLite2-Country.mmdb"));
+    }
+}
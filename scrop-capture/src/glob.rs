@@ -0,0 +1,103 @@
+//! インターフェース名向けの簡易 glob マッチ（`*` と `?` のみサポート）。
+
+/// `pattern` が `name` 全体にマッチするか判定する。
+/// `*` は 0 文字以上、`?` は任意の 1 文字にマッチする。
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0usize, 0usize);
+    // 直近の `*` の位置と、そこから再試行する name 側の位置
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// いずれかのパターンにマッチするか判定する。
+pub fn matches_any<S: AsRef<str>>(patterns: &[S], name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| glob_match(pattern.as_ref(), name))
+}
+
+/// 自動アタッチルールとして受け付けられるパターンか検証する。
+pub fn validate_pattern(pattern: &str) -> Result<(), String> {
+    if pattern.is_empty() {
+        return Err("interface pattern must not be empty".to_string());
+    }
+    if pattern.contains('/') || pattern.chars().any(char::is_whitespace) {
+        return Err(format!("invalid interface pattern: {}", pattern));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_pattern_matches_exact_name_only() {
+        assert!(glob_match("eth0", "eth0"));
+        assert!(!glob_match("eth0", "eth01"));
+        assert!(!glob_match("eth0", "eth"));
+    }
+
+    #[test]
+    fn star_matches_any_suffix_including_empty() {
+        assert!(glob_match("veth*", "veth"));
+        assert!(glob_match("veth*", "veth1a2b3c"));
+        assert!(!glob_match("veth*", "eth0"));
+        assert!(glob_match("*", "lo"));
+    }
+
+    #[test]
+    fn star_backtracks_in_the_middle() {
+        assert!(glob_match("br-*-int", "br-abc-int"));
+        assert!(glob_match("br-*-int", "br-a-int-int"));
+        assert!(!glob_match("br-*-int", "br-abc-ext"));
+    }
+
+    #[test]
+    fn question_mark_matches_single_character() {
+        assert!(glob_match("eth?", "eth1"));
+        assert!(!glob_match("eth?", "eth10"));
+    }
+
+    #[test]
+    fn matches_any_checks_every_pattern() {
+        let patterns = vec!["veth*".to_string(), "eth0".to_string()];
+        assert!(matches_any(&patterns, "eth0"));
+        assert!(matches_any(&patterns, "veth9"));
+        assert!(!matches_any(&patterns, "lo"));
+        assert!(!matches_any::<String>(&[], "lo"));
+    }
+
+    #[test]
+    fn validate_pattern_rejects_empty_and_path_like_patterns() {
+        assert!(validate_pattern("veth*").is_ok());
+        assert!(validate_pattern("").is_err());
+        assert!(validate_pattern("../eth0").is_err());
+        assert!(validate_pattern("eth 0").is_err());
+    }
+}
@@ -1,19 +1,43 @@
+pub mod alerts;
+pub mod clients;
+pub mod config;
+pub mod container_meta;
 #[cfg(feature = "ebpf")]
 pub mod drop_reason;
 #[cfg(feature = "ebpf")]
 pub mod ebpf;
+pub mod enrich;
+pub mod filter;
+pub mod geoip;
+pub mod glob;
+pub mod metrics;
 #[cfg(not(feature = "ebpf"))]
 pub mod mock;
+pub mod names;
+#[cfg(feature = "ebpf")]
+pub mod netlink;
+pub mod netns;
+pub mod sampling;
 pub mod types;
 
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{broadcast, Mutex};
 use tracing::info;
-use types::{CaptureStats, CapturedPacketEnvelope};
+use types::{
+    CaptureStateEvent, CaptureStats, CapturedPacketEnvelope, InterfaceEvent, NetnsInfo,
+    StatusSnapshot,
+};
 
 pub const BATCH_FLUSH_INTERVAL_MS: u64 = 100;
 pub const BATCH_MAX_SIZE: usize = 256;
 pub const EVENT_CHANNEL_CAPACITY: usize = 128;
+pub const INTERFACE_EVENT_CHANNEL_CAPACITY: usize = 64;
+pub const STATE_EVENT_CHANNEL_CAPACITY: usize = 16;
+pub const STATUS_CHANNEL_CAPACITY: usize = 4;
+pub const ALERT_CHANNEL_CAPACITY: usize = 64;
+/// 購読クライアントへ統計を送る間隔
+pub const STATUS_PUSH_INTERVAL_MS: u64 = 1000;
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -65,6 +89,16 @@ impl CaptureBackend {
         }
     }
 
+    /// 停止して、デタッチと最後のバッチの送出が終わるまで待つ（プロセス終了前に呼ぶ）
+    pub async fn shutdown(&self) {
+        match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => m.shutdown().await,
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(e) => e.shutdown().await,
+        }
+    }
+
     pub fn is_running(&self) -> bool {
         match self {
             #[cfg(not(feature = "ebpf"))]
@@ -119,6 +153,44 @@ impl CaptureBackend {
         }
     }
 
+    /// アタッチ中（停止中はアタッチ予定）のインターフェースを名前順で返す
+    pub fn attached_interfaces(&self) -> Vec<String> {
+        let mut names: Vec<String> = match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => m.attached_interfaces().into_iter().collect(),
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(e) => e.attached_interfaces().into_iter().collect(),
+        };
+        names.sort();
+        names
+    }
+
+    pub fn auto_attach_rules(&self) -> Vec<String> {
+        match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => m.auto_attach_rules(),
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(e) => e.auto_attach_rules(),
+        }
+    }
+
+    /// 自動アタッチする glob ルール（例: `veth*`）を置き換える。
+    /// 既存のマッチするインターフェースにも即座に適用される。
+    pub async fn set_auto_attach_rules(&self, rules: Vec<String>) -> Result<(), CaptureError> {
+        for rule in &rules {
+            glob::validate_pattern(rule).map_err(CaptureError::InvalidState)?;
+        }
+        match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => {
+                m.set_auto_attach_rules(rules);
+                Ok(())
+            }
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(e) => e.set_auto_attach_rules(rules).await,
+        }
+    }
+
     pub fn list_interfaces(&self) -> Vec<String> {
         match self {
             #[cfg(not(feature = "ebpf"))]
@@ -128,6 +200,68 @@ impl CaptureBackend {
         }
     }
 
+    /// 相関済みパケットを送信する前に適用するエンリッチメントを差し替える
+    pub fn set_enrichment(&self, pipeline: enrich::EnrichmentPipeline) {
+        match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => m.set_enrichment(pipeline),
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(e) => e.set_enrichment(pipeline),
+        }
+    }
+
+    pub fn process_attribution(&self) -> bool {
+        match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => m.process_attribution(),
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(e) => e.process_attribution(),
+        }
+    }
+
+    /// 配送・ソケットでドロップされたパケットに受信プロセスを付けるか切り替える。
+    /// eBPF ではソケット受信フックを使うため、次回の start から反映される。
+    pub fn set_process_attribution(&self, enabled: bool) {
+        match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => m.set_process_attribution(enabled),
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(e) => e.set_process_attribution(enabled),
+        }
+    }
+
+    /// キャプチャ対象として選べる network namespace を列挙する（先頭はホスト）
+    pub fn list_netns(&self) -> Vec<NetnsInfo> {
+        match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => m.list_netns(),
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(_) => netns::discover(),
+        }
+    }
+
+    /// 指定した netns 内のインターフェースを列挙する
+    pub fn list_netns_interfaces(&self, netns_id: &str) -> Result<Vec<String>, CaptureError> {
+        match self {
+            #[cfg(not(feature = "ebpf"))]
+            CaptureBackend::Mock(m) => m.list_netns_interfaces(netns_id),
+            #[cfg(feature = "ebpf")]
+            CaptureBackend::Ebpf(_) => {
+                let path = netns::resolve_path(netns_id)
+                    .map_err(|e| CaptureError::InterfaceNotFound(e.to_string()))?;
+                let interfaces = netns::run_in(path.as_deref(), netns::list_interfaces)
+                    .and_then(|r| r)
+                    .map_err(|e| match e.kind() {
+                        std::io::ErrorKind::PermissionDenied => {
+                            CaptureError::PermissionDenied(e.to_string())
+                        }
+                        _ => CaptureError::Other(e.to_string()),
+                    })?;
+                Ok(interfaces.into_iter().map(|(_, name)| name).collect())
+            }
+        }
+    }
+
     #[cfg(not(feature = "ebpf"))]
     pub fn get_mock_config(&self) -> mock::MockConfig {
         match self {
@@ -172,30 +306,111 @@ pub fn detect_all_interfaces() -> Vec<String> {
     vec!["eth0".to_string()]
 }
 
-fn create_backend() -> CaptureBackend {
+fn create_backend(
+    interface_tx: &broadcast::Sender<InterfaceEvent>,
+    state_tx: &broadcast::Sender<CaptureStateEvent>,
+    metrics: &Arc<metrics::CaptureMetrics>,
+    config: &config::CaptureConfig,
+) -> CaptureBackend {
     #[cfg(feature = "ebpf")]
     {
         info!("using eBPF capture backend");
-        CaptureBackend::Ebpf(ebpf::EbpfCapture::new())
+        CaptureBackend::Ebpf(
+            ebpf::EbpfCapture::new()
+                .with_interface_events(interface_tx.clone())
+                .with_state_events(state_tx.clone())
+                .with_metrics(Arc::clone(metrics))
+                .with_config(config.clone()),
+        )
     }
     #[cfg(not(feature = "ebpf"))]
     {
+        // モックは相関・リングバッファを使わないため、設定はチャネル容量だけに効く
+        let _ = config;
         info!("using mock capture backend");
-        CaptureBackend::Mock(mock::MockCapture::new())
+        CaptureBackend::Mock(
+            mock::MockCapture::new()
+                .with_interface_events(interface_tx.clone())
+                .with_state_events(state_tx.clone())
+                .with_metrics(Arc::clone(metrics)),
+        )
     }
 }
 
 pub struct AppState {
     pub capture: Arc<Mutex<CaptureBackend>>,
     pub event_tx: broadcast::Sender<CapturedPacketEnvelope>,
+    /// インターフェースのホットプラグ・アタッチ状態変化の通知
+    pub interface_tx: broadcast::Sender<InterfaceEvent>,
+    /// キャプチャの開始・停止などの状態変化の通知
+    pub state_tx: broadcast::Sender<CaptureStateEvent>,
+    /// 定期的な状態・統計の通知（`spawn_status_publisher` が送る）
+    pub status_tx: broadcast::Sender<StatusSnapshot>,
+    /// ストリームを購読中のクライアント
+    pub clients: Arc<clients::ClientRegistry>,
+    /// ラベル別のパケット数と相関遅延・バッチサイズのヒストグラム（`/metrics` 用）
+    pub metrics: Arc<metrics::CaptureMetrics>,
+    /// アラートの発火・解決の通知
+    pub alert_tx: broadcast::Sender<alerts::Alert>,
 }
 
 impl AppState {
     pub fn new() -> Self {
-        let (event_tx, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self::with_config(&config::CaptureConfig::default())
+    }
+
+    /// タイミング・容量の設定を指定して作る。`config` は検証済みであること。
+    pub fn with_config(config: &config::CaptureConfig) -> Self {
+        let (event_tx, _) = broadcast::channel(config.event_channel_capacity);
+        let (interface_tx, _) = broadcast::channel(INTERFACE_EVENT_CHANNEL_CAPACITY);
+        let (state_tx, _) = broadcast::channel(STATE_EVENT_CHANNEL_CAPACITY);
+        let (status_tx, _) = broadcast::channel(STATUS_CHANNEL_CAPACITY);
+        let (alert_tx, _) = broadcast::channel(ALERT_CHANNEL_CAPACITY);
+        let metrics = Arc::new(metrics::CaptureMetrics::new());
         Self {
-            capture: Arc::new(Mutex::new(create_backend())),
+            capture: Arc::new(Mutex::new(create_backend(
+                &interface_tx,
+                &state_tx,
+                &metrics,
+                config,
+            ))),
             event_tx,
+            interface_tx,
+            state_tx,
+            status_tx,
+            clients: Arc::new(clients::ClientRegistry::new()),
+            metrics,
+            alert_tx,
+        }
+    }
+
+    /// 状態と統計を `interval` ごとに `status_tx` へ送るタスクを起動する。
+    /// キャプチャのロックは購読者がいるときだけ、購読者の数によらず 1 周期に 1 回取る。
+    pub fn spawn_status_publisher(
+        self: &Arc<Self>,
+        interval: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let state = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                ticker.tick().await;
+                if state.status_tx.receiver_count() == 0 {
+                    continue;
+                }
+                let _ = state.status_tx.send(state.status_snapshot().await);
+            }
+        })
+    }
+
+    /// 現在の状態と統計
+    pub async fn status_snapshot(&self) -> StatusSnapshot {
+        let capture = self.capture.lock().await;
+        StatusSnapshot {
+            is_capturing: capture.is_running(),
+            mode: capture.mode().to_string(),
+            stats: capture.get_stats(),
         }
     }
 }
@@ -248,6 +463,24 @@ mod tests {
         assert_eq!(capture.mode(), "mock");
     }
 
+    #[cfg(not(feature = "ebpf"))]
+    #[tokio::test]
+    async fn capture_backend_lists_only_host_netns_in_mock_mode() {
+        let state = AppState::new();
+        let capture = state.capture.lock().await;
+        let namespaces = capture.list_netns();
+        assert_eq!(namespaces.len(), 1);
+        assert_eq!(namespaces[0].id, netns::HOST_NETNS_ID);
+        assert_eq!(
+            capture.list_netns_interfaces(netns::HOST_NETNS_ID).unwrap(),
+            capture.list_interfaces()
+        );
+        assert!(matches!(
+            capture.list_netns_interfaces("cni-1"),
+            Err(CaptureError::InterfaceNotFound(_))
+        ));
+    }
+
     #[tokio::test]
     async fn capture_backend_list_interfaces() {
         let state = AppState::new();
@@ -268,6 +501,25 @@ mod tests {
         assert!(capture.attach_interface("nonexistent").await.is_err());
     }
 
+    #[tokio::test]
+    #[cfg(not(feature = "ebpf"))]
+    async fn capture_backend_auto_attach_rules() {
+        let state = AppState::new();
+        let capture = state.capture.lock().await;
+        assert!(capture.attached_interfaces().is_empty());
+        assert!(capture
+            .set_auto_attach_rules(vec!["eth*".to_string()])
+            .await
+            .is_ok());
+        assert_eq!(capture.auto_attach_rules(), vec!["eth*".to_string()]);
+        assert_eq!(capture.attached_interfaces(), vec!["eth0".to_string()]);
+        // Empty patterns are rejected
+        assert!(capture
+            .set_auto_attach_rules(vec![String::new()])
+            .await
+            .is_err());
+    }
+
     #[tokio::test]
     async fn capture_backend_start_stop() {
         let state = AppState::new();
@@ -0,0 +1,270 @@
+//! `/metrics` で公開するキャプチャの詳細メトリクス。
+//!
+//! `CaptureStats` の合計値とは別に、インターフェース・ドロップ理由・プロトコルごとのパケット数と、
+//! 相関の遅延・バッチサイズのヒストグラムを集計する。両バックエンドが送信するバッチごとに記録する。
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::netns;
+use crate::types::{CapturedPacket, PacketResult, Protocol};
+
+/// 相関の遅延（XDP で見てから結果が確定するまで）のバケット境界（ナノ秒）
+pub const CORRELATION_LATENCY_BUCKETS_NS: &[u64] = &[
+    100_000,
+    500_000,
+    1_000_000,
+    5_000_000,
+    10_000_000,
+    25_000_000,
+    50_000_000,
+    100_000_000,
+    250_000_000,
+];
+
+/// 1 バッチに含まれるパケット数のバケット境界
+pub const BATCH_SIZE_BUCKETS: &[u64] = &[1, 4, 16, 32, 64, 128, 256];
+
+/// パケット数を数えるラベルの組
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PacketLabels {
+    /// 受信インターフェース（netns 内なら `netns/name`）。不明なら空文字列。
+    pub interface: String,
+    /// `delivered` / `nic-drop` / `fw-drop`
+    pub result: &'static str,
+    /// ドロップ理由。配送されたパケットは空文字列。
+    pub reason: String,
+    /// `tcp` / `udp`
+    pub protocol: &'static str,
+}
+
+impl PacketLabels {
+    fn of(captured: &CapturedPacket) -> Self {
+        let packet = &captured.packet;
+        let interface = packet
+            .interface
+            .as_deref()
+            .map(|name| netns::qualify_interface(packet.netns.as_deref(), name))
+            .unwrap_or_default();
+        Self {
+            interface,
+            result: result_label(&captured.result),
+            reason: packet.reason.clone().unwrap_or_default(),
+            protocol: match packet.protocol {
+                Protocol::Tcp => "tcp",
+                Protocol::Udp => "udp",
+            },
+        }
+    }
+}
+
+pub fn result_label(result: &PacketResult) -> &'static str {
+    match result {
+        PacketResult::Delivered => "delivered",
+        PacketResult::NicDrop => "nic-drop",
+        PacketResult::FwDrop => "fw-drop",
+    }
+}
+
+/// 固定のバケット境界を持つヒストグラム
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [u64],
+    /// 各境界以下の観測数（累積ではない）。末尾は `+Inf`。
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+/// ヒストグラムの読み出し結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// `(上限, その上限以下の累積観測数)`。`+Inf` は含めない（`count` と同じ）。
+    pub buckets: Vec<(u64, u64)>,
+    pub count: u64,
+    pub sum: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let index = self.bounds.partition_point(|&bound| bound < value);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let buckets = self
+            .bounds
+            .iter()
+            .zip(&self.buckets)
+            .map(|(&bound, bucket)| {
+                cumulative += bucket.load(Ordering::Relaxed);
+                (bound, cumulative)
+            })
+            .collect();
+        let count = cumulative + self.buckets[self.bounds.len()].load(Ordering::Relaxed);
+        HistogramSnapshot {
+            buckets,
+            count,
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.sum.store(0, Ordering::Relaxed);
+    }
+}
+
+/// バックエンドが記録し、`/metrics` が読み出すメトリクス
+#[derive(Debug)]
+pub struct CaptureMetrics {
+    packets: Mutex<BTreeMap<PacketLabels, u64>>,
+    correlation_latency_ns: Histogram,
+    batch_size: Histogram,
+}
+
+/// `CaptureMetrics` の読み出し結果
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    pub packets: Vec<(PacketLabels, u64)>,
+    pub correlation_latency_ns: HistogramSnapshot,
+    pub batch_size: HistogramSnapshot,
+}
+
+impl Default for CaptureMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CaptureMetrics {
+    pub fn new() -> Self {
+        Self {
+            packets: Mutex::new(BTreeMap::new()),
+            correlation_latency_ns: Histogram::new(CORRELATION_LATENCY_BUCKETS_NS),
+            batch_size: Histogram::new(BATCH_SIZE_BUCKETS),
+        }
+    }
+
+    /// 送信するバッチのサイズと、パケットごとのラベル別件数を記録する
+    pub fn record_batch(&self, packets: &[CapturedPacket]) {
+        if packets.is_empty() {
+            return;
+        }
+        self.batch_size.observe(packets.len() as u64);
+        let mut counts = self.packets.lock().unwrap();
+        for captured in packets {
+            *counts.entry(PacketLabels::of(captured)).or_default() += 1;
+        }
+    }
+
+    /// 1 パケットの相関にかかった時間を記録する
+    pub fn observe_correlation_latency(&self, latency_ns: u64) {
+        self.correlation_latency_ns.observe(latency_ns);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let packets = self
+            .packets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(labels, &count)| (labels.clone(), count))
+            .collect();
+        MetricsSnapshot {
+            packets,
+            correlation_latency_ns: self.correlation_latency_ns.snapshot(),
+            batch_size: self.batch_size.snapshot(),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.packets.lock().unwrap().clear();
+        self.correlation_latency_ns.reset();
+        self.batch_size.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AnimatingPacket;
+
+    fn captured(interface: &str, result: PacketResult, reason: Option<&str>) -> CapturedPacket {
+        let mut packet = AnimatingPacket::generate("abc123", 0);
+        packet.protocol = Protocol::Udp;
+        packet.interface = Some(interface.to_string());
+        packet.reason = reason.map(str::to_string);
+        CapturedPacket { packet, result }
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        static BOUNDS: &[u64] = &[10, 100];
+        let histogram = Histogram::new(BOUNDS);
+        for value in [1, 10, 11, 100, 1000] {
+            histogram.observe(value);
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.buckets, vec![(10, 2), (100, 4)]);
+        assert_eq!(snapshot.count, 5);
+        assert_eq!(snapshot.sum, 1122);
+
+        histogram.reset();
+        assert_eq!(histogram.snapshot().count, 0);
+    }
+
+    #[test]
+    fn record_batch_counts_by_labels() {
+        let metrics = CaptureMetrics::new();
+        let mut in_netns = captured("eth0", PacketResult::Delivered, None);
+        in_netns.packet.netns = Some("net:4026532281".to_string());
+        metrics.record_batch(&[
+            captured("eth0", PacketResult::Delivered, None),
+            captured("eth0", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            captured("eth0", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            in_netns,
+        ]);
+        metrics.record_batch(&[]);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.batch_size.count, 1);
+        assert_eq!(snapshot.batch_size.sum, 4);
+        let counts: Vec<_> = snapshot
+            .packets
+            .iter()
+            .map(|(l, n)| {
+                (
+                    l.interface.as_str(),
+                    l.result,
+                    l.reason.as_str(),
+                    l.protocol,
+                    *n,
+                )
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("eth0", "delivered", "", "udp", 1),
+                ("eth0", "fw-drop", "NETFILTER_DROP", "udp", 2),
+                ("net:4026532281/eth0", "delivered", "", "udp", 1),
+            ]
+        );
+
+        metrics.reset();
+        assert!(metrics.snapshot().packets.is_empty());
+    }
+}
@@ -4,14 +4,29 @@ use std::sync::Arc;
 use tokio::sync::broadcast;
 use tokio::time::{sleep, Duration};
 
+use crate::enrich::{self, EnrichmentPipeline, SharedPipeline};
+use crate::metrics::CaptureMetrics;
 use crate::types::{
-    build_packet_id, generate_session_id, monotonic_now_ns, AnimatingPacket, CaptureStats,
-    CapturedPacket, CapturedPacketEnvelope, PacketResult, Protocol,
+    build_packet_id, generate_session_id, monotonic_now_ns, AnimatingPacket, CaptureStateEvent,
+    CaptureStateKind, CaptureStats, CapturedPacket, CapturedPacketEnvelope, InterfaceEvent,
+    InterfaceEventKind, NetnsInfo, PacketResult, ProcessInfo, Protocol,
 };
-use crate::CaptureError;
+use crate::{netns, CaptureError, INTERFACE_EVENT_CHANNEL_CAPACITY, STATE_EVENT_CHANNEL_CAPACITY};
 
 pub const AVAILABLE_INTERFACES: &[&str] = &["eth0", "lo", "wlan0", "docker0"];
 
+/// プロセス帰属のモック用: 宛先ポート → (PID, comm)
+const MOCK_PORT_OWNERS: &[(u16, u32, &str)] = &[
+    (80, 1201, "nginx"),
+    (8080, 1201, "nginx"),
+    (443, 1342, "envoy"),
+    (22, 811, "sshd"),
+    (53, 604, "systemd-resolve"),
+    (25, 1530, "master"),
+    (21, 977, "vsftpd"),
+];
+const MOCK_CGROUP_ID: u64 = 4242;
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MockConfig {
@@ -116,6 +131,16 @@ fn build_dataset_packets(
             target_port: None,
             capture_mono_ns: base_mono_ns.saturating_add(counter * 1_000_000),
             reason: None,
+            interface: None,
+            netns: None,
+            source_meta: None,
+            destination_meta: None,
+            process: None,
+            source_name: None,
+            destination_name: None,
+            service: None,
+            source_geo: None,
+            destination_geo: None,
         };
         let result = classify_packet_result_deterministic(counter, nic_drop_rate, fw_drop_rate);
         out.push(packet_with_result(packet, result));
@@ -153,6 +178,23 @@ fn apply_stats_delta(stats: &std::sync::Mutex<CaptureStats>, delta: BatchStatsDe
     s.delivered += delta.delivered;
 }
 
+/// 配送されたパケットに宛先ポートから決めた受信プロセスを付ける
+fn attribute_mock_processes(packets: &mut [CapturedPacket]) {
+    for captured in packets {
+        if !matches!(captured.result, PacketResult::Delivered) {
+            continue;
+        }
+        captured.packet.process = MOCK_PORT_OWNERS
+            .iter()
+            .find(|(port, _, _)| *port == captured.packet.dest_port)
+            .map(|&(_, pid, comm)| ProcessInfo {
+                pid,
+                comm: comm.to_string(),
+                cgroup_id: MOCK_CGROUP_ID,
+            });
+    }
+}
+
 fn current_epoch_offset_ms() -> f64 {
     chrono::Utc::now().timestamp_millis() as f64 - (monotonic_now_ns() as f64 / 1_000_000.0)
 }
@@ -194,7 +236,21 @@ pub struct MockCapture {
     packet_counter: Arc<AtomicU64>,
     stats: Arc<std::sync::Mutex<CaptureStats>>,
     attached_interfaces: Arc<std::sync::Mutex<HashSet<String>>>,
+    auto_attach_rules: std::sync::Mutex<Vec<String>>,
     config: Arc<std::sync::Mutex<MockConfig>>,
+    enrichment: SharedPipeline,
+    metrics: Arc<CaptureMetrics>,
+    process_attribution: Arc<AtomicBool>,
+    interface_tx: broadcast::Sender<InterfaceEvent>,
+    state_tx: broadcast::Sender<CaptureStateEvent>,
+    /// 実行中の生成タスク。`shutdown` で最後のバッチの送出を待つ
+    task: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+impl Default for MockCapture {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MockCapture {
@@ -205,10 +261,67 @@ impl MockCapture {
             packet_counter: Arc::new(AtomicU64::new(0)),
             stats: Arc::new(std::sync::Mutex::new(CaptureStats::default())),
             attached_interfaces: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            auto_attach_rules: std::sync::Mutex::new(Vec::new()),
             config: Arc::new(std::sync::Mutex::new(MockConfig::default())),
+            enrichment: SharedPipeline::default(),
+            metrics: Arc::new(CaptureMetrics::new()),
+            process_attribution: Arc::new(AtomicBool::new(false)),
+            interface_tx: broadcast::channel(INTERFACE_EVENT_CHANNEL_CAPACITY).0,
+            state_tx: broadcast::channel(STATE_EVENT_CHANNEL_CAPACITY).0,
+            task: std::sync::Mutex::new(None),
         }
     }
 
+    /// インターフェース変化の通知先を差し替える
+    pub fn with_interface_events(
+        mut self,
+        interface_tx: broadcast::Sender<InterfaceEvent>,
+    ) -> Self {
+        self.interface_tx = interface_tx;
+        self
+    }
+
+    /// キャプチャ状態の変化の通知先を差し替える
+    pub fn with_state_events(mut self, state_tx: broadcast::Sender<CaptureStateEvent>) -> Self {
+        self.state_tx = state_tx;
+        self
+    }
+
+    /// ラベル別の件数とヒストグラムの記録先を差し替える
+    pub fn with_metrics(mut self, metrics: Arc<CaptureMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    fn notify_state(&self, kind: CaptureStateKind) {
+        let _ = self.state_tx.send(CaptureStateEvent { kind, error: None });
+    }
+
+    fn notify_interface(&self, kind: InterfaceEventKind, interface: &str) {
+        let mut attached: Vec<String> = self.attached_interfaces().into_iter().collect();
+        attached.sort();
+        let _ = self.interface_tx.send(InterfaceEvent {
+            kind,
+            interface: interface.to_string(),
+            previous_name: None,
+            error: None,
+            interfaces: self.list_interfaces(),
+            attached,
+        });
+    }
+
+    pub fn set_enrichment(&self, pipeline: EnrichmentPipeline) {
+        *self.enrichment.write().unwrap() = Arc::new(pipeline);
+    }
+
+    pub fn process_attribution(&self) -> bool {
+        self.process_attribution.load(Ordering::SeqCst)
+    }
+
+    pub fn set_process_attribution(&self, enabled: bool) {
+        self.process_attribution.store(enabled, Ordering::SeqCst);
+    }
+
     pub fn get_config(&self) -> MockConfig {
         self.config.lock().unwrap().clone()
     }
@@ -279,10 +392,14 @@ impl MockCapture {
                 name
             )));
         }
-        self.attached_interfaces
+        let added = self
+            .attached_interfaces
             .lock()
             .unwrap()
             .insert(name.to_string());
+        if added {
+            self.notify_interface(InterfaceEventKind::Attached, name);
+        }
         Ok(())
     }
 
@@ -293,6 +410,7 @@ impl MockCapture {
                 name
             )));
         }
+        self.notify_interface(InterfaceEventKind::Detached, name);
         Ok(())
     }
 
@@ -300,16 +418,61 @@ impl MockCapture {
         self.attached_interfaces.lock().unwrap().clone()
     }
 
+    pub fn auto_attach_rules(&self) -> Vec<String> {
+        self.auto_attach_rules.lock().unwrap().clone()
+    }
+
+    /// モックのインターフェースは固定なので、ルール設定時にマッチするものをアタッチする
+    pub fn set_auto_attach_rules(&self, rules: Vec<String>) {
+        let added: Vec<&str> = {
+            let mut attached = self.attached_interfaces.lock().unwrap();
+            AVAILABLE_INTERFACES
+                .iter()
+                .copied()
+                .filter(|iface| {
+                    crate::glob::matches_any(&rules, iface) && attached.insert(iface.to_string())
+                })
+                .collect()
+        };
+        *self.auto_attach_rules.lock().unwrap() = rules;
+        for iface in added {
+            self.notify_interface(InterfaceEventKind::Attached, iface);
+        }
+    }
+
     pub fn list_interfaces(&self) -> Vec<String> {
         AVAILABLE_INTERFACES.iter().map(|s| s.to_string()).collect()
     }
 
+    /// モックはホストの netns のみを持つ
+    pub fn list_netns(&self) -> Vec<NetnsInfo> {
+        vec![NetnsInfo {
+            id: netns::HOST_NETNS_ID.to_string(),
+            inode: 0,
+            path: "/proc/self/ns/net".to_string(),
+            pid: None,
+            command: None,
+        }]
+    }
+
+    pub fn list_netns_interfaces(&self, netns_id: &str) -> Result<Vec<String>, CaptureError> {
+        if netns_id != netns::HOST_NETNS_ID {
+            return Err(CaptureError::InterfaceNotFound(format!(
+                "Network namespace {} not found",
+                netns_id
+            )));
+        }
+        Ok(self.list_interfaces())
+    }
+
     pub fn is_running(&self) -> bool {
         self.is_running.load(Ordering::SeqCst)
     }
 
     pub fn get_stats(&self) -> CaptureStats {
-        self.stats.lock().unwrap().clone()
+        let mut stats = self.stats.lock().unwrap().clone();
+        self.enrichment.read().unwrap().write_stats(&mut stats);
+        stats
     }
 
     pub fn start(&self, tx: broadcast::Sender<CapturedPacketEnvelope>) {
@@ -322,9 +485,13 @@ impl MockCapture {
         let stats = Arc::clone(&self.stats);
         let attached_interfaces = Arc::clone(&self.attached_interfaces);
         let config = Arc::clone(&self.config);
+        let enrichment = Arc::clone(&self.enrichment);
+        let metrics = Arc::clone(&self.metrics);
+        let process_attribution = Arc::clone(&self.process_attribution);
         let session_id = generate_session_id();
+        self.notify_state(CaptureStateKind::Started);
 
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             const BENCH_SOURCE: &str = "192.168.1.100";
             const BENCH_DESTINATION: &str = "10.0.0.10";
             const BENCH_SIZE: u32 = 512;
@@ -354,16 +521,23 @@ impl MockCapture {
                 };
 
                 // Skip packet generation if no interfaces are attached
-                if attached_interfaces.lock().unwrap().is_empty() {
+                let mut interfaces: Vec<String> = attached_interfaces
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .cloned()
+                    .collect();
+                if interfaces.is_empty() {
                     sleep(Duration::from_millis(interval_ms)).await;
                     continue;
                 }
+                interfaces.sort();
 
                 let mut out_batch = Vec::with_capacity(batch_size as usize);
                 let mut stats_delta = BatchStatsDelta::default();
                 for _ in 0..batch_size {
                     let counter = packet_counter.fetch_add(1, Ordering::SeqCst);
-                    let captured = match traffic_profile {
+                    let mut captured = match traffic_profile {
                         MockTrafficProfile::Realistic => {
                             let packet = AnimatingPacket::generate(&session_id, counter);
                             let result = classify_packet_result_random(nic_drop_rate, fw_drop_rate);
@@ -384,6 +558,16 @@ impl MockCapture {
                                 target_port: None,
                                 capture_mono_ns: monotonic_now_ns(),
                                 reason: None,
+                                interface: None,
+                                netns: None,
+                                source_meta: None,
+                                destination_meta: None,
+                                process: None,
+                                source_name: None,
+                                destination_name: None,
+                                service: None,
+                                source_geo: None,
+                                destination_geo: None,
                             };
                             let result = classify_packet_result_deterministic(
                                 counter,
@@ -410,11 +594,25 @@ impl MockCapture {
                             packet
                         }
                     };
+                    let (netns, interface) = netns::split_qualified(
+                        &interfaces[(counter % interfaces.len() as u64) as usize],
+                    );
+                    captured.packet.interface = Some(interface.to_string());
+                    captured.packet.netns = netns.map(str::to_string);
                     out_batch.push(captured);
                 }
 
                 if !out_batch.is_empty() {
                     apply_stats_delta(&stats, stats_delta);
+                    if process_attribution.load(Ordering::SeqCst) {
+                        attribute_mock_processes(&mut out_batch);
+                    }
+                    enrich::apply_shared(&enrichment, &mut out_batch);
+                    // モックは相関を行わず、結果は生成時に確定する（遅延 0 として記録する）
+                    for _ in &out_batch {
+                        metrics.observe_correlation_latency(0);
+                    }
+                    metrics.record_batch(&out_batch);
                     let _ = tx.send(CapturedPacketEnvelope {
                         packets: out_batch,
                         epoch_offset_ms: current_epoch_offset_ms(),
@@ -424,15 +622,30 @@ impl MockCapture {
                 sleep(Duration::from_millis(interval_ms)).await;
             }
         });
+        *self.task.lock().unwrap() = Some(task);
     }
 
     pub fn stop(&self) {
-        self.is_running.store(false, Ordering::SeqCst);
+        if self.is_running.swap(false, Ordering::SeqCst) {
+            self.notify_state(CaptureStateKind::Stopped);
+        }
+    }
+
+    /// 停止して、生成タスクが終わるまで待つ
+    pub async fn shutdown(&self) {
+        self.stop();
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
     }
 
     pub fn reset(&self) {
         self.packet_counter.store(0, Ordering::SeqCst);
         *self.stats.lock().unwrap() = CaptureStats::default();
+        self.metrics.reset();
+        self.enrichment.read().unwrap().reset_stats();
+        self.notify_state(CaptureStateKind::Reset);
     }
 }
 
@@ -476,6 +689,23 @@ mod tests {
         assert!(!mock.is_running());
     }
 
+    #[tokio::test]
+    async fn shutdown_waits_for_generator_to_finish() {
+        let mock = MockCapture::new();
+        mock.attach_interface("eth0").unwrap();
+        let (tx, mut rx) = broadcast::channel(1024);
+        mock.start(tx);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        mock.shutdown().await;
+        assert!(!mock.is_running());
+        while rx.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(
+            rx.try_recv(),
+            Err(broadcast::error::TryRecvError::Empty | broadcast::error::TryRecvError::Closed)
+        ));
+    }
+
     #[tokio::test]
     async fn double_start_is_idempotent() {
         let mock = MockCapture::new();
@@ -501,6 +731,8 @@ mod tests {
         assert!(result.is_ok(), "Timed out waiting for packet");
         let captured = first_packet(result.unwrap().unwrap());
         assert!(!captured.packet.id.is_empty());
+        assert_eq!(captured.packet.interface.as_deref(), Some("eth0"));
+        assert_eq!(captured.packet.netns, None);
     }
 
     #[tokio::test]
@@ -570,6 +802,43 @@ mod tests {
         assert_eq!(mock.get_stats().total_packets, 0);
     }
 
+    #[tokio::test]
+    async fn lifecycle_and_attachment_changes_are_notified() {
+        let (state_tx, mut state_rx) = broadcast::channel(16);
+        let (interface_tx, mut interface_rx) = broadcast::channel(16);
+        let mock = MockCapture::new()
+            .with_state_events(state_tx)
+            .with_interface_events(interface_tx);
+
+        mock.attach_interface("eth0").unwrap();
+        let event = interface_rx.try_recv().unwrap();
+        assert_eq!(event.kind, InterfaceEventKind::Attached);
+        assert_eq!(event.interface, "eth0");
+        assert_eq!(event.attached, vec!["eth0".to_string()]);
+
+        let (tx, _rx) = broadcast::channel(16);
+        mock.start(tx.clone());
+        mock.start(tx);
+        mock.stop();
+        mock.stop();
+        mock.reset();
+        let kinds: Vec<CaptureStateKind> =
+            std::iter::from_fn(|| state_rx.try_recv().ok().map(|e| e.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                CaptureStateKind::Started,
+                CaptureStateKind::Stopped,
+                CaptureStateKind::Reset
+            ]
+        );
+
+        mock.detach_interface("eth0").unwrap();
+        let event = interface_rx.try_recv().unwrap();
+        assert_eq!(event.kind, InterfaceEventKind::Detached);
+        assert!(event.attached.is_empty());
+    }
+
     #[test]
     fn attach_unknown_interface_returns_error() {
         let mock = MockCapture::new();
@@ -644,6 +913,18 @@ mod tests {
         assert_eq!(mock.get_stats().total_packets, 0);
     }
 
+    #[test]
+    fn auto_attach_rules_attach_matching_interfaces() {
+        let mock = MockCapture::new();
+        mock.set_auto_attach_rules(vec!["*0".to_string()]);
+        let attached = mock.attached_interfaces();
+        assert_eq!(attached.len(), 3);
+        assert!(attached.contains("eth0"));
+        assert!(attached.contains("wlan0"));
+        assert!(attached.contains("docker0"));
+        assert_eq!(mock.auto_attach_rules(), vec!["*0".to_string()]);
+    }
+
     #[test]
     fn list_interfaces_returns_all_available() {
         let mock = MockCapture::new();
@@ -801,6 +1082,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn mock_process_attribution_tags_delivered_packets_only() {
+        let mut packets: Vec<CapturedPacket> = [
+            (80, PacketResult::Delivered),
+            (443, PacketResult::FwDrop),
+            (9999, PacketResult::Delivered),
+        ]
+        .into_iter()
+        .map(|(port, result)| {
+            let mut packet = AnimatingPacket::generate("abc123", 0);
+            packet.dest_port = port;
+            CapturedPacket { packet, result }
+        })
+        .collect();
+
+        attribute_mock_processes(&mut packets);
+
+        let process = packets[0].packet.process.as_ref().expect("process");
+        assert_eq!(process.comm, "nginx");
+        assert_eq!(process.cgroup_id, MOCK_CGROUP_ID);
+        assert!(packets[1].packet.process.is_none());
+        assert!(packets[2].packet.process.is_none());
+    }
+
+    #[tokio::test]
+    async fn enrichment_is_applied_before_batches_are_sent() {
+        struct MarkSource;
+
+        impl crate::enrich::Enricher for MarkSource {
+            fn name(&self) -> &'static str {
+                "mark"
+            }
+
+            fn enrich(&self, packets: &mut [CapturedPacket]) {
+                for p in packets {
+                    p.packet.source_meta = Some(crate::types::EndpointMetadata {
+                        pod: Some("web-0".to_string()),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        let mock = MockCapture::new();
+        mock.attach_interface("eth0").unwrap();
+        mock.update_config(Some(50), None, None, None, None, None)
+            .unwrap();
+        mock.set_enrichment(EnrichmentPipeline::new().with_stage(Arc::new(MarkSource)));
+
+        let (tx, mut rx) = broadcast::channel(16);
+        mock.start(tx);
+        let packet = first_packet(
+            tokio::time::timeout(Duration::from_secs(2), rx.recv())
+                .await
+                .expect("packet timeout")
+                .expect("packet receive failed"),
+        );
+        mock.stop();
+
+        let meta = packet.packet.source_meta.expect("source meta");
+        assert_eq!(meta.pod.as_deref(), Some("web-0"));
+    }
+
     #[tokio::test]
     async fn start_stop_start_rotates_session_id_without_reset() {
         let mock = MockCapture::new();
@@ -0,0 +1,543 @@
+//! アドレスとポートに名前を付けるエンリッチメント段。
+//!
+//! ホスト名はユーザー指定の CSV → `/etc/hosts` → 逆引き DNS の順に引く。
+//! 逆引きは別スレッドで行い結果を TTL 付きでキャッシュするため、送信経路はブロックしない
+//! （初めて見たアドレスは名前なしで送られ、解決後のパケットから名前が付く）。
+//! サービス名は `/etc/services` から宛先ポート、なければ送信元ポートで引く。
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::enrich::Enricher;
+use crate::types::{CapturedPacket, Protocol};
+
+const HOSTS_PATH: &str = "/etc/hosts";
+const SERVICES_PATH: &str = "/etc/services";
+const DEFAULT_DNS_TTL: Duration = Duration::from_secs(300);
+const DEFAULT_DNS_NEGATIVE_TTL: Duration = Duration::from_secs(60);
+const DNS_WORKERS: usize = 2;
+const DNS_QUEUE_CAPACITY: usize = 1024;
+/// キャッシュの上限。超えたら期限切れを捨て、それでも多ければ全消去する。
+const DNS_CACHE_MAX_ENTRIES: usize = 65_536;
+
+/// 名前解決段の設定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameResolutionConfig {
+    /// 逆引き DNS を使うか
+    pub reverse_dns: bool,
+    pub dns_ttl: Duration,
+    /// 逆引きに失敗・名前なしだった場合のキャッシュ期間
+    pub dns_negative_ttl: Duration,
+    pub hosts_path: Option<PathBuf>,
+    pub services_path: Option<PathBuf>,
+    /// `ip,name` 形式のユーザー指定の対応表（最優先）
+    pub names_csv: Option<PathBuf>,
+}
+
+impl Default for NameResolutionConfig {
+    fn default() -> Self {
+        Self {
+            reverse_dns: false,
+            dns_ttl: DEFAULT_DNS_TTL,
+            dns_negative_ttl: DEFAULT_DNS_NEGATIVE_TTL,
+            hosts_path: Some(PathBuf::from(HOSTS_PATH)),
+            services_path: Some(PathBuf::from(SERVICES_PATH)),
+            names_csv: None,
+        }
+    }
+}
+
+/// 逆引きの実装。ワーカースレッドから呼ばれるためブロックしてよい。
+pub trait ReverseResolver: Send + Sync + 'static {
+    /// PTR レコードの名前。名前がない場合は `Ok(None)`。
+    fn reverse(&self, addr: Ipv4Addr) -> Result<Option<String>, String>;
+}
+
+/// libc の `getnameinfo` による逆引き（NSS の設定に従う）
+pub struct SystemResolver;
+
+impl ReverseResolver for SystemResolver {
+    fn reverse(&self, addr: Ipv4Addr) -> Result<Option<String>, String> {
+        let sockaddr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: 0,
+            sin_addr: libc::in_addr {
+                s_addr: u32::from(addr).to_be(),
+            },
+            sin_zero: [0; 8],
+        };
+        let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+        let rc = unsafe {
+            libc::getnameinfo(
+                &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                host.as_mut_ptr(),
+                host.len() as libc::socklen_t,
+                std::ptr::null_mut(),
+                0,
+                libc::NI_NAMEREQD,
+            )
+        };
+        match rc {
+            0 => {
+                let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+                Ok(Some(name.to_string_lossy().into_owned()))
+            }
+            libc::EAI_NONAME => Ok(None),
+            rc => Err(unsafe { CStr::from_ptr(libc::gai_strerror(rc)) }
+                .to_string_lossy()
+                .into_owned()),
+        }
+    }
+}
+
+enum CacheEntry {
+    /// ワーカーに依頼済み（期限切れで再解決中なら古い名前を持つ）
+    Pending { stale: Option<String> },
+    Resolved {
+        name: Option<String>,
+        expires_at: Instant,
+    },
+}
+
+type DnsEntries = Arc<Mutex<HashMap<Ipv4Addr, CacheEntry>>>;
+
+/// 逆引き結果の TTL 付きキャッシュ。未解決・期限切れのアドレスはワーカーに依頼する。
+pub struct DnsCache {
+    entries: DnsEntries,
+    queue: SyncSender<Ipv4Addr>,
+}
+
+impl DnsCache {
+    pub fn new<R: ReverseResolver>(resolver: R, ttl: Duration, negative_ttl: Duration) -> Self {
+        let entries: DnsEntries = Arc::default();
+        let (queue, rx) = mpsc::sync_channel(DNS_QUEUE_CAPACITY);
+        let rx = Arc::new(Mutex::new(rx));
+        let resolver = Arc::new(resolver);
+        for _ in 0..DNS_WORKERS {
+            let rx = Arc::clone(&rx);
+            let resolver = Arc::clone(&resolver);
+            let entries = Arc::clone(&entries);
+            std::thread::spawn(move || {
+                dns_worker(&rx, resolver.as_ref(), &entries, ttl, negative_ttl)
+            });
+        }
+        Self { entries, queue }
+    }
+
+    /// キャッシュ済みの名前を返す。未解決・期限切れなら解決を依頼する
+    /// （期限切れの場合は更新されるまで古い名前を返す）。
+    pub fn lookup(&self, addr: Ipv4Addr) -> Option<String> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let stale = match entries.get(&addr) {
+            Some(CacheEntry::Pending { stale }) => return stale.clone(),
+            Some(CacheEntry::Resolved { name, expires_at }) if *expires_at > now => {
+                return name.clone();
+            }
+            Some(CacheEntry::Resolved { name, .. }) => name.clone(),
+            None => None,
+        };
+
+        if entries.len() >= DNS_CACHE_MAX_ENTRIES {
+            entries.retain(|_, entry| {
+                matches!(entry, CacheEntry::Resolved { expires_at, .. } if *expires_at > now)
+            });
+            if entries.len() >= DNS_CACHE_MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        match self.queue.try_send(addr) {
+            Ok(()) => {
+                entries.insert(
+                    addr,
+                    CacheEntry::Pending {
+                        stale: stale.clone(),
+                    },
+                );
+            }
+            // キューが満杯なら今回は諦め、次に見たときに再依頼する
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {}
+        }
+        stale
+    }
+}
+
+fn dns_worker(
+    rx: &Mutex<Receiver<Ipv4Addr>>,
+    resolver: &dyn ReverseResolver,
+    entries: &Mutex<HashMap<Ipv4Addr, CacheEntry>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+) {
+    loop {
+        // DnsCache が破棄されて送信側が閉じたら終了する
+        let Ok(addr) = rx.lock().unwrap().recv() else {
+            return;
+        };
+        let name = resolver.reverse(addr).unwrap_or_else(|e| {
+            warn!(addr = %addr, error = %e, "reverse DNS lookup failed");
+            None
+        });
+        let expires_at = Instant::now() + if name.is_some() { ttl } else { negative_ttl };
+        entries
+            .lock()
+            .unwrap()
+            .insert(addr, CacheEntry::Resolved { name, expires_at });
+    }
+}
+
+/// アドレス・ポートに名前を付ける段
+pub struct NameEnricher {
+    /// CSV と `/etc/hosts` の静的な対応表（CSV が優先）
+    static_names: HashMap<Ipv4Addr, String>,
+    services: HashMap<(u16, &'static str), String>,
+    dns: Option<DnsCache>,
+}
+
+impl NameEnricher {
+    pub fn new(config: &NameResolutionConfig) -> Self {
+        let dns = config
+            .reverse_dns
+            .then(|| DnsCache::new(SystemResolver, config.dns_ttl, config.dns_negative_ttl));
+        Self::with_dns(config, dns)
+    }
+
+    /// 逆引きに使うキャッシュ（とその resolver）を指定して作る
+    pub fn with_dns(config: &NameResolutionConfig, dns: Option<DnsCache>) -> Self {
+        let mut static_names = HashMap::new();
+        if let Some(path) = &config.hosts_path {
+            static_names.extend(read_table(path, parse_hosts));
+        }
+        if let Some(path) = &config.names_csv {
+            static_names.extend(read_table(path, parse_names_csv));
+        }
+        let services = config
+            .services_path
+            .as_deref()
+            .map(|path| read_table(path, parse_services).into_iter().collect())
+            .unwrap_or_default();
+        Self {
+            static_names,
+            services,
+            dns,
+        }
+    }
+
+    fn host_name(&self, addr: &str) -> Option<String> {
+        let addr: Ipv4Addr = addr.parse().ok()?;
+        if let Some(name) = self.static_names.get(&addr) {
+            return Some(name.clone());
+        }
+        self.dns.as_ref()?.lookup(addr)
+    }
+
+    fn service_name(&self, port: u16, protocol: &Protocol) -> Option<String> {
+        let protocol = match protocol {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        };
+        self.services.get(&(port, protocol)).cloned()
+    }
+}
+
+impl Enricher for NameEnricher {
+    fn name(&self) -> &'static str {
+        "names"
+    }
+
+    fn enrich(&self, packets: &mut [CapturedPacket]) {
+        for captured in packets {
+            let packet = &mut captured.packet;
+            if packet.source_name.is_none() {
+                packet.source_name = self.host_name(&packet.source);
+            }
+            if packet.destination_name.is_none() {
+                packet.destination_name = self.host_name(&packet.destination);
+            }
+            if packet.service.is_none() {
+                packet.service = self
+                    .service_name(packet.dest_port, &packet.protocol)
+                    .or_else(|| self.service_name(packet.src_port, &packet.protocol));
+            }
+        }
+    }
+}
+
+fn read_table<K, V>(path: &Path, parse: fn(&str) -> Vec<(K, V)>) -> Vec<(K, V)> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse(&text),
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "failed to read name table");
+            Vec::new()
+        }
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split('#').next().unwrap_or_default().trim()
+}
+
+/// `/etc/hosts` から IPv4 アドレスごとの最初のホスト名を取り出す
+fn parse_hosts(text: &str) -> Vec<(Ipv4Addr, String)> {
+    let mut seen = HashMap::new();
+    for line in text.lines() {
+        let mut fields = strip_comment(line).split_whitespace();
+        let (Some(addr), Some(name)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        if let Ok(addr) = addr.parse::<Ipv4Addr>() {
+            seen.entry(addr).or_insert_with(|| name.to_string());
+        }
+    }
+    seen.into_iter().collect()
+}
+
+/// `ip,name` 形式の CSV。ヘッダ行や解釈できない行は読み飛ばす。
+fn parse_names_csv(text: &str) -> Vec<(Ipv4Addr, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let (addr, name) = strip_comment(line).split_once(',')?;
+            let addr = addr.trim().trim_matches('"').parse().ok()?;
+            let name = name.trim().trim_matches('"');
+            (!name.is_empty()).then(|| (addr, name.to_string()))
+        })
+        .collect()
+}
+
+/// `/etc/services` から (ポート, プロトコル) ごとの最初のサービス名を取り出す
+fn parse_services(text: &str) -> Vec<((u16, &'static str), String)> {
+    let mut seen = HashMap::new();
+    for line in text.lines() {
+        let mut fields = strip_comment(line).split_whitespace();
+        let (Some(name), Some(port_proto)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Some((port, protocol)) = port_proto.split_once('/') else {
+            continue;
+        };
+        let protocol = match protocol {
+            "tcp" => "tcp",
+            "udp" => "udp",
+            _ => continue,
+        };
+        if let Ok(port) = port.parse::<u16>() {
+            seen.entry((port, protocol))
+                .or_insert_with(|| name.to_string());
+        }
+    }
+    seen.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AnimatingPacket, PacketResult};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct StubResolver {
+        names: HashMap<Ipv4Addr, &'static str>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl ReverseResolver for StubResolver {
+        fn reverse(&self, addr: Ipv4Addr) -> Result<Option<String>, String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if addr == Ipv4Addr::new(192, 0, 2, 99) {
+                return Err("SERVFAIL".to_string());
+            }
+            Ok(self.names.get(&addr).map(|name| name.to_string()))
+        }
+    }
+
+    fn stub_cache(ttl: Duration, negative_ttl: Duration) -> (DnsCache, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolver = StubResolver {
+            names: HashMap::from([(Ipv4Addr::new(192, 0, 2, 1), "gw.example.test")]),
+            calls: Arc::clone(&calls),
+        };
+        (DnsCache::new(resolver, ttl, negative_ttl), calls)
+    }
+
+    /// 依頼中の解決があれば、ワーカーが結果を書き込むまで待つ
+    fn wait_settled(cache: &DnsCache, addr: Ipv4Addr) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while matches!(
+            cache.entries.lock().unwrap().get(&addr),
+            Some(CacheEntry::Pending { .. })
+        ) && Instant::now() < deadline
+        {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// 解決を依頼し、ワーカーが結果を書き込むまで待ってから引く
+    fn lookup_settled(cache: &DnsCache, addr: Ipv4Addr) -> Option<String> {
+        cache.lookup(addr);
+        wait_settled(cache, addr);
+        cache.lookup(addr)
+    }
+
+    fn no_files() -> NameResolutionConfig {
+        NameResolutionConfig {
+            hosts_path: None,
+            services_path: None,
+            ..NameResolutionConfig::default()
+        }
+    }
+
+    #[test]
+    fn dns_cache_resolves_in_background_and_caches_positive_and_negative() {
+        let (cache, calls) = stub_cache(Duration::from_secs(60), Duration::from_secs(60));
+        let gw = Ipv4Addr::new(192, 0, 2, 1);
+        let unknown = Ipv4Addr::new(192, 0, 2, 2);
+
+        assert_eq!(cache.lookup(gw), None);
+        assert_eq!(
+            lookup_settled(&cache, gw).as_deref(),
+            Some("gw.example.test")
+        );
+        assert_eq!(lookup_settled(&cache, unknown), None);
+        let after_first = calls.load(Ordering::SeqCst);
+
+        assert_eq!(cache.lookup(gw).as_deref(), Some("gw.example.test"));
+        assert_eq!(cache.lookup(unknown), None);
+        assert_eq!(calls.load(Ordering::SeqCst), after_first);
+    }
+
+    /// 呼ばれるたびに `gate` を取ってから答える（テストが持っている間は解決が終わらない）
+    struct GatedResolver {
+        gate: Arc<Mutex<()>>,
+    }
+
+    impl ReverseResolver for GatedResolver {
+        fn reverse(&self, _addr: Ipv4Addr) -> Result<Option<String>, String> {
+            let _gate = self.gate.lock().unwrap();
+            Ok(Some("gw.example.test".to_string()))
+        }
+    }
+
+    #[test]
+    fn dns_cache_returns_stale_name_while_refreshing() {
+        let gate = Arc::new(Mutex::new(()));
+        let resolver = GatedResolver {
+            gate: Arc::clone(&gate),
+        };
+        let cache = DnsCache::new(resolver, Duration::ZERO, Duration::ZERO);
+        let gw = Ipv4Addr::new(192, 0, 2, 1);
+        assert_eq!(
+            lookup_settled(&cache, gw).as_deref(),
+            Some("gw.example.test")
+        );
+        wait_settled(&cache, gw);
+
+        // 更新が終わるまでは、期限切れ後の何度目の lookup でも古い名前を返す
+        let _held = gate.lock().unwrap();
+        assert_eq!(cache.lookup(gw).as_deref(), Some("gw.example.test"));
+        assert!(matches!(
+            cache.entries.lock().unwrap().get(&gw),
+            Some(CacheEntry::Pending { .. })
+        ));
+        assert_eq!(cache.lookup(gw).as_deref(), Some("gw.example.test"));
+        assert_eq!(cache.lookup(gw).as_deref(), Some("gw.example.test"));
+    }
+
+    #[test]
+    fn dns_cache_retries_after_negative_ttl_and_failures() {
+        let (cache, calls) = stub_cache(Duration::from_secs(60), Duration::ZERO);
+        let failing = Ipv4Addr::new(192, 0, 2, 99);
+
+        assert_eq!(lookup_settled(&cache, failing), None);
+        // 失敗は即座に期限切れになるため、最後の lookup が再解決を依頼している
+        wait_settled(&cache, failing);
+        let first = calls.load(Ordering::SeqCst);
+        assert_eq!(lookup_settled(&cache, failing), None);
+        assert!(calls.load(Ordering::SeqCst) > first);
+    }
+
+    #[test]
+    fn parses_hosts_services_and_csv() {
+        let hosts = parse_hosts(
+            "127.0.0.1 localhost\n::1 localhost ip6-localhost\n10.0.0.1 db db.internal # primary\n10.0.0.1 other\n",
+        );
+        let hosts: HashMap<_, _> = hosts.into_iter().collect();
+        assert_eq!(hosts[&Ipv4Addr::new(10, 0, 0, 1)], "db");
+        assert_eq!(hosts.len(), 2);
+
+        let services: HashMap<_, _> = parse_services(
+            "http\t\t80/tcp\t\twww\n# comment\ndomain 53/udp\nbad line\nsctp 9/sctp\n",
+        )
+        .into_iter()
+        .collect();
+        assert_eq!(services[&(80, "tcp")], "http");
+        assert_eq!(services[&(53, "udp")], "domain");
+        assert_eq!(services.len(), 2);
+
+        let csv = parse_names_csv("ip,name\n10.0.0.5, \"api-server\"\n10.0.0.6,\n");
+        assert_eq!(
+            csv,
+            vec![(Ipv4Addr::new(10, 0, 0, 5), "api-server".to_string())]
+        );
+    }
+
+    #[test]
+    fn enricher_prefers_static_names_and_falls_back_to_dns() {
+        let dir = std::env::temp_dir().join(format!("scrop-names-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("hosts"), "10.0.0.1 db\n192.0.2.1 hosts-gw\n").unwrap();
+        std::fs::write(dir.join("names.csv"), "192.0.2.1,csv-gw\n").unwrap();
+        std::fs::write(dir.join("services"), "https 443/tcp\n").unwrap();
+        let config = NameResolutionConfig {
+            hosts_path: Some(dir.join("hosts")),
+            services_path: Some(dir.join("services")),
+            names_csv: Some(dir.join("names.csv")),
+            ..NameResolutionConfig::default()
+        };
+        let (cache, _) = stub_cache(Duration::from_secs(60), Duration::from_secs(60));
+        lookup_settled(&cache, Ipv4Addr::new(192, 0, 2, 1));
+        let enricher = NameEnricher::with_dns(&config, Some(cache));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut packet = AnimatingPacket::generate("abc123", 0);
+        packet.protocol = Protocol::Tcp;
+        packet.source = "192.0.2.1".to_string();
+        packet.destination = "10.0.0.1".to_string();
+        packet.src_port = 51234;
+        packet.dest_port = 443;
+        let mut packets = vec![CapturedPacket {
+            packet,
+            result: PacketResult::Delivered,
+        }];
+        enricher.enrich(&mut packets);
+
+        let packet = &packets[0].packet;
+        assert_eq!(packet.source_name.as_deref(), Some("csv-gw"));
+        assert_eq!(packet.destination_name.as_deref(), Some("db"));
+        assert_eq!(packet.service.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn enricher_uses_source_port_when_destination_is_unknown() {
+        let mut enricher = NameEnricher::with_dns(&no_files(), None);
+        enricher.services.insert((53, "udp"), "domain".to_string());
+
+        let mut packet = AnimatingPacket::generate("abc123", 0);
+        packet.protocol = Protocol::Udp;
+        packet.src_port = 53;
+        packet.dest_port = 40000;
+        let mut packets = vec![CapturedPacket {
+            packet,
+            result: PacketResult::Delivered,
+        }];
+        enricher.enrich(&mut packets);
+        assert_eq!(packets[0].packet.service.as_deref(), Some("domain"));
+        assert!(packets[0].packet.source_name.is_none());
+    }
+}
@@ -0,0 +1,285 @@
+//! rtnetlink (`RTMGRP_LINK`) を購読してインターフェースの追加・削除・状態変化を検知する。
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use tokio::io::unix::AsyncFd;
+
+const NLMSG_HDR_LEN: usize = 16;
+const IFINFOMSG_LEN: usize = 16;
+const RTATTR_HDR_LEN: usize = 4;
+const RECV_BUFFER_SIZE: usize = 32 * 1024;
+
+/// リンクイベントの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkChange {
+    /// 新規作成（`ifi_change == !0`）
+    Added,
+    /// 削除（`RTM_DELLINK`）
+    Removed,
+    /// `IFF_UP` が立った
+    Up,
+    /// `IFF_UP` が落ちた
+    Down,
+    /// 名前変更などその他の属性変化
+    Changed,
+}
+
+/// rtnetlink から受け取ったリンクイベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkEvent {
+    pub change: LinkChange,
+    pub ifindex: u32,
+    pub name: String,
+    pub is_up: bool,
+}
+
+/// `NETLINK_ROUTE` ソケットを `RTMGRP_LINK` にバインドしたモニタ
+pub struct LinkMonitor {
+    fd: AsyncFd<OwnedFd>,
+    buf: Vec<u8>,
+}
+
+impl LinkMonitor {
+    pub fn new() -> io::Result<Self> {
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = libc::RTMGRP_LINK as u32;
+        let rc = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+            buf: vec![0u8; RECV_BUFFER_SIZE],
+        })
+    }
+
+    /// 次のデータグラムを受信し、含まれるリンクイベントを返す。
+    /// ソケットバッファ溢れ（`ENOBUFS`）はイベント取りこぼしを意味するため、
+    /// 呼び出し側は全体の再同期を行うこと。
+    pub async fn recv(&mut self) -> io::Result<Vec<LinkEvent>> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let buf = &mut self.buf;
+            let result = guard.try_io(|inner| {
+                let n = unsafe {
+                    libc::recv(
+                        inner.as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                        0,
+                    )
+                };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+            match result {
+                Ok(Ok(n)) => return Ok(parse_link_messages(&self.buf[..n])),
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// `ENOBUFS` かどうか（イベントを取りこぼした可能性がある）
+pub fn is_overrun(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ENOBUFS)
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset + 2)?;
+    Some(u16::from_ne_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// netlink データグラムから `RTM_NEWLINK` / `RTM_DELLINK` を取り出す。
+pub fn parse_link_messages(buf: &[u8]) -> Vec<LinkEvent> {
+    let mut events = Vec::new();
+    let mut offset = 0usize;
+
+    while offset + NLMSG_HDR_LEN <= buf.len() {
+        let Some(msg_len) = read_u32(buf, offset).map(|v| v as usize) else {
+            break;
+        };
+        let Some(msg_type) = read_u16(buf, offset + 4) else {
+            break;
+        };
+        if msg_len < NLMSG_HDR_LEN || offset + msg_len > buf.len() {
+            break;
+        }
+
+        let payload = &buf[offset + NLMSG_HDR_LEN..offset + msg_len];
+        if msg_type == libc::RTM_NEWLINK || msg_type == libc::RTM_DELLINK {
+            if let Some(event) = parse_ifinfo(msg_type, payload) {
+                events.push(event);
+            }
+        }
+
+        offset += align4(msg_len);
+    }
+
+    events
+}
+
+fn parse_ifinfo(msg_type: u16, payload: &[u8]) -> Option<LinkEvent> {
+    if payload.len() < IFINFOMSG_LEN {
+        return None;
+    }
+    let ifindex = read_u32(payload, 4)?;
+    let flags = read_u32(payload, 8)?;
+    let change_mask = read_u32(payload, 12)?;
+
+    let mut name = None;
+    let mut offset = IFINFOMSG_LEN;
+    while offset + RTATTR_HDR_LEN <= payload.len() {
+        let attr_len = read_u16(payload, offset)? as usize;
+        let attr_type = read_u16(payload, offset + 2)?;
+        if attr_len < RTATTR_HDR_LEN || offset + attr_len > payload.len() {
+            break;
+        }
+        if attr_type == libc::IFLA_IFNAME {
+            let value = &payload[offset + RTATTR_HDR_LEN..offset + attr_len];
+            let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+            name = Some(String::from_utf8_lossy(&value[..end]).into_owned());
+        }
+        offset += align4(attr_len);
+    }
+
+    let is_up = flags & libc::IFF_UP as u32 != 0;
+    let change = if msg_type == libc::RTM_DELLINK {
+        LinkChange::Removed
+    } else if change_mask == u32::MAX {
+        LinkChange::Added
+    } else if change_mask & libc::IFF_UP as u32 != 0 {
+        if is_up {
+            LinkChange::Up
+        } else {
+            LinkChange::Down
+        }
+    } else {
+        LinkChange::Changed
+    };
+
+    Some(LinkEvent {
+        change,
+        ifindex,
+        name: name?,
+        is_up,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_message(msg_type: u16, ifindex: u32, flags: u32, change: u32, name: &str) -> Vec<u8> {
+        let mut attr = Vec::new();
+        let name_len = name.len() + 1;
+        attr.extend_from_slice(&((RTATTR_HDR_LEN + name_len) as u16).to_ne_bytes());
+        attr.extend_from_slice(&libc::IFLA_IFNAME.to_ne_bytes());
+        attr.extend_from_slice(name.as_bytes());
+        attr.push(0);
+        attr.resize(align4(attr.len()), 0);
+
+        let msg_len = NLMSG_HDR_LEN + IFINFOMSG_LEN + attr.len();
+        let mut msg = Vec::with_capacity(msg_len);
+        msg.extend_from_slice(&(msg_len as u32).to_ne_bytes());
+        msg.extend_from_slice(&msg_type.to_ne_bytes());
+        msg.extend_from_slice(&0u16.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        // ifinfomsg: family, pad, type, index, flags, change
+        msg.push(libc::AF_UNSPEC as u8);
+        msg.push(0);
+        msg.extend_from_slice(&0u16.to_ne_bytes());
+        msg.extend_from_slice(&ifindex.to_ne_bytes());
+        msg.extend_from_slice(&flags.to_ne_bytes());
+        msg.extend_from_slice(&change.to_ne_bytes());
+        msg.extend_from_slice(&attr);
+        msg
+    }
+
+    #[test]
+    fn parses_new_link_as_added() {
+        let buf = link_message(libc::RTM_NEWLINK, 7, libc::IFF_UP as u32, u32::MAX, "veth1");
+        let events = parse_link_messages(&buf);
+        assert_eq!(
+            events,
+            vec![LinkEvent {
+                change: LinkChange::Added,
+                ifindex: 7,
+                name: "veth1".to_string(),
+                is_up: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_up_down_and_removed() {
+        let mut buf = link_message(libc::RTM_NEWLINK, 3, 0, libc::IFF_UP as u32, "eth0");
+        buf.extend(link_message(
+            libc::RTM_NEWLINK,
+            3,
+            libc::IFF_UP as u32,
+            libc::IFF_UP as u32,
+            "eth0",
+        ));
+        buf.extend(link_message(libc::RTM_DELLINK, 3, 0, 0, "eth0"));
+
+        let changes: Vec<LinkChange> = parse_link_messages(&buf)
+            .into_iter()
+            .map(|e| e.change)
+            .collect();
+        assert_eq!(
+            changes,
+            vec![LinkChange::Down, LinkChange::Up, LinkChange::Removed]
+        );
+    }
+
+    #[test]
+    fn attribute_only_update_is_reported_as_changed() {
+        let buf = link_message(libc::RTM_NEWLINK, 9, libc::IFF_UP as u32, 0, "renamed0");
+        let events = parse_link_messages(&buf);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].change, LinkChange::Changed);
+        assert_eq!(events[0].name, "renamed0");
+    }
+
+    #[test]
+    fn truncated_message_is_ignored() {
+        let buf = link_message(libc::RTM_NEWLINK, 1, 0, u32::MAX, "lo");
+        assert!(parse_link_messages(&buf[..buf.len() - 4]).is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+//! network namespace の列挙と、指定した netns 内での処理実行。
+//!
+//! netns 内のインターフェースは `"<netns>/<ifname>"` の修飾名で扱う。
+//! インターフェース名にも netns 名（ファイル名）にも `/` は使えないため一意に分解できる。
+
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::types::NetnsInfo;
+
+/// ホスト（キャプチャプロセス自身）の netns を表す ID
+pub const HOST_NETNS_ID: &str = "host";
+const HOST_NETNS_PATH: &str = "/proc/self/ns/net";
+/// `ip netns add` で作られる名前付き netns の置き場所
+const NAMED_NETNS_DIR: &str = "/var/run/netns";
+const PROC_DIR: &str = "/proc";
+const PID_NETNS_PREFIX: &str = "pid:";
+
+/// netns とインターフェース名から修飾名を作る。ホストの場合は素のインターフェース名を返す。
+pub fn qualify_interface(netns: Option<&str>, name: &str) -> String {
+    match netns {
+        Some(netns) if netns != HOST_NETNS_ID => format!("{}/{}", netns, name),
+        _ => name.to_string(),
+    }
+}
+
+/// 修飾名を (netns, インターフェース名) に分解する。ホストの場合 netns は `None`。
+pub fn split_qualified(name: &str) -> (Option<&str>, &str) {
+    match name.split_once('/') {
+        Some((netns, iface)) if netns != HOST_NETNS_ID => (Some(netns), iface),
+        Some((_, iface)) => (None, iface),
+        None => (None, name),
+    }
+}
+
+/// ホストと、名前付き netns・プロセスが使用中の netns を inode で重複排除して列挙する。
+pub fn discover() -> Vec<NetnsInfo> {
+    discover_in(Path::new(NAMED_NETNS_DIR), Path::new(PROC_DIR))
+}
+
+pub(crate) fn discover_in(named_dir: &Path, proc_dir: &Path) -> Vec<NetnsInfo> {
+    let mut seen = HashSet::new();
+    let mut namespaces = Vec::new();
+
+    if let Some(inode) = inode_of(Path::new(HOST_NETNS_PATH)) {
+        seen.insert(inode);
+        namespaces.push(NetnsInfo {
+            id: HOST_NETNS_ID.to_string(),
+            inode,
+            path: HOST_NETNS_PATH.to_string(),
+            pid: None,
+            command: None,
+        });
+    }
+
+    let mut named: Vec<(String, PathBuf)> = std::fs::read_dir(named_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| (e.file_name().to_string_lossy().into_owned(), e.path()))
+        .collect();
+    named.sort();
+    for (id, path) in named {
+        let Some(inode) = inode_of(&path) else {
+            continue;
+        };
+        if seen.insert(inode) {
+            namespaces.push(NetnsInfo {
+                id,
+                inode,
+                path: path.to_string_lossy().into_owned(),
+                pid: None,
+                command: None,
+            });
+        }
+    }
+
+    let mut pids: Vec<u32> = std::fs::read_dir(proc_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str()?.parse().ok())
+        .collect();
+    pids.sort_unstable();
+    for pid in pids {
+        let path = proc_dir.join(pid.to_string()).join("ns/net");
+        let Some(inode) = inode_of(&path) else {
+            continue;
+        };
+        if seen.insert(inode) {
+            let command = std::fs::read_to_string(proc_dir.join(pid.to_string()).join("comm"))
+                .ok()
+                .map(|s| s.trim().to_string());
+            namespaces.push(NetnsInfo {
+                id: format!("{}{}", PID_NETNS_PREFIX, pid),
+                inode,
+                path: path.to_string_lossy().into_owned(),
+                pid: Some(pid),
+                command,
+            });
+        }
+    }
+
+    namespaces
+}
+
+/// netns ID から nsfs のパスを解決する。ホストの場合は `None`（setns 不要）。
+pub fn resolve_path(id: &str) -> io::Result<Option<PathBuf>> {
+    if id == HOST_NETNS_ID {
+        return Ok(None);
+    }
+    let path = match id.strip_prefix(PID_NETNS_PREFIX) {
+        Some(pid) => {
+            let pid: u32 = pid.parse().map_err(|_| not_found(id))?;
+            Path::new(PROC_DIR).join(pid.to_string()).join("ns/net")
+        }
+        None if !id.is_empty() && !id.contains('/') && id != "." && id != ".." => {
+            Path::new(NAMED_NETNS_DIR).join(id)
+        }
+        None => return Err(not_found(id)),
+    };
+    if inode_of(&path).is_none() {
+        return Err(not_found(id));
+    }
+    Ok(Some(path))
+}
+
+/// netns の inode 番号（BPF 側の `net->ns.inum` と一致する）
+pub fn inode(path: Option<&Path>) -> Option<u32> {
+    let inode = inode_of(path.unwrap_or(Path::new(HOST_NETNS_PATH)))?;
+    u32::try_from(inode).ok()
+}
+
+fn inode_of(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|m| m.ino())
+}
+
+fn not_found(id: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("Network namespace {} not found", id),
+    )
+}
+
+/// `path` の netns に入ったスレッドで `f` を実行する。`None` の場合は現在のスレッドでそのまま実行する。
+/// setns はスレッド単位で作用するため、専用スレッドを使い呼び出し元の netns には影響しない。
+pub fn run_in<T, F>(path: Option<&Path>, f: F) -> io::Result<T>
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    let Some(path) = path else {
+        return Ok(f());
+    };
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let file = std::fs::File::open(path)?;
+                let rc = unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) };
+                if rc != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(f())
+            })
+            .join()
+            .map_err(|_| io::Error::other("netns worker thread panicked"))?
+    })
+}
+
+/// 現在のスレッドの netns にあるインターフェースを (ifindex, 名前) で列挙する
+pub fn list_interfaces() -> io::Result<Vec<(u32, String)>> {
+    let head = unsafe { libc::if_nameindex() };
+    if head.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let mut out = Vec::new();
+    let mut cursor = head;
+    unsafe {
+        while (*cursor).if_index != 0 && !(*cursor).if_name.is_null() {
+            let name = CStr::from_ptr((*cursor).if_name)
+                .to_string_lossy()
+                .into_owned();
+            out.push(((*cursor).if_index, name));
+            cursor = cursor.add(1);
+        }
+        libc::if_freenameindex(head);
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// 現在のスレッドの netns でインターフェース名から ifindex を引く
+pub fn ifindex(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+/// 現在のスレッドの netns でインターフェースの MAC アドレスを取得する
+pub fn hw_addr(name: &str) -> Option<[u8; 6]> {
+    let bytes = name.as_bytes();
+    if bytes.len() >= libc::IFNAMSIZ {
+        return None;
+    }
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if sock < 0 {
+        return None;
+    }
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, &src) in req.ifr_name.iter_mut().zip(bytes) {
+        *dst = src as libc::c_char;
+    }
+    let rc = unsafe { libc::ioctl(sock, libc::SIOCGIFHWADDR, &mut req) };
+    unsafe { libc::close(sock) };
+    if rc != 0 {
+        return None;
+    }
+    let data = unsafe { req.ifr_ifru.ifru_hwaddr.sa_data };
+    let mut mac = [0u8; 6];
+    for (dst, &src) in mac.iter_mut().zip(data.iter()) {
+        *dst = src as u8;
+    }
+    Some(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qualify_and_split_round_trip() {
+        assert_eq!(qualify_interface(None, "eth0"), "eth0");
+        assert_eq!(qualify_interface(Some(HOST_NETNS_ID), "eth0"), "eth0");
+        assert_eq!(qualify_interface(Some("cni-1"), "eth0"), "cni-1/eth0");
+
+        assert_eq!(split_qualified("eth0"), (None, "eth0"));
+        assert_eq!(split_qualified("cni-1/eth0"), (Some("cni-1"), "eth0"));
+        assert_eq!(split_qualified("pid:42/eth0"), (Some("pid:42"), "eth0"));
+        assert_eq!(split_qualified("host/lo"), (None, "lo"));
+    }
+
+    #[test]
+    fn discover_lists_host_first_and_dedups_own_process() {
+        let namespaces = discover();
+        assert_eq!(namespaces[0].id, HOST_NETNS_ID);
+        let own = format!("{}{}", PID_NETNS_PREFIX, std::process::id());
+        assert!(namespaces.iter().all(|ns| ns.id != own));
+
+        let mut inodes: Vec<u64> = namespaces.iter().map(|ns| ns.inode).collect();
+        inodes.sort_unstable();
+        inodes.dedup();
+        assert_eq!(inodes.len(), namespaces.len());
+    }
+
+    #[test]
+    fn discover_reads_named_namespaces_from_directory() {
+        let dir = std::env::temp_dir().join(format!("scrop-netns-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("blue"), b"").unwrap();
+        std::fs::write(dir.join("red"), b"").unwrap();
+
+        let namespaces = discover_in(&dir, Path::new("/nonexistent"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let ids: Vec<&str> = namespaces.iter().map(|ns| ns.id.as_str()).collect();
+        assert_eq!(ids, vec![HOST_NETNS_ID, "blue", "red"]);
+    }
+
+    #[test]
+    fn resolve_path_rejects_unknown_and_path_like_ids() {
+        assert!(resolve_path(HOST_NETNS_ID).unwrap().is_none());
+        assert!(resolve_path("..").is_err());
+        assert!(resolve_path("pid:notanumber").is_err());
+        assert!(resolve_path("scrop-test-missing-netns").is_err());
+        let own = format!("{}{}", PID_NETNS_PREFIX, std::process::id());
+        assert!(resolve_path(&own).unwrap().is_some());
+    }
+
+    #[test]
+    fn loopback_is_listed_with_ifindex() {
+        let interfaces = list_interfaces().unwrap();
+        let lo = interfaces.iter().find(|(_, name)| name == "lo").unwrap();
+        assert_eq!(ifindex("lo"), Some(lo.0));
+        assert_eq!(ifindex("scrop-missing0"), None);
+    }
+}
@@ -0,0 +1,264 @@
+//! WebSocket クライアントごとの間引きとレート制限。
+
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{CapturedPacket, PacketResult};
+
+/// 配信モード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeliveryMode {
+    /// すべて配信する
+    #[default]
+    Full,
+    /// N 個に 1 個だけ配信する
+    Sample { one_in: u32 },
+    /// トークンバケットで毎秒のパケット数を制限する（バースト上限も同じ値）
+    RateLimit { max_packets_per_sec: u32 },
+    /// ドロップはすべて配信し、配送済みは N 個に 1 個だけ配信する
+    DropsOnly { delivered_one_in: u32 },
+}
+
+impl DeliveryMode {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Self::Sample { one_in: 0 } => Err("sample rate must be at least 1".to_string()),
+            Self::RateLimit {
+                max_packets_per_sec: 0,
+            } => Err("rate limit must be at least 1 packet/sec".to_string()),
+            Self::DropsOnly {
+                delivered_one_in: 0,
+            } => Err("delivered sample rate must be at least 1".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 間引かれて送られなかったパケット数（結果別）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuppressedCounts {
+    pub delivered: u64,
+    pub nic_drop: u64,
+    pub fw_drop: u64,
+}
+
+impl SuppressedCounts {
+    pub fn total(&self) -> u64 {
+        self.delivered + self.nic_drop + self.fw_drop
+    }
+
+    fn add(&mut self, result: &PacketResult) {
+        match result {
+            PacketResult::Delivered => self.delivered += 1,
+            PacketResult::NicDrop => self.nic_drop += 1,
+            PacketResult::FwDrop => self.fw_drop += 1,
+        }
+    }
+}
+
+/// 接続ごとの間引き状態。間引いた数は次に送るバッチで報告するまで累積する。
+#[derive(Debug)]
+pub struct Sampler {
+    mode: DeliveryMode,
+    seen: u64,
+    tokens: f64,
+    last_refill: Instant,
+    suppressed: SuppressedCounts,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new(DeliveryMode::Full)
+    }
+}
+
+impl Sampler {
+    pub fn new(mode: DeliveryMode) -> Self {
+        let mut sampler = Self {
+            mode,
+            seen: 0,
+            tokens: 0.0,
+            last_refill: Instant::now(),
+            suppressed: SuppressedCounts::default(),
+        };
+        sampler.set_mode(mode);
+        sampler
+    }
+
+    pub fn mode(&self) -> DeliveryMode {
+        self.mode
+    }
+
+    /// モードを切り替える。未報告の間引き数は引き継ぐ。
+    pub fn set_mode(&mut self, mode: DeliveryMode) {
+        self.mode = mode;
+        self.seen = 0;
+        self.last_refill = Instant::now();
+        self.tokens = match mode {
+            DeliveryMode::RateLimit {
+                max_packets_per_sec,
+            } => f64::from(max_packets_per_sec),
+            _ => 0.0,
+        };
+    }
+
+    /// 配信するパケットだけを返す。間引いた分は内部に累積する。
+    pub fn apply(&mut self, packets: Vec<CapturedPacket>, now: Instant) -> Vec<CapturedPacket> {
+        if let DeliveryMode::RateLimit {
+            max_packets_per_sec,
+        } = self.mode
+        {
+            let rate = f64::from(max_packets_per_sec);
+            let elapsed = now
+                .saturating_duration_since(self.last_refill)
+                .as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(rate);
+            self.last_refill = now;
+        }
+
+        let mut kept = Vec::with_capacity(packets.len());
+        for captured in packets {
+            if self.admit(&captured.result) {
+                kept.push(captured);
+            } else {
+                self.suppressed.add(&captured.result);
+            }
+        }
+        kept
+    }
+
+    fn admit(&mut self, result: &PacketResult) -> bool {
+        match self.mode {
+            DeliveryMode::Full => true,
+            DeliveryMode::Sample { one_in } => self.next_in(one_in),
+            DeliveryMode::RateLimit { .. } => {
+                if self.tokens >= 1.0 {
+                    self.tokens -= 1.0;
+                    true
+                } else {
+                    false
+                }
+            }
+            DeliveryMode::DropsOnly { delivered_one_in } => {
+                !matches!(result, PacketResult::Delivered) || self.next_in(delivered_one_in)
+            }
+        }
+    }
+
+    fn next_in(&mut self, one_in: u32) -> bool {
+        let admit = self.seen.is_multiple_of(u64::from(one_in.max(1)));
+        self.seen += 1;
+        admit
+    }
+
+    /// 累積した間引き数を取り出してリセットする
+    pub fn take_suppressed(&mut self) -> SuppressedCounts {
+        std::mem::take(&mut self.suppressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::types::AnimatingPacket;
+
+    fn packets(results: &[PacketResult]) -> Vec<CapturedPacket> {
+        results
+            .iter()
+            .enumerate()
+            .map(|(i, result)| CapturedPacket {
+                packet: AnimatingPacket::generate("abc123", i as u64),
+                result: result.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_mode_keeps_everything() {
+        let mut sampler = Sampler::default();
+        let kept = sampler.apply(packets(&vec![PacketResult::Delivered; 5]), Instant::now());
+        assert_eq!(kept.len(), 5);
+        assert_eq!(sampler.take_suppressed().total(), 0);
+    }
+
+    #[test]
+    fn sample_mode_keeps_one_in_n_across_batches() {
+        let mut sampler = Sampler::new(DeliveryMode::Sample { one_in: 3 });
+        let now = Instant::now();
+        let first = sampler.apply(packets(&vec![PacketResult::Delivered; 4]), now);
+        let second = sampler.apply(packets(&vec![PacketResult::FwDrop; 5]), now);
+        assert_eq!(first.len() + second.len(), 3);
+        assert_eq!(
+            sampler.take_suppressed(),
+            SuppressedCounts {
+                delivered: 2,
+                nic_drop: 0,
+                fw_drop: 4,
+            }
+        );
+        assert_eq!(sampler.take_suppressed().total(), 0);
+    }
+
+    #[test]
+    fn rate_limit_refills_tokens_over_time() {
+        let mut sampler = Sampler::new(DeliveryMode::RateLimit {
+            max_packets_per_sec: 10,
+        });
+        let start = Instant::now();
+        let kept = sampler.apply(packets(&vec![PacketResult::Delivered; 25]), start);
+        assert_eq!(kept.len(), 10);
+        assert_eq!(sampler.take_suppressed().delivered, 15);
+
+        let kept = sampler.apply(
+            packets(&vec![PacketResult::Delivered; 25]),
+            start + Duration::from_millis(500),
+        );
+        assert_eq!(kept.len(), 5);
+
+        // バースト上限を超えては貯まらない
+        let kept = sampler.apply(
+            packets(&vec![PacketResult::Delivered; 25]),
+            start + Duration::from_secs(10),
+        );
+        assert_eq!(kept.len(), 10);
+    }
+
+    #[test]
+    fn drops_only_keeps_all_drops_and_samples_delivered() {
+        let mut sampler = Sampler::new(DeliveryMode::DropsOnly {
+            delivered_one_in: 4,
+        });
+        let mut results = vec![PacketResult::Delivered; 8];
+        results.push(PacketResult::NicDrop);
+        results.push(PacketResult::FwDrop);
+        let kept = sampler.apply(packets(&results), Instant::now());
+        assert_eq!(kept.len(), 4);
+        assert_eq!(
+            kept.iter()
+                .filter(|p| !matches!(p.result, PacketResult::Delivered))
+                .count(),
+            2
+        );
+        assert_eq!(sampler.take_suppressed().delivered, 6);
+    }
+
+    #[test]
+    fn zero_rates_are_rejected() {
+        assert!(DeliveryMode::Sample { one_in: 0 }.validate().is_err());
+        assert!(DeliveryMode::RateLimit {
+            max_packets_per_sec: 0
+        }
+        .validate()
+        .is_err());
+        assert!(DeliveryMode::DropsOnly {
+            delivered_one_in: 0
+        }
+        .validate()
+        .is_err());
+        assert!(DeliveryMode::Full.validate().is_ok());
+    }
+}
@@ -1,6 +1,8 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum PacketResult {
     Delivered,
@@ -23,7 +25,7 @@ pub struct CapturedPacketEnvelope {
 }
 
 /// L4プロトコル（パケットヘッダに含まれる情報）
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum Protocol {
     Tcp,
@@ -79,6 +81,15 @@ pub fn build_packet_id(session_id: &str, counter: u64) -> String {
     format!("pkt-{}-{}", session_id, counter)
 }
 
+/// `build_packet_id` の逆変換。形式が異なる ID は `None`。
+pub fn parse_packet_id(id: &str) -> Option<(&str, u64)> {
+    let (session_id, counter) = id.strip_prefix("pkt-")?.split_once('-')?;
+    if session_id.is_empty() || !counter.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((session_id, counter.parse().ok()?))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AnimatingPacket {
@@ -93,6 +104,81 @@ pub struct AnimatingPacket {
     pub capture_mono_ns: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reason: Option<String>,
+    /// 受信したインターフェース名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// 受信インターフェースが属する netns の ID（ホストの場合は `None`）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netns: Option<String>,
+    /// 送信元のコンテナ / Pod 情報（エンリッチメント有効時）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_meta: Option<EndpointMetadata>,
+    /// 宛先のコンテナ / Pod 情報（エンリッチメント有効時）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_meta: Option<EndpointMetadata>,
+    /// 受信ソケットを所有するプロセス（プロセス帰属有効時、ソケットに届いたパケットのみ）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process: Option<ProcessInfo>,
+    /// 送信元アドレスのホスト名（名前解決有効時）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_name: Option<String>,
+    /// 宛先アドレスのホスト名（名前解決有効時）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_name: Option<String>,
+    /// ポートから引いたサービス名（`/etc/services`）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// 送信元アドレスの国・AS（GeoIP 有効時）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_geo: Option<GeoInfo>,
+    /// 宛先アドレスの国・AS（GeoIP 有効時）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_geo: Option<GeoInfo>,
+}
+
+/// アドレスの国と AS（ローカルの MMDB から引く）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 の国コード
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asn: Option<u32>,
+    /// AS の組織名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub as_org: Option<String>,
+}
+
+/// パケットを受信したソケットの所有プロセス
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub comm: String,
+    /// cgroup v2 の cgroup ID（cgroupfs 上のディレクトリの inode 番号）
+    pub cgroup_id: u64,
+}
+
+/// エンドポイント（IP・netns）に対応するコンテナ / Pod / cgroup の情報
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod_uid: Option<String>,
+    /// Kubernetes の namespace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
 impl AnimatingPacket {
@@ -117,6 +203,16 @@ impl AnimatingPacket {
             target_port: None,
             capture_mono_ns: monotonic_now_ns(),
             reason: None,
+            interface: None,
+            netns: None,
+            source_meta: None,
+            destination_meta: None,
+            process: None,
+            source_name: None,
+            destination_name: None,
+            service: None,
+            source_geo: None,
+            destination_geo: None,
         }
     }
 
@@ -126,6 +222,79 @@ impl AnimatingPacket {
     }
 }
 
+/// キャプチャ対象として選べる network namespace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetnsInfo {
+    /// `host`、名前付き netns の名前、または `pid:<pid>`
+    pub id: String,
+    pub inode: u64,
+    pub path: String,
+    /// プロセス由来の netns の場合、代表プロセスの PID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+/// インターフェースの変化の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InterfaceEventKind {
+    Added,
+    Removed,
+    Renamed,
+    Up,
+    Down,
+    Attached,
+    Detached,
+    AttachFailed,
+}
+
+/// インターフェースの追加・削除・アタッチ状態の変化をクライアントへ通知するイベント。
+/// 変化後のインターフェース一覧とアタッチ中の一覧を併せて持つ。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterfaceEvent {
+    pub kind: InterfaceEventKind,
+    pub interface: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub interfaces: Vec<String>,
+    pub attached: Vec<String>,
+}
+
+/// キャプチャ状態の変化の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureStateKind {
+    Started,
+    Stopped,
+    Reset,
+    /// キャプチャタスクが致命的なエラーで終了した
+    Failed,
+}
+
+/// キャプチャの開始・停止などの状態変化をクライアントへ通知するイベント
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureStateEvent {
+    pub kind: CaptureStateKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 定期的にクライアントへ送るキャプチャ状態と統計
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusSnapshot {
+    pub is_capturing: bool,
+    pub mode: String,
+    pub stats: CaptureStats,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CaptureStats {
@@ -160,6 +329,12 @@ pub struct CaptureStats {
     pub status_lock_wait_samples: u64,
     pub status_lock_hold_ns: u64,
     pub status_lock_hold_samples: u64,
+    /// 送信元（不明なら宛先）の国コードごとのパケット数（GeoIP 有効時）
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub packets_by_country: BTreeMap<String, u64>,
+    /// 送信元（不明なら宛先）の AS 番号ごとのパケット数（GeoIP 有効時）
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub packets_by_asn: BTreeMap<u32, u64>,
 }
 
 #[cfg(test)]
@@ -243,6 +418,20 @@ mod tests {
         assert!(json.contains("\"reason\":\"blocked\""));
     }
 
+    #[test]
+    fn animating_packet_includes_interface_and_netns_only_when_known() {
+        let mut pkt = AnimatingPacket::generate("abc123", 0);
+        let json = serde_json::to_string(&pkt).unwrap();
+        assert!(!json.contains("\"interface\""));
+        assert!(!json.contains("\"netns\""));
+
+        pkt.interface = Some("eth0".to_string());
+        pkt.netns = Some("cni-1".to_string());
+        let json = serde_json::to_string(&pkt).unwrap();
+        assert!(json.contains("\"interface\":\"eth0\""));
+        assert!(json.contains("\"netns\":\"cni-1\""));
+    }
+
     #[test]
     fn generate_session_id_is_base36_with_fixed_length() {
         let session_id = generate_session_id();
@@ -256,6 +445,16 @@ mod tests {
     fn build_packet_id_uses_expected_format() {
         let id = build_packet_id("a1b2c3", 42);
         assert_eq!(id, "pkt-a1b2c3-42");
+        assert_eq!(parse_packet_id(&id), Some(("a1b2c3", 42)));
+    }
+
+    #[test]
+    fn parse_packet_id_rejects_other_formats() {
+        assert_eq!(parse_packet_id("pkt-a1b2c3-"), None);
+        assert_eq!(parse_packet_id("pkt--1"), None);
+        assert_eq!(parse_packet_id("pkt-a1b2c3-+1"), None);
+        assert_eq!(parse_packet_id("pkt-a1-b2-3"), None);
+        assert_eq!(parse_packet_id("flow-a1b2c3-1"), None);
     }
 
     #[test]
@@ -293,6 +492,22 @@ mod tests {
         assert_eq!(stats.status_lock_hold_samples, 0);
     }
 
+    #[test]
+    fn interface_event_serializes_to_camel_case() {
+        let event = InterfaceEvent {
+            kind: InterfaceEventKind::AttachFailed,
+            interface: "veth1".to_string(),
+            previous_name: None,
+            error: Some("boom".to_string()),
+            interfaces: vec!["lo".to_string()],
+            attached: vec![],
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains("\"kind\":\"attach-failed\""));
+        assert!(json.contains("\"attached\":[]"));
+        assert!(!json.contains("\"previousName\""));
+    }
+
     #[test]
     fn captured_packet_envelope_serializes_to_camel_case() {
         let envelope = CapturedPacketEnvelope {
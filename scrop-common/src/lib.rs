@@ -3,6 +3,8 @@
 // アクション定数
 pub const ACTION_XDP_PASS: u32 = 2;
 pub const ACTION_KFREE_SKB: u32 = 100;
+/// パケットがソケットの受信キューに渡された（プロセス帰属用、パケット結果には数えない）
+pub const ACTION_SOCK_RCV: u32 = 101;
 
 /// eBPFプログラムからユーザースペースへ送るパケット情報。
 /// `#[repr(C)]`でメモリレイアウトを固定し、eBPF側とユーザースペース側で安全に共有する。
@@ -28,6 +30,27 @@ pub struct PacketEvent {
     pub action: u32,
     /// skb_drop_reason（0 = ドロップなし、kfree_skb時のみ有効）
     pub drop_reason: u32,
+    /// 受信インターフェースの ifindex（XDP: ingress_ifindex, kfree_skb: skb_iif）
+    pub ifindex: u32,
     /// monotonic 時刻（ns）
     pub ktime_ns: u64,
+    /// 受信デバイスが属する network namespace の inode 番号（0 = 不明、XDP では常に 0）
+    pub netns_ino: u32,
+    /// 宛先 MAC アドレス（XDP時のみ有効）。同じ ifindex が複数 netns にある場合の識別に使う
+    pub dst_mac: [u8; 6],
+    /// アラインメント用パディング
+    pub _padding2: [u8; 6],
+    /// 受信ソケットを所有するプロセスの cgroup ID（0 = 不明）
+    pub cgroup_id: u64,
+    /// 受信ソケットを所有するプロセスの PID（tgid、0 = 不明）
+    pub pid: u32,
+    /// 受信ソケットを所有するプロセスの comm（NUL 終端）
+    pub comm: [u8; 16],
+    /// アラインメント用パディング
+    pub _padding3: [u8; 4],
+}
+
+/// `MONITORED_IFS` のキー。ifindex は netns ごとに独立しているため inode と組で引く。
+pub const fn monitored_if_key(netns_ino: u32, ifindex: u32) -> u64 {
+    ((netns_ino as u64) << 32) | ifindex as u64
 }
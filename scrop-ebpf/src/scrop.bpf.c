@@ -1,10 +1,12 @@
 // SPDX-License-Identifier: GPL-2.0
 // scrop eBPF programs: XDP packet monitor + kfree_skb tracepoint
+//                      + optional socket receive kprobes (process attribution)
 
 #include "vmlinux.h"
 #include <bpf/bpf_helpers.h>
 #include <bpf/bpf_core_read.h>
 #include <bpf/bpf_endian.h>
+#include <bpf/bpf_tracing.h>
 
 // ---------------------------------------------------------------------------
 // Constants (must match scrop-common/src/lib.rs)
@@ -12,6 +14,7 @@
 
 #define ACTION_XDP_PASS   2
 #define ACTION_KFREE_SKB  100
+#define ACTION_SOCK_RCV   101
 
 #define ETH_P_IP  0x0800
 #define IPPROTO_TCP  6
@@ -31,7 +34,22 @@ struct packet_event {
     __u32 pkt_len;
     __u32 action;
     __u32 drop_reason;
+    __u32 ifindex;
     __u64 ktime_ns;
+    __u32 netns_ino;
+    __u8  dst_mac[6];
+    __u8  _padding2[6];
+    __u64 cgroup_id;
+    __u32 pid;
+    char  comm[16];
+    __u8  _padding3[4];
+};
+
+// Last process seen using a socket (see record_sock_owner)
+struct sock_owner {
+    __u64 cgroup_id;
+    __u32 pid;
+    char  comm[16];
 };
 
 // ---------------------------------------------------------------------------
@@ -50,13 +68,22 @@ struct {
     __type(value, __u64);
 } RINGBUF_DROPS SEC(".maps");
 
+// key: (netns inode << 32) | ifindex — see scrop_common::monitored_if_key
 struct {
     __uint(type, BPF_MAP_TYPE_HASH);
-    __type(key, __u32);
+    __type(key, __u64);
     __type(value, __u32);
-    __uint(max_entries, 32);
+    __uint(max_entries, 64);
 } MONITORED_IFS SEC(".maps");
 
+// key: struct sock * — LRU so that closed sockets age out without a hook
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __type(key, __u64);
+    __type(value, struct sock_owner);
+    __uint(max_entries, 16384);
+} SOCK_OWNERS SEC(".maps");
+
 static __always_inline void emit_event(void *ctx, const struct packet_event *event)
 {
     long rc = bpf_ringbuf_output(&EVENTS, event, sizeof(*event), 0);
@@ -170,13 +197,99 @@ int scrop_xdp(struct xdp_md *ctx)
     event.pkt_len     = bpf_ntohs(iph->tot_len);
     event.action      = ACTION_XDP_PASS;
     event.drop_reason = 0;
+    event.ifindex     = ctx->ingress_ifindex;
     event.ktime_ns    = bpf_ktime_get_ns();
+    // netns is not readable from XDP; user space resolves it from ifindex + dst MAC
+    __builtin_memcpy(event.dst_mac, eth->h_dest, sizeof(event.dst_mac));
 
     emit_event(ctx, &event);
 
     return XDP_PASS;
 }
 
+// ---------------------------------------------------------------------------
+// sk_buff helpers (kfree_skb / socket receive)
+// ---------------------------------------------------------------------------
+
+// Fill the 5-tuple and receive device of an IPv4 TCP/UDP skb.
+// Returns 0 only when the skb arrived on a monitored interface.
+// pkt_len is the IP total length, the same as the XDP event reports.
+static __always_inline int read_skb_packet(struct sk_buff *skb, struct packet_event *event)
+{
+    // ifindex is only unique within a netns, so the key includes the
+    // netns inode of the receiving device (0 when skb->dev is gone).
+    int iif = BPF_CORE_READ(skb, skb_iif);
+    if (iif <= 0)
+        return -1;
+    __u32 netns_ino = 0;
+    struct net_device *dev = BPF_CORE_READ(skb, dev);
+    if (dev)
+        netns_ino = BPF_CORE_READ(dev, nd_net.net, ns.inum);
+    __u64 mon_key = ((__u64)netns_ino << 32) | (__u32)iif;
+    if (!bpf_map_lookup_elem(&MONITORED_IFS, &mon_key))
+        return -1;
+
+    // Read sk_buff fields via CO-RE
+    unsigned char *head = BPF_CORE_READ(skb, head);
+    if (!head)
+        return -1;
+    __u16 network_header    = BPF_CORE_READ(skb, network_header);
+    __u16 transport_header  = BPF_CORE_READ(skb, transport_header);
+
+    // Read IP header from kernel memory
+    struct xdp_iphdr iph;
+    if (bpf_probe_read_kernel(&iph, sizeof(iph), head + network_header) < 0)
+        return -1;
+
+    __u8 proto = iph.protocol;
+    if (proto != IPPROTO_TCP && proto != IPPROTO_UDP)
+        return -1;
+
+    // Read transport header
+    __u16 src_port = 0;
+    __u16 dst_port = 0;
+    void *th_ptr = head + transport_header;
+
+    if (proto == IPPROTO_TCP) {
+        struct xdp_tcphdr tcph;
+        if (bpf_probe_read_kernel(&tcph, sizeof(tcph), th_ptr) < 0)
+            return -1;
+        src_port = bpf_ntohs(tcph.source);
+        dst_port = bpf_ntohs(tcph.dest);
+    } else {
+        struct xdp_udphdr udph;
+        if (bpf_probe_read_kernel(&udph, sizeof(udph), th_ptr) < 0)
+            return -1;
+        src_port = bpf_ntohs(udph.source);
+        dst_port = bpf_ntohs(udph.dest);
+    }
+
+    event->src_addr  = iph.saddr;
+    event->dst_addr  = iph.daddr;
+    event->src_port  = src_port;
+    event->dst_port  = dst_port;
+    event->protocol  = proto;
+    event->pkt_len   = bpf_ntohs(iph.tot_len);
+    event->ifindex   = (__u32)iif;
+    event->ktime_ns  = bpf_ktime_get_ns();
+    event->netns_ino = netns_ino;
+    return 0;
+}
+
+// Copy the recorded owner of sk into the event (no-op when unknown)
+static __always_inline void fill_sock_owner(struct packet_event *event, struct sock *sk)
+{
+    if (!sk)
+        return;
+    __u64 key = (__u64)sk;
+    struct sock_owner *owner = bpf_map_lookup_elem(&SOCK_OWNERS, &key);
+    if (!owner)
+        return;
+    event->cgroup_id = owner->cgroup_id;
+    event->pid       = owner->pid;
+    __builtin_memcpy(event->comm, owner->comm, sizeof(event->comm));
+}
+
 // ---------------------------------------------------------------------------
 // kfree_skb tracepoint
 // ---------------------------------------------------------------------------
@@ -201,74 +314,95 @@ struct kfree_skb_ctx {
 SEC("tracepoint/skb/kfree_skb")
 int scrop_kfree_skb(struct kfree_skb_ctx *ctx)
 {
-    // 1. IPv4 only
+    // IPv4 only
     if (ctx->protocol != ETH_P_IP)
         return 0;
 
-    __u32 reason = ctx->reason;
     struct sk_buff *skb = ctx->skbaddr;
     if (!skb)
         return 0;
 
-    // 2. Check if the interface is monitored
-    int iif = BPF_CORE_READ(skb, skb_iif);
-    if (iif <= 0)
-        return 0;
-    __u32 iif_u32 = (__u32)iif;
-    if (!bpf_map_lookup_elem(&MONITORED_IFS, &iif_u32))
+    struct packet_event event = {};
+    if (read_skb_packet(skb, &event) < 0)
         return 0;
+    // Dropped skbs are matched by their current length
+    event.pkt_len     = BPF_CORE_READ(skb, len);
+    event.action      = ACTION_KFREE_SKB;
+    event.drop_reason = ctx->reason;
+    // Set when the socket layer dropped the packet (e.g. receive buffer full)
+    fill_sock_owner(&event, BPF_CORE_READ(skb, sk));
 
-    // 3. Read sk_buff fields via CO-RE
-    unsigned char *head = BPF_CORE_READ(skb, head);
-    if (!head)
-        return 0;
-    __u16 network_header    = BPF_CORE_READ(skb, network_header);
-    __u16 transport_header  = BPF_CORE_READ(skb, transport_header);
-    __u32 pkt_len           = BPF_CORE_READ(skb, len);
+    emit_event(ctx, &event);
 
-    // 4. Read IP header from kernel memory
-    struct xdp_iphdr iph;
-    if (bpf_probe_read_kernel(&iph, sizeof(iph), head + network_header) < 0)
-        return 0;
+    return 0;
+}
 
-    __u8 proto = iph.protocol;
-    if (proto != IPPROTO_TCP && proto != IPPROTO_UDP)
-        return 0;
+// ---------------------------------------------------------------------------
+// Process attribution (attached only when enabled from user space)
+//
+// Owners are learned in process context from the syscalls that use a socket,
+// and attached to packets in softirq context when they reach the socket.
+// ---------------------------------------------------------------------------
 
-    // 5. Read transport header
-    __u16 src_port = 0;
-    __u16 dst_port = 0;
-    void *th_ptr = head + transport_header;
+static __always_inline void record_sock_owner(struct sock *sk)
+{
+    if (!sk)
+        return;
+    __u64 key = (__u64)sk;
+    struct sock_owner owner = {};
+    owner.cgroup_id = bpf_get_current_cgroup_id();
+    owner.pid       = bpf_get_current_pid_tgid() >> 32;
+    bpf_get_current_comm(owner.comm, sizeof(owner.comm));
+    bpf_map_update_elem(&SOCK_OWNERS, &key, &owner, BPF_ANY);
+}
 
-    if (proto == IPPROTO_TCP) {
-        struct xdp_tcphdr tcph;
-        if (bpf_probe_read_kernel(&tcph, sizeof(tcph), th_ptr) < 0)
-            return 0;
-        src_port = bpf_ntohs(tcph.source);
-        dst_port = bpf_ntohs(tcph.dest);
-    } else {
-        struct xdp_udphdr udph;
-        if (bpf_probe_read_kernel(&udph, sizeof(udph), th_ptr) < 0)
-            return 0;
-        src_port = bpf_ntohs(udph.source);
-        dst_port = bpf_ntohs(udph.dest);
-    }
+SEC("kprobe/sock_recvmsg")
+int BPF_KPROBE(scrop_sock_recvmsg, struct socket *sock)
+{
+    record_sock_owner(BPF_CORE_READ(sock, sk));
+    return 0;
+}
+
+SEC("kprobe/sock_sendmsg")
+int BPF_KPROBE(scrop_sock_sendmsg, struct socket *sock)
+{
+    record_sock_owner(BPF_CORE_READ(sock, sk));
+    return 0;
+}
 
-    // 6. Emit event
+// Listening sockets never see recvmsg; the accepting process owns them.
+SEC("kprobe/inet_csk_accept")
+int BPF_KPROBE(scrop_inet_csk_accept, struct sock *sk)
+{
+    record_sock_owner(sk);
+    return 0;
+}
+
+static __always_inline int emit_sock_rcv(void *ctx, struct sock *sk, struct sk_buff *skb)
+{
     struct packet_event event = {};
-    event.src_addr    = iph.saddr;
-    event.dst_addr    = iph.daddr;
-    event.src_port    = src_port;
-    event.dst_port    = dst_port;
-    event.protocol    = proto;
-    event.pkt_len     = pkt_len;
-    event.action      = ACTION_KFREE_SKB;
-    event.drop_reason = reason;
-    event.ktime_ns    = bpf_ktime_get_ns();
+    if (read_skb_packet(skb, &event) < 0)
+        return 0;
+    fill_sock_owner(&event, sk);
+    if (!event.pid && !event.cgroup_id)
+        return 0;
+    event.action = ACTION_SOCK_RCV;
 
     emit_event(ctx, &event);
 
     return 0;
 }
 
+SEC("kprobe/tcp_v4_do_rcv")
+int BPF_KPROBE(scrop_tcp_v4_do_rcv, struct sock *sk, struct sk_buff *skb)
+{
+    return emit_sock_rcv(ctx, sk, skb);
+}
+
+SEC("kprobe/__udp_enqueue_schedule_skb")
+int BPF_KPROBE(scrop_udp_enqueue, struct sock *sk, struct sk_buff *skb)
+{
+    return emit_sock_rcv(ctx, sk, skb);
+}
+
 char LICENSE[] SEC("license") = "GPL";
@@ -0,0 +1,109 @@
+//! スキーマバージョン 2（PacketBatchEnvelope）と 3（CompactPacketBatch）のエンコード比較。
+//! 実行時にバッチサイズごとのペイロードサイズも表示する。
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use prost::Message as _;
+
+use scrop_capture::types::{AnimatingPacket, CapturedPacket, PacketResult};
+
+#[allow(dead_code)]
+#[path = "../src/ws_proto.rs"]
+mod ws_proto;
+
+use ws_proto::{encode_outbound, Outbound, OutboundBatch, StreamVersion};
+
+const BATCH_SIZES: &[usize] = &[16, 256, 2048];
+
+/// 実際のキャプチャに近い、インターフェース・名前解決・サービス名付きのパケット
+fn sample_packets(count: usize) -> Vec<CapturedPacket> {
+    const INTERFACES: &[&str] = &["eth0", "veth3f2a1c", "wlan0"];
+    const SERVICES: &[&str] = &["https", "dns", "ssh", "http"];
+    (0..count)
+        .map(|i| {
+            let mut packet = AnimatingPacket::generate("a1b2c3", 10_000 + i as u64);
+            packet.capture_mono_ns = 5_000_000_000 + i as u64 * 12_345;
+            packet.interface = Some(INTERFACES[i % INTERFACES.len()].to_string());
+            packet.netns = Some("host".to_string());
+            packet.destination_name = Some(format!("svc-{}.example.internal", i % 8));
+            packet.service = Some(SERVICES[i % SERVICES.len()].to_string());
+            let result = match i % 10 {
+                0 => PacketResult::FwDrop,
+                1 => PacketResult::NicDrop,
+                _ => PacketResult::Delivered,
+            };
+            if result == PacketResult::FwDrop {
+                packet.reason = Some("policy".to_string());
+            }
+            CapturedPacket { packet, result }
+        })
+        .collect()
+}
+
+fn encode(schema: u32, packets: &[CapturedPacket]) -> Vec<u8> {
+    let version = StreamVersion {
+        protocol: ws_proto::PROTOCOL_VERSION,
+        schema,
+    };
+    let batch = OutboundBatch {
+        packets: packets.into(),
+        epoch_offset_ms: 1_700_000_000_000.0,
+        sampled: false,
+        suppressed: Default::default(),
+    };
+    encode_outbound(version, Outbound::Batch(batch)).expect("batch is always encodable")
+}
+
+fn encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_batch");
+    for &size in BATCH_SIZES {
+        let packets = sample_packets(size);
+        let v2 = encode(ws_proto::SCHEMA_VERSION, &packets).len();
+        let v3 = encode(ws_proto::COMPACT_SCHEMA_VERSION, &packets).len();
+        println!(
+            "{} packets: v2 {} bytes ({:.1}/pkt), v3 {} bytes ({:.1}/pkt), {:.0}% smaller",
+            size,
+            v2,
+            v2 as f64 / size as f64,
+            v3,
+            v3 as f64 / size as f64,
+            (1.0 - v3 as f64 / v2 as f64) * 100.0
+        );
+
+        group.throughput(Throughput::Elements(size as u64));
+        for schema in [ws_proto::SCHEMA_VERSION, ws_proto::COMPACT_SCHEMA_VERSION] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("v{}", schema), size),
+                &packets,
+                |b, packets| b.iter(|| encode(schema, black_box(packets))),
+            );
+        }
+    }
+    group.finish();
+}
+
+/// コンパクトスキーマ（v3、フレーム圧縮なし）のバッチの復号コストも v2 と比べる
+fn decoding(c: &mut Criterion) {
+    let packets = sample_packets(256);
+    let v2 = encode(ws_proto::SCHEMA_VERSION, &packets);
+    let v3 = encode(ws_proto::COMPACT_SCHEMA_VERSION, &packets);
+    let mut group = c.benchmark_group("decode_batch");
+    group.throughput(Throughput::Elements(packets.len() as u64));
+    for (schema, payload) in [
+        (ws_proto::SCHEMA_VERSION, &v2),
+        (ws_proto::COMPACT_SCHEMA_VERSION, &v3),
+    ] {
+        group.bench_with_input(
+            BenchmarkId::new(format!("v{}", schema), packets.len()),
+            payload,
+            |b, payload| {
+                b.iter(|| ws_proto::pb::ServerMessage::decode(black_box(payload.as_slice())))
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encoding, decoding);
+criterion_main!(benches);
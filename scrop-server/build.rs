@@ -1,11 +1,19 @@
 fn main() {
     println!("cargo:rerun-if-changed=../proto/packet_stream.proto");
+    println!("cargo:rerun-if-changed=../proto/capture_service.proto");
 
     let protoc_path = protoc_bin_vendored::protoc_bin_path().expect("failed to find protoc");
 
     let mut config = prost_build::Config::new();
     config.protoc_executable(protoc_path);
-    config
-        .compile_protos(&["../proto/packet_stream.proto"], &["../proto"])
+    tonic_prost_build::configure()
+        .compile_with_config(
+            config,
+            &[
+                "../proto/packet_stream.proto",
+                "../proto/capture_service.proto",
+            ],
+            &["../proto"],
+        )
         .expect("failed to compile protobuf schema");
 }
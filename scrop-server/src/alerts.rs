@@ -0,0 +1,269 @@
+//! アラートルールの定期評価と通知先（ログ・Webhook・WebSocket）への配送。
+//!
+//! `event_tx` のバッチを評価間隔ごとに集計して `AlertEngine` で評価し、
+//! 発火・解決したアラートをすべての通知先に送る。状態は `/api/alerts` で返す。
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::body::Bytes;
+use axum::http::{header, Method, Request, Uri};
+use axum::response::Json;
+use axum::Extension;
+use http_body_util::Full;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use scrop_capture::alerts::{Alert, AlertEngine, AlertRule, AlertState, AlertWindow, Evaluation};
+use scrop_capture::AppState;
+
+/// Webhook への送信を諦めるまでの時間
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// アラートの通知先
+pub trait AlertSink: Send + Sync {
+    /// 発火・解決のたびに評価タスクから呼ばれる。時間のかかる送信はタスクを起動して行う。
+    fn notify(&self, alert: &Alert);
+}
+
+/// tracing のログに出す
+pub struct LogSink;
+
+impl AlertSink for LogSink {
+    fn notify(&self, alert: &Alert) {
+        match alert.state {
+            AlertState::Resolved => info!(
+                rule = %alert.rule,
+                labels = ?alert.labels,
+                "alert resolved"
+            ),
+            _ => warn!(
+                rule = %alert.rule,
+                expr = %alert.expr,
+                labels = ?alert.labels,
+                value = alert.value,
+                "alert firing"
+            ),
+        }
+    }
+}
+
+/// WebSocket クライアントへ `Alert` メッセージとして送る（`AppState::alert_tx`）
+pub struct BroadcastSink(pub broadcast::Sender<Alert>);
+
+impl AlertSink for BroadcastSink {
+    fn notify(&self, alert: &Alert) {
+        // 購読者がいなければ送らない
+        let _ = self.0.send(alert.clone());
+    }
+}
+
+/// Webhook の URL。`http://` だけ受け付ける。
+#[derive(Debug, Clone)]
+pub struct WebhookUrl(Uri);
+
+impl FromStr for WebhookUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uri: Uri = s
+            .parse()
+            .map_err(|e| format!("invalid webhook URL {:?}: {}", s, e))?;
+        match uri.scheme_str() {
+            Some("http") if uri.host().is_some() => Ok(Self(uri)),
+            Some("https") => Err(format!(
+                "webhook URL {:?} uses https, which is not supported; use http",
+                s
+            )),
+            _ => Err(format!("invalid webhook URL {:?}: expected http://HOST", s)),
+        }
+    }
+}
+
+impl fmt::Display for WebhookUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// アラートの JSON を 1 件ずつ POST する
+pub struct WebhookSink {
+    url: WebhookUrl,
+    client: Client<HttpConnector, Full<Bytes>>,
+}
+
+impl WebhookSink {
+    pub fn new(url: WebhookUrl) -> Self {
+        Self {
+            url,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn notify(&self, alert: &Alert) {
+        let body = match serde_json::to_vec(alert) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!(error = %e, "failed to encode alert");
+                return;
+            }
+        };
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.0.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .expect("valid webhook request");
+        let client = self.client.clone();
+        let url = self.url.to_string();
+        tokio::spawn(async move {
+            match tokio::time::timeout(WEBHOOK_TIMEOUT, client.request(request)).await {
+                Ok(Ok(response)) if response.status().is_success() => {}
+                Ok(Ok(response)) => {
+                    warn!(url = %url, status = %response.status(), "alert webhook rejected")
+                }
+                Ok(Err(e)) => warn!(url = %url, error = %e, "alert webhook failed"),
+                Err(_) => warn!(url = %url, "alert webhook timed out"),
+            }
+        });
+    }
+}
+
+/// アラートの設定
+pub struct AlertConfig {
+    pub rules: Vec<AlertRule>,
+    pub webhooks: Vec<WebhookUrl>,
+    /// ルールを評価する間隔
+    pub interval: Duration,
+}
+
+/// `/api/alerts` の応答
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertsResponse {
+    pub rules: Vec<RuleResponse>,
+    /// pending と firing のアラート
+    pub active: Vec<Alert>,
+    /// 最近解決したアラート（新しい順）
+    pub resolved: Vec<Alert>,
+}
+
+#[derive(Serialize)]
+pub struct RuleResponse {
+    pub name: String,
+    pub expr: String,
+}
+
+/// ルールの評価状態と通知先
+pub struct AlertManager {
+    engine: Mutex<AlertEngine>,
+    sinks: Vec<Box<dyn AlertSink>>,
+}
+
+impl AlertManager {
+    pub fn new(config: &AlertConfig, state: &AppState) -> Self {
+        let mut sinks: Vec<Box<dyn AlertSink>> = vec![
+            Box::new(LogSink),
+            Box::new(BroadcastSink(state.alert_tx.clone())),
+        ];
+        for url in &config.webhooks {
+            sinks.push(Box::new(WebhookSink::new(url.clone())));
+        }
+        Self {
+            engine: Mutex::new(AlertEngine::new(config.rules.clone())),
+            sinks,
+        }
+    }
+
+    pub fn snapshot(&self) -> AlertsResponse {
+        let engine = self.engine.lock().unwrap();
+        AlertsResponse {
+            rules: engine
+                .rules()
+                .iter()
+                .map(|rule| RuleResponse {
+                    name: rule.name.clone(),
+                    expr: rule.expr.clone(),
+                })
+                .collect(),
+            active: engine.active(),
+            resolved: engine.resolved(),
+        }
+    }
+
+    /// 評価タスクを起動する。ルールがなければ何もしない。
+    pub fn spawn(
+        self: &Arc<Self>,
+        state: Arc<AppState>,
+        interval: Duration,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        if self.engine.lock().unwrap().rules().is_empty() {
+            return None;
+        }
+        let manager = Arc::clone(self);
+        let mut rx = state.event_tx.subscribe();
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+            let mut window = AlertWindow::default();
+            let mut last_eval = Instant::now();
+            loop {
+                tokio::select! {
+                    result = rx.recv() => match result {
+                        Ok(batch) => window.record_batch(&batch.packets),
+                        Err(RecvError::Lagged(n)) => {
+                            warn!(skipped_batches = n, "alert evaluation lagged");
+                            window.record_lag(n);
+                        }
+                        Err(RecvError::Closed) => return,
+                    },
+                    _ = ticker.tick() => {
+                        let needs_ringbuf = manager.engine.lock().unwrap().needs_ringbuf_dropped();
+                        let ringbuf_dropped = if needs_ringbuf {
+                            Some(state.status_snapshot().await.stats.transport_dropped)
+                        } else {
+                            None
+                        };
+                        let now = Instant::now();
+                        let evaluation = Evaluation {
+                            window: &window,
+                            elapsed: now - last_eval,
+                            ringbuf_dropped,
+                            now_ms: now_ms(),
+                        };
+                        last_eval = now;
+                        let events = manager.engine.lock().unwrap().evaluate(&evaluation);
+                        for alert in &events {
+                            for sink in &manager.sinks {
+                                sink.notify(alert);
+                            }
+                        }
+                        window = AlertWindow::default();
+                    }
+                }
+            }
+        }))
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// `/api/alerts` — ルールと未解決・最近解決したアラート
+pub async fn get_alerts(Extension(manager): Extension<Arc<AlertManager>>) -> Json<AlertsResponse> {
+    Json(manager.snapshot())
+}
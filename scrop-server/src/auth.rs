@@ -0,0 +1,386 @@
+//! HTTP API・WebSocket・gRPC の認証と、閲覧者・操作者の権限。
+//!
+//! 資格情報を 1 つも設定しなければ認証は無効で、誰でも操作者として扱う。
+//! 設定すると、参照（GET）には閲覧者、キャプチャの開始やアタッチなどの変更には操作者の権限が要る。
+//!
+//! ```toml
+//! [auth]
+//! tokens = ["operator:s3cr3t", "viewer:dashboard-token"]
+//! users = ["viewer:alice:password"]
+//! anonymous = "none"
+//! ```
+//!
+//! ヘッダーを付けられないブラウザの WebSocket や EventSource は `?access_token=TOKEN` で渡す。
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::extract::connect_info::Connected;
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+use axum::serve::IncomingStream;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+
+/// 権限。`Operator` は `Viewer` のできることをすべてできる。
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// 何もできない（認証が必要）
+    #[default]
+    None,
+    /// パケットの購読と状態の参照
+    Viewer,
+    /// キャプチャの開始・停止・リセット、アタッチ・デタッチ、設定の変更
+    Operator,
+}
+
+impl FromStr for Access {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "viewer" => Ok(Self::Viewer),
+            "operator" => Ok(Self::Operator),
+            _ => Err(format!(
+                "unknown role {:?}: expected viewer, operator or none",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Viewer => "viewer",
+            Self::Operator => "operator",
+        })
+    }
+}
+
+/// 表示するときに秘密の代わりに出す文字列
+const REDACTED: &str = "<redacted>";
+
+fn parse_role(role: &str) -> Result<Access, String> {
+    match role.parse()? {
+        Access::None => Err("credentials must grant viewer or operator".to_string()),
+        access => Ok(access),
+    }
+}
+
+/// Bearer トークン。`ROLE:TOKEN` の形式で指定する。
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TokenCredential {
+    pub access: Access,
+    token: String,
+}
+
+impl FromStr for TokenCredential {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (role, token) = s
+            .split_once(':')
+            .ok_or_else(|| "expected ROLE:TOKEN".to_string())?;
+        if token.is_empty() {
+            return Err("token is empty".to_string());
+        }
+        // --print-config の出力をそのまま使うと、伏せ字がトークンになってしまう
+        if token == REDACTED {
+            return Err("token is redacted; set the real token".to_string());
+        }
+        Ok(Self {
+            access: parse_role(role)?,
+            token: token.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for TokenCredential {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TokenCredential> for String {
+    fn from(credential: TokenCredential) -> Self {
+        format!("{}:{}", credential.access, credential.token)
+    }
+}
+
+impl TokenCredential {
+    /// トークンを伏せた表示用のコピー
+    pub fn redacted(&self) -> Self {
+        Self {
+            access: self.access,
+            token: REDACTED.to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for TokenCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.access, REDACTED)
+    }
+}
+
+/// Basic 認証のユーザー。`ROLE:USER:PASSWORD` の形式で指定する。
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UserCredential {
+    pub access: Access,
+    pub user: String,
+    password: String,
+}
+
+impl FromStr for UserCredential {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(role), Some(user), Some(password)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("expected ROLE:USER:PASSWORD".to_string());
+        };
+        if user.is_empty() || password.is_empty() {
+            return Err("user and password must not be empty".to_string());
+        }
+        if password == REDACTED {
+            return Err("password is redacted; set the real password".to_string());
+        }
+        Ok(Self {
+            access: parse_role(role)?,
+            user: user.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for UserCredential {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<UserCredential> for String {
+    fn from(credential: UserCredential) -> Self {
+        format!(
+            "{}:{}:{}",
+            credential.access, credential.user, credential.password
+        )
+    }
+}
+
+impl UserCredential {
+    /// パスワードを伏せた表示用のコピー
+    pub fn redacted(&self) -> Self {
+        Self {
+            access: self.access,
+            user: self.user.clone(),
+            password: REDACTED.to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for UserCredential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.access, self.user, REDACTED)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub tokens: Vec<TokenCredential>,
+    pub users: Vec<UserCredential>,
+    /// 資格情報を送らなかったリクエストの権限（`viewer` にすると参照だけ公開できる）
+    pub anonymous: Access,
+    /// 接続元（クライアント証明書など）で権限を与える待ち受けがある。
+    /// 資格情報を設定していなくても、それ以外の接続には認証を求める。
+    #[serde(skip)]
+    pub peer_auth: bool,
+}
+
+/// 資格情報が正しくない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidCredentials;
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || !self.users.is_empty() || self.peer_auth
+    }
+
+    /// トークンとパスワードを伏せた表示用のコピー
+    pub fn redacted(&self) -> Self {
+        Self {
+            tokens: self.tokens.iter().map(TokenCredential::redacted).collect(),
+            users: self.users.iter().map(UserCredential::redacted).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// `Authorization` ヘッダーか `access_token` の値から権限を決める。
+    /// どちらもなければ `anonymous`。送られた資格情報が正しくなければ `anonymous` にはしない。
+    pub fn authenticate(
+        &self,
+        authorization: Option<&str>,
+        access_token: Option<&str>,
+    ) -> Result<Access, InvalidCredentials> {
+        if !self.is_enabled() {
+            return Ok(Access::Operator);
+        }
+        if let Some(value) = authorization {
+            let (scheme, credentials) = value.split_once(' ').ok_or(InvalidCredentials)?;
+            let credentials = credentials.trim();
+            return if scheme.eq_ignore_ascii_case("bearer") {
+                self.token_access(credentials)
+            } else if scheme.eq_ignore_ascii_case("basic") {
+                self.basic_access(credentials)
+            } else {
+                Err(InvalidCredentials)
+            };
+        }
+        match access_token {
+            Some(token) => self.token_access(token),
+            None => Ok(self.anonymous),
+        }
+    }
+
+    fn token_access(&self, token: &str) -> Result<Access, InvalidCredentials> {
+        // 一致したものがあっても最後まで比べ、時間から推測されないようにする
+        self.tokens
+            .iter()
+            .filter(|credential| constant_time_eq(credential.token.as_bytes(), token.as_bytes()))
+            .map(|credential| credential.access)
+            .max()
+            .ok_or(InvalidCredentials)
+    }
+
+    fn basic_access(&self, encoded: &str) -> Result<Access, InvalidCredentials> {
+        let decoded = STANDARD.decode(encoded).map_err(|_| InvalidCredentials)?;
+        let decoded = String::from_utf8(decoded).map_err(|_| InvalidCredentials)?;
+        let (user, password) = decoded.split_once(':').ok_or(InvalidCredentials)?;
+        self.users
+            .iter()
+            .filter(|credential| {
+                credential.user == user
+                    && constant_time_eq(credential.password.as_bytes(), password.as_bytes())
+            })
+            .map(|credential| credential.access)
+            .max()
+            .ok_or(InvalidCredentials)
+    }
+
+    fn challenge(&self) -> Vec<HeaderValue> {
+        let mut challenges = Vec::new();
+        if !self.tokens.is_empty() {
+            challenges.push(HeaderValue::from_static("Bearer realm=\"scrop\""));
+        }
+        if !self.users.is_empty() {
+            challenges.push(HeaderValue::from_static("Basic realm=\"scrop\""));
+        }
+        challenges
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// メソッドから必要な権限を決める。参照は閲覧者、それ以外（POST/PUT など）は操作者。
+pub fn required_access(method: &Method) -> Access {
+    if method == Method::GET || method == Method::HEAD {
+        Access::Viewer
+    } else {
+        Access::Operator
+    }
+}
+
+/// 接続そのものに与えられた権限。`ConnectInfo` として待ち受けごとに決まり、
+/// 資格情報で認証した権限と大きい方を使う。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerAccess(pub Access);
+
+/// 平文の TCP 接続は接続元だけでは信用しない
+impl Connected<IncomingStream<'_, TcpListener>> for PeerAccess {
+    fn connect_info(_stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self(Access::None)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    access_token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuthErrorBody {
+    error: String,
+}
+
+/// 権限のないリクエストを 401 / 403 で拒否するミドルウェア。
+/// 認証した権限はリクエストの extension として後段に渡す。
+pub async fn require(
+    State(auth): State<Arc<AuthConfig>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    let access_token = Query::<TokenQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.access_token);
+    let required = required_access(request.method());
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<PeerAccess>>()
+        .map_or(Access::None, |ConnectInfo(PeerAccess(access))| *access);
+    let result = auth
+        .authenticate(authorization, access_token.as_deref())
+        .map(|access| access.max(peer));
+    match result {
+        Ok(access) if access >= required => {
+            request.extensions_mut().insert(access);
+            next.run(request).await
+        }
+        Ok(Access::None) | Err(InvalidCredentials) => {
+            let mut response = (
+                StatusCode::UNAUTHORIZED,
+                Json(AuthErrorBody {
+                    error: "authentication required".to_string(),
+                }),
+            )
+                .into_response();
+            for challenge in auth.challenge() {
+                response
+                    .headers_mut()
+                    .append(header::WWW_AUTHENTICATE, challenge);
+            }
+            response
+        }
+        Ok(_) => (
+            StatusCode::FORBIDDEN,
+            Json(AuthErrorBody {
+                error: format!("{} access required", required),
+            }),
+        )
+            .into_response(),
+    }
+}
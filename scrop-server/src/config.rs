@@ -0,0 +1,364 @@
+//! サーバーの設定ファイル（TOML）と、環境変数・コマンドライン引数による上書き。
+//!
+//! 優先順位は コマンドライン引数 > 環境変数 > 設定ファイル > 既定値。
+//! 環境変数はキー名を大文字にして `SCROP_` を付けたもの（`[capture]` の中は `SCROP_CAPTURE_`）。
+//!
+//! ```toml
+//! host = "0.0.0.0"
+//! port = 3000
+//! attach = ["eth*", "veth*"]
+//! capture_on_start = true
+//!
+//! [capture]
+//! correlation_timeout_ms = 100
+//! ring_buffer_bytes = 33554432
+//!
+//! [auth]
+//! tokens = ["operator:s3cr3t"]
+//!
+//! [tls]
+//! cert = "/etc/scrop/tls/server.pem"
+//! key = "/etc/scrop/tls/server.key"
+//! ```
+//!
+//! `listen` を指定すると `host` と `port` の代わりにそれらで待ち受ける（UNIX ソケットも指定できる）。
+
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use scrop_capture::config::{self as capture_config, CaptureConfig};
+use scrop_capture::STATUS_PUSH_INTERVAL_MS;
+
+use crate::auth::{Access, AuthConfig, TokenCredential, UserCredential};
+use crate::listen::{ListenAddr, PeerRule, UnixSocketConfig};
+use crate::tls::TlsConfig;
+
+/// 環境変数の接頭辞
+pub const ENV_PREFIX: &str = "SCROP_";
+/// `--config` を省略したときに設定ファイルのパスを読む環境変数
+pub const CONFIG_PATH_ENV: &str = "SCROP_CONFIG";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// 待ち受けるアドレス
+    pub host: String,
+    /// 待ち受けるポート
+    pub port: u16,
+    /// 待ち受け先（`host:port` か `unix:<path>`）。空なら `host` と `port` の TCP だけ。
+    pub listen: Vec<ListenAddr>,
+    /// WebSocket クライアントへ状態・統計を送る間隔
+    pub stats_interval_ms: u64,
+    /// 起動時に設定する自動アタッチのルール（インターフェース名の glob）
+    pub attach: Vec<String>,
+    /// 起動時にキャプチャを開始する（API から start しなくてよい）
+    pub capture_on_start: bool,
+    pub capture: CaptureConfig,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
+    pub unix: UnixSocketConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 3000,
+            listen: Vec::new(),
+            stats_interval_ms: STATUS_PUSH_INTERVAL_MS,
+            attach: Vec::new(),
+            capture_on_start: false,
+            capture: CaptureConfig::default(),
+            auth: AuthConfig::default(),
+            tls: TlsConfig::default(),
+            unix: UnixSocketConfig::default(),
+        }
+    }
+}
+
+/// 設定ファイルの項目を上書きするコマンドライン引数
+#[derive(Debug, Default, Args)]
+pub struct ConfigArgs {
+    /// Read settings from this TOML file (default: $SCROP_CONFIG)
+    #[arg(long = "config", value_name = "PATH")]
+    pub path: Option<PathBuf>,
+
+    /// Print the effective settings as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Host address to bind to [default: 127.0.0.1]
+    #[arg(long)]
+    pub host: Option<String>,
+
+    /// Port to listen on [default: 3000]
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Listen on ADDR (`host:port` or `unix:<path>`) instead of --host/--port.
+    /// May be given multiple times to serve on several listeners at once.
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<Vec<ListenAddr>>,
+
+    /// Milliseconds between capture status and stats pushes to WebSocket clients
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub stats_interval_ms: Option<u64>,
+
+    /// Interface name globs to auto-attach on startup (comma-separated)
+    #[arg(long, value_name = "GLOBS", value_delimiter = ',')]
+    pub attach: Option<Vec<String>>,
+
+    /// Start capturing on startup instead of waiting for POST /api/capture/start
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub capture_on_start: Option<bool>,
+
+    /// Milliseconds to wait for a drop before a passed packet counts as delivered
+    #[arg(long, value_name = "MS")]
+    pub correlation_timeout_ms: Option<u64>,
+
+    /// Milliseconds between correlated packet batches
+    #[arg(long, value_name = "MS")]
+    pub batch_flush_interval_ms: Option<u64>,
+
+    /// Maximum packets per batch
+    #[arg(long, value_name = "N")]
+    pub batch_max_size: Option<usize>,
+
+    /// Batches buffered for each packet subscriber before it starts missing them
+    #[arg(long, value_name = "N")]
+    pub event_channel_capacity: Option<usize>,
+
+    /// Size of the eBPF event ring buffer (a power of two, at least 4096)
+    #[arg(long, value_name = "BYTES")]
+    pub ring_buffer_bytes: Option<u32>,
+
+    /// Run the legacy correlator alongside the current one and compare results
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub shadow_correlator: Option<bool>,
+
+    /// Require this bearer token, given as ROLE:TOKEN with ROLE viewer or operator.
+    /// May be given multiple times.
+    #[arg(long = "auth-token", value_name = "ROLE:TOKEN")]
+    pub auth_tokens: Option<Vec<TokenCredential>>,
+
+    /// Accept this basic auth user, given as ROLE:USER:PASSWORD. May be given multiple times.
+    #[arg(long = "auth-user", value_name = "ROLE:USER:PASSWORD")]
+    pub auth_users: Option<Vec<UserCredential>>,
+
+    /// Role of requests without credentials when authentication is enabled [default: none]
+    #[arg(long, value_name = "ROLE")]
+    pub auth_anonymous: Option<Access>,
+
+    /// Serve HTTPS with this PEM certificate chain (requires --tls-key). Reloaded on SIGHUP.
+    #[arg(long, value_name = "PATH")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, value_name = "PATH")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Grant operator access to clients presenting a certificate signed by this PEM CA
+    #[arg(long, value_name = "PATH")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Octal permissions of unix socket listeners [default: 0660]
+    #[arg(long, value_name = "MODE")]
+    pub unix_mode: Option<String>,
+
+    /// Group (name or GID) owning unix socket listeners
+    #[arg(long, value_name = "GROUP")]
+    pub unix_group: Option<String>,
+
+    /// Grant unix socket clients running as a user or group a role, given as
+    /// ROLE:user:NAME or ROLE:group:NAME. May be given multiple times.
+    #[arg(long = "unix-peer", value_name = "ROLE:KIND:NAME")]
+    pub unix_peers: Option<Vec<PeerRule>>,
+}
+
+impl ConfigArgs {
+    /// 設定ファイル・環境変数・引数を重ねて、検証済みの設定を作る
+    pub fn load(&self, env: impl Fn(&str) -> Option<String>) -> Result<ServerConfig, String> {
+        let path = self
+            .path
+            .clone()
+            .or_else(|| env(CONFIG_PATH_ENV).map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => ServerConfig::read(&path)?,
+            None => ServerConfig::default(),
+        };
+        config.apply_env(&env)?;
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(&self, config: &mut ServerConfig) {
+        let capture = &mut config.capture;
+        override_with(&mut config.host, &self.host);
+        override_with(&mut config.port, &self.port);
+        override_with(&mut config.listen, &self.listen);
+        override_with(&mut config.stats_interval_ms, &self.stats_interval_ms);
+        override_with(&mut config.attach, &self.attach);
+        override_with(&mut config.capture_on_start, &self.capture_on_start);
+        override_with(
+            &mut capture.correlation_timeout_ms,
+            &self.correlation_timeout_ms,
+        );
+        override_with(
+            &mut capture.batch_flush_interval_ms,
+            &self.batch_flush_interval_ms,
+        );
+        override_with(&mut capture.batch_max_size, &self.batch_max_size);
+        override_with(
+            &mut capture.event_channel_capacity,
+            &self.event_channel_capacity,
+        );
+        override_with(&mut capture.ring_buffer_bytes, &self.ring_buffer_bytes);
+        override_with(&mut capture.shadow_correlator, &self.shadow_correlator);
+        override_with(&mut config.auth.tokens, &self.auth_tokens);
+        override_with(&mut config.auth.users, &self.auth_users);
+        override_with(&mut config.auth.anonymous, &self.auth_anonymous);
+        override_with(&mut config.tls.cert, &self.tls_cert);
+        override_with(&mut config.tls.key, &self.tls_key);
+        override_with(&mut config.tls.client_ca, &self.tls_client_ca);
+        override_with(&mut config.unix.mode, &self.unix_mode);
+        override_with(&mut config.unix.group, &self.unix_group);
+        override_with(&mut config.unix.peers, &self.unix_peers);
+    }
+}
+
+fn override_with<T: Clone>(target: &mut T, value: &Option<T>) {
+    if let Some(value) = value {
+        *target = value.clone();
+    }
+}
+
+impl ServerConfig {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read config {}: {}", path.display(), e))?;
+        Self::from_toml(&text).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// `--print-config` 用の TOML。トークンとパスワードは伏せる。
+    pub fn to_toml(&self) -> String {
+        let redacted = Self {
+            auth: self.auth.redacted(),
+            ..self.clone()
+        };
+        toml::to_string(&redacted).expect("server config serializes to TOML")
+    }
+
+    /// 実際に待ち受ける先
+    pub fn listeners(&self) -> Vec<ListenAddr> {
+        if self.listen.is_empty() {
+            vec![ListenAddr::Tcp(format!("{}:{}", self.host, self.port))]
+        } else {
+            self.listen.clone()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.stats_interval_ms == 0 {
+            return Err("stats_interval_ms must be greater than 0".to_string());
+        }
+        if self.attach.iter().any(|rule| rule.trim().is_empty()) {
+            return Err("attach rules must not be empty".to_string());
+        }
+        self.tls.validate()?;
+        self.unix.validate()?;
+        self.capture.validate()
+    }
+
+    /// `SCROP_<KEY>` / `SCROP_CAPTURE_<KEY>` の環境変数で上書きする。
+    /// 値は既存の値と同じ型として読む（配列はカンマ区切り）。
+    fn apply_env(&mut self, env: &impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        let mut value = toml::Value::try_from(&*self).map_err(|e| e.to_string())?;
+        let toml::Value::Table(table) = &mut value else {
+            unreachable!("server config serializes to a table");
+        };
+        // 設定ファイル導入前からある環境変数も引き続き受け付ける
+        if let Some(raw) = env(capture_config::SHADOW_CORRELATOR_ENV) {
+            let shadow = capture_config::parse_flag(&raw).ok_or_else(|| {
+                format!(
+                    "{}: expected a boolean, got {:?}",
+                    capture_config::SHADOW_CORRELATOR_ENV,
+                    raw
+                )
+            })?;
+            if let Some(toml::Value::Table(capture)) = table.get_mut("capture") {
+                capture.insert(
+                    "shadow_correlator".to_string(),
+                    toml::Value::Boolean(shadow),
+                );
+            }
+        }
+        apply_env_table(table, ENV_PREFIX, env)?;
+        *self = value
+            .try_into()
+            .map_err(|e: toml::de::Error| e.to_string())?;
+        Ok(())
+    }
+}
+
+fn apply_env_table(
+    table: &mut toml::Table,
+    prefix: &str,
+    env: &impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    for (key, value) in table.iter_mut() {
+        let name = format!("{}{}", prefix, key.to_ascii_uppercase());
+        if let toml::Value::Table(nested) = value {
+            apply_env_table(nested, &format!("{}_", name), env)?;
+            continue;
+        }
+        let Some(raw) = env(&name) else {
+            continue;
+        };
+        *value = parse_env_value(value, &raw).map_err(|e| format!("{}: {}", name, e))?;
+    }
+    Ok(())
+}
+
+fn parse_env_value(current: &toml::Value, raw: &str) -> Result<toml::Value, String> {
+    match current {
+        toml::Value::String(_) => Ok(toml::Value::String(raw.to_string())),
+        toml::Value::Integer(_) => raw
+            .trim()
+            .parse()
+            .map(toml::Value::Integer)
+            .map_err(|_| format!("expected an integer, got {:?}", raw)),
+        toml::Value::Boolean(_) => capture_config::parse_flag(raw)
+            .map(toml::Value::Boolean)
+            .ok_or_else(|| format!("expected a boolean, got {:?}", raw)),
+        toml::Value::Array(_) => Ok(toml::Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        )),
+        other => Err(format!(
+            "cannot set a {} from the environment",
+            other.type_str()
+        )),
+    }
+}
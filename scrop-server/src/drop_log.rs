@@ -0,0 +1,595 @@
+//! ドロップしたパケットの監査ログ（JSON Lines ファイル・syslog・journald）。
+//!
+//! UI の接続とは関係なく `event_tx` を購読し、ドロップしたパケットを 1 件ずつ構造化したイベントとして書く。
+//! 毎秒の件数に上限を設けた場合、超えた分は理由ごとの件数にまとめて 1 秒に 1 回サマリーとして書く。
+//! 購読が遅れて読めなかったバッチがあれば、その穴を `gap` イベントとして書く。
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use scrop_capture::clients::BatchSizeEstimator;
+use scrop_capture::metrics::result_label;
+use scrop_capture::types::{
+    AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult, Protocol,
+};
+use scrop_capture::AppState;
+
+use crate::shutdown::Shutdown;
+
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+pub const DEFAULT_JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_IDENTIFIER: &str = "scrop-server";
+/// daemon.warning
+const SYSLOG_PRIORITY: u8 = (3 << 3) | 4;
+const JOURNALD_PRIORITY: &str = "4";
+
+/// 書き込み先。`file:<path>` / `syslog[:<socket>]` / `journald[:<socket>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DropLogTarget {
+    File(PathBuf),
+    Syslog(PathBuf),
+    Journald(PathBuf),
+}
+
+impl FromStr for DropLogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = match s.split_once(':') {
+            Some((kind, path)) => (kind, Some(path)),
+            None => (s, None),
+        };
+        if path == Some("") {
+            return Err(format!("drop log path is empty in {:?}", s));
+        }
+        match (kind, path) {
+            ("file", Some(path)) => Ok(Self::File(PathBuf::from(path))),
+            ("file", None) => Err("file drop log needs a path (file:<path>)".to_string()),
+            ("syslog", path) => Ok(Self::Syslog(PathBuf::from(
+                path.unwrap_or(DEFAULT_SYSLOG_SOCKET),
+            ))),
+            ("journald", path) => Ok(Self::Journald(PathBuf::from(
+                path.unwrap_or(DEFAULT_JOURNALD_SOCKET),
+            ))),
+            _ => Err(format!(
+                "unknown drop log {:?} (use file:<path>, syslog or journald)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DropLogTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Syslog(path) => write!(f, "syslog:{}", path.display()),
+            Self::Journald(path) => write!(f, "journald:{}", path.display()),
+        }
+    }
+}
+
+/// 監査ログの設定
+#[derive(Debug, Clone)]
+pub struct DropLogConfig {
+    pub targets: Vec<DropLogTarget>,
+    /// JSON Lines ファイルをローテートするサイズ
+    pub max_file_bytes: u64,
+    /// ローテートした古いファイルを残す数（`<path>.1` … `<path>.N`）
+    pub keep_files: usize,
+    /// 1 秒あたりに書くドロップの上限（0 なら無制限）
+    pub max_per_sec: u64,
+}
+
+/// ドロップ 1 件のイベント
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DropEvent<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    timestamp_ms: f64,
+    result: &'a PacketResult,
+    #[serde(flatten)]
+    packet: &'a AnimatingPacket,
+}
+
+/// 上限を超えて書かなかったドロップのまとめ
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SummaryEvent {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    timestamp_ms: f64,
+    suppressed: u64,
+    /// `<result>/<reason>` ごとの件数
+    by_reason: BTreeMap<String, u64>,
+}
+
+/// 購読が遅れて読めなかったバッチ。含まれていたドロップは書けていない。
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GapEvent {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    timestamp_ms: f64,
+    skipped_batches: u64,
+    /// 読めなかったパケット数の推定（ドロップ以外も含む）
+    approx_packets: u64,
+}
+
+/// 書き込み先ごとの出力
+trait DropSink: Send {
+    fn write_drop(&mut self, event: &DropEvent<'_>, json: &str) -> io::Result<()>;
+    fn write_summary(&mut self, summary: &SummaryEvent, json: &str) -> io::Result<()>;
+    fn write_gap(&mut self, gap: &GapEvent, json: &str) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// サイズでローテートする JSON Lines ファイル
+struct FileSink {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    written: u64,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl FileSink {
+    fn open(path: &Path, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            writer: BufWriter::new(file),
+            written,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn write_line(&mut self, json: &str) -> io::Result<()> {
+        let len = json.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            self.rotate()?;
+        }
+        self.writer.write_all(json.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.written += len;
+        Ok(())
+    }
+
+    /// `<path>.N-1` → `<path>.N` … `<path>` → `<path>.1` と名前をずらし、新しいファイルを開く
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl DropSink for FileSink {
+    fn write_drop(&mut self, _event: &DropEvent<'_>, json: &str) -> io::Result<()> {
+        self.write_line(json)
+    }
+
+    fn write_summary(&mut self, _summary: &SummaryEvent, json: &str) -> io::Result<()> {
+        self.write_line(json)
+    }
+
+    fn write_gap(&mut self, _gap: &GapEvent, json: &str) -> io::Result<()> {
+        self.write_line(json)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// ローカルの syslog ソケットに `<PRI>TAG[PID]: JSON` を送る
+struct SyslogSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl SyslogSink {
+    fn send(&self, json: &str) -> io::Result<()> {
+        let message = format!(
+            "<{}>{}[{}]: {}",
+            SYSLOG_PRIORITY,
+            SYSLOG_IDENTIFIER,
+            std::process::id(),
+            json
+        );
+        self.socket
+            .send_to(message.as_bytes(), &self.path)
+            .map(|_| ())
+    }
+}
+
+impl DropSink for SyslogSink {
+    fn write_drop(&mut self, _event: &DropEvent<'_>, json: &str) -> io::Result<()> {
+        self.send(json)
+    }
+
+    fn write_summary(&mut self, _summary: &SummaryEvent, json: &str) -> io::Result<()> {
+        self.send(json)
+    }
+
+    fn write_gap(&mut self, _gap: &GapEvent, json: &str) -> io::Result<()> {
+        self.send(json)
+    }
+}
+
+/// journald のネイティブプロトコル（`KEY=value` の行）で送る。
+/// 各フィールドは `SCROP_` で始め、journalctl の出力形式や `-F` で絞り込めるようにする。
+struct JournaldSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl JournaldSink {
+    fn send(&self, fields: &[(&str, String)]) -> io::Result<()> {
+        let mut payload = Vec::new();
+        for (key, value) in fields {
+            append_journal_field(&mut payload, key, value);
+        }
+        self.socket.send_to(&payload, &self.path).map(|_| ())
+    }
+}
+
+/// 値に改行を含む場合は `KEY\n<長さ (u64 LE)><値>\n` の形で書く
+fn append_journal_field(payload: &mut Vec<u8>, key: &str, value: &str) {
+    payload.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        payload.push(b'\n');
+        payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        payload.push(b'=');
+    }
+    payload.extend_from_slice(value.as_bytes());
+    payload.push(b'\n');
+}
+
+impl DropSink for JournaldSink {
+    fn write_drop(&mut self, event: &DropEvent<'_>, json: &str) -> io::Result<()> {
+        let packet = event.packet;
+        let mut fields = vec![
+            ("MESSAGE", describe_drop(event.result, packet)),
+            ("PRIORITY", JOURNALD_PRIORITY.to_string()),
+            ("SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER.to_string()),
+            ("SCROP_EVENT", "drop".to_string()),
+            ("SCROP_PACKET_ID", packet.id.clone()),
+            ("SCROP_RESULT", result_label(event.result).to_string()),
+            (
+                "SCROP_PROTOCOL",
+                protocol_label(&packet.protocol).to_string(),
+            ),
+            ("SCROP_SOURCE", packet.source.clone()),
+            ("SCROP_SOURCE_PORT", packet.src_port.to_string()),
+            ("SCROP_DESTINATION", packet.destination.clone()),
+            ("SCROP_DESTINATION_PORT", packet.dest_port.to_string()),
+            ("SCROP_JSON", json.to_string()),
+        ];
+        if let Some(reason) = &packet.reason {
+            fields.push(("SCROP_REASON", reason.clone()));
+        }
+        if let Some(interface) = &packet.interface {
+            fields.push(("SCROP_INTERFACE", interface.clone()));
+        }
+        if let Some(netns) = &packet.netns {
+            fields.push(("SCROP_NETNS", netns.clone()));
+        }
+        if let Some(process) = &packet.process {
+            fields.push(("SCROP_PID", process.pid.to_string()));
+            fields.push(("SCROP_COMM", process.comm.clone()));
+        }
+        self.send(&fields)
+    }
+
+    fn write_summary(&mut self, summary: &SummaryEvent, json: &str) -> io::Result<()> {
+        self.send(&[
+            (
+                "MESSAGE",
+                format!(
+                    "{} dropped packets not logged (rate limit)",
+                    summary.suppressed
+                ),
+            ),
+            ("PRIORITY", JOURNALD_PRIORITY.to_string()),
+            ("SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER.to_string()),
+            ("SCROP_EVENT", "summary".to_string()),
+            ("SCROP_SUPPRESSED", summary.suppressed.to_string()),
+            ("SCROP_JSON", json.to_string()),
+        ])
+    }
+
+    fn write_gap(&mut self, gap: &GapEvent, json: &str) -> io::Result<()> {
+        self.send(&[
+            (
+                "MESSAGE",
+                format!(
+                    "{} batches (about {} packets) skipped; drops in them were not logged",
+                    gap.skipped_batches, gap.approx_packets
+                ),
+            ),
+            ("PRIORITY", JOURNALD_PRIORITY.to_string()),
+            ("SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER.to_string()),
+            ("SCROP_EVENT", "gap".to_string()),
+            ("SCROP_SKIPPED_BATCHES", gap.skipped_batches.to_string()),
+            ("SCROP_APPROX_PACKETS", gap.approx_packets.to_string()),
+            ("SCROP_JSON", json.to_string()),
+        ])
+    }
+}
+
+fn protocol_label(protocol: &Protocol) -> &'static str {
+    match protocol {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+    }
+}
+
+/// `fw-drop tcp 10.0.0.1:1234 -> 10.0.0.2:80 on eth0 (NETFILTER_DROP)`
+fn describe_drop(result: &PacketResult, packet: &AnimatingPacket) -> String {
+    let mut message = format!(
+        "{} {} {}:{} -> {}:{}",
+        result_label(result),
+        protocol_label(&packet.protocol),
+        packet.source,
+        packet.src_port,
+        packet.destination,
+        packet.dest_port
+    );
+    if let Some(interface) = &packet.interface {
+        message.push_str(&format!(" on {}", interface));
+    }
+    if let Some(reason) = &packet.reason {
+        message.push_str(&format!(" ({})", reason));
+    }
+    message
+}
+
+fn open_sink(target: &DropLogTarget, config: &DropLogConfig) -> io::Result<Box<dyn DropSink>> {
+    Ok(match target {
+        DropLogTarget::File(path) => Box::new(FileSink::open(
+            path,
+            config.max_file_bytes,
+            config.keep_files,
+        )?),
+        DropLogTarget::Syslog(path) => Box::new(SyslogSink {
+            socket: UnixDatagram::unbound()?,
+            path: path.clone(),
+        }),
+        DropLogTarget::Journald(path) => Box::new(JournaldSink {
+            socket: UnixDatagram::unbound()?,
+            path: path.clone(),
+        }),
+    })
+}
+
+/// 書き込み先と毎秒の上限の状態
+pub struct DropLogger {
+    sinks: Vec<(DropLogTarget, Box<dyn DropSink>)>,
+    max_per_sec: u64,
+    /// 現在の 1 秒の開始時刻（UNIX ミリ秒）と、その間に書いた件数
+    window_start_ms: u64,
+    written_in_window: u64,
+    suppressed: SummaryEvent,
+}
+
+impl DropLogger {
+    /// すべての書き込み先を開く。開けないものがあればエラー。
+    pub fn open(config: &DropLogConfig) -> Result<Self, String> {
+        let sinks = config
+            .targets
+            .iter()
+            .map(|target| {
+                open_sink(target, config)
+                    .map(|sink| (target.clone(), sink))
+                    .map_err(|e| format!("failed to open drop log {}: {}", target, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            sinks,
+            max_per_sec: config.max_per_sec,
+            window_start_ms: 0,
+            written_in_window: 0,
+            suppressed: SummaryEvent::default(),
+        })
+    }
+
+    /// バッチ内のドロップを書く
+    pub fn write_batch(&mut self, batch: &CapturedPacketEnvelope, now_ms: u64) {
+        self.roll_window(now_ms);
+        for CapturedPacket { packet, result } in &batch.packets {
+            if *result == PacketResult::Delivered {
+                continue;
+            }
+            if self.max_per_sec > 0 && self.written_in_window >= self.max_per_sec {
+                self.suppressed.suppressed += 1;
+                let key = format!(
+                    "{}/{}",
+                    result_label(result),
+                    packet.reason.as_deref().unwrap_or("")
+                );
+                *self.suppressed.by_reason.entry(key).or_default() += 1;
+                continue;
+            }
+            self.written_in_window += 1;
+            let event = DropEvent {
+                kind: "drop",
+                timestamp_ms: packet.capture_mono_ns as f64 / 1e6 + batch.epoch_offset_ms,
+                result,
+                packet,
+            };
+            let Ok(json) = serde_json::to_string(&event) else {
+                continue;
+            };
+            for (target, sink) in &mut self.sinks {
+                if let Err(e) = sink.write_drop(&event, &json) {
+                    warn!(target = %target, error = %e, "failed to write drop log");
+                }
+            }
+        }
+        self.flush();
+    }
+
+    /// 購読が遅れて読めなかったバッチを、監査ログの穴として書く
+    pub fn write_gap(&mut self, skipped_batches: u64, approx_packets: u64, now_ms: u64) {
+        let gap = GapEvent {
+            kind: "gap",
+            timestamp_ms: now_ms as f64,
+            skipped_batches,
+            approx_packets,
+        };
+        if let Ok(json) = serde_json::to_string(&gap) {
+            for (target, sink) in &mut self.sinks {
+                if let Err(e) = sink.write_gap(&gap, &json) {
+                    warn!(target = %target, error = %e, "failed to write drop log");
+                }
+            }
+        }
+        self.flush();
+    }
+
+    /// 1 秒が過ぎていれば、書かなかった分のサマリーを書いて次の 1 秒を始める
+    pub fn roll_window(&mut self, now_ms: u64) {
+        if now_ms.saturating_sub(self.window_start_ms) < 1000 {
+            return;
+        }
+        self.finish(now_ms);
+    }
+
+    /// 1 秒を待たずに、書かなかった分のサマリーを書いて書き出す（終了時に呼ぶ）
+    pub fn finish(&mut self, now_ms: u64) {
+        if self.suppressed.suppressed > 0 {
+            let summary = SummaryEvent {
+                kind: "summary",
+                timestamp_ms: now_ms as f64,
+                ..std::mem::take(&mut self.suppressed)
+            };
+            if let Ok(json) = serde_json::to_string(&summary) {
+                for (target, sink) in &mut self.sinks {
+                    if let Err(e) = sink.write_summary(&summary, &json) {
+                        warn!(target = %target, error = %e, "failed to write drop log");
+                    }
+                }
+            }
+            self.flush();
+        }
+        self.window_start_ms = now_ms;
+        self.written_in_window = 0;
+    }
+
+    fn flush(&mut self) {
+        for (target, sink) in &mut self.sinks {
+            if let Err(e) = sink.flush() {
+                warn!(target = %target, error = %e, "failed to flush drop log");
+            }
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 書き込みタスクが受け取ったもの
+enum Received {
+    Batch(CapturedPacketEnvelope),
+    /// 読めなかったバッチの数と、そのパケット数の推定
+    Gap(u64, u64),
+    Tick,
+}
+
+/// `event_tx` を購読して書き込むタスクを起動する。書き込みはブロッキングのスレッドで行う。
+/// `shutdown` の合図を受けたら、受信済みのバッチを書いてサマリーを書き出してから終わる。
+pub fn spawn(
+    logger: DropLogger,
+    state: &AppState,
+    shutdown: Shutdown,
+) -> tokio::task::JoinHandle<()> {
+    let logger = Arc::new(Mutex::new(logger));
+    let mut rx = state.event_tx.subscribe();
+    tokio::spawn(async move {
+        // ドロップが止まっても、書かなかった分のサマリーを遅れずに書く
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut shutdown = std::pin::pin!(shutdown.wait());
+        let mut batch_sizes = BatchSizeEstimator::default();
+        loop {
+            let received = tokio::select! {
+                result = rx.recv() => match result {
+                    Ok(batch) => {
+                        batch_sizes.record(batch.packets.len());
+                        Received::Batch(batch)
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        let approx_packets = batch_sizes.approx_packets(n);
+                        warn!(skipped_batches = n, approx_packets, "drop log lagged");
+                        Received::Gap(n, approx_packets)
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                _ = ticker.tick() => Received::Tick,
+                _ = &mut shutdown => break,
+            };
+            let logger = Arc::clone(&logger);
+            let _ = tokio::task::spawn_blocking(move || {
+                let mut logger = logger.lock().unwrap();
+                match received {
+                    Received::Batch(batch) => logger.write_batch(&batch, now_ms()),
+                    Received::Gap(skipped_batches, approx_packets) => {
+                        logger.write_gap(skipped_batches, approx_packets, now_ms())
+                    }
+                    Received::Tick => logger.roll_window(now_ms()),
+                }
+            })
+            .await;
+        }
+        let _ = tokio::task::spawn_blocking(move || {
+            let mut logger = logger.lock().unwrap();
+            while let Ok(batch) = rx.try_recv() {
+                logger.write_batch(&batch, now_ms());
+            }
+            logger.finish(now_ms());
+        })
+        .await;
+    })
+}
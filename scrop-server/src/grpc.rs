@@ -0,0 +1,279 @@
+//! `proto/capture_service.proto` の `ScropCapture` gRPC サービス。
+//!
+//! HTTP API と同じ操作と、WebSocket と同じ型でのパケット購読を提供する。
+//! 認証を有効にした場合は `authorization` メタデータで HTTP と同じ資格情報を受け付ける。
+
+use std::borrow::Cow;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures_util::stream::{Stream, StreamExt};
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use scrop_capture::{netns, AppState, CaptureError};
+
+use crate::auth::{Access, AuthConfig};
+use crate::stream::{self, StreamItem};
+use crate::ws_proto::pb::scrop_capture_server::{ScropCapture, ScropCaptureServer};
+use crate::ws_proto::{
+    delivery_mode_from_proto, filter_from_proto, outbound_to_envelope, pb, stats_update, Outbound,
+    OutboundBatch,
+};
+
+/// gRPC サーバーの待ち受け先。`host:port` または `unix:<path>`。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrpcListen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for GrpcListen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err("unix socket path is empty".to_string()),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => s
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|e| format!("invalid listen address {:?}: {}", s, e)),
+        }
+    }
+}
+
+impl fmt::Display for GrpcListen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// gRPC で使える認証の設定か確かめる。
+/// クライアント証明書や接続元だけで認証する設定では、gRPC の呼び出しは一つも通らない。
+pub fn check_auth(auth: &AuthConfig) -> Result<(), String> {
+    if auth.is_enabled()
+        && auth.tokens.is_empty()
+        && auth.users.is_empty()
+        && auth.anonymous == Access::None
+    {
+        return Err("gRPC only accepts tokens and passwords, but authentication relies on client certificates or unix peers; configure auth tokens or users".to_string());
+    }
+    Ok(())
+}
+
+/// 待ち受けを開始し、サーバーが終了するまで処理する。
+/// UNIX ソケットは前回の起動で残ったファイルを削除してから作り直す。
+pub async fn serve(
+    listen: &GrpcListen,
+    state: Arc<AppState>,
+    auth: Arc<AuthConfig>,
+) -> std::io::Result<()> {
+    let service = CaptureService::new(state).with_auth(auth);
+    let router = Server::builder().add_service(service.into_server());
+    let result = match listen {
+        GrpcListen::Tcp(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            router
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+        }
+        GrpcListen::Unix(path) => {
+            match std::fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            let listener = tokio::net::UnixListener::bind(path)?;
+            router
+                .serve_with_incoming(UnixListenerStream::new(listener))
+                .await
+        }
+    };
+    result.map_err(std::io::Error::other)
+}
+
+pub struct CaptureService {
+    state: Arc<AppState>,
+    auth: Arc<AuthConfig>,
+}
+
+impl CaptureService {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self {
+            state,
+            auth: Arc::default(),
+        }
+    }
+
+    /// 資格情報を設定する（既定では認証しない）。
+    /// gRPC の待ち受けは接続元で権限を与えないため、`peer_auth` で認証が有効なら常に資格情報を求める。
+    pub fn with_auth(mut self, auth: Arc<AuthConfig>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    fn authorize<T>(&self, request: &Request<T>, required: Access) -> Result<(), Status> {
+        let authorization = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok());
+        match self.auth.authenticate(authorization, None) {
+            Ok(access) if access >= required => Ok(()),
+            Ok(Access::None) | Err(_) => Err(Status::unauthenticated("authentication required")),
+            Ok(_) => Err(Status::permission_denied(format!(
+                "{} access required",
+                required
+            ))),
+        }
+    }
+
+    pub fn into_server(self) -> ScropCaptureServer<Self> {
+        ScropCaptureServer::new(self)
+    }
+}
+
+fn capture_status(err: CaptureError) -> Status {
+    let message = err.to_string();
+    match err {
+        CaptureError::InterfaceNotFound(_) => Status::not_found(message),
+        CaptureError::InvalidState(_) => Status::failed_precondition(message),
+        CaptureError::PermissionDenied(_) => Status::permission_denied(message),
+        #[cfg(feature = "ebpf")]
+        CaptureError::EbpfLoadFailed(_) => Status::internal(message),
+        CaptureError::Other(_) => Status::internal(message),
+    }
+}
+
+fn message(message: String) -> Response<pb::MessageResponse> {
+    Response::new(pb::MessageResponse { message })
+}
+
+type PacketStream = Pin<Box<dyn Stream<Item = Result<pb::PacketBatchEnvelope, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl ScropCapture for CaptureService {
+    type StreamPacketsStream = PacketStream;
+
+    /// バッチと取りこぼしの通知を、WebSocket のバージョン 1 と同じエンベロープで送る
+    async fn stream_packets(
+        &self,
+        request: Request<pb::StreamPacketsRequest>,
+    ) -> Result<Response<Self::StreamPacketsStream>, Status> {
+        self.authorize(&request, Access::Viewer)?;
+        let request = request.into_inner();
+        let filter = request
+            .filter
+            .map(filter_from_proto)
+            .transpose()
+            .map_err(Status::invalid_argument)?
+            .unwrap_or_default();
+        let mode = request
+            .mode
+            .map(delivery_mode_from_proto)
+            .transpose()
+            .map_err(Status::invalid_argument)?
+            .unwrap_or_default();
+
+        let envelopes = stream::subscribe_with(&self.state, filter, mode, "grpc").filter_map(
+            move |item| async move {
+                let outbound = match item {
+                    StreamItem::Batch {
+                        batch,
+                        sampled,
+                        suppressed,
+                    } => Outbound::Batch(OutboundBatch {
+                        packets: Cow::Owned(batch.packets),
+                        epoch_offset_ms: batch.epoch_offset_ms,
+                        sampled,
+                        suppressed,
+                    }),
+                    StreamItem::Gap(gap) => Outbound::Gap {
+                        skipped_batches: gap.skipped_batches,
+                        approx_packets: gap.approx_packets,
+                    },
+                };
+                outbound_to_envelope(outbound).map(Ok)
+            },
+        );
+        Ok(Response::new(Box::pin(envelopes)))
+    }
+
+    async fn get_status(
+        &self,
+        request: Request<pb::GetStatusRequest>,
+    ) -> Result<Response<pb::StatsUpdate>, Status> {
+        self.authorize(&request, Access::Viewer)?;
+        let snapshot = self.state.status_snapshot().await;
+        Ok(Response::new(stats_update(&snapshot)))
+    }
+
+    async fn start(
+        &self,
+        request: Request<pb::StartRequest>,
+    ) -> Result<Response<pb::MessageResponse>, Status> {
+        self.authorize(&request, Access::Operator)?;
+        let capture = self.state.capture.lock().await;
+        capture.start(self.state.event_tx.clone());
+        Ok(message("Capture started".to_string()))
+    }
+
+    async fn stop(
+        &self,
+        request: Request<pb::StopRequest>,
+    ) -> Result<Response<pb::MessageResponse>, Status> {
+        self.authorize(&request, Access::Operator)?;
+        let capture = self.state.capture.lock().await;
+        capture.stop();
+        Ok(message("Capture stopped".to_string()))
+    }
+
+    async fn attach(
+        &self,
+        request: Request<pb::InterfaceRequest>,
+    ) -> Result<Response<pb::MessageResponse>, Status> {
+        self.authorize(&request, Access::Operator)?;
+        let request = request.into_inner();
+        let name = netns::qualify_interface(request.netns.as_deref(), &request.name);
+        let capture = self.state.capture.lock().await;
+        capture
+            .attach_interface(&name)
+            .await
+            .map_err(capture_status)?;
+        Ok(message(format!("Interface {} attached", name)))
+    }
+
+    async fn detach(
+        &self,
+        request: Request<pb::InterfaceRequest>,
+    ) -> Result<Response<pb::MessageResponse>, Status> {
+        self.authorize(&request, Access::Operator)?;
+        let request = request.into_inner();
+        let name = netns::qualify_interface(request.netns.as_deref(), &request.name);
+        let capture = self.state.capture.lock().await;
+        capture
+            .detach_interface(&name)
+            .await
+            .map_err(capture_status)?;
+        Ok(message(format!("Interface {} detached", name)))
+    }
+
+    async fn list_interfaces(
+        &self,
+        request: Request<pb::ListInterfacesRequest>,
+    ) -> Result<Response<pb::ListInterfacesResponse>, Status> {
+        self.authorize(&request, Access::Viewer)?;
+        let capture = self.state.capture.lock().await;
+        Ok(Response::new(pb::ListInterfacesResponse {
+            interfaces: capture.list_interfaces(),
+        }))
+    }
+}
@@ -0,0 +1,455 @@
+//! HTTP の待ち受け先（TCP と UNIX ソケット）と、UNIX ソケットの接続元による認可。
+//!
+//! `--listen` を複数指定すると、同じ API・WebSocket をすべての待ち受けで提供する。
+//! UNIX ソケットはファイルのモードとグループで接続できる利用者を絞り、
+//! 接続元のユーザー・グループ（SO_PEERCRED・SO_PEERGROUPS）で権限を与えられる。
+//! root とサーバー自身のユーザーは常に操作者として扱う。
+//!
+//! ```toml
+//! listen = ["unix:/run/scrop/scrop.sock", "127.0.0.1:3000"]
+//!
+//! [unix]
+//! mode = "0660"
+//! group = "scrop"
+//! peers = ["operator:group:scrop-admin", "viewer:group:scrop"]
+//! ```
+
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use serde::{Deserialize, Serialize};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, warn};
+
+use crate::auth::{Access, PeerAccess};
+
+/// HTTP の待ち受け先。`host:port` または `unix:<path>`。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// 同じホストからしか接続できない（ループバックか UNIX ソケット）
+    pub fn is_local(&self) -> bool {
+        match self {
+            Self::Tcp(addr) => {
+                let host = addr
+                    .rsplit_once(':')
+                    .map_or(addr.as_str(), |(host, _)| host);
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                host == "localhost"
+                    || host
+                        .parse::<std::net::IpAddr>()
+                        .is_ok_and(|ip| ip.is_loopback())
+            }
+            Self::Unix(_) => true,
+        }
+    }
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix socket path is empty".to_string());
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(Self::Tcp(s.to_string()))
+            }
+            _ => Err(format!(
+                "invalid listen address {:?}: expected host:port or unix:<path>",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ListenAddr> for String {
+    fn from(addr: ListenAddr) -> Self {
+        addr.to_string()
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => f.write_str(addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// 接続元の資格情報で照合する相手
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerSubject {
+    /// ユーザー名か UID
+    User(String),
+    /// グループ名か GID（補助グループも含めて照合する）
+    Group(String),
+}
+
+/// UNIX ソケットの接続元に与える権限。`ROLE:user:NAME` または `ROLE:group:NAME` の形式で指定する。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PeerRule {
+    pub access: Access,
+    pub subject: PeerSubject,
+}
+
+impl FromStr for PeerRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(role), Some(kind), Some(name)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("expected ROLE:user:NAME or ROLE:group:NAME".to_string());
+        };
+        if name.is_empty() {
+            return Err("user or group name is empty".to_string());
+        }
+        let access = match role.parse()? {
+            Access::None => return Err("peers must be granted viewer or operator".to_string()),
+            access => access,
+        };
+        let subject = match kind {
+            "user" => PeerSubject::User(name.to_string()),
+            "group" => PeerSubject::Group(name.to_string()),
+            _ => {
+                return Err(format!(
+                    "unknown peer kind {:?}: expected user or group",
+                    kind
+                ))
+            }
+        };
+        Ok(Self { access, subject })
+    }
+}
+
+impl TryFrom<String> for PeerRule {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PeerRule> for String {
+    fn from(rule: PeerRule) -> Self {
+        match rule.subject {
+            PeerSubject::User(name) => format!("{}:user:{}", rule.access, name),
+            PeerSubject::Group(name) => format!("{}:group:{}", rule.access, name),
+        }
+    }
+}
+
+/// UNIX ソケットの待ち受けの設定
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UnixSocketConfig {
+    /// ソケットファイルのモード（8 進数）
+    pub mode: String,
+    /// ソケットファイルのグループ（名前か GID、空なら変えない）
+    pub group: String,
+    /// 接続元のユーザー・グループに与える権限
+    pub peers: Vec<PeerRule>,
+}
+
+impl Default for UnixSocketConfig {
+    fn default() -> Self {
+        Self {
+            mode: "0660".to_string(),
+            group: String::new(),
+            peers: Vec::new(),
+        }
+    }
+}
+
+impl UnixSocketConfig {
+    pub fn mode(&self) -> Result<u32, String> {
+        match u32::from_str_radix(self.mode.trim(), 8) {
+            Ok(mode) if mode <= 0o777 => Ok(mode),
+            _ => Err(format!(
+                "unix socket mode must be octal permissions like 0660, got {:?}",
+                self.mode
+            )),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.mode().map(|_| ())
+    }
+}
+
+/// 名前を解決済みの、接続元に権限を与える規則
+#[derive(Debug, Clone, Default)]
+pub struct PeerPolicy {
+    owner: u32,
+    users: Vec<(u32, Access)>,
+    groups: Vec<(u32, Access)>,
+}
+
+impl PeerPolicy {
+    /// ユーザー名・グループ名を UID・GID に解決する
+    pub fn resolve(rules: &[PeerRule]) -> Result<Self, String> {
+        let mut policy = Self {
+            owner: unsafe { libc::geteuid() },
+            ..Self::default()
+        };
+        for rule in rules {
+            match &rule.subject {
+                PeerSubject::User(name) => policy.users.push((lookup_user(name)?, rule.access)),
+                PeerSubject::Group(name) => policy.groups.push((lookup_group(name)?, rule.access)),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// 接続元の UID・GID と補助グループに与える権限
+    pub fn access(&self, uid: u32, gid: u32, groups: &[u32]) -> Access {
+        if uid == 0 || uid == self.owner {
+            return Access::Operator;
+        }
+        let user_access = self
+            .users
+            .iter()
+            .filter(|(rule_uid, _)| *rule_uid == uid)
+            .map(|(_, access)| *access);
+        let group_access = self
+            .groups
+            .iter()
+            .filter(|(rule_gid, _)| *rule_gid == gid || groups.contains(rule_gid))
+            .map(|(_, access)| *access);
+        user_access
+            .chain(group_access)
+            .max()
+            .unwrap_or(Access::None)
+    }
+}
+
+/// 接続した時点の接続元の補助グループ（SO_PEERGROUPS）。
+/// `/proc/<pid>` と違い、接続後にプロセスが入れ替わっても偽れない。
+fn peer_groups(stream: &UnixStream) -> io::Result<Vec<u32>> {
+    let fd = stream.as_raw_fd();
+    let mut groups = vec![0 as libc::gid_t; 64];
+    loop {
+        let mut len = (groups.len() * std::mem::size_of::<libc::gid_t>()) as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PEERGROUPS,
+                groups.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        if rc == 0 {
+            groups.truncate(len as usize / std::mem::size_of::<libc::gid_t>());
+            return Ok(groups);
+        }
+        let error = io::Error::last_os_error();
+        // 足りなければ必要な長さが `len` に入る
+        if error.raw_os_error() != Some(libc::ERANGE) {
+            return Err(error);
+        }
+        let needed = len as usize / std::mem::size_of::<libc::gid_t>();
+        groups.resize(needed.max(groups.len() * 2), 0);
+    }
+}
+
+/// 数字ならそのまま UID として使う
+pub fn lookup_user(name: &str) -> Result<u32, String> {
+    if let Ok(uid) = name.parse() {
+        return Ok(uid);
+    }
+    let c_name = CString::new(name).map_err(|_| format!("invalid user name {:?}", name))?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match rc {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if !result.is_null() => return Ok(passwd.pw_uid),
+            0 => return Err(format!("unknown user {:?}", name)),
+            errno => {
+                return Err(format!(
+                    "failed to look up user {:?}: {}",
+                    name,
+                    io::Error::from_raw_os_error(errno)
+                ))
+            }
+        }
+    }
+}
+
+/// 数字ならそのまま GID として使う
+pub fn lookup_group(name: &str) -> Result<u32, String> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let c_name = CString::new(name).map_err(|_| format!("invalid group name {:?}", name))?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut group: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                &mut group,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match rc {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if !result.is_null() => return Ok(group.gr_gid),
+            0 => return Err(format!("unknown group {:?}", name)),
+            errno => {
+                return Err(format!(
+                    "failed to look up group {:?}: {}",
+                    name,
+                    io::Error::from_raw_os_error(errno)
+                ))
+            }
+        }
+    }
+}
+
+/// UNIX ソケットの接続元（資格情報を読めなければ `None`）
+#[derive(Debug, Clone, Copy)]
+pub struct UnixPeer {
+    pub uid: Option<u32>,
+    pub pid: Option<i32>,
+    /// 接続元のユーザー・グループから決めた権限
+    pub access: Access,
+}
+
+/// 接続ごとに SO_PEERCRED を読み、接続元の権限を決める UNIX ソケットの待ち受け
+pub struct UnixSocketListener {
+    listener: UnixListener,
+    policy: Arc<PeerPolicy>,
+}
+
+impl UnixSocketListener {
+    /// `path` にソケットを作り、モードとグループを設定する。
+    /// 前回の起動で残ったソケットファイルは削除してから作り直す。
+    pub fn bind(
+        path: &Path,
+        config: &UnixSocketConfig,
+        policy: Arc<PeerPolicy>,
+    ) -> io::Result<Self> {
+        let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+        let mode = config.mode().map_err(invalid)?;
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        // 作った直後のソケットに他のユーザーが先に接続しないよう、
+        // 所有者だけが読み書きできる状態で作ってからグループとモードを設定する
+        let previous = unsafe { libc::umask(0o177) };
+        let listener = UnixListener::bind(path);
+        unsafe { libc::umask(previous) };
+        let listener = listener?;
+        if !config.group.is_empty() {
+            let gid = lookup_group(&config.group).map_err(invalid)?;
+            std::os::unix::fs::chown(path, None, Some(gid))?;
+        }
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        Ok(Self { listener, policy })
+    }
+}
+
+impl Listener for UnixSocketListener {
+    type Io = UnixStream;
+    type Addr = UnixPeer;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    let cred = stream.peer_cred();
+                    if let Err(e) = &cred {
+                        debug!(error = %e, "failed to read unix socket peer credentials");
+                    }
+                    let cred = cred.ok();
+                    let groups = peer_groups(&stream).unwrap_or_else(|e| {
+                        debug!(error = %e, "failed to read unix socket peer groups");
+                        Vec::new()
+                    });
+                    let peer = UnixPeer {
+                        uid: cred.map(|cred| cred.uid()),
+                        pid: cred.and_then(|cred| cred.pid()),
+                        access: cred.map_or(Access::None, |cred| {
+                            self.policy.access(cred.uid(), cred.gid(), &groups)
+                        }),
+                    };
+                    debug!(uid = ?peer.uid, pid = ?peer.pid, access = %peer.access, "accepted unix socket connection");
+                    return (stream, peer);
+                }
+                Err(e) => {
+                    // ファイルディスクリプタの枯渇などは少し待ってから再試行する
+                    warn!(error = %e, "failed to accept unix socket connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    /// 待ち受け側には接続元の資格情報がないため、ソケットが有効かだけを確かめる
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.listener.local_addr()?;
+        Ok(UnixPeer {
+            uid: None,
+            pid: None,
+            access: Access::None,
+        })
+    }
+}
+
+impl Connected<IncomingStream<'_, UnixSocketListener>> for PeerAccess {
+    fn connect_info(stream: IncomingStream<'_, UnixSocketListener>) -> Self {
+        Self(stream.remote_addr().access)
+    }
+}
@@ -1,23 +1,47 @@
+mod alerts;
+mod auth;
+mod config;
+mod drop_log;
+mod grpc;
+mod listen;
+mod metrics;
+#[cfg(feature = "otel")]
+mod otel;
+mod query;
 mod routes;
+mod shutdown;
+mod stream;
+mod systemd;
+mod tls;
 mod ws;
+mod ws_compression;
 mod ws_proto;
 
 #[cfg(debug_assertions)]
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
+use axum::extract::connect_info::Connected;
 use axum::http::{header, StatusCode, Uri};
 use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
-use axum::Router;
-use clap::Parser;
+use axum::serve::{IncomingStream, Listener};
+use axum::{middleware, Extension, Router};
+use clap::{Parser, ValueEnum};
+use futures_util::future::BoxFuture;
 #[cfg(not(debug_assertions))]
 use rust_embed::Embed;
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 use tracing_subscriber::filter::{filter_fn, LevelFilter};
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{fmt, EnvFilter};
 
+use scrop_capture::alerts::AlertRule;
+use scrop_capture::enrich::{self, EnrichSource};
+use scrop_capture::geoip::GeoIpEnricher;
+use scrop_capture::names::{NameEnricher, NameResolutionConfig};
 use scrop_capture::AppState;
 
 #[cfg(not(debug_assertions))]
@@ -25,16 +49,219 @@ use scrop_capture::AppState;
 #[folder = "../dist/"]
 struct Assets;
 
+/// WebSocket のフレーム圧縮方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum WsCompression {
+    None,
+    Deflate,
+    Zstd,
+}
+
 #[derive(Parser)]
 #[command(name = "scrop-server", about = "Scrop packet capture web server")]
 struct Cli {
-    /// Host address to bind to
-    #[arg(long, default_value = "127.0.0.1")]
-    host: String,
+    #[command(flatten)]
+    config: config::ConfigArgs,
+
+    /// Enrich packets with container/pod metadata from SOURCE (proc, cri or file:<path>).
+    /// May be given multiple times; earlier sources take precedence.
+    #[arg(long = "enrich", value_name = "SOURCE")]
+    enrich: Vec<EnrichSource>,
+
+    /// Attribute delivered and socket-dropped packets to the receiving process
+    /// (pid, comm, cgroup id) using socket receive kprobes
+    #[arg(long)]
+    process_attribution: bool,
+
+    /// Add host names from /etc/hosts and service names from /etc/services
+    #[arg(long)]
+    resolve_names: bool,
+
+    /// Also resolve host names with reverse DNS (implies --resolve-names)
+    #[arg(long)]
+    reverse_dns: bool,
+
+    /// Seconds to cache reverse DNS answers
+    #[arg(long, value_name = "SECS", default_value_t = 300)]
+    dns_ttl: u64,
+
+    /// Seconds to cache failed or empty reverse DNS lookups
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    dns_negative_ttl: u64,
+
+    /// CSV of `ip,name` pairs that take precedence over other name sources
+    /// (implies --resolve-names)
+    #[arg(long, value_name = "PATH")]
+    names_csv: Option<PathBuf>,
+
+    /// MaxMind-format country database (GeoLite2/GeoIP2 Country or City)
+    #[arg(long, value_name = "PATH")]
+    geoip_db: Option<PathBuf>,
+
+    /// MaxMind-format ASN database (GeoLite2 ASN)
+    #[arg(long, value_name = "PATH")]
+    asn_db: Option<PathBuf>,
+
+    /// Also serve the ScropCapture gRPC API on ADDR (`host:port` or `unix:<path>`)
+    #[arg(long, value_name = "ADDR")]
+    grpc_listen: Option<grpc::GrpcListen>,
+
+    /// Frame compression codecs WebSocket clients may negotiate in their hello
+    /// (comma-separated; `none` disables compression)
+    #[arg(
+        long,
+        value_name = "CODECS",
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [WsCompression::Zstd, WsCompression::Deflate]
+    )]
+    ws_compression: Vec<WsCompression>,
+
+    /// Highest compression level a WebSocket client may request; caps per-client CPU usage
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        default_value_t = 3,
+        value_parser = clap::value_parser!(i32).range(1..=22)
+    )]
+    ws_compression_max_level: i32,
+
+    /// WebSocket frames smaller than this many bytes are sent uncompressed
+    #[arg(long, value_name = "BYTES", default_value_t = 256)]
+    ws_compression_min_bytes: usize,
+
+    /// Alert rule as `[NAME=]EXPR`, e.g. `fw drops on eth0 > 100/s for 30s`,
+    /// `new drop reason` or `ringbuf drops > 0`. May be given multiple times.
+    #[arg(long = "alert-rule", value_name = "RULE")]
+    alert_rules: Vec<AlertRule>,
+
+    /// POST each firing and resolved alert as JSON to this http:// URL.
+    /// May be given multiple times.
+    #[arg(long = "alert-webhook", value_name = "URL")]
+    alert_webhooks: Vec<alerts::WebhookUrl>,
 
-    /// Port to listen on
-    #[arg(long, default_value_t = 3000)]
-    port: u16,
+    /// Milliseconds between alert rule evaluations
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    alert_interval_ms: u64,
+
+    /// Write every dropped packet as a structured event to TARGET: `file:<path>`
+    /// (rotating JSON lines), `syslog[:<socket>]` or `journald[:<socket>]`.
+    /// May be given multiple times.
+    #[arg(long = "drop-log", value_name = "TARGET")]
+    drop_logs: Vec<drop_log::DropLogTarget>,
+
+    /// Rotate the JSON-lines drop log when it would grow past this many bytes
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 64 * 1024 * 1024,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    drop_log_max_bytes: u64,
+
+    /// Number of rotated JSON-lines drop log files to keep
+    #[arg(long, value_name = "N", default_value_t = 5)]
+    drop_log_keep: usize,
+
+    /// Log at most N dropped packets per second; the rest are written as a
+    /// per-reason summary once a second (0 logs every drop)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    drop_log_rate: u64,
+
+    /// Export metrics and sampled dropped packets to this OTLP/gRPC collector
+    /// (e.g. `http://localhost:4317`)
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
+    /// Milliseconds between OTLP metric exports
+    #[cfg(feature = "otel")]
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 10_000,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    otlp_interval_ms: u64,
+
+    /// Export one in N dropped packets as OTLP span events
+    #[cfg(feature = "otel")]
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    otlp_drop_sample: u64,
+}
+
+impl Cli {
+    fn name_resolution(&self) -> Option<NameResolutionConfig> {
+        if !self.resolve_names && !self.reverse_dns && self.names_csv.is_none() {
+            return None;
+        }
+        Some(NameResolutionConfig {
+            reverse_dns: self.reverse_dns,
+            dns_ttl: Duration::from_secs(self.dns_ttl),
+            dns_negative_ttl: Duration::from_secs(self.dns_negative_ttl),
+            names_csv: self.names_csv.clone(),
+            ..NameResolutionConfig::default()
+        })
+    }
+
+    #[cfg(feature = "otel")]
+    fn otel_config(&self) -> Option<otel::OtelConfig> {
+        Some(otel::OtelConfig {
+            endpoint: self.otlp_endpoint.clone()?,
+            export_interval: Duration::from_millis(self.otlp_interval_ms),
+            drop_sample: self.otlp_drop_sample,
+        })
+    }
+
+    fn alert_config(&self) -> alerts::AlertConfig {
+        alerts::AlertConfig {
+            rules: self.alert_rules.clone(),
+            webhooks: self.alert_webhooks.clone(),
+            interval: Duration::from_millis(self.alert_interval_ms),
+        }
+    }
+
+    fn drop_log_config(&self) -> Option<drop_log::DropLogConfig> {
+        if self.drop_logs.is_empty() {
+            return None;
+        }
+        Some(drop_log::DropLogConfig {
+            targets: self.drop_logs.clone(),
+            max_file_bytes: self.drop_log_max_bytes,
+            keep_files: self.drop_log_keep,
+            max_per_sec: self.drop_log_rate,
+        })
+    }
+
+    fn ws_config(&self) -> ws::WsConfig {
+        let codecs = self
+            .ws_compression
+            .iter()
+            .map(|codec| match codec {
+                WsCompression::None => None,
+                WsCompression::Deflate => Some(ws_proto::pb::Compression::Deflate),
+                WsCompression::Zstd => Some(ws_proto::pb::Compression::Zstd),
+            })
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default();
+        ws::WsConfig {
+            compression: ws_compression::CompressionConfig {
+                codecs,
+                max_level: self.ws_compression_max_level,
+                min_bytes: self.ws_compression_min_bytes,
+            },
+        }
+    }
 }
 
 fn init_tracing() {
@@ -99,6 +326,26 @@ async fn main() {
     init_tracing();
 
     let cli = Cli::parse();
+    let config = match cli.config.load(|name| std::env::var(name).ok()) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to load configuration");
+            std::process::exit(1);
+        }
+    };
+    if cli.config.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+    let shutdown = match shutdown::Shutdown::on_signals() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to install signal handlers");
+            std::process::exit(1);
+        }
+    };
+    // キャプチャの最後のバッチを書き終えてから通知先を閉じるため、別の合図にする
+    let (sinks_shutdown_tx, sinks_shutdown) = shutdown::Shutdown::channel();
 
     #[cfg(feature = "ebpf")]
     if let Err(e) = scrop_capture::check_permissions() {
@@ -106,7 +353,122 @@ async fn main() {
         std::process::exit(1);
     }
 
-    let state = Arc::new(AppState::new());
+    let state = Arc::new(AppState::with_config(&config.capture));
+
+    if !config.attach.is_empty() {
+        if let Err(e) = state
+            .capture
+            .lock()
+            .await
+            .set_auto_attach_rules(config.attach.clone())
+            .await
+        {
+            tracing::error!(error = %e, "failed to set auto-attach rules");
+            std::process::exit(1);
+        }
+        info!(rules = ?config.attach, "auto-attach rules set");
+    }
+
+    let mut pipeline = enrich::build_pipeline(&cli.enrich);
+    if let Some(config) = cli.name_resolution() {
+        pipeline.push(Arc::new(NameEnricher::new(&config)));
+    }
+    if cli.geoip_db.is_some() || cli.asn_db.is_some() {
+        match GeoIpEnricher::open(cli.geoip_db.as_deref(), cli.asn_db.as_deref()) {
+            Ok(geoip) => pipeline.push(Arc::new(geoip)),
+            Err(e) => {
+                tracing::error!(error = %e, "failed to open GeoIP database");
+                std::process::exit(1);
+            }
+        }
+    }
+    if !pipeline.is_empty() {
+        info!(stages = ?pipeline.stage_names(), "packet enrichment enabled");
+        state.capture.lock().await.set_enrichment(pipeline);
+    }
+
+    if cli.process_attribution {
+        info!("process attribution enabled");
+        state.capture.lock().await.set_process_attribution(true);
+    }
+
+    state.spawn_status_publisher(Duration::from_millis(config.stats_interval_ms));
+
+    let drop_log_task = cli.drop_log_config().map(|config| {
+        match drop_log::DropLogger::open(&config) {
+            Ok(logger) => {
+                info!(targets = ?config.targets.iter().map(ToString::to_string).collect::<Vec<_>>(), "drop log enabled");
+                drop_log::spawn(logger, &state, sinks_shutdown.clone())
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to open drop log");
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let alert_config = cli.alert_config();
+    let alert_manager = Arc::new(alerts::AlertManager::new(&alert_config, &state));
+    if alert_manager
+        .spawn(state.clone(), alert_config.interval)
+        .is_some()
+    {
+        info!(
+            rules = alert_config.rules.len(),
+            webhooks = alert_config.webhooks.len(),
+            "alerting enabled"
+        );
+    }
+
+    #[cfg(feature = "otel")]
+    let otel_exporter = cli
+        .otel_config()
+        .map(|config| match otel::start(&config, state.clone()) {
+            Ok(exporter) => {
+                info!(endpoint = %config.endpoint, "OTLP export enabled");
+                exporter
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to start OTLP export");
+                std::process::exit(1);
+            }
+        });
+
+    let listeners = config.listeners();
+    let unix_peers = !config.unix.peers.is_empty()
+        && listeners
+            .iter()
+            .any(|listen| matches!(listen, listen::ListenAddr::Unix(_)));
+    let auth = Arc::new(auth::AuthConfig {
+        peer_auth: config.tls.client_auth() || unix_peers,
+        ..config.auth.clone()
+    });
+    if auth.is_enabled() {
+        info!(
+            tokens = auth.tokens.len(),
+            users = auth.users.len(),
+            client_certificates = config.tls.client_auth(),
+            unix_peers = config.unix.peers.len(),
+            anonymous = %auth.anonymous,
+            "authentication enabled"
+        );
+    }
+
+    if let Some(listen) = cli.grpc_listen.clone() {
+        if let Err(e) = grpc::check_auth(&auth) {
+            tracing::error!(error = %e, addr = %listen, "refusing to start gRPC server");
+            std::process::exit(1);
+        }
+        let state = state.clone();
+        let auth = auth.clone();
+        info!(addr = %listen, "scrop gRPC server listening");
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(&listen, state, auth).await {
+                tracing::error!(error = %e, addr = %listen, "gRPC server failed");
+                std::process::exit(1);
+            }
+        });
+    }
 
     let api_routes = Router::new()
         .route("/capture/start", post(routes::start_capture))
@@ -114,8 +476,20 @@ async fn main() {
         .route("/capture/status", get(routes::get_capture_status))
         .route("/capture/reset", post(routes::reset_capture))
         .route("/interfaces", get(routes::list_interfaces))
+        .route(
+            "/interfaces/rules",
+            get(routes::get_interface_rules).put(routes::update_interface_rules),
+        )
         .route("/interfaces/{name}/attach", post(routes::attach_interface))
-        .route("/interfaces/{name}/detach", post(routes::detach_interface));
+        .route("/interfaces/{name}/detach", post(routes::detach_interface))
+        .route("/netns", get(routes::list_netns))
+        .route("/netns/{id}/interfaces", get(routes::list_netns_interfaces))
+        .route("/stream.ndjson", get(stream::ndjson_handler))
+        .route("/stream/sse", get(stream::sse_handler))
+        .route(
+            "/alerts",
+            get(alerts::get_alerts).layer(Extension(alert_manager)),
+        );
 
     #[cfg(not(feature = "ebpf"))]
     let api_routes = api_routes.route(
@@ -125,16 +499,120 @@ async fn main() {
 
     let app = Router::new()
         .nest("/api", api_routes)
-        .route("/ws", get(ws::ws_handler))
+        .route("/ws", get(ws::ws_handler).layer(Extension(cli.ws_config())))
+        .route("/metrics", get(routes::get_metrics))
+        // 静的ファイルは認証しない（API を呼ぶまでデータは含まない）
+        .route_layer(middleware::from_fn_with_state(auth.clone(), auth::require))
         .fallback(get(static_handler))
-        .with_state(state);
+        .with_state(state.clone());
+
+    let tls = config.tls.is_enabled().then(|| {
+        let tls = match tls::ReloadableTls::load(&config.tls) {
+            Ok(tls) => tls,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to load TLS certificates");
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = tls.reload_on_sighup(config.tls.clone()) {
+            warn!(error = %e, "failed to install SIGHUP handler; TLS certificates will not be reloaded");
+        }
+        tls
+    });
+    let peer_policy = match listen::PeerPolicy::resolve(&config.unix.peers) {
+        Ok(policy) => Arc::new(policy),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to resolve unix socket peers");
+            std::process::exit(1);
+        }
+    };
+    let mut servers: Vec<BoxFuture<'static, std::io::Result<()>>> = Vec::new();
+    for listen in &listeners {
+        if !auth.is_enabled() && !listen.is_local() {
+            warn!(addr = %listen, "listening beyond localhost without authentication; anyone who can reach it can attach XDP programs");
+        }
+        match listen {
+            listen::ListenAddr::Tcp(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr)
+                    .await
+                    .expect("Failed to bind address");
+                if let Some(tls) = &tls {
+                    let listener = tls::TlsListener::new(listener, tls.clone())
+                        .expect("Failed to bind address");
+                    info!(addr = %listen, "scrop server listening (https)");
+                    servers.push(Box::pin(serve(listener, app.clone(), shutdown.clone())));
+                } else {
+                    info!(addr = %listen, "scrop server listening");
+                    servers.push(Box::pin(serve(listener, app.clone(), shutdown.clone())));
+                }
+            }
+            listen::ListenAddr::Unix(path) => {
+                let listener = listen::UnixSocketListener::bind(
+                    path,
+                    &config.unix,
+                    peer_policy.clone(),
+                )
+                .unwrap_or_else(|e| {
+                    tracing::error!(error = %e, addr = %listen, "failed to bind unix socket");
+                    std::process::exit(1);
+                });
+                info!(addr = %listen, mode = %config.unix.mode, "scrop server listening");
+                servers.push(Box::pin(serve(listener, app.clone(), shutdown.clone())));
+            }
+        }
+    }
+    let server = futures_util::future::try_join_all(servers);
+
+    if config.capture_on_start {
+        state.capture.lock().await.start(state.event_tx.clone());
+        info!("capture started on startup");
+    }
+
+    systemd::notify_or_warn("READY=1");
+    systemd::spawn_watchdog();
+
+    tokio::select! {
+        result = server => {
+            result.expect("Server error");
+        }
+        // SSE などの長く続く応答は閉じるのを待たない
+        _ = async {
+            shutdown.wait().await;
+            tokio::time::sleep(shutdown::GRACE_PERIOD).await;
+        } => info!("closing remaining connections"),
+    }
 
-    let addr = format!("{}:{}", cli.host, cli.port);
-    info!(addr = %addr, "scrop server listening");
+    systemd::notify_or_warn("STOPPING=1");
+    for listen in &listeners {
+        if let listen::ListenAddr::Unix(path) = listen {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    state.capture.lock().await.shutdown().await;
+    let _ = sinks_shutdown_tx.send(true);
+    if let Some(task) = drop_log_task {
+        let _ = task.await;
+    }
 
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .expect("Failed to bind address");
+    #[cfg(feature = "otel")]
+    if let Some(exporter) = otel_exporter {
+        let _ = tokio::task::spawn_blocking(move || exporter.shutdown()).await;
+    }
+    info!("scrop server stopped");
+}
 
-    axum::serve(listener, app).await.expect("Server error");
+/// `listener` で `app` を提供し、終了の合図で新しい接続の受け付けをやめる。
+/// 接続ごとの権限（`PeerAccess`）は待ち受けの種類から決まる。
+async fn serve<L>(listener: L, app: Router, shutdown: shutdown::Shutdown) -> std::io::Result<()>
+where
+    L: Listener,
+    L::Addr: std::fmt::Debug,
+    for<'a> auth::PeerAccess: Connected<IncomingStream<'a, L>>,
+{
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<auth::PeerAccess>(),
+    )
+    .with_graceful_shutdown(shutdown.wait())
+    .await
 }
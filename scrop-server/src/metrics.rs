@@ -0,0 +1,340 @@
+//! `/metrics` の OpenMetrics テキスト形式のエンコード。
+//!
+//! `CaptureStats` の合計値・診断カウンタと、`CaptureMetrics` のラベル別件数・ヒストグラム、
+//! ストリーム購読クライアントの数を 1 つの応答にまとめる。
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+
+use scrop_capture::clients::ClientStats;
+use scrop_capture::metrics::{HistogramSnapshot, MetricsSnapshot};
+use scrop_capture::types::StatusSnapshot;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// 購読がなくても 0 として出力するクライアントの種類
+const CLIENT_KINDS: &[&str] = &["websocket", "ndjson", "sse", "grpc"];
+
+const RESULTS: &[&str] = &["delivered", "nic-drop", "fw-drop"];
+
+pub fn render(
+    status: &StatusSnapshot,
+    metrics: &MetricsSnapshot,
+    clients: &[ClientStats],
+) -> String {
+    let stats = &status.stats;
+    let mut w = Writer::default();
+
+    w.family(
+        "scrop_capture_running",
+        "gauge",
+        "Whether capture is running.",
+    );
+    w.sample(
+        "scrop_capture_running",
+        &[("mode", &status.mode)],
+        u64::from(status.is_capturing),
+    );
+
+    w.family("scrop_packets", "counter", "Packets with a final result.");
+    w.sample("scrop_packets_total", &[], stats.total_packets);
+    w.family(
+        "scrop_packets_by_result",
+        "counter",
+        "Packets by final result.",
+    );
+    for (result, value) in
+        RESULTS
+            .iter()
+            .zip([stats.delivered, stats.nic_dropped, stats.fw_dropped])
+    {
+        w.sample(
+            "scrop_packets_by_result_total",
+            &[("result", result)],
+            value,
+        );
+    }
+    w.family(
+        "scrop_interface_packets",
+        "counter",
+        "Packets by interface, result, drop reason and protocol.",
+    );
+    for (labels, value) in &metrics.packets {
+        w.sample(
+            "scrop_interface_packets_total",
+            &[
+                ("interface", &labels.interface),
+                ("result", labels.result),
+                ("reason", &labels.reason),
+                ("protocol", labels.protocol),
+            ],
+            value,
+        );
+    }
+    w.family(
+        "scrop_ringbuf_dropped_events",
+        "counter",
+        "Events the kernel could not write to the ring buffer.",
+    );
+    w.sample(
+        "scrop_ringbuf_dropped_events_total",
+        &[],
+        stats.transport_dropped,
+    );
+
+    if !stats.packets_by_country.is_empty() {
+        w.family(
+            "scrop_packets_by_country",
+            "counter",
+            "Packets by source (or destination) country.",
+        );
+        for (country, value) in &stats.packets_by_country {
+            w.sample(
+                "scrop_packets_by_country_total",
+                &[("country", country)],
+                value,
+            );
+        }
+    }
+    if !stats.packets_by_asn.is_empty() {
+        w.family(
+            "scrop_packets_by_asn",
+            "counter",
+            "Packets by source (or destination) autonomous system.",
+        );
+        for (asn, value) in &stats.packets_by_asn {
+            w.sample(
+                "scrop_packets_by_asn_total",
+                &[("asn", &asn.to_string())],
+                value,
+            );
+        }
+    }
+
+    w.histogram(
+        "scrop_correlation_latency_seconds",
+        "seconds",
+        "Time from XDP pass to the packet's final result.",
+        &metrics.correlation_latency_ns,
+        |ns| ns as f64 / 1e9,
+    );
+    w.histogram(
+        "scrop_batch_size_packets",
+        "packets",
+        "Packets per batch sent to stream clients.",
+        &metrics.batch_size,
+        |n| n as f64,
+    );
+
+    w.counter_seconds(
+        "scrop_reader_send_wait_seconds",
+        "Time the ring buffer reader waited on the correlator channel.",
+        stats.reader_send_wait_raw_ns,
+    );
+    w.counter(
+        "scrop_reader_send_wait_batches",
+        "Batches the ring buffer reader sent to the correlator.",
+        stats.reader_send_wait_batch_count,
+    );
+    w.counter(
+        "scrop_reader_send_wait_events",
+        "Events the ring buffer reader sent to the correlator.",
+        stats.reader_send_wait_event_count,
+    );
+    w.counter(
+        "scrop_correlator_remove_scan_steps",
+        "Pending entries scanned while matching drops.",
+        stats.correlator_remove_scan_steps,
+    );
+    w.counter(
+        "scrop_correlator_remove_calls",
+        "Pending entries removed by drop matching.",
+        stats.correlator_remove_calls,
+    );
+    w.counter(
+        "scrop_correlator_timeout_drains",
+        "Timeout drain passes of the correlator.",
+        stats.correlator_timeout_drain_calls,
+    );
+    w.counter(
+        "scrop_correlator_timeout_expired_packets",
+        "Packets resolved as delivered by timeout.",
+        stats.correlator_timeout_expired_packets,
+    );
+    w.family(
+        "scrop_correlator_timeout_expired_max_batch",
+        "gauge",
+        "Largest number of packets expired in one drain pass.",
+    );
+    w.sample(
+        "scrop_correlator_timeout_expired_max_batch",
+        &[],
+        stats.correlator_timeout_expired_max_batch,
+    );
+
+    w.family(
+        "scrop_shadow_compare_enabled",
+        "gauge",
+        "Whether the legacy shadow correlator is running.",
+    );
+    w.sample(
+        "scrop_shadow_compare_enabled",
+        &[],
+        stats.shadow_compare_enabled,
+    );
+    w.counter(
+        "scrop_shadow_pairs",
+        "Packets resolved by both correlators.",
+        stats.shadow_pairs_total,
+    );
+    w.family(
+        "scrop_shadow_unmatched",
+        "counter",
+        "Packets resolved by only one correlator.",
+    );
+    w.sample(
+        "scrop_shadow_unmatched_total",
+        &[("correlator", "legacy")],
+        stats.shadow_unmatched_legacy,
+    );
+    w.sample(
+        "scrop_shadow_unmatched_total",
+        &[("correlator", "ktime")],
+        stats.shadow_unmatched_ktime,
+    );
+    w.family(
+        "scrop_shadow_results",
+        "counter",
+        "Result pairs of the legacy and ktime correlators.",
+    );
+    let shadow = [
+        stats.shadow_legacy_delivered_ktime_delivered,
+        stats.shadow_legacy_delivered_ktime_nic_drop,
+        stats.shadow_legacy_delivered_ktime_fw_drop,
+        stats.shadow_legacy_nic_drop_ktime_delivered,
+        stats.shadow_legacy_nic_drop_ktime_nic_drop,
+        stats.shadow_legacy_nic_drop_ktime_fw_drop,
+        stats.shadow_legacy_fw_drop_ktime_delivered,
+        stats.shadow_legacy_fw_drop_ktime_nic_drop,
+        stats.shadow_legacy_fw_drop_ktime_fw_drop,
+    ];
+    for (i, value) in shadow.into_iter().enumerate() {
+        w.sample(
+            "scrop_shadow_results_total",
+            &[("legacy", RESULTS[i / 3]), ("ktime", RESULTS[i % 3])],
+            value,
+        );
+    }
+
+    w.counter_seconds(
+        "scrop_status_lock_wait_seconds",
+        "Time status requests waited for the capture lock.",
+        stats.status_lock_wait_ns,
+    );
+    w.counter(
+        "scrop_status_lock_wait_samples",
+        "Status requests that waited for the capture lock.",
+        stats.status_lock_wait_samples,
+    );
+    w.counter_seconds(
+        "scrop_status_lock_hold_seconds",
+        "Time status requests held the capture lock.",
+        stats.status_lock_hold_ns,
+    );
+    w.counter(
+        "scrop_status_lock_hold_samples",
+        "Status requests that held the capture lock.",
+        stats.status_lock_hold_samples,
+    );
+
+    let mut by_kind: BTreeMap<&str, u64> = CLIENT_KINDS.iter().map(|&k| (k, 0)).collect();
+    for client in clients {
+        *by_kind.entry(client.kind.as_str()).or_default() += 1;
+    }
+    w.family(
+        "scrop_stream_clients",
+        "gauge",
+        "Connected stream clients by transport.",
+    );
+    for (kind, value) in by_kind {
+        w.sample("scrop_stream_clients", &[("kind", kind)], value);
+    }
+
+    w.out.push_str("# EOF\n");
+    w.out
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+}
+
+impl Writer {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+    }
+
+    /// 単位付きのファミリー。OpenMetrics では名前の末尾が `_<unit>` でなければならない。
+    fn family_with_unit(&mut self, name: &str, kind: &str, unit: &str, help: &str) {
+        debug_assert!(name.ends_with(&format!("_{}", unit)));
+        self.family(name, kind, help);
+        let _ = writeln!(self.out, "# UNIT {} {}", name, unit);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (i, (key, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                let _ = write!(self.out, "{}=\"{}\"", key, escape_label(value));
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.family(name, "counter", help);
+        self.sample(&format!("{}_total", name), &[], value);
+    }
+
+    fn counter_seconds(&mut self, name: &str, help: &str, nanos: u64) {
+        self.family_with_unit(name, "counter", "seconds", help);
+        self.sample(&format!("{}_total", name), &[], nanos as f64 / 1e9);
+    }
+
+    fn histogram(
+        &mut self,
+        name: &str,
+        unit: &str,
+        help: &str,
+        histogram: &HistogramSnapshot,
+        scale: impl Fn(u64) -> f64,
+    ) {
+        self.family_with_unit(name, "histogram", unit, help);
+        let bucket = format!("{}_bucket", name);
+        for &(bound, count) in &histogram.buckets {
+            self.sample(&bucket, &[("le", &scale(bound).to_string())], count);
+        }
+        self.sample(&bucket, &[("le", "+Inf")], histogram.count);
+        self.sample(&format!("{}_count", name), &[], histogram.count);
+        self.sample(&format!("{}_sum", name), &[], scale(histogram.sum));
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,277 @@
+//! OTLP（gRPC）でのメトリクスとドロップパケットのエクスポート（`otel` フィーチャー）。
+//!
+//! メトリクスは次の値を observable instrument として定期的に送る。
+//!
+//! - `scrop.packets`: 結果ごとのパケット数
+//! - `scrop.interface.packets`: インターフェース・結果・ドロップ理由・プロトコルごとのパケット数
+//! - `scrop.ringbuf.dropped_events`: リングバッファに書けなかったイベント数
+//! - `scrop.stream.clients`: 接続の種類ごとの購読クライアント数
+//!
+//! 相関の遅延やバッチサイズのヒストグラム、診断用のカウンタは送らない（`/metrics` で参照する）。
+//! フロー（5-tuple）ごとのメトリクスもない。個々のフローは、ドロップしたパケットを
+//! `1/drop_sample` に間引いてバッチごとのスパンのイベントとして送ることで追える。
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use opentelemetry::metrics::{Meter, MeterProvider as _};
+use opentelemetry::trace::{Span as _, Tracer as _, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use scrop_capture::metrics::result_label;
+use scrop_capture::types::{CapturedPacket, PacketResult, Protocol, StatusSnapshot};
+use scrop_capture::AppState;
+
+const SERVICE_NAME: &str = "scrop";
+const SCOPE_NAME: &str = "scrop";
+
+/// エクスポートの設定
+#[derive(Debug, Clone)]
+pub struct OtelConfig {
+    /// OTLP/gRPC のコレクター（例: `http://localhost:4317`）
+    pub endpoint: String,
+    /// メトリクスを送る間隔
+    pub export_interval: Duration,
+    /// ドロップしたパケットを何個に 1 個スパンイベントとして送るか（1 ならすべて）
+    pub drop_sample: u64,
+}
+
+/// 起動したエクスポーター。`shutdown` で残りを送ってから止める。
+pub struct OtelExporter {
+    meter_provider: SdkMeterProvider,
+    tracer_provider: SdkTracerProvider,
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+}
+
+impl OtelExporter {
+    /// 送信待ちのメトリクスとスパンを送ってから停止する。コレクターへの送信を待つため、
+    /// 非同期タスクからは `spawn_blocking` で呼ぶ。
+    pub fn shutdown(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if let Err(e) = self.tracer_provider.shutdown() {
+            warn!(error = %e, "failed to flush OTLP spans");
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            warn!(error = %e, "failed to flush OTLP metrics");
+        }
+    }
+}
+
+/// エクスポーターを起動する。tonic のクライアントを作るため Tokio ランタイム内で呼ぶ。
+pub fn start(config: &OtelConfig, state: Arc<AppState>) -> Result<OtelExporter, String> {
+    let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+
+    let metric_exporter = MetricExporter::builder()
+        .with_tonic()
+        .with_endpoint(&config.endpoint)
+        .build()
+        .map_err(|e| format!("failed to build OTLP metric exporter: {}", e))?;
+    let reader = PeriodicReader::builder(metric_exporter)
+        .with_interval(config.export_interval)
+        .build();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource.clone())
+        .build();
+
+    let span_exporter = SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&config.endpoint)
+        .build()
+        .map_err(|e| format!("failed to build OTLP span exporter: {}", e))?;
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(span_exporter)
+        .with_resource(resource)
+        .build();
+
+    // observable instrument のコールバックは同期なので、キャプチャのロックを取らずに
+    // 状態の定期通知（`status_tx`）の最新値を使う
+    let latest_status = Arc::new(Mutex::new(None::<StatusSnapshot>));
+    register_metrics(
+        &meter_provider.meter(SCOPE_NAME),
+        &state,
+        Arc::clone(&latest_status),
+    );
+
+    let tasks = vec![
+        spawn_status_listener(&state, latest_status),
+        spawn_drop_exporter(
+            &state,
+            tracer_provider.tracer(SCOPE_NAME),
+            config.drop_sample.max(1),
+        ),
+    ];
+    Ok(OtelExporter {
+        meter_provider,
+        tracer_provider,
+        tasks,
+    })
+}
+
+fn spawn_status_listener(
+    state: &AppState,
+    latest_status: Arc<Mutex<Option<StatusSnapshot>>>,
+) -> tokio::task::JoinHandle<()> {
+    let mut rx = state.status_tx.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(status) => *latest_status.lock().unwrap() = Some(status),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            }
+        }
+    })
+}
+
+fn register_metrics(
+    meter: &Meter,
+    state: &Arc<AppState>,
+    latest_status: Arc<Mutex<Option<StatusSnapshot>>>,
+) {
+    let status = Arc::clone(&latest_status);
+    meter
+        .u64_observable_counter("scrop.packets")
+        .with_description("Packets with a final result, by result")
+        .with_unit("{packet}")
+        .with_callback(move |observer| {
+            let Some(status) = status.lock().unwrap().clone() else {
+                return;
+            };
+            let stats = &status.stats;
+            for (result, value) in [
+                (PacketResult::Delivered, stats.delivered),
+                (PacketResult::NicDrop, stats.nic_dropped),
+                (PacketResult::FwDrop, stats.fw_dropped),
+            ] {
+                observer.observe(value, &[KeyValue::new("result", result_label(&result))]);
+            }
+        })
+        .build();
+
+    let status = latest_status;
+    meter
+        .u64_observable_counter("scrop.ringbuf.dropped_events")
+        .with_description("Events the kernel could not write to the ring buffer")
+        .with_unit("{event}")
+        .with_callback(move |observer| {
+            if let Some(status) = status.lock().unwrap().as_ref() {
+                observer.observe(status.stats.transport_dropped, &[]);
+            }
+        })
+        .build();
+
+    let metrics = Arc::clone(&state.metrics);
+    meter
+        .u64_observable_counter("scrop.interface.packets")
+        .with_description("Packets by interface, result, drop reason and protocol")
+        .with_unit("{packet}")
+        .with_callback(move |observer| {
+            for (labels, value) in metrics.snapshot().packets {
+                observer.observe(
+                    value,
+                    &[
+                        KeyValue::new("network.interface.name", labels.interface),
+                        KeyValue::new("result", labels.result),
+                        KeyValue::new("reason", labels.reason),
+                        KeyValue::new("network.transport", labels.protocol),
+                    ],
+                );
+            }
+        })
+        .build();
+
+    let clients = Arc::clone(&state.clients);
+    meter
+        .u64_observable_gauge("scrop.stream.clients")
+        .with_description("Connected stream clients by transport")
+        .with_unit("{client}")
+        .with_callback(move |observer| {
+            let mut by_kind = std::collections::BTreeMap::<String, u64>::new();
+            for client in clients.snapshot() {
+                *by_kind.entry(client.kind).or_default() += 1;
+            }
+            for (kind, value) in by_kind {
+                observer.observe(value, &[KeyValue::new("kind", kind)]);
+            }
+        })
+        .build();
+}
+
+fn spawn_drop_exporter(
+    state: &AppState,
+    tracer: SdkTracer,
+    drop_sample: u64,
+) -> tokio::task::JoinHandle<()> {
+    let mut rx = state.event_tx.subscribe();
+    let mut drops_seen: u64 = 0;
+    tokio::spawn(async move {
+        loop {
+            let batch = match rx.recv().await {
+                Ok(batch) => batch,
+                Err(RecvError::Lagged(n)) => {
+                    warn!(skipped_batches = n, "OTLP drop exporter lagged");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let mut sampled = Vec::new();
+            for captured in &batch.packets {
+                if captured.result == PacketResult::Delivered {
+                    continue;
+                }
+                if drops_seen.is_multiple_of(drop_sample) {
+                    sampled.push(captured);
+                }
+                drops_seen += 1;
+            }
+            if sampled.is_empty() {
+                continue;
+            }
+            let mut span = tracer.start("scrop.dropped_packets");
+            span.set_attribute(KeyValue::new("scrop.drop.sample_rate", drop_sample as i64));
+            for captured in sampled {
+                span.add_event("packet.dropped", drop_attributes(captured));
+            }
+            span.end();
+        }
+    })
+}
+
+/// ドロップしたパケットのイベント属性（5 タプル・理由・インターフェース）
+fn drop_attributes(captured: &CapturedPacket) -> Vec<KeyValue> {
+    let packet = &captured.packet;
+    let mut attributes = vec![
+        KeyValue::new(
+            "network.transport",
+            match packet.protocol {
+                Protocol::Tcp => "tcp",
+                Protocol::Udp => "udp",
+            },
+        ),
+        KeyValue::new("source.address", packet.source.clone()),
+        KeyValue::new("source.port", i64::from(packet.src_port)),
+        KeyValue::new("destination.address", packet.destination.clone()),
+        KeyValue::new("destination.port", i64::from(packet.dest_port)),
+        KeyValue::new("scrop.drop.result", result_label(&captured.result)),
+        KeyValue::new("packet.id", packet.id.clone()),
+    ];
+    if let Some(reason) = &packet.reason {
+        attributes.push(KeyValue::new("scrop.drop.reason", reason.clone()));
+    }
+    if let Some(interface) = &packet.interface {
+        attributes.push(KeyValue::new("network.interface.name", interface.clone()));
+    }
+    if let Some(netns) = &packet.netns {
+        attributes.push(KeyValue::new("scrop.netns", netns.clone()));
+    }
+    attributes
+}
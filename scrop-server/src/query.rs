@@ -0,0 +1,109 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use scrop_capture::filter::{Ipv4Cidr, PacketFilter, ProcessFilter};
+use scrop_capture::sampling::DeliveryMode;
+use scrop_capture::types::{PacketResult, Protocol};
+
+/// `/ws` とストリーミング API（NDJSON・SSE）で共通の接続パラメータ。
+/// `result`・`protocol`・`port`・`cidr`・`interface` はカンマ区切りで複数指定できる。
+/// 知らないパラメータは指定の誤りとみなして拒否する。
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct StreamQuery {
+    pid: Option<u32>,
+    comm: Option<String>,
+    cgroup_id: Option<u64>,
+    /// `delivered` / `nic-drop` / `fw-drop`
+    result: Option<String>,
+    /// `tcp` / `udp`
+    protocol: Option<String>,
+    /// 送信元・宛先のどちらかのポート
+    port: Option<String>,
+    /// 送信元・宛先のどちらかを含む IPv4 の CIDR
+    cidr: Option<String>,
+    /// 修飾インターフェース名（`<netns>/<ifname>` またはホストの ifname）
+    interface: Option<String>,
+    /// `full` / `sample` / `rateLimit` / `dropsOnly`
+    mode: Option<String>,
+    /// `sample`・`dropsOnly` では N（N 個に 1 個）、`rateLimit` では毎秒のパケット数
+    rate: Option<u32>,
+    /// 認証ミドルウェアが読むトークン（ここでは使わない）
+    #[serde(rename = "access_token")]
+    _access_token: Option<String>,
+}
+
+impl StreamQuery {
+    pub fn delivery_mode(&self) -> Result<DeliveryMode, String> {
+        let rate = || {
+            self.rate
+                .ok_or_else(|| "rate is required for this mode".to_string())
+        };
+        let mode = match self.mode.as_deref() {
+            None | Some("full") => DeliveryMode::Full,
+            Some("sample") => DeliveryMode::Sample { one_in: rate()? },
+            Some("rateLimit") => DeliveryMode::RateLimit {
+                max_packets_per_sec: rate()?,
+            },
+            Some("dropsOnly") => DeliveryMode::DropsOnly {
+                delivered_one_in: rate()?,
+            },
+            Some(other) => return Err(format!("unknown delivery mode: {}", other)),
+        };
+        mode.validate()?;
+        Ok(mode)
+    }
+
+    /// 購読フィルタ
+    pub fn filter(&self) -> Result<PacketFilter, String> {
+        Ok(PacketFilter {
+            results: parse_list(&self.result, parse_result)?,
+            protocols: parse_list(&self.protocol, parse_protocol)?,
+            ports: parse_list(&self.port, |port| {
+                port.parse().map_err(|_| format!("invalid port: {}", port))
+            })?,
+            cidrs: parse_list(&self.cidr, Ipv4Cidr::from_str)?,
+            interfaces: parse_list(&self.interface, |name| Ok(name.to_string()))?,
+            process: ProcessFilter {
+                pid: self.pid,
+                comm: self.comm.clone(),
+                cgroup_id: self.cgroup_id,
+            },
+        })
+    }
+}
+
+/// カンマ区切りの値を読む。空の要素は無視する。
+fn parse_list<T>(
+    value: &Option<String>,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    value
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse)
+        .collect()
+}
+
+fn parse_result(s: &str) -> Result<PacketResult, String> {
+    match s {
+        "delivered" => Ok(PacketResult::Delivered),
+        "nic-drop" => Ok(PacketResult::NicDrop),
+        "fw-drop" => Ok(PacketResult::FwDrop),
+        _ => Err(format!(
+            "unknown packet result {:?}: expected delivered, nic-drop or fw-drop",
+            s
+        )),
+    }
+}
+
+fn parse_protocol(s: &str) -> Result<Protocol, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "tcp" => Ok(Protocol::Tcp),
+        "udp" => Ok(Protocol::Udp),
+        _ => Err(format!("unknown protocol {:?}: expected tcp or udp", s)),
+    }
+}
@@ -2,18 +2,19 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Json, Response};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use scrop_capture::types::CaptureStats;
-use scrop_capture::{AppState, CaptureError};
+use scrop_capture::clients::ClientStats;
+use scrop_capture::types::{CaptureStats, NetnsInfo, StatusSnapshot};
+use scrop_capture::{netns, AppState, CaptureError};
+
+use crate::metrics;
 
 #[cfg(not(feature = "ebpf"))]
 use scrop_capture::mock::MockTrafficProfile;
-#[cfg(not(feature = "ebpf"))]
-use serde::Deserialize;
 
 static STATUS_LOCK_WAIT_NS_TOTAL: AtomicU64 = AtomicU64::new(0);
 static STATUS_LOCK_WAIT_SAMPLES: AtomicU64 = AtomicU64::new(0);
@@ -26,6 +27,8 @@ pub struct CaptureStatusResponse {
     pub is_capturing: bool,
     pub stats: CaptureStats,
     pub mode: String,
+    /// ストリーム購読中のクライアントごとの送信・遅延統計
+    pub clients: Vec<ClientStats>,
 }
 
 #[derive(Serialize)]
@@ -90,6 +93,28 @@ pub async fn stop_capture(
 pub async fn get_capture_status(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<CaptureStatusResponse>, ApiError> {
+    let status = timed_status_snapshot(&state).await;
+    Ok(Json(CaptureStatusResponse {
+        is_capturing: status.is_capturing,
+        stats: status.stats,
+        mode: status.mode,
+        clients: state.clients.snapshot(),
+    }))
+}
+
+/// `/metrics` — 統計とラベル別の件数を OpenMetrics のテキスト形式で返す
+pub async fn get_metrics(State(state): State<Arc<AppState>>) -> Response {
+    let status = timed_status_snapshot(&state).await;
+    let body = metrics::render(
+        &status,
+        &state.metrics.snapshot(),
+        &state.clients.snapshot(),
+    );
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], body).into_response()
+}
+
+/// キャプチャのロックの待ち時間・保持時間を記録しつつ、状態と統計を読む
+async fn timed_status_snapshot(state: &AppState) -> StatusSnapshot {
     let lock_started = Instant::now();
     let capture = state.capture.lock().await;
     let waited_ns = duration_as_u64_ns(lock_started.elapsed());
@@ -108,11 +133,11 @@ pub async fn get_capture_status(
     stats.status_lock_wait_samples = STATUS_LOCK_WAIT_SAMPLES.load(Ordering::Relaxed);
     stats.status_lock_hold_ns = STATUS_LOCK_HOLD_NS_TOTAL.load(Ordering::Relaxed);
     stats.status_lock_hold_samples = STATUS_LOCK_HOLD_SAMPLES.load(Ordering::Relaxed);
-    Ok(Json(CaptureStatusResponse {
+    StatusSnapshot {
         is_capturing,
-        stats,
         mode,
-    }))
+        stats,
+    }
 }
 
 fn duration_as_u64_ns(duration: Duration) -> u64 {
@@ -139,10 +164,34 @@ pub async fn list_interfaces(
     Ok(Json(capture.list_interfaces()))
 }
 
+#[derive(Deserialize)]
+pub struct NetnsQuery {
+    pub netns: Option<String>,
+}
+
+pub async fn list_netns(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<NetnsInfo>>, ApiError> {
+    let capture = state.capture.lock().await;
+    Ok(Json(capture.list_netns()))
+}
+
+pub async fn list_netns_interfaces(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let capture = state.capture.lock().await;
+    Ok(Json(
+        capture.list_netns_interfaces(&id).map_err(ApiError::from)?,
+    ))
+}
+
 pub async fn attach_interface(
     State(state): State<Arc<AppState>>,
     Path(name): Path<String>,
+    Query(query): Query<NetnsQuery>,
 ) -> Result<Json<MessageResponse>, ApiError> {
+    let name = netns::qualify_interface(query.netns.as_deref(), &name);
     let capture = state.capture.lock().await;
     capture
         .attach_interface(&name)
@@ -156,7 +205,9 @@ pub async fn attach_interface(
 pub async fn detach_interface(
     State(state): State<Arc<AppState>>,
     Path(name): Path<String>,
+    Query(query): Query<NetnsQuery>,
 ) -> Result<Json<MessageResponse>, ApiError> {
+    let name = netns::qualify_interface(query.netns.as_deref(), &name);
     let capture = state.capture.lock().await;
     capture
         .detach_interface(&name)
@@ -167,6 +218,35 @@ pub async fn detach_interface(
     }))
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterfaceRulesBody {
+    pub auto_attach: Vec<String>,
+}
+
+pub async fn get_interface_rules(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<InterfaceRulesBody>, ApiError> {
+    let capture = state.capture.lock().await;
+    Ok(Json(InterfaceRulesBody {
+        auto_attach: capture.auto_attach_rules(),
+    }))
+}
+
+pub async fn update_interface_rules(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<InterfaceRulesBody>,
+) -> Result<Json<InterfaceRulesBody>, ApiError> {
+    let capture = state.capture.lock().await;
+    capture
+        .set_auto_attach_rules(req.auto_attach)
+        .await
+        .map_err(ApiError::from)?;
+    Ok(Json(InterfaceRulesBody {
+        auto_attach: capture.auto_attach_rules(),
+    }))
+}
+
 #[cfg(not(feature = "ebpf"))]
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -0,0 +1,46 @@
+//! SIGTERM / SIGINT による終了の通知。
+//!
+//! 受信したら新しい接続の受け付けをやめ、キャプチャを止めて XDP を外し、
+//! 通知先（ドロップログ・OTLP）を書き出してから終了する。
+
+use std::time::Duration;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::info;
+
+/// 終了の合図を受けてから、開いている HTTP 接続（SSE など）の終了を待つ最大時間
+pub const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// 終了の合図。クローンしてタスクごとに待てる。
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// 手動で合図を送る送信側と組で作る
+    pub fn channel() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, Self(rx))
+    }
+
+    /// SIGTERM か SIGINT を受けたら合図する
+    pub fn on_signals() -> std::io::Result<Self> {
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let (tx, shutdown) = Self::channel();
+        tokio::spawn(async move {
+            let name = tokio::select! {
+                _ = sigterm.recv() => "SIGTERM",
+                _ = sigint.recv() => "SIGINT",
+            };
+            info!(signal = name, "shutting down");
+            let _ = tx.send(true);
+        });
+        Ok(shutdown)
+    }
+
+    /// 合図を待つ。送信側がなくなった場合も終了とみなす。
+    pub async fn wait(mut self) {
+        let _ = self.0.wait_for(|triggered| *triggered).await;
+    }
+}
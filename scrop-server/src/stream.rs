@@ -0,0 +1,225 @@
+//! スクリプトや `jq` 向けの JSON ストリーミング（NDJSON・Server-Sent Events）。
+//!
+//! どちらも `CapturedPacketEnvelope` の JSON に間引きの情報（`sampled`・`suppressed`）を加えて 1 バッチずつ送り、
+//! 取りこぼしも通知する。クライアントが切断するまで続ける。
+//!
+//! ```text
+//! {"type":"batch","packets":[...],"epochOffsetMs":0.0,"sampled":false,"suppressed":{...}}
+//! {"type":"gap","skippedBatches":3,"approxPackets":120}
+//! ```
+//! 購読部分（`subscribe_with`）は gRPC のストリームでも使う。
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use scrop_capture::clients::{BatchSizeEstimator, ClientHandle};
+use scrop_capture::filter::PacketFilter;
+use scrop_capture::sampling::{DeliveryMode, Sampler, SuppressedCounts};
+use scrop_capture::types::CapturedPacketEnvelope;
+use scrop_capture::AppState;
+
+use crate::query::StreamQuery;
+
+/// ストリームに流す項目
+pub enum StreamItem {
+    /// `suppressed` は前のバッチを送ってから間引いたパケット数
+    Batch {
+        batch: CapturedPacketEnvelope,
+        sampled: bool,
+        suppressed: SuppressedCounts,
+    },
+    Gap(GapEvent),
+}
+
+/// JSON で送るバッチ。`CapturedPacketEnvelope` に間引きの情報を加える。
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchEvent<'a> {
+    #[serde(flatten)]
+    batch: &'a CapturedPacketEnvelope,
+    /// 間引きが有効な配信モード
+    sampled: bool,
+    suppressed: SuppressedCounts,
+}
+
+/// JSON で送る項目。NDJSON では `type` でバッチと取りこぼしを区別する。
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum JsonItem<'a> {
+    Batch(BatchEvent<'a>),
+    Gap(&'a GapEvent),
+}
+
+impl StreamItem {
+    fn to_json(&self) -> JsonItem<'_> {
+        match self {
+            Self::Batch {
+                batch,
+                sampled,
+                suppressed,
+            } => JsonItem::Batch(BatchEvent {
+                batch,
+                sampled: *sampled,
+                suppressed: *suppressed,
+            }),
+            Self::Gap(gap) => JsonItem::Gap(gap),
+        }
+    }
+}
+
+/// 取りこぼしの通知。SSE では `gap` イベント、NDJSON では `"type":"gap"` の行で送る。
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GapEvent {
+    pub skipped_batches: u64,
+    pub approx_packets: u64,
+}
+
+/// `/api/stream.ndjson?pid=..&mode=..` — 1 行に 1 つの JSON（バッチか取りこぼし）を chunked で送る
+pub async fn ndjson_handler(
+    Query(query): Query<StreamQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let items = match subscribe(&state, &query, "ndjson") {
+        Ok(items) => items,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let lines = items.filter_map(|item| async move {
+        let mut line = serde_json::to_vec(&item.to_json()).ok()?;
+        line.push(b'\n');
+        Some(Ok::<_, Infallible>(Bytes::from(line)))
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+/// `/api/stream/sse?pid=..&mode=..` — バッチを既定の `message` イベント、取りこぼしを `gap` イベントで送る。
+/// バッチには NDJSON と同じく `sampled` と `suppressed` を含める。
+pub async fn sse_handler(
+    Query(query): Query<StreamQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let items = match subscribe(&state, &query, "sse") {
+        Ok(items) => items,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let events = items.filter_map(|item| async move {
+        let event = match item.to_json() {
+            JsonItem::Batch(batch) => Event::default().json_data(batch),
+            JsonItem::Gap(gap) => Event::default().event("gap").json_data(gap),
+        };
+        event.ok().map(Ok::<_, Infallible>)
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// 接続ごとの購読状態。ストリームが drop されると（クライアントの切断）登録も解除される。
+struct Subscription {
+    rx: tokio::sync::broadcast::Receiver<CapturedPacketEnvelope>,
+    filter: PacketFilter,
+    sampler: Sampler,
+    client: ClientHandle,
+    batch_sizes: BatchSizeEstimator,
+}
+
+fn subscribe(
+    state: &AppState,
+    query: &StreamQuery,
+    kind: &str,
+) -> Result<impl Stream<Item = StreamItem>, String> {
+    let mode = query.delivery_mode()?;
+    Ok(subscribe_with(state, query.filter()?, mode, kind))
+}
+
+/// キャプチャのバッチを購読し、フィルタと間引きを適用したストリームにする。
+/// `kind` はクライアント一覧に表示する接続の種類。
+pub fn subscribe_with(
+    state: &AppState,
+    filter: PacketFilter,
+    mode: DeliveryMode,
+    kind: &str,
+) -> impl Stream<Item = StreamItem> + Send + 'static {
+    let subscription = Subscription {
+        sampler: Sampler::new(mode),
+        filter,
+        rx: state.event_tx.subscribe(),
+        client: state.clients.register(kind),
+        batch_sizes: BatchSizeEstimator::default(),
+    };
+    stream::unfold(subscription, |mut sub| async move {
+        let item = sub.next_item().await?;
+        Some((item, sub))
+    })
+}
+
+impl Subscription {
+    /// 次に送る項目。キャプチャのチャネルが閉じたら `None`。
+    async fn next_item(&mut self) -> Option<StreamItem> {
+        loop {
+            match self.rx.recv().await {
+                Ok(batch) => {
+                    self.batch_sizes.record(batch.packets.len());
+                    if let Some(batch) = self.prepare(batch) {
+                        // 前回送信以降に間引いた数を載せる
+                        let suppressed = self.sampler.take_suppressed();
+                        let metrics = self.client.metrics();
+                        metrics.record_sent(batch.packets.len() as u64);
+                        metrics.record_suppressed(suppressed.total());
+                        return Some(StreamItem::Batch {
+                            batch,
+                            sampled: self.sampler.mode() != DeliveryMode::Full,
+                            suppressed,
+                        });
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    let approx_packets = self.batch_sizes.approx_packets(n);
+                    warn!(
+                        client_id = self.client.id(),
+                        skipped_batches = n,
+                        approx_packets,
+                        "stream client lagged"
+                    );
+                    self.client.metrics().record_lag(n, approx_packets);
+                    return Some(StreamItem::Gap(GapEvent {
+                        skipped_batches: n,
+                        approx_packets,
+                    }));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// フィルタと間引きを適用する。送るパケットがなければ `None`。
+    fn prepare(&mut self, batch: CapturedPacketEnvelope) -> Option<CapturedPacketEnvelope> {
+        let mut batch = if self.filter.is_empty() {
+            batch
+        } else {
+            self.filter.apply(&batch)?
+        };
+        if self.sampler.mode() != DeliveryMode::Full {
+            batch.packets = self.sampler.apply(batch.packets, Instant::now());
+        }
+        (!batch.packets.is_empty()).then_some(batch)
+    }
+}
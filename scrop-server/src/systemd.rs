@@ -0,0 +1,63 @@
+//! systemd の `Type=notify` サービスとして動かすための sd_notify。
+//!
+//! `NOTIFY_SOCKET` がなければ何もしないため、systemd の外でもそのまま動く。
+//!
+//! ```ini
+//! [Service]
+//! Type=notify
+//! ExecStart=/usr/local/bin/scrop-server --config /etc/scrop/scrop.toml
+//! WatchdogSec=30
+//! ```
+
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use tracing::warn;
+
+pub const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+pub const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+
+/// `state`（例: `READY=1`）を `NOTIFY_SOCKET` に送る。ソケットが指定されていなければ `Ok(false)`。
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os(NOTIFY_SOCKET_ENV) else {
+        return Ok(false);
+    };
+    notify_to(&path.to_string_lossy(), state)?;
+    Ok(true)
+}
+
+/// `path` が `@` で始まれば抽象ソケットとして扱う
+pub fn notify_to(path: &str, state: &str) -> io::Result<()> {
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// 失敗してもサービスは続けるため、警告だけ出す
+pub fn notify_or_warn(state: &str) {
+    if let Err(e) = notify(state) {
+        warn!(state, error = %e, "failed to notify systemd");
+    }
+}
+
+/// `WATCHDOG_USEC` が指定されていれば、その半分の間隔で `WATCHDOG=1` を送り続ける
+pub fn spawn_watchdog() -> Option<tokio::task::JoinHandle<()>> {
+    let usec: u64 = std::env::var(WATCHDOG_USEC_ENV).ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    let interval = Duration::from_micros(usec / 2);
+    Some(tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            notify_or_warn("WATCHDOG=1");
+        }
+    }))
+}
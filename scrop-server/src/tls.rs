@@ -0,0 +1,231 @@
+//! rustls による TLS の終端と、SIGHUP での証明書の再読み込み。
+//!
+//! `client_ca` を指定すると、その CA が署名したクライアント証明書を出した接続は
+//! 資格情報なしで操作者として扱う（証明書を出さない接続は通常どおりトークンなどで認証する）。
+//!
+//! ```toml
+//! [tls]
+//! cert = "/etc/scrop/tls/server.pem"
+//! key = "/etc/scrop/tls/server.key"
+//! client_ca = "/etc/scrop/tls/operators-ca.pem"
+//! ```
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tracing::{debug, info, warn};
+
+use crate::auth::{Access, PeerAccess};
+
+/// ハンドシェイクを諦めるまでの時間
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// ハンドシェイクを終えて、axum に渡されるのを待つ接続の数
+const PENDING_CONNECTIONS: usize = 64;
+
+/// TLS の設定。空のパスは未指定。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// サーバー証明書（中間証明書を含む PEM）
+    pub cert: PathBuf,
+    /// 秘密鍵（PEM）
+    pub key: PathBuf,
+    /// 操作者のクライアント証明書を署名した CA（PEM）
+    pub client_ca: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.cert.as_os_str().is_empty()
+    }
+
+    pub fn client_auth(&self) -> bool {
+        !self.client_ca.as_os_str().is_empty()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let has_key = !self.key.as_os_str().is_empty();
+        if self.is_enabled() != has_key {
+            return Err("tls cert and key must be given together".to_string());
+        }
+        if self.client_auth() && !self.is_enabled() {
+            return Err("tls client_ca requires cert and key".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// 証明書・秘密鍵・CA を読んで rustls の設定を作る
+pub fn server_config(tls: &TlsConfig) -> Result<rustls::ServerConfig, String> {
+    let certs = read_certs(&tls.cert)?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key).map_err(|e| {
+        format!(
+            "failed to read private key from {}: {}",
+            tls.key.display(),
+            e
+        )
+    })?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?;
+    let builder = if tls.client_auth() {
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(&tls.client_ca)? {
+            roots
+                .add(cert)
+                .map_err(|e| format!("invalid CA in {}: {}", tls.client_ca.display(), e))?;
+        }
+        // 証明書を出さないクライアントも受け入れ、トークンなどで認証する
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+            .allow_unauthenticated()
+            .build()
+            .map_err(|e| e.to_string())?;
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid certificate or key: {}", e))?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// 差し替えられる TLS の設定。新しい接続から反映される。
+#[derive(Clone)]
+pub struct ReloadableTls(Arc<RwLock<Arc<rustls::ServerConfig>>>);
+
+impl ReloadableTls {
+    pub fn load(tls: &TlsConfig) -> Result<Self, String> {
+        Ok(Self(Arc::new(RwLock::new(Arc::new(server_config(tls)?)))))
+    }
+
+    /// 読み直しに失敗したら、それまでの設定を使い続ける
+    pub fn reload(&self, tls: &TlsConfig) -> Result<(), String> {
+        let config = server_config(tls)?;
+        *self.0.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    fn acceptor(&self) -> tokio_rustls::TlsAcceptor {
+        tokio_rustls::TlsAcceptor::from(Arc::clone(&self.0.read().unwrap()))
+    }
+
+    /// SIGHUP を受けるたびに証明書を読み直す
+    pub fn reload_on_sighup(&self, tls: TlsConfig) -> io::Result<tokio::task::JoinHandle<()>> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let reloadable = self.clone();
+        Ok(tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match reloadable.reload(&tls) {
+                    Ok(()) => info!(cert = %tls.cert.display(), "TLS certificates reloaded"),
+                    Err(e) => {
+                        warn!(error = %e, "failed to reload TLS certificates; keeping the previous ones")
+                    }
+                }
+            }
+        }))
+    }
+}
+
+/// TCP で受けた接続の TLS ハンドシェイクを済ませてから axum に渡す待ち受け。
+/// ハンドシェイクは接続ごとのタスクで行い、遅いクライアントが他の接続を待たせないようにする。
+pub struct TlsListener {
+    incoming: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, tls: ReloadableTls) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, incoming) = mpsc::channel(PENDING_CONNECTIONS);
+        tokio::spawn(async move {
+            loop {
+                let (stream, remote) = tokio::select! {
+                    _ = tx.closed() => return,
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            // ファイルディスクリプタの枯渇などは少し待ってから再試行する
+                            warn!(error = %e, "failed to accept TLS connection");
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            continue;
+                        }
+                    },
+                };
+                let acceptor = tls.acceptor();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send((stream, remote)).await;
+                        }
+                        Ok(Err(e)) => debug!(remote = %remote, error = %e, "TLS handshake failed"),
+                        Err(_) => debug!(remote = %remote, "TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(accepted) => accepted,
+            // 受け付けタスクは受信側が残っている間は終わらない
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// 検証済みのクライアント証明書を出した接続は操作者
+impl Connected<IncomingStream<'_, TlsListener>> for PeerAccess {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        let (_, session) = stream.io().get_ref();
+        let verified = session
+            .peer_certificates()
+            .is_some_and(|certs| !certs.is_empty());
+        Self(if verified {
+            Access::Operator
+        } else {
+            Access::None
+        })
+    }
+}
@@ -1,52 +1,323 @@
+use std::borrow::Cow;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
-use axum::extract::State;
-use axum::response::IntoResponse;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
 use prost::Message as _;
-use tracing::warn;
+use tracing::{debug, warn};
 
+use scrop_capture::clients::{BatchSizeEstimator, ClientMetrics};
+use scrop_capture::filter::PacketFilter;
+use scrop_capture::sampling::{DeliveryMode, Sampler};
+use scrop_capture::types::CapturedPacketEnvelope;
 use scrop_capture::AppState;
 
-use crate::ws_proto::batch_to_envelope;
+use crate::query::StreamQuery;
+use crate::ws_compression::{CompressionConfig, Compressor};
+use crate::ws_proto::{
+    delivery_mode_from_proto, encode_outbound, filter_from_proto, negotiate_protocol, pb, Outbound,
+    OutboundBatch, StreamVersion,
+};
 
+/// 接続直後に ClientHello を待つ時間。届かなければ旧来のプロトコルで配信する。
+const HELLO_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// `/ws` のサーバー側設定。ルートに `Extension` として渡す。
+#[derive(Debug, Clone, Default)]
+pub struct WsConfig {
+    pub compression: CompressionConfig,
+}
+
+/// 接続ごとに合意したエンコード方法
+struct StreamEncoder {
+    version: StreamVersion,
+    compressor: Option<Compressor>,
+}
+
+/// `/ws?pid=..&comm=..&cgroupId=..` で受信プロセスによる絞り込みを、
+/// `/ws?mode=sample&rate=10` のように配信モードを指定できる。
+/// 接続後はクライアントが `ClientMessage` を送ることで、購読フィルタと配信モードをいつでも差し替えられる。
+/// 不正な `ClientMessage` を送ると、`close_code::POLICY` と理由を付けて接続を閉じる。
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    Query(query): Query<StreamQuery>,
     State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+    Extension(config): Extension<WsConfig>,
+) -> Response {
+    let (mode, filter) = match query
+        .delivery_mode()
+        .and_then(|mode| Ok((mode, query.filter()?)))
+    {
+        Ok(parsed) => parsed,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    ws.on_upgrade(move |socket| handle_socket(socket, state, config, filter, Sampler::new(mode)))
 }
 
-async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>) {
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: Arc<AppState>,
+    config: WsConfig,
+    mut filter: PacketFilter,
+    mut sampler: Sampler,
+) {
+    // ネゴシエーション中のバッチも取りこぼさないよう、先に購読しておく
     let mut rx = state.event_tx.subscribe();
+    let mut state_rx = state.state_tx.subscribe();
+    let mut interface_rx = state.interface_tx.subscribe();
+    let mut status_rx = state.status_tx.subscribe();
+    let mut alert_rx = state.alert_tx.subscribe();
+    let client = state.clients.register("websocket");
+    let mut batch_sizes = BatchSizeEstimator::default();
+
+    let Some(mut encoder) = negotiate(&mut socket, &config, &mut filter, &mut sampler).await else {
+        return;
+    };
+    if encoder.version != StreamVersion::LEGACY {
+        // Hello は圧縮しない。圧縮は Hello より後のフレームから始める。
+        let compressor = encoder.compressor.take();
+        let hello = Outbound::Hello {
+            compression: compressor
+                .as_ref()
+                .map_or(pb::Compression::None, Compressor::codec),
+            compression_level: compressor.as_ref().map_or(0, Compressor::level),
+        };
+        if send(&mut socket, &mut encoder, client.metrics(), hello)
+            .await
+            .is_err()
+        {
+            return;
+        }
+        encoder.compressor = compressor;
+    }
 
     loop {
         tokio::select! {
             result = rx.recv() => {
                 match result {
                     Ok(batch) => {
-                        let envelope = batch_to_envelope(&batch);
-                        let payload = envelope.encode_to_vec();
-
-                        if socket.send(Message::Binary(payload.into())).await.is_err() {
+                        batch_sizes.record(batch.packets.len());
+                        let Some(outbound) = prepare_batch(&batch, &filter, &mut sampler) else {
+                            continue;
+                        };
+                        client.metrics().record_sent(outbound.packets.len() as u64);
+                        client.metrics().record_suppressed(outbound.suppressed.total());
+                        if send(&mut socket, &mut encoder, client.metrics(), Outbound::Batch(outbound)).await.is_err() {
                             break; // Client disconnected
                         }
                     }
                     Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        warn!(skipped_batches = n, "websocket client lagged");
+                        let approx_packets = batch_sizes.approx_packets(n);
+                        warn!(
+                            client_id = client.id(),
+                            skipped_batches = n,
+                            approx_packets,
+                            "websocket client lagged"
+                        );
+                        client.metrics().record_lag(n, approx_packets);
+                        let gap = Outbound::Gap {
+                            skipped_batches: n,
+                            approx_packets,
+                        };
+                        if send(&mut socket, &mut encoder, client.metrics(), gap).await.is_err() {
+                            break; // Client disconnected
+                        }
                     }
                     Err(tokio::sync::broadcast::error::RecvError::Closed) => {
                         break; // Channel closed
                     }
                 }
             }
+            // 状態・統計の通知は取りこぼしても次の通知で追いつくため、遅延は無視する
+            Ok(event) = state_rx.recv() => {
+                if send(&mut socket, &mut encoder, client.metrics(), Outbound::CaptureState(&event)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(event) = interface_rx.recv() => {
+                if send(&mut socket, &mut encoder, client.metrics(), Outbound::Interface(&event)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(snapshot) = status_rx.recv() => {
+                if send(&mut socket, &mut encoder, client.metrics(), Outbound::Stats(&snapshot)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(alert) = alert_rx.recv() => {
+                if send(&mut socket, &mut encoder, client.metrics(), Outbound::Alert(&alert)).await.is_err() {
+                    break;
+                }
+            }
             msg = socket.recv() => {
                 match msg {
                     Some(Ok(Message::Close(_))) | None => break,
                     Some(Err(_)) => break,
+                    Some(Ok(Message::Binary(data))) => {
+                        if let Err(reason) = apply_client_message(&data, &mut filter, &mut sampler) {
+                            close_policy_violation(&mut socket, reason).await;
+                            break;
+                        }
+                    }
                     _ => {} // Ignore other messages
                 }
             }
         }
     }
 }
+
+/// 接続直後の ClientHello からプロトコルとスキーマのバージョンを決める。
+/// 時間内に届かない場合や、先に別の制御メッセージが届いた場合（そのメッセージは適用する）は旧来のプロトコルとする。
+/// 共通のバージョンがない場合や、制御メッセージが不正な場合は接続を閉じて `None` を返す。
+async fn negotiate(
+    socket: &mut WebSocket,
+    config: &WsConfig,
+    filter: &mut PacketFilter,
+    sampler: &mut Sampler,
+) -> Option<StreamEncoder> {
+    let legacy = || StreamEncoder {
+        version: StreamVersion::LEGACY,
+        compressor: None,
+    };
+    let data = match tokio::time::timeout(HELLO_TIMEOUT, socket.recv()).await {
+        Err(_) => return Some(legacy()),
+        Ok(Some(Ok(Message::Binary(data)))) => data,
+        Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => return None,
+        Ok(Some(Ok(_))) => return Some(legacy()),
+    };
+    let hello = match pb::ClientMessage::decode(data.clone()) {
+        Ok(pb::ClientMessage {
+            kind: Some(pb::client_message::Kind::Hello(hello)),
+        }) => hello,
+        _ => {
+            if let Err(reason) = apply_client_message(&data, filter, sampler) {
+                close_policy_violation(socket, reason).await;
+                return None;
+            }
+            return Some(legacy());
+        }
+    };
+    match negotiate_protocol(&hello) {
+        // 旧来のプロトコルでは Hello を返さないため、圧縮も合意できない
+        Some(version) if version == StreamVersion::LEGACY => Some(legacy()),
+        Some(version) => {
+            let compressor = Compressor::negotiate(&config.compression, &hello);
+            debug!(
+                client = %hello.client_name,
+                protocol = version.protocol,
+                schema = version.schema,
+                compression = ?compressor.as_ref().map(Compressor::codec),
+                "websocket client negotiated"
+            );
+            Some(StreamEncoder {
+                version,
+                compressor,
+            })
+        }
+        None => {
+            warn!(
+                client = %hello.client_name,
+                versions = ?hello.protocol_versions,
+                "websocket client has no supported protocol version"
+            );
+            let _ = socket
+                .send(Message::Close(Some(CloseFrame {
+                    code: close_code::PROTOCOL,
+                    reason: "unsupported protocol version".into(),
+                })))
+                .await;
+            None
+        }
+    }
+}
+
+/// ネゴシエーションしたプロトコルでメッセージを送る。そのプロトコルで送れないメッセージは送らない。
+async fn send(
+    socket: &mut WebSocket,
+    encoder: &mut StreamEncoder,
+    metrics: &ClientMetrics,
+    outbound: Outbound<'_>,
+) -> Result<(), axum::Error> {
+    let Some(payload) = encode_outbound(encoder.version, outbound) else {
+        return Ok(());
+    };
+    let payload_len = payload.len() as u64;
+    let frame = match encoder.compressor.as_mut() {
+        Some(compressor) => compressor.compress(&payload),
+        None => payload,
+    };
+    metrics.record_bytes(payload_len, frame.len() as u64);
+    socket.send(Message::Binary(frame.into())).await
+}
+
+/// フィルタと間引きを適用して送信するバッチを作る。送るパケットがなければ `None`。
+fn prepare_batch<'a>(
+    batch: &'a CapturedPacketEnvelope,
+    filter: &PacketFilter,
+    sampler: &mut Sampler,
+) -> Option<OutboundBatch<'a>> {
+    let sampled = sampler.mode() != DeliveryMode::Full;
+    let packets = if filter.is_empty() && !sampled {
+        Cow::Borrowed(batch.packets.as_slice())
+    } else {
+        let packets = filter.apply(batch)?.packets;
+        let packets = sampler.apply(packets, Instant::now());
+        if packets.is_empty() {
+            return None;
+        }
+        Cow::Owned(packets)
+    };
+    // 前回送信以降に間引いた数を載せる
+    Some(OutboundBatch {
+        packets,
+        epoch_offset_ms: batch.epoch_offset_ms,
+        sampled,
+        suppressed: sampler.take_suppressed(),
+    })
+}
+
+/// 制御メッセージを適用する。不正なメッセージはそれまでの設定を維持し、理由を返す。
+fn apply_client_message(
+    data: &[u8],
+    filter: &mut PacketFilter,
+    sampler: &mut Sampler,
+) -> Result<(), String> {
+    let message =
+        pb::ClientMessage::decode(data).map_err(|e| format!("invalid client message: {}", e))?;
+    match message.kind {
+        Some(pb::client_message::Kind::SetFilter(update)) => {
+            *filter = filter_from_proto(update)
+                .map_err(|e| format!("invalid subscription filter: {}", e))?;
+        }
+        Some(pb::client_message::Kind::SetMode(mode)) => {
+            let mode = delivery_mode_from_proto(mode)
+                .map_err(|e| format!("invalid delivery mode: {}", e))?;
+            sampler.set_mode(mode);
+        }
+        Some(pb::client_message::Kind::Hello(_)) => {
+            warn!("websocket client hello is only accepted as the first message")
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// 不正な制御メッセージを送ったクライアントとの接続を、理由を付けて閉じる
+async fn close_policy_violation(socket: &mut WebSocket, reason: String) {
+    warn!(reason = %reason, "closing websocket after invalid client message");
+    // Close フレームの理由は 123 バイトまで
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: close_code::POLICY,
+            reason: reason[..end].into(),
+        })))
+        .await;
+}
@@ -0,0 +1,116 @@
+//! ClientHello で合意した WebSocket フレームの圧縮。
+//!
+//! 使っている WebSocket 実装は permessage-deflate 拡張に対応していないため、アプリケーション層で
+//! フレームごとに圧縮する。圧縮するフレームは先頭 1 バイトに `pb::Compression` の値を置く。
+
+use std::io::Write as _;
+
+use flate2::write::DeflateEncoder;
+
+use crate::ws_proto::pb;
+
+/// zstd の圧縮レベルの上限（`zstd::compression_level_range` の上限と同じ）
+const ZSTD_MAX_LEVEL: i32 = 22;
+/// DEFLATE の圧縮レベルの上限
+const DEFLATE_MAX_LEVEL: i32 = 9;
+/// クライアントがレベルを指定しなかった場合の圧縮レベル
+const DEFAULT_LEVEL: i32 = 3;
+
+/// サーバー側の圧縮設定。レベルの上限と最小サイズで、接続ごとの CPU 使用量を抑える。
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// 許可する方式。空なら圧縮しない。
+    pub codecs: Vec<pb::Compression>,
+    /// クライアントが希望できる圧縮レベルの上限
+    pub max_level: i32,
+    /// これより小さいフレームは圧縮しない
+    pub min_bytes: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            codecs: vec![pb::Compression::Zstd, pb::Compression::Deflate],
+            max_level: DEFAULT_LEVEL,
+            min_bytes: 256,
+        }
+    }
+}
+
+/// 接続ごとの圧縮器。zstd のコンテキストは接続中使い回す。
+pub struct Compressor {
+    codec: pb::Compression,
+    level: i32,
+    min_bytes: usize,
+    zstd: Option<zstd::bulk::Compressor<'static>>,
+}
+
+impl Compressor {
+    /// クライアントの希望順で、サーバーも許可する最初の方式を選ぶ。圧縮しない場合は `None`。
+    pub fn negotiate(config: &CompressionConfig, hello: &pb::ClientHello) -> Option<Self> {
+        let codec = hello
+            .compressions()
+            .find(|codec| *codec != pb::Compression::None && config.codecs.contains(codec))?;
+        let max_level = match codec {
+            pb::Compression::Zstd => ZSTD_MAX_LEVEL,
+            _ => DEFLATE_MAX_LEVEL,
+        };
+        let level = hello
+            .compression_level
+            .unwrap_or(DEFAULT_LEVEL)
+            .min(config.max_level)
+            .clamp(1, max_level);
+        let zstd = match codec {
+            pb::Compression::Zstd => Some(zstd::bulk::Compressor::new(level).ok()?),
+            _ => None,
+        };
+        Some(Self {
+            codec,
+            level,
+            min_bytes: config.min_bytes,
+            zstd,
+        })
+    }
+
+    pub fn codec(&self) -> pb::Compression {
+        self.codec
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    /// 先頭に方式の 1 バイトを付けたフレームにする。
+    /// 小さいフレームや、圧縮に失敗した・かえって大きくなったフレームは NONE のまま送る。
+    pub fn compress(&mut self, payload: &[u8]) -> Vec<u8> {
+        if payload.len() >= self.min_bytes {
+            if let Some(compressed) = self.compress_payload(payload) {
+                if compressed.len() < payload.len() {
+                    let mut frame = Vec::with_capacity(compressed.len() + 1);
+                    frame.push(self.codec as u8);
+                    frame.extend_from_slice(&compressed);
+                    return frame;
+                }
+            }
+        }
+        let mut frame = Vec::with_capacity(payload.len() + 1);
+        frame.push(pb::Compression::None as u8);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn compress_payload(&mut self, payload: &[u8]) -> Option<Vec<u8>> {
+        match self.codec {
+            pb::Compression::Zstd => self.zstd.as_mut()?.compress(payload).ok(),
+            pb::Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(
+                    Vec::with_capacity(payload.len() / 2),
+                    flate2::Compression::new(self.level as u32),
+                );
+                encoder.write_all(payload).ok()?;
+                encoder.finish().ok()
+            }
+            pb::Compression::None => None,
+        }
+    }
+}
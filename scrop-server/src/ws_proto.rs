@@ -1,18 +1,382 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+use prost::Message as _;
+use scrop_capture::alerts::{Alert, AlertState};
+use scrop_capture::filter::{PacketFilter, ProcessFilter};
+use scrop_capture::sampling::{DeliveryMode, SuppressedCounts};
 use scrop_capture::types::{
-    AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult, Protocol,
+    parse_packet_id, AnimatingPacket, CaptureStateEvent, CaptureStateKind, CapturedPacket,
+    EndpointMetadata, GeoInfo, InterfaceEvent, InterfaceEventKind, PacketResult, ProcessInfo,
+    Protocol, StatusSnapshot,
 };
 
 pub const SCHEMA_VERSION: u32 = 2;
+/// `CompactPacketBatch`（数値アドレス・文字列テーブル・差分符号化）
+pub const COMPACT_SCHEMA_VERSION: u32 = 3;
+pub const SUPPORTED_SCHEMA_VERSIONS: &[u32] = &[SCHEMA_VERSION, COMPACT_SCHEMA_VERSION];
+/// `PacketBatchEnvelope` をそのまま送る旧来のストリーム（ClientHello を送らないクライアント向け）
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+/// `ServerMessage` で多重化したストリーム
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u32] = &[LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION];
 
 pub mod pb {
     include!(concat!(env!("OUT_DIR"), "/scrop.packet.rs"));
 }
 
-pub fn batch_to_envelope(batch: &CapturedPacketEnvelope) -> pb::PacketBatchEnvelope {
+/// 接続ごとに合意したプロトコルとスキーマのバージョン
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamVersion {
+    pub protocol: u32,
+    pub schema: u32,
+}
+
+impl StreamVersion {
+    /// ClientHello を送らないクライアント向け
+    pub const LEGACY: Self = Self {
+        protocol: LEGACY_PROTOCOL_VERSION,
+        schema: SCHEMA_VERSION,
+    };
+}
+
+/// フィルタと間引きを適用した送信前のバッチ
+pub struct OutboundBatch<'a> {
+    pub packets: Cow<'a, [CapturedPacket]>,
+    pub epoch_offset_ms: f64,
+    pub sampled: bool,
+    pub suppressed: SuppressedCounts,
+}
+
+/// ストリームで送るメッセージ。ネゴシエーションしたバージョンに応じてエンコードする。
+pub enum Outbound<'a> {
+    Hello {
+        compression: pb::Compression,
+        compression_level: i32,
+    },
+    Batch(OutboundBatch<'a>),
+    Gap {
+        skipped_batches: u64,
+        approx_packets: u64,
+    },
+    Stats(&'a StatusSnapshot),
+    CaptureState(&'a CaptureStateEvent),
+    Interface(&'a InterfaceEvent),
+    Alert(&'a Alert),
+}
+
+/// バージョン 1 では送れないメッセージ（Hello、アタッチ以外のインターフェースの変化、アラート）は `None`
+pub fn encode_outbound(version: StreamVersion, outbound: Outbound<'_>) -> Option<Vec<u8>> {
+    if version.protocol == LEGACY_PROTOCOL_VERSION {
+        return outbound_to_envelope(outbound).map(|envelope| envelope.encode_to_vec());
+    }
+    use pb::server_message::Kind;
+    let kind = match outbound {
+        Outbound::Hello {
+            compression,
+            compression_level,
+        } => Kind::Hello(pb::Hello {
+            protocol_version: version.protocol,
+            schema_version: version.schema,
+            server_version: env!("CARGO_PKG_VERSION").to_string(),
+            compression: compression as i32,
+            compression_level,
+        }),
+        Outbound::Batch(batch) if version.schema == COMPACT_SCHEMA_VERSION => {
+            Kind::CompactBatch(batch_to_compact(&batch))
+        }
+        Outbound::Batch(batch) => Kind::PacketBatch(batch_to_envelope(&batch)),
+        Outbound::Gap {
+            skipped_batches,
+            approx_packets,
+        } => Kind::Gap(pb::StreamGap {
+            skipped_batches,
+            approx_packets,
+        }),
+        Outbound::Stats(snapshot) => Kind::Stats(stats_update(snapshot)),
+        Outbound::CaptureState(event) => Kind::StateChange(capture_state_change(event)),
+        Outbound::Interface(event) => Kind::InterfaceUpdate(interface_update(event)),
+        Outbound::Alert(alert) => Kind::Alert(alert_to_proto(alert)),
+    };
+    Some(pb::ServerMessage { kind: Some(kind) }.encode_to_vec())
+}
+
+/// バージョン 1 と gRPC のストリームでは、パケット以外の通知もパケットのないエンベロープで送る
+pub fn outbound_to_envelope(outbound: Outbound<'_>) -> Option<pb::PacketBatchEnvelope> {
+    use pb::packet_batch_envelope::Control;
+    let control = match outbound {
+        Outbound::Hello { .. } | Outbound::Alert(_) => return None,
+        Outbound::Batch(batch) => return Some(batch_to_envelope(&batch)),
+        Outbound::Gap {
+            skipped_batches,
+            approx_packets,
+        } => {
+            return Some(pb::PacketBatchEnvelope {
+                schema_version: SCHEMA_VERSION,
+                gap: Some(pb::StreamGap {
+                    skipped_batches,
+                    approx_packets,
+                }),
+                ..Default::default()
+            })
+        }
+        Outbound::Stats(snapshot) => Control::Stats(stats_update(snapshot)),
+        Outbound::CaptureState(event) => Control::StateChange(capture_state_change(event)),
+        Outbound::Interface(event) => Control::StateChange(interface_state_change(event)?),
+    };
+    Some(pb::PacketBatchEnvelope {
+        schema_version: SCHEMA_VERSION,
+        control: Some(control),
+        ..Default::default()
+    })
+}
+
+fn batch_to_envelope(batch: &OutboundBatch<'_>) -> pb::PacketBatchEnvelope {
     pb::PacketBatchEnvelope {
         schema_version: SCHEMA_VERSION,
         packets: batch.packets.iter().map(packet_to_proto).collect(),
         epoch_offset_ms: batch.epoch_offset_ms,
+        sampled: batch.sampled,
+        suppressed: (batch.suppressed.total() > 0).then(|| suppressed_to_proto(&batch.suppressed)),
+        ..Default::default()
+    }
+}
+
+/// クライアントが対応するバージョンのうち、サーバーも対応する最新のもの。
+/// スキーマは共通のものがなければ 2 に落とす。
+pub fn negotiate_protocol(hello: &pb::ClientHello) -> Option<StreamVersion> {
+    let protocol = latest_common(&hello.protocol_versions, SUPPORTED_PROTOCOL_VERSIONS)?;
+    let schema = match protocol {
+        LEGACY_PROTOCOL_VERSION => None,
+        _ => latest_common(&hello.schema_versions, SUPPORTED_SCHEMA_VERSIONS),
+    };
+    Some(StreamVersion {
+        protocol,
+        schema: schema.unwrap_or(SCHEMA_VERSION),
+    })
+}
+
+fn latest_common(offered: &[u32], supported: &[u32]) -> Option<u32> {
+    offered
+        .iter()
+        .copied()
+        .filter(|version| supported.contains(version))
+        .max()
+}
+
+/// バッチ内で繰り返し現れる文字列を文字列テーブルに集める
+#[derive(Default)]
+struct StringTable<'a> {
+    strings: Vec<String>,
+    index: HashMap<&'a str, u32>,
+}
+
+impl<'a> StringTable<'a> {
+    fn intern(&mut self, value: &'a str) -> u32 {
+        *self.index.entry(value).or_insert_with(|| {
+            self.strings.push(value.to_string());
+            (self.strings.len() - 1) as u32
+        })
+    }
+
+    fn intern_opt(&mut self, value: &'a Option<String>) -> Option<u32> {
+        value.as_deref().map(|value| self.intern(value))
+    }
+}
+
+/// スキーマバージョン 3 のバッチにする。
+/// ID の counter と capture_mono_ns は直前のパケットからの差分、IPv4 アドレスは数値で持つ。
+pub fn batch_to_compact(batch: &OutboundBatch<'_>) -> pb::CompactPacketBatch {
+    let session_id = batch
+        .packets
+        .iter()
+        .find_map(|captured| parse_packet_id(&captured.packet.id))
+        .map(|(session_id, _)| session_id)
+        .unwrap_or_default();
+    let base_counter = batch
+        .packets
+        .iter()
+        .find_map(|captured| match parse_packet_id(&captured.packet.id) {
+            Some((session, counter)) if session == session_id => Some(counter),
+            _ => None,
+        })
+        .unwrap_or(0);
+    let base_capture_mono_ns = batch
+        .packets
+        .first()
+        .map(|captured| captured.packet.capture_mono_ns)
+        .unwrap_or(0);
+
+    let mut strings = StringTable::default();
+    let mut prev_counter = base_counter;
+    let mut prev_mono_ns = base_capture_mono_ns;
+    let packets = batch
+        .packets
+        .iter()
+        .map(|captured| {
+            let packet = &captured.packet;
+            let (counter_delta, id) = match parse_packet_id(&packet.id) {
+                Some((session, counter)) if session == session_id => {
+                    let delta = counter.wrapping_sub(prev_counter) as i64;
+                    prev_counter = counter;
+                    (delta, None)
+                }
+                _ => (0, Some(packet.id.clone())),
+            };
+            let capture_mono_ns_delta = packet.capture_mono_ns.wrapping_sub(prev_mono_ns) as i64;
+            prev_mono_ns = packet.capture_mono_ns;
+            let (source, source_text) = compact_address(&packet.source);
+            let (destination, destination_text) = compact_address(&packet.destination);
+            pb::CompactPacket {
+                counter_delta,
+                capture_mono_ns_delta,
+                result: packet_result_to_proto(&captured.result) as i32,
+                protocol: protocol_to_proto(&packet.protocol) as i32,
+                size: packet.size,
+                source,
+                src_port: packet.src_port as u32,
+                destination,
+                dest_port: packet.dest_port as u32,
+                target_port: packet.target_port.map(u32::from),
+                reason_ref: strings.intern_opt(&packet.reason),
+                interface_ref: strings.intern_opt(&packet.interface),
+                netns_ref: strings.intern_opt(&packet.netns),
+                source_meta: packet.source_meta.as_ref().map(endpoint_metadata_to_proto),
+                destination_meta: packet
+                    .destination_meta
+                    .as_ref()
+                    .map(endpoint_metadata_to_proto),
+                process: packet.process.as_ref().map(process_info_to_proto),
+                source_name_ref: strings.intern_opt(&packet.source_name),
+                destination_name_ref: strings.intern_opt(&packet.destination_name),
+                service_ref: strings.intern_opt(&packet.service),
+                source_geo: packet.source_geo.as_ref().map(geo_info_to_proto),
+                destination_geo: packet.destination_geo.as_ref().map(geo_info_to_proto),
+                id,
+                source_text,
+                destination_text,
+            }
+        })
+        .collect();
+
+    pb::CompactPacketBatch {
+        session_id: session_id.to_string(),
+        epoch_offset_ms: batch.epoch_offset_ms,
+        sampled: batch.sampled,
+        suppressed: (batch.suppressed.total() > 0).then(|| suppressed_to_proto(&batch.suppressed)),
+        base_counter,
+        base_capture_mono_ns,
+        strings: strings.strings,
+        packets,
+    }
+}
+
+/// IPv4 なら数値、それ以外は文字列のまま
+fn compact_address(address: &str) -> (u32, Option<String>) {
+    match address.parse::<Ipv4Addr>() {
+        Ok(ip) => (u32::from(ip), None),
+        Err(_) => (0, Some(address.to_string())),
+    }
+}
+
+pub fn stats_update(snapshot: &StatusSnapshot) -> pb::StatsUpdate {
+    let stats = &snapshot.stats;
+    pb::StatsUpdate {
+        is_capturing: snapshot.is_capturing,
+        mode: snapshot.mode.clone(),
+        total_packets: stats.total_packets,
+        nic_dropped: stats.nic_dropped,
+        fw_dropped: stats.fw_dropped,
+        delivered: stats.delivered,
+        transport_dropped: stats.transport_dropped,
+        packets_by_country: stats
+            .packets_by_country
+            .iter()
+            .map(|(k, v)| (k.clone(), *v))
+            .collect(),
+        packets_by_asn: stats.packets_by_asn.iter().map(|(k, v)| (*k, *v)).collect(),
+    }
+}
+
+fn capture_state_change(event: &CaptureStateEvent) -> pb::StateChange {
+    let kind = match event.kind {
+        CaptureStateKind::Started => pb::StateChangeKind::Started,
+        CaptureStateKind::Stopped => pb::StateChangeKind::Stopped,
+        CaptureStateKind::Reset => pb::StateChangeKind::Reset,
+        CaptureStateKind::Failed => pb::StateChangeKind::Failed,
+    };
+    pb::StateChange {
+        kind: kind as i32,
+        error: event.error.clone(),
+        ..Default::default()
+    }
+}
+
+/// アタッチ状態の変化だけを StateChange にする。それ以外のインターフェースの変化は `None`。
+fn interface_state_change(event: &InterfaceEvent) -> Option<pb::StateChange> {
+    let kind = match event.kind {
+        InterfaceEventKind::Attached => pb::StateChangeKind::Attached,
+        InterfaceEventKind::Detached => pb::StateChangeKind::Detached,
+        InterfaceEventKind::AttachFailed => pb::StateChangeKind::AttachFailed,
+        _ => return None,
+    };
+    Some(pb::StateChange {
+        kind: kind as i32,
+        interface: Some(event.interface.clone()),
+        error: event.error.clone(),
+        attached: event.attached.clone(),
+    })
+}
+
+fn interface_update(event: &InterfaceEvent) -> pb::InterfaceUpdate {
+    let kind = match event.kind {
+        InterfaceEventKind::Added => pb::InterfaceEventKind::Added,
+        InterfaceEventKind::Removed => pb::InterfaceEventKind::Removed,
+        InterfaceEventKind::Renamed => pb::InterfaceEventKind::Renamed,
+        InterfaceEventKind::Up => pb::InterfaceEventKind::Up,
+        InterfaceEventKind::Down => pb::InterfaceEventKind::Down,
+        InterfaceEventKind::Attached => pb::InterfaceEventKind::Attached,
+        InterfaceEventKind::Detached => pb::InterfaceEventKind::Detached,
+        InterfaceEventKind::AttachFailed => pb::InterfaceEventKind::AttachFailed,
+    };
+    pb::InterfaceUpdate {
+        kind: kind as i32,
+        interface: event.interface.clone(),
+        previous_name: event.previous_name.clone(),
+        error: event.error.clone(),
+        interfaces: event.interfaces.clone(),
+        attached: event.attached.clone(),
+    }
+}
+
+fn alert_to_proto(alert: &Alert) -> pb::Alert {
+    let state = match alert.state {
+        AlertState::Pending => pb::AlertState::Pending,
+        AlertState::Firing => pb::AlertState::Firing,
+        AlertState::Resolved => pb::AlertState::Resolved,
+    };
+    pb::Alert {
+        rule: alert.rule.clone(),
+        expr: alert.expr.clone(),
+        labels: alert
+            .labels
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        state: state as i32,
+        value: alert.value,
+        active_since_ms: alert.active_since_ms,
+        fired_at_ms: alert.fired_at_ms,
+        resolved_at_ms: alert.resolved_at_ms,
+    }
+}
+
+pub fn suppressed_to_proto(counts: &SuppressedCounts) -> pb::SuppressedCounts {
+    pb::SuppressedCounts {
+        delivered: counts.delivered,
+        nic_drop: counts.nic_drop,
+        fw_drop: counts.fw_drop,
     }
 }
 
@@ -35,6 +399,51 @@ fn animating_packet_to_proto(packet: &AnimatingPacket) -> pb::AnimatingPacket {
         target_port: packet.target_port.map(u32::from),
         reason: packet.reason.clone(),
         capture_mono_ns: packet.capture_mono_ns as f64,
+        interface: packet.interface.clone(),
+        netns: packet.netns.clone(),
+        source_meta: packet.source_meta.as_ref().map(endpoint_metadata_to_proto),
+        destination_meta: packet
+            .destination_meta
+            .as_ref()
+            .map(endpoint_metadata_to_proto),
+        process: packet.process.as_ref().map(process_info_to_proto),
+        source_name: packet.source_name.clone(),
+        destination_name: packet.destination_name.clone(),
+        service: packet.service.clone(),
+        source_geo: packet.source_geo.as_ref().map(geo_info_to_proto),
+        destination_geo: packet.destination_geo.as_ref().map(geo_info_to_proto),
+    }
+}
+
+fn geo_info_to_proto(geo: &GeoInfo) -> pb::GeoInfo {
+    pb::GeoInfo {
+        country: geo.country.clone(),
+        asn: geo.asn,
+        as_org: geo.as_org.clone(),
+    }
+}
+
+fn process_info_to_proto(process: &ProcessInfo) -> pb::ProcessInfo {
+    pb::ProcessInfo {
+        pid: process.pid,
+        comm: process.comm.clone(),
+        cgroup_id: process.cgroup_id,
+    }
+}
+
+fn endpoint_metadata_to_proto(meta: &EndpointMetadata) -> pb::EndpointMetadata {
+    pb::EndpointMetadata {
+        container: meta.container.clone(),
+        container_id: meta.container_id.clone(),
+        pod: meta.pod.clone(),
+        pod_uid: meta.pod_uid.clone(),
+        namespace: meta.namespace.clone(),
+        cgroup: meta.cgroup.clone(),
+        labels: meta
+            .labels
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
     }
 }
 
@@ -52,3 +461,67 @@ fn packet_result_to_proto(result: &PacketResult) -> pb::PacketResult {
         PacketResult::FwDrop => pb::PacketResult::FwDrop,
     }
 }
+
+/// クライアントから受け取った購読フィルタを検証して変換する
+pub fn filter_from_proto(filter: pb::SubscriptionFilter) -> Result<PacketFilter, String> {
+    let results = filter
+        .results()
+        .map(|result| match result {
+            pb::PacketResult::Delivered => Ok(PacketResult::Delivered),
+            pb::PacketResult::NicDrop => Ok(PacketResult::NicDrop),
+            pb::PacketResult::FwDrop => Ok(PacketResult::FwDrop),
+            pb::PacketResult::Unspecified => Err("unspecified packet result".to_string()),
+        })
+        .collect::<Result<_, _>>()?;
+    let protocols = filter
+        .protocols()
+        .map(|protocol| match protocol {
+            pb::Protocol::Tcp => Ok(Protocol::Tcp),
+            pb::Protocol::Udp => Ok(Protocol::Udp),
+            pb::Protocol::Unspecified => Err("unspecified protocol".to_string()),
+        })
+        .collect::<Result<_, _>>()?;
+    let ports = filter
+        .ports
+        .iter()
+        .map(|&port| u16::try_from(port).map_err(|_| format!("invalid port: {}", port)))
+        .collect::<Result<_, _>>()?;
+    let cidrs = filter
+        .cidrs
+        .iter()
+        .map(|cidr| cidr.parse())
+        .collect::<Result<_, _>>()?;
+
+    Ok(PacketFilter {
+        results,
+        protocols,
+        ports,
+        cidrs,
+        interfaces: filter.interfaces,
+        process: ProcessFilter {
+            pid: filter.pid,
+            comm: filter.comm,
+            cgroup_id: filter.cgroup_id,
+        },
+    })
+}
+
+/// クライアントから受け取った配信モードを検証して変換する
+pub fn delivery_mode_from_proto(mode: pb::DeliveryMode) -> Result<DeliveryMode, String> {
+    use pb::delivery_mode::Mode;
+
+    let mode = match mode.mode {
+        None | Some(Mode::Full(_)) => DeliveryMode::Full,
+        Some(Mode::Sample(sample)) => DeliveryMode::Sample {
+            one_in: sample.one_in,
+        },
+        Some(Mode::RateLimit(limit)) => DeliveryMode::RateLimit {
+            max_packets_per_sec: limit.max_packets_per_sec,
+        },
+        Some(Mode::DropsOnly(drops)) => DeliveryMode::DropsOnly {
+            delivered_one_in: drops.delivered_one_in,
+        },
+    };
+    mode.validate()?;
+    Ok(mode)
+}
@@ -0,0 +1,178 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use http_body_util::BodyExt;
+use tower::ServiceExt;
+
+use scrop_capture::types::{AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult};
+use scrop_capture::AppState;
+
+#[path = "../src/alerts.rs"]
+mod alerts;
+
+/// 受け取った JSON を保存するだけの Webhook
+async fn start_webhook_stub() -> (
+    Arc<Mutex<Vec<serde_json::Value>>>,
+    String,
+    tokio::task::JoinHandle<()>,
+) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let app = Router::new().route(
+        "/hook",
+        post({
+            let received = received.clone();
+            move |Json(body): Json<serde_json::Value>| async move {
+                received.lock().unwrap().push(body);
+                StatusCode::NO_CONTENT
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind webhook stub");
+    let addr = listener.local_addr().expect("read local addr");
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .await
+            .expect("serve webhook stub");
+    });
+    (received, format!("http://{}/hook", addr), server)
+}
+
+fn fw_drop(id: &str) -> CapturedPacket {
+    let mut packet = AnimatingPacket::generate("abc123", 0);
+    packet.id = id.to_string();
+    packet.interface = Some("eth0".to_string());
+    packet.reason = Some("NETFILTER_DROP".to_string());
+    CapturedPacket {
+        packet,
+        result: PacketResult::FwDrop,
+    }
+}
+
+async fn wait_for_webhooks(
+    received: &Mutex<Vec<serde_json::Value>>,
+    count: usize,
+) -> Vec<serde_json::Value> {
+    for _ in 0..100 {
+        if received.lock().unwrap().len() >= count {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    received.lock().unwrap().clone()
+}
+
+async fn get_alerts(manager: &Arc<alerts::AlertManager>) -> serde_json::Value {
+    let app = Router::new().route(
+        "/api/alerts",
+        get(alerts::get_alerts).layer(Extension(manager.clone())),
+    );
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/alerts")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn fires_and_resolves_to_webhook_websocket_and_api() {
+    let (received, url, stub) = start_webhook_stub().await;
+    let state = Arc::new(AppState::new());
+    let config = alerts::AlertConfig {
+        rules: vec!["eth0-fw=fw drops on eth0 > 1".parse().unwrap()],
+        webhooks: vec![url.parse().expect("webhook url")],
+        interval: Duration::from_millis(100),
+    };
+    let manager = Arc::new(alerts::AlertManager::new(&config, &state));
+    let mut alert_rx = state.alert_tx.subscribe();
+    let task = manager
+        .spawn(state.clone(), config.interval)
+        .expect("rules configured");
+
+    // 評価タスクが購読するまで待つ
+    while state.event_tx.receiver_count() == 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets: vec![fw_drop("pkt-1"), fw_drop("pkt-2")],
+            epoch_offset_ms: 0.0,
+        })
+        .expect("send batch");
+
+    let firing = tokio::time::timeout(Duration::from_secs(2), alert_rx.recv())
+        .await
+        .expect("timed out waiting for alert")
+        .expect("alert");
+    assert_eq!(firing.rule, "eth0-fw");
+    assert_eq!(firing.state, scrop_capture::alerts::AlertState::Firing);
+    assert_eq!(firing.value, 2.0);
+
+    let body = get_alerts(&manager).await;
+    assert_eq!(body["rules"][0]["name"], "eth0-fw");
+    assert_eq!(body["rules"][0]["expr"], "fw drops on eth0 > 1");
+    assert_eq!(body["active"][0]["state"], "firing");
+    assert_eq!(body["active"][0]["labels"]["interface"], "eth0");
+
+    // 次の評価間隔にドロップがなければ解決する
+    let resolved = tokio::time::timeout(Duration::from_secs(2), alert_rx.recv())
+        .await
+        .expect("timed out waiting for resolution")
+        .expect("alert");
+    assert_eq!(resolved.state, scrop_capture::alerts::AlertState::Resolved);
+
+    let hooks = wait_for_webhooks(&received, 2).await;
+    assert_eq!(hooks.len(), 2);
+    assert_eq!(hooks[0]["rule"], "eth0-fw");
+    assert_eq!(hooks[0]["state"], "firing");
+    assert!(hooks[0]["firedAtMs"].is_u64());
+    assert_eq!(hooks[1]["state"], "resolved");
+    assert!(hooks[1]["resolvedAtMs"].is_u64());
+
+    let body = get_alerts(&manager).await;
+    assert_eq!(body["active"].as_array().unwrap().len(), 0);
+    assert_eq!(body["resolved"][0]["state"], "resolved");
+
+    task.abort();
+    stub.abort();
+}
+
+#[test]
+fn webhook_url_requires_http() {
+    assert!("http://127.0.0.1:9000/hook"
+        .parse::<alerts::WebhookUrl>()
+        .is_ok());
+    let err = "https://example.com/hook"
+        .parse::<alerts::WebhookUrl>()
+        .unwrap_err();
+    assert!(err.contains("https"), "{}", err);
+    assert!("example.com/hook".parse::<alerts::WebhookUrl>().is_err());
+}
+
+#[tokio::test]
+async fn no_rules_no_task() {
+    let state = Arc::new(AppState::new());
+    let config = alerts::AlertConfig {
+        rules: Vec::new(),
+        webhooks: Vec::new(),
+        interval: Duration::from_secs(1),
+    };
+    let manager = Arc::new(alerts::AlertManager::new(&config, &state));
+    assert!(manager.spawn(state.clone(), config.interval).is_none());
+    let body = get_alerts(&manager).await;
+    assert_eq!(body["rules"].as_array().unwrap().len(), 0);
+    assert_eq!(body["active"].as_array().unwrap().len(), 0);
+}
@@ -9,6 +9,10 @@ use tower::ServiceExt;
 
 use scrop_capture::AppState;
 
+#[allow(dead_code)]
+#[path = "../src/auth.rs"]
+mod auth;
+
 // Re-import the route handlers - we build the same router as main.rs
 // Note: routes module is private to scrop-server binary, so we use
 // the public API types directly and build a test router.
@@ -56,15 +60,16 @@ mod helpers {
 mod scrop_server_routes {
     use std::sync::Arc;
 
-    use axum::extract::{Path, State};
+    use axum::extract::{Path, Query, State};
     use axum::http::StatusCode;
     use axum::response::{IntoResponse, Json, Response};
     use serde::Serialize;
 
+    use scrop_capture::clients::ClientStats;
     #[cfg(not(feature = "ebpf"))]
     use scrop_capture::mock::MockTrafficProfile;
-    use scrop_capture::types::CaptureStats;
-    use scrop_capture::{AppState, CaptureError};
+    use scrop_capture::types::{CaptureStats, NetnsInfo};
+    use scrop_capture::{netns, AppState, CaptureError};
 
     #[derive(Serialize)]
     #[serde(rename_all = "camelCase")]
@@ -72,6 +77,7 @@ mod scrop_server_routes {
         pub is_capturing: bool,
         pub stats: CaptureStats,
         pub mode: String,
+        pub clients: Vec<ClientStats>,
     }
 
     #[derive(Serialize)]
@@ -142,6 +148,7 @@ mod scrop_server_routes {
             is_capturing: capture.is_running(),
             stats,
             mode: capture.mode().to_string(),
+            clients: state.clients.snapshot(),
         }))
     }
 
@@ -165,10 +172,34 @@ mod scrop_server_routes {
         Ok(Json(capture.list_interfaces()))
     }
 
+    #[derive(serde::Deserialize)]
+    pub struct NetnsQuery {
+        pub netns: Option<String>,
+    }
+
+    pub async fn list_netns(
+        State(state): State<Arc<AppState>>,
+    ) -> Result<Json<Vec<NetnsInfo>>, ApiError> {
+        let capture = state.capture.lock().await;
+        Ok(Json(capture.list_netns()))
+    }
+
+    pub async fn list_netns_interfaces(
+        State(state): State<Arc<AppState>>,
+        Path(id): Path<String>,
+    ) -> Result<Json<Vec<String>>, ApiError> {
+        let capture = state.capture.lock().await;
+        Ok(Json(
+            capture.list_netns_interfaces(&id).map_err(ApiError::from)?,
+        ))
+    }
+
     pub async fn attach_interface(
         State(state): State<Arc<AppState>>,
         Path(name): Path<String>,
+        Query(query): Query<NetnsQuery>,
     ) -> Result<Json<MessageResponse>, ApiError> {
+        let name = netns::qualify_interface(query.netns.as_deref(), &name);
         let capture = state.capture.lock().await;
         capture
             .attach_interface(&name)
@@ -182,7 +213,9 @@ mod scrop_server_routes {
     pub async fn detach_interface(
         State(state): State<Arc<AppState>>,
         Path(name): Path<String>,
+        Query(query): Query<NetnsQuery>,
     ) -> Result<Json<MessageResponse>, ApiError> {
+        let name = netns::qualify_interface(query.netns.as_deref(), &name);
         let capture = state.capture.lock().await;
         capture
             .detach_interface(&name)
@@ -193,6 +226,35 @@ mod scrop_server_routes {
         }))
     }
 
+    #[derive(Serialize, serde::Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct InterfaceRulesBody {
+        pub auto_attach: Vec<String>,
+    }
+
+    pub async fn get_interface_rules(
+        State(state): State<Arc<AppState>>,
+    ) -> Result<Json<InterfaceRulesBody>, ApiError> {
+        let capture = state.capture.lock().await;
+        Ok(Json(InterfaceRulesBody {
+            auto_attach: capture.auto_attach_rules(),
+        }))
+    }
+
+    pub async fn update_interface_rules(
+        State(state): State<Arc<AppState>>,
+        Json(req): Json<InterfaceRulesBody>,
+    ) -> Result<Json<InterfaceRulesBody>, ApiError> {
+        let capture = state.capture.lock().await;
+        capture
+            .set_auto_attach_rules(req.auto_attach)
+            .await
+            .map_err(ApiError::from)?;
+        Ok(Json(InterfaceRulesBody {
+            auto_attach: capture.auto_attach_rules(),
+        }))
+    }
+
     #[cfg(not(feature = "ebpf"))]
     #[derive(serde::Deserialize)]
     #[serde(rename_all = "camelCase")]
@@ -279,6 +341,7 @@ async fn get_capture_status_returns_200() {
     let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
     assert_eq!(json["isCapturing"], false);
     assert_eq!(json["mode"], "mock");
+    assert_eq!(json["clients"], serde_json::json!([]));
 }
 
 #[tokio::test]
@@ -498,6 +561,11 @@ fn build_stateful_test_app() -> (Router, Arc<AppState>) {
         )
         .route("/capture/reset", post(scrop_server_routes::reset_capture))
         .route("/interfaces", get(scrop_server_routes::list_interfaces))
+        .route(
+            "/interfaces/rules",
+            get(scrop_server_routes::get_interface_rules)
+                .put(scrop_server_routes::update_interface_rules),
+        )
         .route(
             "/interfaces/{name}/attach",
             post(scrop_server_routes::attach_interface),
@@ -505,6 +573,11 @@ fn build_stateful_test_app() -> (Router, Arc<AppState>) {
         .route(
             "/interfaces/{name}/detach",
             post(scrop_server_routes::detach_interface),
+        )
+        .route("/netns", get(scrop_server_routes::list_netns))
+        .route(
+            "/netns/{id}/interfaces",
+            get(scrop_server_routes::list_netns_interfaces),
         );
 
     #[cfg(not(feature = "ebpf"))]
@@ -675,6 +748,46 @@ async fn double_attach_is_idempotent() {
     assert_eq!(response2.status(), StatusCode::OK);
 }
 
+// --- Interface auto-attach rule API tests ---
+
+#[tokio::test]
+async fn get_interface_rules_defaults_to_empty() {
+    let (app, _state) = build_stateful_test_app();
+
+    let response = get_request(&app, "/api/interfaces/rules").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["autoAttach"], serde_json::json!([]));
+}
+
+#[tokio::test]
+#[cfg(not(feature = "ebpf"))]
+async fn put_interface_rules_attaches_matching_interfaces() {
+    let (app, state) = build_stateful_test_app();
+
+    let response =
+        put_json_request(&app, "/api/interfaces/rules", r#"{"autoAttach":["eth*"]}"#).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["autoAttach"], serde_json::json!(["eth*"]));
+
+    let capture = state.capture.lock().await;
+    assert_eq!(capture.attached_interfaces(), vec!["eth0".to_string()]);
+}
+
+#[tokio::test]
+#[cfg(not(feature = "ebpf"))]
+async fn put_interface_rules_rejects_empty_pattern() {
+    let (app, _state) = build_stateful_test_app();
+
+    let response = put_json_request(&app, "/api/interfaces/rules", r#"{"autoAttach":[""]}"#).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
 // --- Mock config API tests ---
 
 #[tokio::test]
@@ -834,3 +947,260 @@ async fn put_mock_config_validation_dataset_size_zero() {
     let response = put_json_request(&app, "/api/mock/config", r#"{"datasetSize": 0}"#).await;
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
+
+#[tokio::test]
+async fn list_netns_starts_with_host() {
+    let (app, _state) = build_stateful_test_app();
+
+    let response = get_request(&app, "/api/netns").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json[0]["id"], "host");
+    assert!(json[0]["inode"].is_u64());
+}
+
+#[cfg(not(feature = "ebpf"))]
+#[tokio::test]
+async fn list_netns_interfaces_for_unknown_netns_returns_400() {
+    let (app, _state) = build_stateful_test_app();
+
+    let response = get_request(&app, "/api/netns/host/interfaces").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(json.as_array().unwrap().iter().any(|v| v == "eth0"));
+
+    let response = get_request(&app, "/api/netns/cni-missing/interfaces").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[cfg(not(feature = "ebpf"))]
+#[tokio::test]
+async fn attach_with_netns_query_qualifies_interface_name() {
+    let (app, state) = build_stateful_test_app();
+
+    let response = post_request(&app, "/api/interfaces/eth0/attach?netns=host").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        state.capture.lock().await.attached_interfaces(),
+        vec!["eth0".to_string()]
+    );
+
+    let response = post_request(&app, "/api/interfaces/eth0/attach?netns=cni-missing").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(json["error"].as_str().unwrap().contains("cni-missing/eth0"));
+}
+
+// --- Authentication and roles ---
+
+fn test_auth() -> auth::AuthConfig {
+    auth::AuthConfig {
+        tokens: vec![
+            "viewer:view-token".parse().unwrap(),
+            "operator:op-token".parse().unwrap(),
+        ],
+        users: vec!["operator:alice:wonderland".parse().unwrap()],
+        anonymous: auth::Access::None,
+        peer_auth: false,
+    }
+}
+
+fn build_authed_test_app(auth: auth::AuthConfig) -> (Router, Arc<AppState>) {
+    let (app, state) = build_stateful_test_app();
+    let app = app.route_layer(axum::middleware::from_fn_with_state(
+        Arc::new(auth),
+        auth::require,
+    ));
+    (app, state)
+}
+
+async fn request_with_auth(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    authorization: Option<&str>,
+) -> axum::http::Response<Body> {
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(value) = authorization {
+        builder = builder.header("authorization", value);
+    }
+    app.clone()
+        .oneshot(builder.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn auth_rejects_requests_without_credentials() {
+    let (app, state) = build_authed_test_app(test_auth());
+
+    let response = request_with_auth(&app, "GET", "/api/capture/status", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let challenges: Vec<_> = response
+        .headers()
+        .get_all("www-authenticate")
+        .iter()
+        .map(|v| v.to_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        challenges,
+        vec!["Bearer realm=\"scrop\"", "Basic realm=\"scrop\""]
+    );
+
+    let response = request_with_auth(&app, "POST", "/api/capture/start", None).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response =
+        request_with_auth(&app, "POST", "/api/capture/start", Some("Bearer wrong")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!state.capture.lock().await.is_running());
+}
+
+#[tokio::test]
+async fn viewer_can_read_but_not_control() {
+    let (app, state) = build_authed_test_app(test_auth());
+    let viewer = Some("Bearer view-token");
+
+    let response = request_with_auth(&app, "GET", "/api/capture/status", viewer).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request_with_auth(&app, "GET", "/api/interfaces", viewer).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for uri in [
+        "/api/capture/start",
+        "/api/capture/reset",
+        "/api/interfaces/eth0/attach",
+    ] {
+        let response = request_with_auth(&app, "POST", uri, viewer).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"], "operator access required");
+    }
+    let capture = state.capture.lock().await;
+    assert!(!capture.is_running());
+}
+
+#[cfg(not(feature = "ebpf"))]
+#[tokio::test]
+async fn operator_can_attach_start_and_detach() {
+    let (app, state) = build_authed_test_app(test_auth());
+    let operator = Some("Bearer op-token");
+
+    let response = request_with_auth(&app, "POST", "/api/interfaces/eth0/attach", operator).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request_with_auth(&app, "POST", "/api/capture/start", operator).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(state.capture.lock().await.is_running());
+    let response = request_with_auth(&app, "POST", "/api/interfaces/eth0/detach", operator).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request_with_auth(&app, "POST", "/api/capture/stop", operator).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn basic_auth_user_is_accepted() {
+    let (app, _state) = build_authed_test_app(test_auth());
+
+    // alice:wonderland
+    let response = request_with_auth(
+        &app,
+        "POST",
+        "/api/capture/stop",
+        Some("Basic YWxpY2U6d29uZGVybGFuZA=="),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // alice:wrong
+    let response = request_with_auth(
+        &app,
+        "GET",
+        "/api/capture/status",
+        Some("Basic YWxpY2U6d3Jvbmc="),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn anonymous_viewer_can_read_but_not_control() {
+    let (app, _state) = build_authed_test_app(auth::AuthConfig {
+        anonymous: auth::Access::Viewer,
+        ..test_auth()
+    });
+
+    let response = request_with_auth(&app, "GET", "/api/capture/status", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request_with_auth(&app, "POST", "/api/capture/start", None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn access_token_query_parameter_is_accepted() {
+    let (app, _state) = build_authed_test_app(test_auth());
+
+    let response = get_request(&app, "/api/capture/status?access_token=view-token").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = get_request(&app, "/api/capture/status?access_token=nope").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn websocket_upgrade_requires_token() {
+    use axum::extract::ws::{Message as WsMessage, WebSocketUpgrade};
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::{self, Message};
+
+    let app = Router::new()
+        .route(
+            "/ws",
+            get(|ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|mut socket| async move {
+                    let _ = socket.send(WsMessage::Text("hello".into())).await;
+                })
+            }),
+        )
+        .route_layer(axum::middleware::from_fn_with_state(
+            Arc::new(test_auth()),
+            auth::require,
+        ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("read local addr");
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve app");
+    });
+
+    match tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await {
+        Err(tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+        }
+        other => panic!(
+            "expected 401 handshake failure, got {:?}",
+            other.map(|_| ())
+        ),
+    }
+
+    // ブラウザはヘッダーを付けられないため、クエリのトークンで接続する
+    let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://{}/ws?access_token=view-token", addr))
+            .await
+            .expect("connect with access_token");
+    let message = socket.next().await.expect("message").expect("read");
+    assert_eq!(message, Message::Text("hello".into()));
+
+    let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("authorization", "Bearer op-token".parse().unwrap());
+    tokio_tungstenite::connect_async(request)
+        .await
+        .expect("connect with bearer header");
+
+    server.abort();
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+#[allow(dead_code)]
+#[path = "../src/auth.rs"]
+mod auth;
+#[path = "../src/config.rs"]
+mod config;
+#[allow(dead_code)]
+#[path = "../src/listen.rs"]
+mod listen;
+#[allow(dead_code)]
+#[path = "../src/tls.rs"]
+mod tls;
+
+use config::{ConfigArgs, ServerConfig};
+
+#[derive(Parser)]
+struct TestCli {
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+fn args(argv: &[&str]) -> ConfigArgs {
+    TestCli::parse_from(std::iter::once("scrop-server").chain(argv.iter().copied())).config
+}
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+fn write_config(name: &str, text: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("scrop-config-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, text).expect("write config");
+    path
+}
+
+#[test]
+fn defaults_without_file_env_or_args() {
+    let config = args(&[]).load(env(&[])).unwrap();
+    assert_eq!(config, ServerConfig::default());
+}
+
+#[test]
+fn cli_overrides_env_which_overrides_file() {
+    let path = write_config(
+        "precedence",
+        r#"
+port = 4000
+host = "0.0.0.0"
+attach = ["eth*"]
+
+[capture]
+correlation_timeout_ms = 100
+batch_max_size = 64
+"#,
+    );
+    let config = args(&["--config", path.to_str().unwrap(), "--port", "5000"])
+        .load(env(&[
+            ("SCROP_PORT", "4500"),
+            ("SCROP_ATTACH", "veth*, br-*"),
+            ("SCROP_CAPTURE_BATCH_MAX_SIZE", "32"),
+        ]))
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(config.port, 5000);
+    assert_eq!(config.host, "0.0.0.0");
+    assert_eq!(config.attach, vec!["veth*", "br-*"]);
+    assert_eq!(config.capture.correlation_timeout_ms, 100);
+    assert_eq!(config.capture.batch_max_size, 32);
+}
+
+#[test]
+fn config_path_from_env() {
+    let path = write_config("env-path", "stats_interval_ms = 250\n");
+    let config = args(&[])
+        .load(env(&[("SCROP_CONFIG", path.to_str().unwrap())]))
+        .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(config.stats_interval_ms, 250);
+}
+
+#[test]
+fn legacy_shadow_correlator_env_is_still_honored() {
+    let config = args(&[])
+        .load(env(&[("SCROP_SHADOW_CORRELATOR", "yes")]))
+        .unwrap();
+    assert!(config.capture.shadow_correlator);
+
+    let config = args(&["--shadow-correlator=false"])
+        .load(env(&[("SCROP_SHADOW_CORRELATOR", "1")]))
+        .unwrap();
+    assert!(!config.capture.shadow_correlator);
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    let path = write_config("unknown", "[capture]\nbatch_size = 10\n");
+    let err = args(&["--config", path.to_str().unwrap()])
+        .load(env(&[]))
+        .unwrap_err();
+    let _ = std::fs::remove_file(&path);
+    assert!(err.contains("batch_size"), "{}", err);
+
+    let err = args(&["--ring-buffer-bytes", "5000"])
+        .load(env(&[]))
+        .unwrap_err();
+    assert!(err.contains("ring_buffer_bytes"), "{}", err);
+
+    let err = args(&[])
+        .load(env(&[("SCROP_CAPTURE_SHADOW_CORRELATOR", "maybe")]))
+        .unwrap_err();
+    assert!(err.contains("SCROP_CAPTURE_SHADOW_CORRELATOR"), "{}", err);
+}
+
+#[test]
+fn capture_on_start_from_file_and_flag() {
+    let path = write_config("on-start", "capture_on_start = true\n");
+    let config = args(&["--config", path.to_str().unwrap()])
+        .load(env(&[]))
+        .unwrap();
+    assert!(config.capture_on_start);
+
+    let config = args(&[
+        "--config",
+        path.to_str().unwrap(),
+        "--capture-on-start=false",
+    ])
+    .load(env(&[]))
+    .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(!config.capture_on_start);
+
+    let config = args(&["--capture-on-start"]).load(env(&[])).unwrap();
+    assert!(config.capture_on_start);
+}
+
+#[test]
+fn printed_config_round_trips() {
+    let config = args(&[
+        "--attach",
+        "eth*",
+        "--capture-on-start",
+        "--correlation-timeout-ms",
+        "80",
+    ])
+    .load(env(&[]))
+    .unwrap();
+    let parsed = ServerConfig::from_toml(&config.to_toml()).unwrap();
+    assert_eq!(parsed, config);
+}
+
+#[test]
+fn auth_credentials_from_file_env_and_args() {
+    let path = write_config(
+        "auth",
+        "[auth]\ntokens = [\"viewer:file-token\"]\nanonymous = \"viewer\"\n",
+    );
+    let config = args(&["--config", path.to_str().unwrap()])
+        .load(env(&[("SCROP_AUTH_USERS", "operator:alice:pa:ss")]))
+        .unwrap();
+    assert!(config.auth.is_enabled());
+    assert_eq!(config.auth.anonymous, auth::Access::Viewer);
+    assert_eq!(config.auth.tokens.len(), 1);
+    assert_eq!(config.auth.users[0].user, "alice");
+    assert_eq!(
+        config
+            .auth
+            .authenticate(Some("Basic YWxpY2U6cGE6c3M="), None),
+        Ok(auth::Access::Operator)
+    );
+
+    let config = args(&[
+        "--config",
+        path.to_str().unwrap(),
+        "--auth-token",
+        "operator:cli-token",
+        "--auth-anonymous",
+        "none",
+    ])
+    .load(env(&[]))
+    .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(config.auth.anonymous, auth::Access::None);
+    assert_eq!(
+        config.auth.authenticate(Some("Bearer cli-token"), None),
+        Ok(auth::Access::Operator)
+    );
+    assert!(config
+        .auth
+        .authenticate(Some("Bearer file-token"), None)
+        .is_err());
+
+    // 役割は viewer か operator だけ
+    assert!(TestCli::try_parse_from(["scrop-server", "--auth-token", "admin:x"]).is_err());
+    assert!(TestCli::try_parse_from(["scrop-server", "--auth-token", "none:x"]).is_err());
+    assert!(TestCli::try_parse_from(["scrop-server", "--auth-user", "viewer:bob"]).is_err());
+}
+
+#[test]
+fn tls_paths_from_file_env_and_args() {
+    let path = write_config("tls", "[tls]\ncert = \"/etc/scrop/server.pem\"\n");
+    let config = args(&["--config", path.to_str().unwrap()])
+        .load(env(&[("SCROP_TLS_KEY", "/etc/scrop/server.key")]))
+        .unwrap();
+    assert!(config.tls.is_enabled());
+    assert!(!config.tls.client_auth());
+    assert_eq!(config.tls.key, PathBuf::from("/etc/scrop/server.key"));
+
+    let config = args(&[
+        "--config",
+        path.to_str().unwrap(),
+        "--tls-key",
+        "/run/server.key",
+        "--tls-client-ca",
+        "/etc/scrop/operators.pem",
+    ])
+    .load(env(&[]))
+    .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(config.tls.key, PathBuf::from("/run/server.key"));
+    assert!(config.tls.client_auth());
+
+    // 証明書と鍵は揃えて指定する
+    let err = args(&["--tls-cert", "/etc/scrop/server.pem"])
+        .load(env(&[]))
+        .unwrap_err();
+    assert!(err.contains("cert and key"), "{}", err);
+    let err = args(&["--tls-client-ca", "/etc/scrop/operators.pem"])
+        .load(env(&[]))
+        .unwrap_err();
+    assert!(err.contains("client_ca"), "{}", err);
+}
+
+#[test]
+fn listeners_from_file_env_and_args() {
+    assert_eq!(
+        ServerConfig::default().listeners(),
+        vec![listen::ListenAddr::Tcp("127.0.0.1:3000".to_string())]
+    );
+
+    let path = write_config(
+        "listen",
+        "listen = [\"unix:/run/scrop.sock\"]\n[unix]\ngroup = \"scrop\"\n",
+    );
+    let config = args(&["--config", path.to_str().unwrap()])
+        .load(env(&[(
+            "SCROP_UNIX_PEERS",
+            "operator:group:wheel,viewer:user:alice",
+        )]))
+        .unwrap();
+    assert_eq!(
+        config.listeners(),
+        vec![listen::ListenAddr::Unix("/run/scrop.sock".into())]
+    );
+    assert_eq!(config.unix.group, "scrop");
+    assert_eq!(config.unix.mode, "0660");
+    assert_eq!(config.unix.peers.len(), 2);
+
+    let config = args(&[
+        "--config",
+        path.to_str().unwrap(),
+        "--listen",
+        "unix:/run/scrop/api.sock",
+        "--listen",
+        "0.0.0.0:8443",
+        "--unix-mode",
+        "600",
+    ])
+    .load(env(&[]))
+    .unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(
+        config.listeners(),
+        vec![
+            listen::ListenAddr::Unix("/run/scrop/api.sock".into()),
+            listen::ListenAddr::Tcp("0.0.0.0:8443".to_string()),
+        ]
+    );
+    assert_eq!(config.unix.mode().unwrap(), 0o600);
+
+    let err = args(&["--unix-mode", "0999"]).load(env(&[])).unwrap_err();
+    assert!(err.contains("octal"), "{}", err);
+    assert!(TestCli::try_parse_from(["scrop-server", "--listen", "unix:"]).is_err());
+    assert!(TestCli::try_parse_from(["scrop-server", "--unix-peer", "admin:user:root"]).is_err());
+}
+
+#[test]
+fn printed_config_redacts_secrets() {
+    let config = args(&[
+        "--auth-token",
+        "operator:t0p-s3cret",
+        "--auth-user",
+        "viewer:alice:hunter2",
+    ])
+    .load(env(&[]))
+    .unwrap();
+    let printed = config.to_toml();
+    assert!(!printed.contains("t0p-s3cret"), "{}", printed);
+    assert!(!printed.contains("hunter2"), "{}", printed);
+    assert!(printed.contains("operator:<redacted>"), "{}", printed);
+    assert!(printed.contains("viewer:alice:<redacted>"), "{}", printed);
+    // 伏せ字のまま読み込んでもトークンにはならない
+    assert!(ServerConfig::from_toml(&printed).is_err());
+
+    // 環境変数での上書きは伏せていない値のまま行う
+    let config = args(&["--auth-token", "operator:t0p-s3cret"])
+        .load(env(&[("SCROP_PORT", "4000")]))
+        .unwrap();
+    assert_eq!(
+        config.auth.authenticate(Some("Bearer t0p-s3cret"), None),
+        Ok(auth::Access::Operator)
+    );
+}
@@ -0,0 +1,369 @@
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use scrop_capture::types::{
+    AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult, Protocol,
+};
+use scrop_capture::{AppState, EVENT_CHANNEL_CAPACITY};
+
+#[path = "../src/drop_log.rs"]
+mod drop_log;
+#[path = "../src/shutdown.rs"]
+#[allow(dead_code)]
+mod shutdown;
+
+use drop_log::{DropLogConfig, DropLogTarget, DropLogger};
+use shutdown::Shutdown;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("scrop-drop-log-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("create temp dir");
+    dir
+}
+
+fn captured(id: &str, result: PacketResult, reason: Option<&str>) -> CapturedPacket {
+    let mut packet = AnimatingPacket::generate("abc123", 0);
+    packet.id = id.to_string();
+    packet.protocol = Protocol::Tcp;
+    packet.source = "10.0.0.1".to_string();
+    packet.src_port = 40000;
+    packet.destination = "10.0.0.2".to_string();
+    packet.dest_port = 80;
+    packet.capture_mono_ns = 2_000_000;
+    packet.interface = Some("eth0".to_string());
+    packet.reason = reason.map(str::to_string);
+    CapturedPacket { packet, result }
+}
+
+fn batch(packets: Vec<CapturedPacket>) -> CapturedPacketEnvelope {
+    CapturedPacketEnvelope {
+        packets,
+        epoch_offset_ms: 1_000.0,
+    }
+}
+
+fn config(targets: Vec<DropLogTarget>) -> DropLogConfig {
+    DropLogConfig {
+        targets,
+        max_file_bytes: 64 * 1024 * 1024,
+        keep_files: 5,
+        max_per_sec: 0,
+    }
+}
+
+fn read_lines(path: &std::path::Path) -> Vec<serde_json::Value> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("json line"))
+        .collect()
+}
+
+#[test]
+fn parses_targets() {
+    assert_eq!(
+        "file:/var/log/scrop/drops.jsonl".parse(),
+        Ok(DropLogTarget::File("/var/log/scrop/drops.jsonl".into()))
+    );
+    assert_eq!(
+        "syslog".parse(),
+        Ok(DropLogTarget::Syslog(
+            drop_log::DEFAULT_SYSLOG_SOCKET.into()
+        ))
+    );
+    assert_eq!(
+        "journald:/tmp/journal.sock".parse(),
+        Ok(DropLogTarget::Journald("/tmp/journal.sock".into()))
+    );
+    assert!("file".parse::<DropLogTarget>().is_err());
+    assert!("file:".parse::<DropLogTarget>().is_err());
+    assert!("kafka:topic".parse::<DropLogTarget>().is_err());
+}
+
+#[test]
+fn writes_only_drops_as_json_lines() {
+    let dir = temp_dir("jsonl");
+    let path = dir.join("drops.jsonl");
+    let mut logger = DropLogger::open(&config(vec![DropLogTarget::File(path.clone())])).unwrap();
+
+    logger.write_batch(
+        &batch(vec![
+            captured("pkt-delivered", PacketResult::Delivered, None),
+            captured("pkt-fw", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            captured("pkt-nic", PacketResult::NicDrop, Some("NO_SOCKET")),
+        ]),
+        10_000,
+    );
+
+    let lines = read_lines(&path);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["type"], "drop");
+    assert_eq!(lines[0]["id"], "pkt-fw");
+    assert_eq!(lines[0]["result"], "fw-drop");
+    assert_eq!(lines[0]["reason"], "NETFILTER_DROP");
+    assert_eq!(lines[0]["interface"], "eth0");
+    assert_eq!(lines[0]["source"], "10.0.0.1");
+    assert_eq!(lines[0]["destPort"], 80);
+    assert_eq!(lines[0]["timestampMs"], 1_002.0);
+    assert_eq!(lines[1]["result"], "nic-drop");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rotates_file_by_size() {
+    let dir = temp_dir("rotate");
+    let path = dir.join("drops.jsonl");
+    let mut logger = DropLogger::open(&DropLogConfig {
+        max_file_bytes: 1,
+        keep_files: 2,
+        ..config(vec![DropLogTarget::File(path.clone())])
+    })
+    .unwrap();
+
+    // 1 行ごとにローテートし、古いものは 2 個まで残す
+    for id in ["pkt-1", "pkt-2", "pkt-3", "pkt-4"] {
+        logger.write_batch(
+            &batch(vec![captured(
+                id,
+                PacketResult::FwDrop,
+                Some("NETFILTER_DROP"),
+            )]),
+            10_000,
+        );
+    }
+
+    let id = |path: PathBuf| read_lines(&path)[0]["id"].as_str().unwrap().to_string();
+    assert_eq!(id(path.clone()), "pkt-4");
+    assert_eq!(id(dir.join("drops.jsonl.1")), "pkt-3");
+    assert_eq!(id(dir.join("drops.jsonl.2")), "pkt-2");
+    assert!(!dir.join("drops.jsonl.3").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn rate_limit_writes_summary_for_the_rest() {
+    let dir = temp_dir("rate");
+    let path = dir.join("drops.jsonl");
+    let mut logger = DropLogger::open(&DropLogConfig {
+        max_per_sec: 1,
+        ..config(vec![DropLogTarget::File(path.clone())])
+    })
+    .unwrap();
+
+    logger.write_batch(
+        &batch(vec![
+            captured("pkt-1", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            captured("pkt-2", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            captured("pkt-3", PacketResult::NicDrop, Some("NO_SOCKET")),
+        ]),
+        10_000,
+    );
+    assert_eq!(read_lines(&path).len(), 1);
+
+    // 1 秒経つと書かなかった分のサマリーを書く
+    logger.roll_window(11_000);
+    let lines = read_lines(&path);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["type"], "summary");
+    assert_eq!(lines[1]["suppressed"], 2);
+    assert_eq!(lines[1]["byReason"]["fw-drop/NETFILTER_DROP"], 1);
+    assert_eq!(lines[1]["byReason"]["nic-drop/NO_SOCKET"], 1);
+
+    // 書かなかった分がなければサマリーは書かない
+    logger.roll_window(12_000);
+    assert_eq!(read_lines(&path).len(), 2);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn sends_to_syslog_and_journald_sockets() {
+    let dir = temp_dir("sockets");
+    let syslog_path = dir.join("syslog.sock");
+    let journald_path = dir.join("journal.sock");
+    let syslog = UnixDatagram::bind(&syslog_path).expect("bind syslog socket");
+    let journald = UnixDatagram::bind(&journald_path).expect("bind journald socket");
+    syslog
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    journald
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+
+    let mut logger = DropLogger::open(&config(vec![
+        DropLogTarget::Syslog(syslog_path),
+        DropLogTarget::Journald(journald_path),
+    ]))
+    .unwrap();
+    logger.write_batch(
+        &batch(vec![captured(
+            "pkt-fw",
+            PacketResult::FwDrop,
+            Some("NETFILTER_DROP"),
+        )]),
+        10_000,
+    );
+
+    let mut buf = vec![0; 64 * 1024];
+    let n = syslog.recv(&mut buf).expect("syslog datagram");
+    let message = std::str::from_utf8(&buf[..n]).unwrap();
+    let prefix = format!("<28>scrop-server[{}]: ", std::process::id());
+    let json: serde_json::Value =
+        serde_json::from_str(message.strip_prefix(&prefix).expect("syslog header")).unwrap();
+    assert_eq!(json["id"], "pkt-fw");
+
+    let n = journald.recv(&mut buf).expect("journald datagram");
+    let fields: Vec<&str> = std::str::from_utf8(&buf[..n]).unwrap().lines().collect();
+    for field in [
+        "MESSAGE=fw-drop tcp 10.0.0.1:40000 -> 10.0.0.2:80 on eth0 (NETFILTER_DROP)",
+        "PRIORITY=4",
+        "SYSLOG_IDENTIFIER=scrop-server",
+        "SCROP_PACKET_ID=pkt-fw",
+        "SCROP_RESULT=fw-drop",
+        "SCROP_REASON=NETFILTER_DROP",
+        "SCROP_INTERFACE=eth0",
+        "SCROP_DESTINATION_PORT=80",
+    ] {
+        assert!(fields.contains(&field), "missing {}", field);
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn open_fails_for_unwritable_file() {
+    let dir = temp_dir("unwritable");
+    let err = DropLogger::open(&config(vec![DropLogTarget::File(
+        dir.join("missing").join("drops.jsonl"),
+    )]))
+    .err()
+    .expect("open should fail");
+    assert!(err.contains("drops.jsonl"), "{}", err);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn spawned_logger_writes_without_clients() {
+    let dir = temp_dir("spawn");
+    let path = dir.join("drops.jsonl");
+    let state = Arc::new(AppState::new());
+    let logger = DropLogger::open(&config(vec![DropLogTarget::File(path.clone())])).unwrap();
+    let (_shutdown_tx, shutdown) = Shutdown::channel();
+    let task = drop_log::spawn(logger, &state, shutdown);
+
+    state
+        .event_tx
+        .send(batch(vec![captured(
+            "pkt-fw",
+            PacketResult::FwDrop,
+            Some("NETFILTER_DROP"),
+        )]))
+        .expect("send batch");
+
+    let mut lines = Vec::new();
+    for _ in 0..100 {
+        lines = read_lines(&path);
+        if !lines.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["id"], "pkt-fw");
+
+    task.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn spawned_logger_records_gap_when_lagged() {
+    let dir = temp_dir("gap");
+    let path = dir.join("drops.jsonl");
+    let state = Arc::new(AppState::new());
+    let logger = DropLogger::open(&config(vec![DropLogTarget::File(path.clone())])).unwrap();
+    let (_shutdown_tx, shutdown) = Shutdown::channel();
+    let task = drop_log::spawn(logger, &state, shutdown);
+
+    let pair = |i: usize| {
+        batch(vec![
+            captured(&format!("pkt-{}-a", i), PacketResult::Delivered, None),
+            captured(
+                &format!("pkt-{}-b", i),
+                PacketResult::NicDrop,
+                Some("NO_SOCKET"),
+            ),
+        ])
+    };
+    state.event_tx.send(pair(0)).expect("send batch");
+    let mut lines = Vec::new();
+    for _ in 0..100 {
+        lines = read_lines(&path);
+        if !lines.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(lines.len(), 1);
+
+    // 書き込みタスクが読む前にチャネルがあふれると、読めなかった分を gap として書く
+    for i in 1..=EVENT_CHANNEL_CAPACITY + 2 {
+        state.event_tx.send(pair(i)).expect("send batch");
+    }
+    let mut gap = None;
+    for _ in 0..100 {
+        gap = read_lines(&path)
+            .into_iter()
+            .find(|line| line["type"] == "gap");
+        if gap.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let gap = gap.expect("gap event written");
+    assert_eq!(gap["skippedBatches"], 2);
+    assert_eq!(gap["approxPackets"], 4);
+
+    task.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn shutdown_writes_pending_summary_and_stops() {
+    let dir = temp_dir("shutdown");
+    let path = dir.join("drops.jsonl");
+    let state = Arc::new(AppState::new());
+    let logger = DropLogger::open(&DropLogConfig {
+        max_per_sec: 1,
+        ..config(vec![DropLogTarget::File(path.clone())])
+    })
+    .unwrap();
+    let (shutdown_tx, shutdown) = Shutdown::channel();
+    let task = drop_log::spawn(logger, &state, shutdown);
+
+    state
+        .event_tx
+        .send(batch(vec![
+            captured("pkt-1", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+            captured("pkt-2", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+        ]))
+        .expect("send batch");
+    shutdown_tx.send(true).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .expect("drop log task finishes after shutdown")
+        .unwrap();
+
+    // 1 秒を待たずにサマリーが書かれている
+    let lines = read_lines(&path);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["id"], "pkt-1");
+    assert_eq!(lines[1]["type"], "summary");
+    assert_eq!(lines[1]["suppressed"], 1);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
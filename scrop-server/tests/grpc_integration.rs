@@ -0,0 +1,398 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Endpoint, Server, Uri};
+use tonic::Code;
+
+use scrop_capture::types::{AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult};
+use scrop_capture::AppState;
+
+#[allow(dead_code)]
+#[path = "../src/auth.rs"]
+mod auth;
+#[allow(dead_code)]
+#[path = "../src/grpc.rs"]
+mod grpc;
+#[allow(dead_code)]
+#[path = "../src/query.rs"]
+mod query;
+#[allow(dead_code)]
+#[path = "../src/stream.rs"]
+mod stream;
+#[allow(dead_code)]
+#[path = "../src/ws_proto.rs"]
+mod ws_proto;
+
+use ws_proto::pb;
+use ws_proto::pb::scrop_capture_client::ScropCaptureClient;
+
+async fn start_server(
+    state: Arc<AppState>,
+) -> (ScropCaptureClient<Channel>, tokio::task::JoinHandle<()>) {
+    start_service(grpc::CaptureService::new(state)).await
+}
+
+async fn start_service(
+    service: grpc::CaptureService,
+) -> (ScropCaptureClient<Channel>, tokio::task::JoinHandle<()>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("read local addr");
+    let server = tokio::spawn(async move {
+        Server::builder()
+            .add_service(service.into_server())
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+            .expect("serve grpc");
+    });
+    let client = ScropCaptureClient::connect(format!("http://{}", addr))
+        .await
+        .expect("connect grpc");
+    (client, server)
+}
+
+fn captured(id: &str, result: PacketResult) -> CapturedPacket {
+    let mut packet = AnimatingPacket::generate("abc123", 0);
+    packet.id = id.to_string();
+    CapturedPacket { packet, result }
+}
+
+#[tokio::test]
+async fn stream_packets_applies_filter() {
+    let state = Arc::new(AppState::new());
+    let (mut client, server) = start_server(state.clone()).await;
+
+    let mut stream = client
+        .stream_packets(pb::StreamPacketsRequest {
+            filter: Some(pb::SubscriptionFilter {
+                results: vec![pb::PacketResult::FwDrop as i32],
+                ..Default::default()
+            }),
+            mode: None,
+        })
+        .await
+        .expect("stream packets")
+        .into_inner();
+
+    // ストリームの購読はレスポンスを返す前に済んでいる
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets: vec![
+                captured("pkt-delivered", PacketResult::Delivered),
+                captured("pkt-dropped", PacketResult::FwDrop),
+            ],
+            epoch_offset_ms: 3.0,
+        })
+        .expect("send batch");
+
+    let envelope = tokio::time::timeout(Duration::from_secs(2), stream.message())
+        .await
+        .expect("timed out waiting for batch")
+        .expect("stream error")
+        .expect("stream ended");
+    assert_eq!(envelope.schema_version, ws_proto::SCHEMA_VERSION);
+    assert_eq!(envelope.epoch_offset_ms, 3.0);
+    assert_eq!(envelope.packets.len(), 1);
+    let packet = envelope.packets[0].packet.as_ref().expect("packet payload");
+    assert_eq!(packet.id, "pkt-dropped");
+
+    let clients = state.clients.snapshot();
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].kind, "grpc");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn stream_packets_reports_suppressed_packets() {
+    let state = Arc::new(AppState::new());
+    let (mut client, server) = start_server(state.clone()).await;
+
+    let mut stream = client
+        .stream_packets(pb::StreamPacketsRequest {
+            filter: None,
+            mode: Some(pb::DeliveryMode {
+                mode: Some(pb::delivery_mode::Mode::DropsOnly(
+                    pb::delivery_mode::DropsOnly {
+                        delivered_one_in: 3,
+                    },
+                )),
+            }),
+        })
+        .await
+        .expect("stream packets")
+        .into_inner();
+
+    let mut packets: Vec<CapturedPacket> = (0..6)
+        .map(|i| captured(&format!("pkt-delivered-{}", i), PacketResult::Delivered))
+        .collect();
+    packets.push(captured("pkt-nic-drop", PacketResult::NicDrop));
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets,
+            epoch_offset_ms: 0.0,
+        })
+        .expect("send batch");
+
+    let envelope = tokio::time::timeout(Duration::from_secs(2), stream.message())
+        .await
+        .expect("timed out waiting for batch")
+        .expect("stream error")
+        .expect("stream ended");
+    assert!(envelope.sampled);
+    assert_eq!(envelope.packets.len(), 3);
+    let suppressed = envelope.suppressed.expect("suppressed counts");
+    assert_eq!(suppressed.delivered, 4);
+    assert_eq!(suppressed.nic_drop, 0);
+    assert_eq!(state.clients.snapshot()[0].suppressed_packets, 4);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn stream_packets_rejects_invalid_filter() {
+    let state = Arc::new(AppState::new());
+    let (mut client, server) = start_server(state).await;
+
+    let status = client
+        .stream_packets(pb::StreamPacketsRequest {
+            filter: Some(pb::SubscriptionFilter {
+                cidrs: vec!["not-a-cidr".to_string()],
+                ..Default::default()
+            }),
+            mode: None,
+        })
+        .await
+        .expect_err("invalid filter must be rejected");
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let status = client
+        .stream_packets(pb::StreamPacketsRequest {
+            filter: None,
+            mode: Some(pb::DeliveryMode {
+                mode: Some(pb::delivery_mode::Mode::Sample(pb::delivery_mode::Sample {
+                    one_in: 0,
+                })),
+            }),
+        })
+        .await
+        .expect_err("invalid mode must be rejected");
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn interface_and_capture_control() {
+    let state = Arc::new(AppState::new());
+    let (mut client, server) = start_server(state).await;
+
+    let interfaces = client
+        .list_interfaces(pb::ListInterfacesRequest {})
+        .await
+        .expect("list interfaces")
+        .into_inner()
+        .interfaces;
+    assert!(!interfaces.is_empty());
+
+    let status = client
+        .attach(pb::InterfaceRequest {
+            name: "does-not-exist0".to_string(),
+            netns: None,
+        })
+        .await
+        .expect_err("unknown interface must be rejected");
+    assert_eq!(status.code(), Code::NotFound);
+
+    let attached = client
+        .attach(pb::InterfaceRequest {
+            name: interfaces[0].clone(),
+            netns: None,
+        })
+        .await
+        .expect("attach interface")
+        .into_inner();
+    assert_eq!(
+        attached.message,
+        format!("Interface {} attached", interfaces[0])
+    );
+
+    client
+        .start(pb::StartRequest {})
+        .await
+        .expect("start capture");
+    let status = client
+        .get_status(pb::GetStatusRequest {})
+        .await
+        .expect("get status")
+        .into_inner();
+    assert!(status.is_capturing);
+
+    client.stop(pb::StopRequest {}).await.expect("stop capture");
+    let status = client
+        .get_status(pb::GetStatusRequest {})
+        .await
+        .expect("get status")
+        .into_inner();
+    assert!(!status.is_capturing);
+
+    client
+        .detach(pb::InterfaceRequest {
+            name: interfaces[0].clone(),
+            netns: None,
+        })
+        .await
+        .expect("detach interface");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn serves_on_unix_socket() {
+    let path = std::env::temp_dir().join(format!("scrop-grpc-test-{}.sock", std::process::id()));
+    // 前回の起動で残ったソケットファイルは作り直される
+    std::fs::write(&path, b"stale").expect("create stale socket file");
+
+    let listen: grpc::GrpcListen = format!("unix:{}", path.display())
+        .parse()
+        .expect("parse listen address");
+    let state = Arc::new(AppState::new());
+    let server = tokio::spawn(async move {
+        grpc::serve(&listen, state, Arc::default())
+            .await
+            .expect("serve grpc");
+    });
+
+    let mut connected = None;
+    for _ in 0..50 {
+        let socket_path = path.clone();
+        let channel = Endpoint::from_static("http://localhost")
+            .connect_with_connector(tower::service_fn(move |_: Uri| {
+                let socket_path = socket_path.clone();
+                async move {
+                    let stream = tokio::net::UnixStream::connect(socket_path).await?;
+                    Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+                }
+            }))
+            .await;
+        if let Ok(channel) = channel {
+            connected = Some(channel);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let mut client = ScropCaptureClient::new(connected.expect("connect over unix socket"));
+    let status = client
+        .get_status(pb::GetStatusRequest {})
+        .await
+        .expect("get status")
+        .into_inner();
+    assert!(!status.is_capturing);
+
+    server.abort();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn listen_address_parsing() {
+    assert_eq!(
+        "127.0.0.1:50051".parse::<grpc::GrpcListen>(),
+        Ok(grpc::GrpcListen::Tcp("127.0.0.1:50051".parse().unwrap()))
+    );
+    assert_eq!(
+        "unix:/run/scrop/grpc.sock".parse::<grpc::GrpcListen>(),
+        Ok(grpc::GrpcListen::Unix("/run/scrop/grpc.sock".into()))
+    );
+    assert!("unix:".parse::<grpc::GrpcListen>().is_err());
+    assert!("localhost".parse::<grpc::GrpcListen>().is_err());
+}
+
+fn with_bearer<T>(message: T, token: &str) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.metadata_mut().insert(
+        "authorization",
+        format!("Bearer {}", token).parse().unwrap(),
+    );
+    request
+}
+
+#[tokio::test]
+async fn control_requires_operator_token_when_auth_is_enabled() {
+    let state = Arc::new(AppState::new());
+    let auth = auth::AuthConfig {
+        tokens: vec![
+            "viewer:view-token".parse().unwrap(),
+            "operator:op-token".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+    let service = grpc::CaptureService::new(state.clone()).with_auth(Arc::new(auth));
+    let (mut client, server) = start_service(service).await;
+
+    let status = client
+        .list_interfaces(pb::ListInterfacesRequest {})
+        .await
+        .expect_err("missing token must be rejected");
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    client
+        .list_interfaces(with_bearer(pb::ListInterfacesRequest {}, "view-token"))
+        .await
+        .expect("viewer may list interfaces");
+    let status = client
+        .start(with_bearer(pb::StartRequest {}, "view-token"))
+        .await
+        .expect_err("viewer must not start capture");
+    assert_eq!(status.code(), Code::PermissionDenied);
+    assert!(!state.capture.lock().await.is_running());
+
+    client
+        .start(with_bearer(pb::StartRequest {}, "op-token"))
+        .await
+        .expect("operator may start capture");
+    assert!(state.capture.lock().await.is_running());
+    state.capture.lock().await.stop();
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn client_ca_without_tokens_keeps_grpc_closed() {
+    // --tls-client-ca（や --unix-peer）だけを設定したときに main が作る設定
+    let state = Arc::new(AppState::new());
+    let auth = auth::AuthConfig {
+        peer_auth: true,
+        ..Default::default()
+    };
+    // main はこの設定では gRPC を起動しない
+    assert!(grpc::check_auth(&auth).is_err());
+
+    let service = grpc::CaptureService::new(state.clone()).with_auth(Arc::new(auth));
+    let (mut client, server) = start_service(service).await;
+    let status = client
+        .list_interfaces(pb::ListInterfacesRequest {})
+        .await
+        .expect_err("gRPC must not be open without credentials");
+    assert_eq!(status.code(), Code::Unauthenticated);
+    let status = client
+        .start(pb::StartRequest {})
+        .await
+        .expect_err("gRPC control must not be open without credentials");
+    assert_eq!(status.code(), Code::Unauthenticated);
+    assert!(!state.capture.lock().await.is_running());
+
+    // トークンがあれば gRPC でも認証できる
+    let auth = auth::AuthConfig {
+        tokens: vec!["operator:s3cr3t".parse().unwrap()],
+        peer_auth: true,
+        ..Default::default()
+    };
+    assert!(grpc::check_auth(&auth).is_ok());
+    assert!(grpc::check_auth(&auth::AuthConfig::default()).is_ok());
+
+    server.abort();
+}
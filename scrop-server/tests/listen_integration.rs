@@ -0,0 +1,205 @@
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::Extension;
+use axum::response::Response;
+use axum::routing::get;
+use axum::{middleware, Router};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio_tungstenite::tungstenite::Message;
+
+#[allow(dead_code)]
+#[path = "../src/auth.rs"]
+mod auth;
+#[allow(dead_code)]
+#[path = "../src/listen.rs"]
+mod listen;
+
+use auth::{Access, AuthConfig, PeerAccess};
+use listen::{ListenAddr, PeerPolicy, PeerRule, UnixSocketConfig, UnixSocketListener};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("scrop-listen-{}-{}.sock", name, std::process::id()))
+}
+
+async fn whoami(Extension(access): Extension<Access>) -> String {
+    access.to_string()
+}
+
+async fn echo(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(|mut socket: WebSocket| async move {
+        while let Some(Ok(message)) = socket.recv().await {
+            if let WsMessage::Text(text) = message {
+                let _ = socket.send(WsMessage::Text(text)).await;
+            }
+        }
+    })
+}
+
+fn start_server(listener: UnixSocketListener, auth: AuthConfig) {
+    let app = Router::new()
+        .route("/api/whoami", get(whoami))
+        .route("/ws", get(echo))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(auth),
+            auth::require,
+        ));
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<PeerAccess>(),
+        )
+        .await
+        .unwrap();
+    });
+}
+
+async fn get_over_unix(path: &std::path::Path, uri: &str) -> (u16, String) {
+    let mut stream = UnixStream::connect(path).await.unwrap();
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                uri
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8_lossy(&response);
+    let status = response
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .unwrap_or(0);
+    let body = response
+        .split_once("\r\n\r\n")
+        .map_or("", |(_, body)| body)
+        .to_string();
+    (status, body)
+}
+
+#[test]
+fn listen_addr_parses_tcp_and_unix() {
+    assert_eq!(
+        "127.0.0.1:3000".parse::<ListenAddr>(),
+        Ok(ListenAddr::Tcp("127.0.0.1:3000".to_string()))
+    );
+    assert_eq!(
+        "[::1]:3000".parse::<ListenAddr>(),
+        Ok(ListenAddr::Tcp("[::1]:3000".to_string()))
+    );
+    assert_eq!(
+        "unix:/run/scrop.sock".parse::<ListenAddr>(),
+        Ok(ListenAddr::Unix("/run/scrop.sock".into()))
+    );
+    assert!("unix:".parse::<ListenAddr>().is_err());
+    assert!("localhost".parse::<ListenAddr>().is_err());
+    assert!(":3000".parse::<ListenAddr>().is_err());
+
+    assert!("localhost:3000".parse::<ListenAddr>().unwrap().is_local());
+    assert!("[::1]:3000".parse::<ListenAddr>().unwrap().is_local());
+    assert!("unix:/run/scrop.sock"
+        .parse::<ListenAddr>()
+        .unwrap()
+        .is_local());
+    assert!(!"0.0.0.0:3000".parse::<ListenAddr>().unwrap().is_local());
+}
+
+#[test]
+fn peer_rules_parse_role_kind_and_name() {
+    let rule: PeerRule = "operator:group:wheel".parse().unwrap();
+    assert_eq!(rule.access, Access::Operator);
+    assert_eq!(String::from(rule), "operator:group:wheel");
+    assert!("viewer:user:1000".parse::<PeerRule>().is_ok());
+    assert!("none:user:alice".parse::<PeerRule>().is_err());
+    assert!("viewer:host:alice".parse::<PeerRule>().is_err());
+    assert!("viewer:user:".parse::<PeerRule>().is_err());
+}
+
+#[test]
+fn peer_policy_grants_roles_by_uid_and_group() {
+    let rules: Vec<PeerRule> = ["viewer:user:4242", "operator:group:4000"]
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect();
+    let policy = PeerPolicy::resolve(&rules).unwrap();
+
+    assert_eq!(policy.access(4242, 100, &[]), Access::Viewer);
+    assert_eq!(policy.access(4242, 4000, &[]), Access::Operator);
+    assert_eq!(policy.access(5000, 4000, &[]), Access::Operator);
+    assert_eq!(policy.access(5000, 100, &[]), Access::None);
+    // 補助グループも照合する
+    assert_eq!(policy.access(5000, 100, &[10, 4000]), Access::Operator);
+    // root とサーバー自身のユーザーは規則がなくても操作者
+    assert_eq!(policy.access(0, 0, &[]), Access::Operator);
+    let own_uid = std::fs::metadata("/proc/self").unwrap().uid();
+    assert_eq!(policy.access(own_uid, 100, &[]), Access::Operator);
+
+    assert!(PeerPolicy::resolve(&["operator:user:no-such-scrop-user".parse().unwrap()]).is_err());
+}
+
+#[tokio::test]
+async fn unix_socket_sets_mode_group_and_replaces_stale_socket() {
+    let path = socket_path("mode");
+    let _ = std::fs::remove_file(&path);
+    let gid = std::fs::metadata(std::env::temp_dir()).unwrap().gid();
+    let config = UnixSocketConfig {
+        mode: "0600".to_string(),
+        group: gid.to_string(),
+        ..UnixSocketConfig::default()
+    };
+    let policy = Arc::new(PeerPolicy::default());
+
+    let listener = UnixSocketListener::bind(&path, &config, policy.clone()).unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    assert_eq!(metadata.gid(), gid);
+    drop(listener);
+
+    // 前回の起動で残ったソケットは作り直す
+    UnixSocketListener::bind(&path, &UnixSocketConfig::default(), policy.clone()).unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o660);
+    let _ = std::fs::remove_file(&path);
+
+    // ソケット以外のファイルは消さない
+    std::fs::write(&path, "data").unwrap();
+    assert!(UnixSocketListener::bind(&path, &config, policy).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn serves_api_and_websocket_over_unix_socket() {
+    let path = socket_path("serve");
+    let auth = AuthConfig {
+        tokens: vec!["viewer:view-token".parse().unwrap()],
+        peer_auth: true,
+        ..AuthConfig::default()
+    };
+    let policy = Arc::new(PeerPolicy::resolve(&[]).unwrap());
+    let listener = UnixSocketListener::bind(&path, &UnixSocketConfig::default(), policy).unwrap();
+    start_server(listener, auth);
+
+    // テストを動かすユーザーはサーバーと同じなので、資格情報なしで操作者
+    assert_eq!(
+        get_over_unix(&path, "/api/whoami").await,
+        (200, "operator".to_string())
+    );
+
+    let stream = UnixStream::connect(&path).await.unwrap();
+    let (mut socket, _response) = tokio_tungstenite::client_async("ws://localhost/ws", stream)
+        .await
+        .expect("WebSocket upgrade over unix socket");
+    socket.send(Message::text("ping")).await.unwrap();
+    let reply = socket.next().await.unwrap().unwrap();
+    assert_eq!(reply, Message::text("ping"));
+    let _ = std::fs::remove_file(&path);
+}
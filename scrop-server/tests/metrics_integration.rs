@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use scrop_capture::clients::ClientRegistry;
+use scrop_capture::metrics::CaptureMetrics;
+use scrop_capture::types::{
+    AnimatingPacket, CaptureStats, CapturedPacket, PacketResult, Protocol, StatusSnapshot,
+};
+
+#[allow(dead_code)]
+#[path = "../src/metrics.rs"]
+mod metrics;
+
+fn captured(interface: &str, result: PacketResult, reason: Option<&str>) -> CapturedPacket {
+    let mut packet = AnimatingPacket::generate("abc123", 0);
+    packet.protocol = Protocol::Tcp;
+    packet.interface = Some(interface.to_string());
+    packet.reason = reason.map(str::to_string);
+    CapturedPacket { packet, result }
+}
+
+/// サンプル行（`name{labels} value`）の値
+fn sample_value(text: &str, series: &str) -> Option<String> {
+    text.lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map(str::to_string)
+}
+
+#[test]
+fn renders_openmetrics_text() {
+    let status = StatusSnapshot {
+        is_capturing: true,
+        mode: "mock".to_string(),
+        stats: CaptureStats {
+            total_packets: 3,
+            delivered: 1,
+            fw_dropped: 2,
+            transport_dropped: 7,
+            correlator_remove_scan_steps: 42,
+            status_lock_wait_ns: 1_500_000_000,
+            ..Default::default()
+        },
+    };
+    let capture_metrics = CaptureMetrics::new();
+    capture_metrics.record_batch(&[
+        captured("eth0", PacketResult::Delivered, None),
+        captured("eth0", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+        captured("eth0", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+    ]);
+    capture_metrics.observe_correlation_latency(2_000_000);
+    capture_metrics.observe_correlation_latency(300_000_000);
+
+    let registry = Arc::new(ClientRegistry::new());
+    let _ws = registry.register("websocket");
+    let _ws2 = registry.register("websocket");
+    let _grpc = registry.register("grpc");
+
+    let text = metrics::render(&status, &capture_metrics.snapshot(), &registry.snapshot());
+    assert!(text.ends_with("# EOF\n"));
+    assert!(text.contains("# TYPE scrop_packets counter\n"));
+    assert!(text.contains("# UNIT scrop_correlation_latency_seconds seconds\n"));
+
+    let expected = [
+        ("scrop_capture_running{mode=\"mock\"}", "1"),
+        ("scrop_packets_total", "3"),
+        ("scrop_packets_by_result_total{result=\"fw-drop\"}", "2"),
+        ("scrop_ringbuf_dropped_events_total", "7"),
+        ("scrop_correlator_remove_scan_steps_total", "42"),
+        ("scrop_status_lock_wait_seconds_total", "1.5"),
+        (
+            "scrop_interface_packets_total{interface=\"eth0\",result=\"fw-drop\",reason=\"NETFILTER_DROP\",protocol=\"tcp\"}",
+            "2",
+        ),
+        (
+            "scrop_interface_packets_total{interface=\"eth0\",result=\"delivered\",reason=\"\",protocol=\"tcp\"}",
+            "1",
+        ),
+        ("scrop_correlation_latency_seconds_bucket{le=\"0.005\"}", "1"),
+        ("scrop_correlation_latency_seconds_bucket{le=\"0.25\"}", "1"),
+        ("scrop_correlation_latency_seconds_bucket{le=\"+Inf\"}", "2"),
+        ("scrop_correlation_latency_seconds_count", "2"),
+        ("scrop_correlation_latency_seconds_sum", "0.302"),
+        ("scrop_batch_size_packets_bucket{le=\"4\"}", "1"),
+        ("scrop_batch_size_packets_sum", "3"),
+        ("scrop_stream_clients{kind=\"websocket\"}", "2"),
+        ("scrop_stream_clients{kind=\"grpc\"}", "1"),
+        ("scrop_stream_clients{kind=\"sse\"}", "0"),
+    ];
+    for (series, value) in expected {
+        assert_eq!(
+            sample_value(&text, series).as_deref(),
+            Some(value),
+            "{}",
+            series
+        );
+    }
+    // GeoIP が無効なら国・AS のファミリーは出さない
+    assert!(!text.contains("scrop_packets_by_country"));
+}
+
+#[test]
+fn escapes_label_values() {
+    let status = StatusSnapshot {
+        is_capturing: false,
+        mode: "mock".to_string(),
+        stats: CaptureStats::default(),
+    };
+    let capture_metrics = CaptureMetrics::new();
+    capture_metrics.record_batch(&[captured(
+        "eth0",
+        PacketResult::NicDrop,
+        Some("say \"hi\"\\"),
+    )]);
+
+    let text = metrics::render(&status, &capture_metrics.snapshot(), &[]);
+    assert!(text.contains("reason=\"say \\\"hi\\\"\\\\\""));
+    assert_eq!(
+        sample_value(&text, "scrop_capture_running{mode=\"mock\"}").as_deref(),
+        Some("0")
+    );
+}
+
+#[cfg(not(feature = "ebpf"))]
+#[tokio::test]
+async fn mock_capture_records_per_interface_metrics() {
+    let state = Arc::new(scrop_capture::AppState::new());
+    {
+        let capture = state.capture.lock().await;
+        capture.attach_interface("eth0").await.expect("attach eth0");
+        capture.start(state.event_tx.clone());
+    }
+
+    let mut snapshot = state.metrics.snapshot();
+    for _ in 0..50 {
+        if snapshot.batch_size.count > 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        snapshot = state.metrics.snapshot();
+    }
+    state.capture.lock().await.stop();
+
+    assert!(
+        snapshot.batch_size.count > 0,
+        "expected a batch to be recorded"
+    );
+    assert!(snapshot.correlation_latency_ns.count > 0);
+    assert!(snapshot
+        .packets
+        .iter()
+        .all(|(labels, _)| labels.interface == "eth0"));
+
+    let text = metrics::render(
+        &state.status_snapshot().await,
+        &snapshot,
+        &state.clients.snapshot(),
+    );
+    assert!(text.contains("scrop_interface_packets_total{interface=\"eth0\","));
+
+    state.capture.lock().await.reset();
+    assert!(state.metrics.snapshot().packets.is_empty());
+}
@@ -0,0 +1,211 @@
+#![cfg(feature = "otel")]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_server::{
+    MetricsService, MetricsServiceServer,
+};
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+    TraceService, TraceServiceServer,
+};
+use opentelemetry_proto::tonic::collector::trace::v1::{
+    ExportTraceServiceRequest, ExportTraceServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::{any_value, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::{metric, number_data_point};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use scrop_capture::types::{
+    AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult, Protocol,
+};
+use scrop_capture::AppState;
+
+#[path = "../src/otel.rs"]
+mod otel;
+
+/// 受け取ったリクエストを保存するだけのコレクター
+#[derive(Clone, Default)]
+struct Collector {
+    metrics: Arc<Mutex<Vec<ExportMetricsServiceRequest>>>,
+    traces: Arc<Mutex<Vec<ExportTraceServiceRequest>>>,
+}
+
+#[tonic::async_trait]
+impl MetricsService for Collector {
+    async fn export(
+        &self,
+        request: Request<ExportMetricsServiceRequest>,
+    ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+        self.metrics.lock().unwrap().push(request.into_inner());
+        Ok(Response::new(ExportMetricsServiceResponse::default()))
+    }
+}
+
+#[tonic::async_trait]
+impl TraceService for Collector {
+    async fn export(
+        &self,
+        request: Request<ExportTraceServiceRequest>,
+    ) -> Result<Response<ExportTraceServiceResponse>, Status> {
+        self.traces.lock().unwrap().push(request.into_inner());
+        Ok(Response::new(ExportTraceServiceResponse::default()))
+    }
+}
+
+async fn start_collector() -> (Collector, String, tokio::task::JoinHandle<()>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind collector");
+    let addr = listener.local_addr().expect("read local addr");
+    let collector = Collector::default();
+    let server = {
+        let collector = collector.clone();
+        tokio::spawn(async move {
+            Server::builder()
+                .add_service(MetricsServiceServer::new(collector.clone()))
+                .add_service(TraceServiceServer::new(collector))
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+                .expect("serve collector");
+        })
+    };
+    (collector, format!("http://{}", addr), server)
+}
+
+fn captured(id: &str, result: PacketResult, reason: Option<&str>) -> CapturedPacket {
+    let mut packet = AnimatingPacket::generate("abc123", 0);
+    packet.id = id.to_string();
+    packet.protocol = Protocol::Tcp;
+    packet.interface = Some("eth0".to_string());
+    packet.reason = reason.map(str::to_string);
+    CapturedPacket { packet, result }
+}
+
+fn attribute<'a>(attributes: &'a [KeyValue], key: &str) -> Option<&'a any_value::Value> {
+    attributes
+        .iter()
+        .find(|kv| kv.key == key)
+        .and_then(|kv| kv.value.as_ref()?.value.as_ref())
+}
+
+fn string_attribute<'a>(attributes: &'a [KeyValue], key: &str) -> Option<&'a str> {
+    match attribute(attributes, key)? {
+        any_value::Value::StringValue(value) => Some(value),
+        _ => None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn exports_metrics_and_sampled_drops() {
+    let (collector, endpoint, server) = start_collector().await;
+    let state = Arc::new(AppState::new());
+    state.spawn_status_publisher(Duration::from_millis(20));
+
+    let exporter = otel::start(
+        &otel::OtelConfig {
+            endpoint,
+            export_interval: Duration::from_secs(60),
+            drop_sample: 2,
+        },
+        state.clone(),
+    )
+    .expect("start exporter");
+
+    let packets = vec![
+        captured("pkt-delivered", PacketResult::Delivered, None),
+        captured("pkt-drop-1", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+        captured("pkt-drop-2", PacketResult::FwDrop, Some("NETFILTER_DROP")),
+        captured("pkt-drop-3", PacketResult::NicDrop, Some("NO_SOCKET")),
+    ];
+    state.metrics.record_batch(&packets);
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets,
+            epoch_offset_ms: 0.0,
+        })
+        .expect("send batch");
+
+    // ドロップの購読と状態の定期通知が処理されるのを待つ
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // shutdown で残りのメトリクスとスパンが送られる
+    tokio::task::spawn_blocking(move || exporter.shutdown())
+        .await
+        .expect("shutdown exporter");
+
+    let traces = collector.traces.lock().unwrap().clone();
+    let spans: Vec<_> = traces
+        .iter()
+        .flat_map(|r| &r.resource_spans)
+        .flat_map(|r| &r.scope_spans)
+        .flat_map(|s| &s.spans)
+        .collect();
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].name, "scrop.dropped_packets");
+    // 3 個のドロップを 2 個に 1 個ずつ送る
+    let events = &spans[0].events;
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|e| e.name == "packet.dropped"));
+    assert_eq!(
+        string_attribute(&events[0].attributes, "packet.id"),
+        Some("pkt-drop-1")
+    );
+    assert_eq!(
+        string_attribute(&events[1].attributes, "packet.id"),
+        Some("pkt-drop-3")
+    );
+    assert_eq!(
+        string_attribute(&events[1].attributes, "scrop.drop.reason"),
+        Some("NO_SOCKET")
+    );
+    assert_eq!(
+        string_attribute(&events[1].attributes, "network.interface.name"),
+        Some("eth0")
+    );
+    for key in [
+        "network.transport",
+        "source.address",
+        "source.port",
+        "destination.address",
+        "destination.port",
+    ] {
+        assert!(attribute(&events[0].attributes, key).is_some(), "{}", key);
+    }
+
+    let requests = collector.metrics.lock().unwrap().clone();
+    let metrics: Vec<_> = requests
+        .iter()
+        .flat_map(|r| &r.resource_metrics)
+        .flat_map(|r| &r.scope_metrics)
+        .flat_map(|s| &s.metrics)
+        .collect();
+    let sum_points = |name: &str| {
+        metrics
+            .iter()
+            .filter(|m| m.name == name)
+            .flat_map(|m| match &m.data {
+                Some(metric::Data::Sum(sum)) => sum.data_points.clone(),
+                _ => Vec::new(),
+            })
+            .collect::<Vec<_>>()
+    };
+    let fw_drops = sum_points("scrop.interface.packets")
+        .into_iter()
+        .find(|p| {
+            string_attribute(&p.attributes, "result") == Some("fw-drop")
+                && string_attribute(&p.attributes, "network.interface.name") == Some("eth0")
+        })
+        .expect("fw-drop data point");
+    assert_eq!(fw_drops.value, Some(number_data_point::Value::AsInt(2)));
+    assert!(sum_points("scrop.packets")
+        .iter()
+        .any(|p| string_attribute(&p.attributes, "result") == Some("delivered")));
+
+    server.abort();
+}
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::routing::get;
+use axum::Router;
+use http_body_util::BodyExt;
+use tower::ServiceExt;
+
+use scrop_capture::types::{
+    AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult, ProcessInfo, Protocol,
+};
+use scrop_capture::{AppState, EVENT_CHANNEL_CAPACITY};
+
+#[path = "../src/query.rs"]
+mod query;
+#[allow(dead_code)]
+#[path = "../src/stream.rs"]
+mod stream;
+
+fn build_app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/api/stream.ndjson", get(stream::ndjson_handler))
+        .route("/api/stream/sse", get(stream::sse_handler))
+        .with_state(state)
+}
+
+fn packet(id: &str, pid: Option<u32>) -> CapturedPacket {
+    let mut packet = AnimatingPacket::generate("abc123", 0);
+    packet.id = id.to_string();
+    packet.process = pid.map(|pid| ProcessInfo {
+        pid,
+        comm: "curl".to_string(),
+        cgroup_id: 1,
+    });
+    CapturedPacket {
+        packet,
+        result: PacketResult::Delivered,
+    }
+}
+
+async fn open_stream(app: Router, uri: &str) -> axum::http::Response<Body> {
+    app.oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+/// 次のデータフレームを文字列で読む
+async fn next_chunk(body: &mut Body) -> String {
+    loop {
+        let frame = tokio::time::timeout(Duration::from_secs(2), body.frame())
+            .await
+            .expect("timed out waiting for stream data")
+            .expect("stream ended")
+            .expect("stream error");
+        if let Ok(data) = frame.into_data() {
+            return String::from_utf8(data.to_vec()).expect("utf-8 chunk");
+        }
+    }
+}
+
+#[tokio::test]
+async fn ndjson_streams_filtered_batches_as_json_lines() {
+    let state = Arc::new(AppState::new());
+    let response = open_stream(build_app(state.clone()), "/api/stream.ndjson?pid=42").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/x-ndjson"
+    );
+    let mut body = response.into_body();
+
+    // 条件に合うパケットがないバッチは送られない
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets: vec![packet("pkt-other", Some(7))],
+            epoch_offset_ms: 1.0,
+        })
+        .expect("send batch");
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets: vec![packet("pkt-curl", Some(42)), packet("pkt-unknown", None)],
+            epoch_offset_ms: 2.0,
+        })
+        .expect("send batch");
+
+    let line = next_chunk(&mut body).await;
+    assert!(line.ends_with('\n'));
+    let value: serde_json::Value = serde_json::from_str(line.trim_end()).expect("json line");
+    assert_eq!(value["type"], "batch");
+    assert_eq!(value["sampled"], false);
+    assert_eq!(value["suppressed"]["delivered"], 0);
+    let batch: CapturedPacketEnvelope = serde_json::from_value(value).expect("batch line");
+    assert_eq!(batch.epoch_offset_ms, 2.0);
+    assert_eq!(batch.packets.len(), 1);
+    assert_eq!(batch.packets[0].packet.id, "pkt-curl");
+}
+
+#[tokio::test]
+async fn ndjson_reports_gaps_and_suppressed_packets() {
+    let state = Arc::new(AppState::new());
+    let response = open_stream(
+        build_app(state.clone()),
+        "/api/stream.ndjson?mode=sample&rate=2",
+    )
+    .await;
+    let mut body = response.into_body();
+
+    for i in 0..EVENT_CHANNEL_CAPACITY + 3 {
+        state
+            .event_tx
+            .send(CapturedPacketEnvelope {
+                packets: vec![
+                    packet(&format!("pkt-{}-a", i), None),
+                    packet(&format!("pkt-{}-b", i), None),
+                ],
+                epoch_offset_ms: 0.0,
+            })
+            .expect("send batch");
+    }
+
+    let gap: serde_json::Value =
+        serde_json::from_str(next_chunk(&mut body).await.trim_end()).expect("gap line");
+    assert_eq!(gap["type"], "gap");
+    assert_eq!(gap["skippedBatches"], 3);
+    // まだバッチを受け取っていないので、パケット数は見積もれない
+    assert_eq!(gap["approxPackets"], 0);
+
+    let batch: serde_json::Value =
+        serde_json::from_str(next_chunk(&mut body).await.trim_end()).expect("batch line");
+    assert_eq!(batch["type"], "batch");
+    assert_eq!(batch["sampled"], true);
+    assert_eq!(batch["packets"].as_array().unwrap().len(), 1);
+    assert_eq!(batch["suppressed"]["delivered"], 1);
+}
+
+#[tokio::test]
+async fn stream_query_filters_by_result_port_and_interface() {
+    let state = Arc::new(AppState::new());
+    let response = open_stream(
+        build_app(state.clone()),
+        "/api/stream.ndjson?result=fw-drop,nic-drop&protocol=TCP&port=443&cidr=10.0.0.0/8&interface=eth0",
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut body = response.into_body();
+
+    let matching = |id: &str, result: PacketResult| {
+        let mut captured = packet(id, None);
+        captured.result = result;
+        captured.packet.protocol = Protocol::Tcp;
+        captured.packet.source = "10.1.2.3".to_string();
+        captured.packet.destination = "192.0.2.1".to_string();
+        captured.packet.src_port = 50000;
+        captured.packet.dest_port = 443;
+        captured.packet.interface = Some("eth0".to_string());
+        captured.packet.netns = None;
+        captured
+    };
+    let mut other_port = matching("pkt-other-port", PacketResult::FwDrop);
+    other_port.packet.dest_port = 80;
+    let mut other_iface = matching("pkt-other-iface", PacketResult::FwDrop);
+    other_iface.packet.interface = Some("eth1".to_string());
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets: vec![
+                matching("pkt-delivered", PacketResult::Delivered),
+                other_port,
+                other_iface,
+                matching("pkt-fw-drop", PacketResult::FwDrop),
+            ],
+            epoch_offset_ms: 0.0,
+        })
+        .expect("send batch");
+
+    let batch: CapturedPacketEnvelope =
+        serde_json::from_str(next_chunk(&mut body).await.trim_end()).expect("batch line");
+    let ids: Vec<&str> = batch.packets.iter().map(|p| p.packet.id.as_str()).collect();
+    assert_eq!(ids, vec!["pkt-fw-drop"]);
+}
+
+#[tokio::test]
+async fn sse_streams_batches_and_reports_gaps() {
+    let state = Arc::new(AppState::new());
+    let response = open_stream(build_app(state.clone()), "/api/stream/sse").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    let mut body = response.into_body();
+
+    // 読む前にチャネルがあふれると、まず gap イベントが届く
+    for i in 0..EVENT_CHANNEL_CAPACITY + 3 {
+        state
+            .event_tx
+            .send(CapturedPacketEnvelope {
+                packets: vec![packet(&format!("pkt-{}", i), None)],
+                epoch_offset_ms: 0.0,
+            })
+            .expect("send batch");
+    }
+
+    let gap = next_chunk(&mut body).await;
+    assert!(gap.starts_with("event: gap\n"), "unexpected event: {}", gap);
+    assert!(
+        gap.contains(r#""skippedBatches":3"#),
+        "unexpected gap: {}",
+        gap
+    );
+
+    let event = next_chunk(&mut body).await;
+    let data = event
+        .strip_prefix("data: ")
+        .and_then(|data| data.strip_suffix("\n\n"))
+        .expect("sse data event");
+    let batch: serde_json::Value = serde_json::from_str(data).expect("json data");
+    assert_eq!(batch["sampled"], false);
+    assert!(batch["suppressed"].is_object());
+    let batch: CapturedPacketEnvelope = serde_json::from_value(batch).expect("batch data");
+    assert_eq!(batch.packets[0].packet.id, "pkt-3");
+
+    let clients = state.clients.snapshot();
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].kind, "sse");
+    assert_eq!(clients[0].lag_events, 1);
+}
+
+#[tokio::test]
+async fn stream_client_is_deregistered_on_disconnect() {
+    let state = Arc::new(AppState::new());
+    let response = open_stream(
+        build_app(state.clone()),
+        "/api/stream.ndjson?mode=sample&rate=2",
+    )
+    .await;
+    assert_eq!(state.clients.snapshot().len(), 1);
+    assert_eq!(state.clients.snapshot()[0].kind, "ndjson");
+
+    drop(response);
+    assert!(state.clients.snapshot().is_empty());
+}
+
+#[tokio::test]
+async fn stream_rejects_invalid_delivery_mode() {
+    let state = Arc::new(AppState::new());
+    for uri in [
+        "/api/stream.ndjson?mode=bogus",
+        "/api/stream/sse?mode=sample",
+        // 知らないパラメータや不正な条件で全件を流さない
+        "/api/stream.ndjson?ports=443",
+        "/api/stream.ndjson?port=https",
+        "/api/stream.ndjson?result=dropped",
+        "/api/stream/sse?cidr=not-a-cidr",
+    ] {
+        let response = open_stream(build_app(state.clone()), uri).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+    assert!(state.clients.snapshot().is_empty());
+}
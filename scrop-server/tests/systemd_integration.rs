@@ -0,0 +1,33 @@
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+#[path = "../src/systemd.rs"]
+#[allow(dead_code)]
+mod systemd;
+
+#[test]
+fn notify_sends_state_to_path_socket() {
+    let path = std::env::temp_dir().join(format!("scrop-notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixDatagram::bind(&path).expect("bind notify socket");
+
+    systemd::notify_to(path.to_str().unwrap(), "READY=1").unwrap();
+
+    let mut buf = [0u8; 64];
+    let n = listener.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"READY=1");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn notify_sends_state_to_abstract_socket() {
+    let name = format!("scrop-notify-test-{}", std::process::id());
+    let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let listener = UnixDatagram::bind_addr(&addr).expect("bind abstract socket");
+
+    systemd::notify_to(&format!("@{}", name), "STOPPING=1").unwrap();
+
+    let mut buf = [0u8; 64];
+    let n = listener.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"STOPPING=1");
+}
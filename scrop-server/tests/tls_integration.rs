@@ -0,0 +1,377 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::Extension;
+use axum::routing::{get, post};
+use axum::{middleware, Router};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair,
+    KeyUsagePurpose,
+};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[allow(dead_code)]
+#[path = "../src/auth.rs"]
+mod auth;
+#[allow(dead_code)]
+#[path = "../src/tls.rs"]
+mod tls;
+
+use auth::{Access, AuthConfig, PeerAccess, TokenCredential};
+use tls::{ReloadableTls, TlsConfig, TlsListener};
+
+const OPERATOR_TOKEN: &str = "s3cr3t";
+
+struct Ca {
+    pem: String,
+    issuer: Issuer<'static, KeyPair>,
+}
+
+fn ca(name: &str) -> Ca {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name.push(DnType::CommonName, name);
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let cert = params.self_signed(&key).unwrap();
+    Ca {
+        pem: cert.pem(),
+        issuer: Issuer::new(params, key),
+    }
+}
+
+/// `ca` が署名した証明書と秘密鍵の PEM
+fn leaf(ca: &Ca, name: &str, usage: ExtendedKeyUsagePurpose) -> (String, String) {
+    let key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+    params.distinguished_name.push(DnType::CommonName, name);
+    params.extended_key_usages = vec![usage];
+    let cert = params.signed_by(&key, &ca.issuer).unwrap();
+    (cert.pem(), key.serialize_pem())
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("scrop-tls-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `localhost` のサーバー証明書を書き出し、その設定を返す
+fn write_server_cert(dir: &Path, server_ca: &Ca) -> TlsConfig {
+    let (cert, key) = leaf(server_ca, "localhost", ExtendedKeyUsagePurpose::ServerAuth);
+    std::fs::write(dir.join("server.pem"), cert).unwrap();
+    std::fs::write(dir.join("server.key"), key).unwrap();
+    TlsConfig {
+        cert: dir.join("server.pem"),
+        key: dir.join("server.key"),
+        client_ca: PathBuf::new(),
+    }
+}
+
+async fn whoami(Extension(access): Extension<Access>) -> String {
+    access.to_string()
+}
+
+async fn start_server(tls: ReloadableTls, auth: AuthConfig) -> std::net::SocketAddr {
+    let app = Router::new()
+        .route("/api/whoami", get(whoami))
+        .route("/api/capture/start", post(whoami))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(auth),
+            auth::require,
+        ));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let listener = TlsListener::new(listener, tls).unwrap();
+    let addr = axum::serve::Listener::local_addr(&listener).unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<PeerAccess>(),
+        )
+        .await
+        .unwrap();
+    });
+    addr
+}
+
+struct Client {
+    roots: String,
+    identity: Option<(String, String)>,
+}
+
+impl Client {
+    fn trusting(ca: &Ca) -> Self {
+        Self {
+            roots: ca.pem.clone(),
+            identity: None,
+        }
+    }
+
+    fn with_identity(mut self, (cert, key): (String, String)) -> Self {
+        self.identity = Some((cert, key));
+        self
+    }
+
+    fn config(&self) -> rustls::ClientConfig {
+        self.config_with_alpn(Vec::new())
+    }
+
+    fn config_with_alpn(&self, alpn_protocols: Vec<Vec<u8>>) -> rustls::ClientConfig {
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(self.roots.as_bytes()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let builder = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+        let mut config = match &self.identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    CertificateDer::pem_slice_iter(cert.as_bytes())
+                        .collect::<Result<Vec<_>, _>>()
+                        .unwrap(),
+                    PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = alpn_protocols;
+        config
+    }
+
+    /// HTTP/1.1 のリクエストを 1 つ送り、ステータスと本文を返す
+    async fn request(
+        &self,
+        addr: std::net::SocketAddr,
+        request: &str,
+    ) -> std::io::Result<(u16, String)> {
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(self.config()));
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = connector
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let response = String::from_utf8_lossy(&response);
+        let status = response
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .unwrap_or(0);
+        let body = response
+            .split_once("\r\n\r\n")
+            .map_or("", |(_, body)| body)
+            .to_string();
+        Ok((status, body))
+    }
+
+    async fn get(&self, addr: std::net::SocketAddr, path: &str) -> std::io::Result<(u16, String)> {
+        self.request(
+            addr,
+            &format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            ),
+        )
+        .await
+    }
+}
+
+#[tokio::test]
+async fn serves_https_with_configured_certificate() {
+    let dir = temp_dir("serve");
+    let server_ca = ca("server ca");
+    let config = write_server_cert(&dir, &server_ca);
+    let addr = start_server(ReloadableTls::load(&config).unwrap(), AuthConfig::default()).await;
+
+    let (status, body) = Client::trusting(&server_ca)
+        .get(addr, "/api/whoami")
+        .await
+        .unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(body, "operator");
+
+    // 別の CA しか信頼しないクライアントはハンドシェイクに失敗する
+    assert!(Client::trusting(&ca("other ca"))
+        .get(addr, "/api/whoami")
+        .await
+        .is_err());
+
+    // 平文の HTTP は応答を得られない
+    let mut plain = TcpStream::connect(addr).await.unwrap();
+    plain
+        .write_all(b"GET /api/whoami HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = plain.read_to_end(&mut response).await;
+    assert!(!String::from_utf8_lossy(&response).contains("operator"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn serves_http2_when_negotiated_with_alpn() {
+    let dir = temp_dir("h2");
+    let server_ca = ca("server ca");
+    let config = write_server_cert(&dir, &server_ca);
+    let addr = start_server(ReloadableTls::load(&config).unwrap(), AuthConfig::default()).await;
+
+    let client = Client::trusting(&server_ca).config_with_alpn(vec![b"h2".to_vec()]);
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = connector
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+    assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+    // HTTP/2 の接続プリフェースに、サーバーは SETTINGS フレームで応える
+    stream
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+        .await
+        .unwrap();
+    let mut header = [0u8; 9];
+    tokio::time::timeout(Duration::from_secs(2), stream.read_exact(&mut header))
+        .await
+        .expect("timed out waiting for SETTINGS")
+        .expect("server closed the HTTP/2 connection");
+    assert_eq!(header[3], 0x4, "expected a SETTINGS frame");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn sighup_reloads_certificates() {
+    let dir = temp_dir("reload");
+    let old_ca = ca("old ca");
+    let config = write_server_cert(&dir, &old_ca);
+    let tls = ReloadableTls::load(&config).unwrap();
+    tls.reload_on_sighup(config.clone()).unwrap();
+    let addr = start_server(tls.clone(), AuthConfig::default()).await;
+    assert_eq!(
+        Client::trusting(&old_ca)
+            .get(addr, "/api/whoami")
+            .await
+            .unwrap()
+            .0,
+        200
+    );
+
+    // 壊れたファイルは読み込まず、それまでの証明書を使い続ける
+    std::fs::write(&config.cert, "not a certificate").unwrap();
+    assert!(tls.reload(&config).is_err());
+    assert_eq!(
+        Client::trusting(&old_ca)
+            .get(addr, "/api/whoami")
+            .await
+            .unwrap()
+            .0,
+        200
+    );
+
+    let new_ca = ca("new ca");
+    write_server_cert(&dir, &new_ca);
+    let status = std::process::Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let new_client = Client::trusting(&new_ca);
+    let mut reloaded = false;
+    for _ in 0..50 {
+        if matches!(new_client.get(addr, "/api/whoami").await, Ok((200, _))) {
+            reloaded = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(reloaded, "new certificate was not served after SIGHUP");
+    assert!(Client::trusting(&old_ca)
+        .get(addr, "/api/whoami")
+        .await
+        .is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn client_certificate_grants_operator_access() {
+    let dir = temp_dir("mtls");
+    let server_ca = ca("server ca");
+    let client_ca = ca("operators ca");
+    std::fs::write(dir.join("client-ca.pem"), &client_ca.pem).unwrap();
+    let config = TlsConfig {
+        client_ca: dir.join("client-ca.pem"),
+        ..write_server_cert(&dir, &server_ca)
+    };
+    let auth = AuthConfig {
+        tokens: vec![format!("operator:{}", OPERATOR_TOKEN)
+            .parse::<TokenCredential>()
+            .unwrap()],
+        peer_auth: config.client_auth(),
+        ..AuthConfig::default()
+    };
+    let addr = start_server(ReloadableTls::load(&config).unwrap(), auth).await;
+
+    let operator = Client::trusting(&server_ca).with_identity(leaf(
+        &client_ca,
+        "operator",
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ));
+    assert_eq!(
+        operator.get(addr, "/api/whoami").await.unwrap(),
+        (200, "operator".to_string())
+    );
+    let (status, _) = operator
+        .request(
+            addr,
+            "POST /api/capture/start HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    assert_eq!(status, 200);
+
+    // 証明書のない接続はトークンで認証する
+    let anonymous = Client::trusting(&server_ca);
+    assert_eq!(anonymous.get(addr, "/api/whoami").await.unwrap().0, 401);
+    assert_eq!(
+        anonymous
+            .get(
+                addr,
+                &format!("/api/whoami?access_token={}", OPERATOR_TOKEN)
+            )
+            .await
+            .unwrap(),
+        (200, "operator".to_string())
+    );
+
+    // 信頼していない CA の証明書はハンドシェイクで拒否する
+    let stranger = Client::trusting(&server_ca).with_identity(leaf(
+        &ca("stranger ca"),
+        "stranger",
+        ExtendedKeyUsagePurpose::ClientAuth,
+    ));
+    assert!(!matches!(
+        stranger.get(addr, "/api/whoami").await,
+        Ok((200, _))
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn server_config_rejects_mismatched_key() {
+    let dir = temp_dir("mismatch");
+    let config = write_server_cert(&dir, &ca("server ca"));
+    let other = KeyPair::generate().unwrap();
+    std::fs::write(&config.key, other.serialize_pem()).unwrap();
+    assert!(tls::server_config(&config).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -3,17 +3,23 @@ use std::time::Duration;
 
 use axum::routing::get;
 use axum::Router;
-use futures_util::StreamExt;
+use futures_util::{SinkExt, StreamExt};
 use prost::Message as _;
 use tokio_tungstenite::tungstenite::Message;
 
+use scrop_capture::alerts::{Alert, AlertState};
 use scrop_capture::types::{
-    AnimatingPacket, CapturedPacket, CapturedPacketEnvelope, PacketResult, Protocol,
+    AnimatingPacket, CaptureStateEvent, CaptureStateKind, CapturedPacket, CapturedPacketEnvelope,
+    InterfaceEvent, InterfaceEventKind, PacketResult, ProcessInfo, Protocol,
 };
 use scrop_capture::AppState;
 
+#[path = "../src/query.rs"]
+mod query;
 #[path = "../src/ws.rs"]
 mod ws;
+#[path = "../src/ws_compression.rs"]
+mod ws_compression;
 #[path = "../src/ws_proto.rs"]
 mod ws_proto;
 
@@ -30,6 +36,16 @@ fn sample_captured_packet(id: &str) -> CapturedPacket {
             target_port: Some(80),
             capture_mono_ns: 1_000_000_000,
             reason: None,
+            interface: Some("eth0".to_string()),
+            netns: Some("cni-1".to_string()),
+            source_meta: None,
+            destination_meta: None,
+            process: None,
+            source_name: None,
+            destination_name: None,
+            service: None,
+            source_geo: None,
+            destination_geo: None,
         },
         result: PacketResult::Delivered,
     }
@@ -49,7 +65,10 @@ async fn send_batch_when_subscribed(state: &Arc<AppState>, batch: CapturedPacket
 async fn websocket_streams_batches_as_binary_protobuf() {
     let state = Arc::new(AppState::new());
     let app = Router::new()
-        .route("/ws", get(ws::ws_handler))
+        .route(
+            "/ws",
+            get(ws::ws_handler).layer(axum::Extension(ws::WsConfig::default())),
+        )
         .with_state(state.clone());
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
@@ -95,9 +114,993 @@ async fn websocket_streams_batches_as_binary_protobuf() {
     let packet = first.packet.as_ref().expect("captured packet payload");
     assert_eq!(packet.id, "pkt-test-1");
     assert_eq!(packet.capture_mono_ns, 1_000_000_000.0);
+    assert_eq!(packet.interface.as_deref(), Some("eth0"));
+    assert_eq!(packet.netns.as_deref(), Some("cni-1"));
 
     let result = ws_proto::pb::PacketResult::try_from(first.result).expect("packet result enum");
     assert_eq!(result, ws_proto::pb::PacketResult::Delivered);
 
     server.abort();
 }
+
+fn packet_owned_by(id: &str, pid: u32, comm: &str) -> CapturedPacket {
+    let mut captured = sample_captured_packet(id);
+    captured.packet.process = Some(ProcessInfo {
+        pid,
+        comm: comm.to_string(),
+        cgroup_id: 4242,
+    });
+    captured
+}
+
+#[tokio::test]
+async fn websocket_filters_packets_by_process() {
+    let state = Arc::new(AppState::new());
+    let app = Router::new()
+        .route(
+            "/ws",
+            get(ws::ws_handler).layer(axum::Extension(ws::WsConfig::default())),
+        )
+        .with_state(state.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("read local addr");
+
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve app");
+    });
+
+    let ws_url = format!("ws://{}/ws?comm=nginx", addr);
+    let (mut socket, _response) = tokio_tungstenite::connect_async(ws_url)
+        .await
+        .expect("connect websocket");
+
+    // 条件に合うパケットがないバッチは送られない
+    let unmatched = CapturedPacketEnvelope {
+        packets: vec![packet_owned_by("pkt-sshd", 811, "sshd")],
+        epoch_offset_ms: 0.0,
+    };
+    send_batch_when_subscribed(&state, unmatched).await;
+
+    let batch = CapturedPacketEnvelope {
+        packets: vec![
+            packet_owned_by("pkt-nginx", 1201, "nginx"),
+            sample_captured_packet("pkt-unknown"),
+            packet_owned_by("pkt-sshd-2", 811, "sshd"),
+        ],
+        epoch_offset_ms: 0.0,
+    };
+    state.event_tx.send(batch).expect("send batch");
+
+    let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error");
+    let bytes = match next {
+        Message::Binary(bytes) => bytes,
+        other => panic!("expected websocket binary message, got {:?}", other),
+    };
+
+    let envelope = ws_proto::pb::PacketBatchEnvelope::decode(bytes).expect("decode protobuf");
+    assert_eq!(envelope.packets.len(), 1);
+    let packet = envelope.packets[0].packet.as_ref().expect("packet payload");
+    assert_eq!(packet.id, "pkt-nginx");
+    let process = packet.process.as_ref().expect("process");
+    assert_eq!(process.pid, 1201);
+    assert_eq!(process.comm, "nginx");
+    assert_eq!(process.cgroup_id, 4242);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn websocket_applies_subscription_filter_mid_stream() {
+    let state = Arc::new(AppState::new());
+    let app = Router::new()
+        .route(
+            "/ws",
+            get(ws::ws_handler).layer(axum::Extension(ws::WsConfig::default())),
+        )
+        .with_state(state.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("read local addr");
+
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve app");
+    });
+
+    let ws_url = format!("ws://{}/ws", addr);
+    let (mut socket, _response) = tokio_tungstenite::connect_async(ws_url)
+        .await
+        .expect("connect websocket");
+
+    let mut dropped = sample_captured_packet("pkt-fw-drop");
+    dropped.result = PacketResult::FwDrop;
+    let batch = CapturedPacketEnvelope {
+        packets: vec![sample_captured_packet("pkt-delivered"), dropped],
+        epoch_offset_ms: 0.0,
+    };
+    send_batch_when_subscribed(&state, batch.clone()).await;
+
+    let read_envelope = |message: Message| match message {
+        Message::Binary(bytes) => {
+            ws_proto::pb::PacketBatchEnvelope::decode(bytes).expect("decode protobuf")
+        }
+        other => panic!("expected websocket binary message, got {:?}", other),
+    };
+
+    // フィルタ未設定の間はすべて届く
+    let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error");
+    assert_eq!(read_envelope(next).packets.len(), 2);
+
+    let filter = ws_proto::pb::ClientMessage {
+        kind: Some(ws_proto::pb::client_message::Kind::SetFilter(
+            ws_proto::pb::SubscriptionFilter {
+                results: vec![ws_proto::pb::PacketResult::FwDrop as i32],
+                cidrs: vec!["10.0.0.0/24".to_string()],
+                interfaces: vec!["cni-1/eth0".to_string()],
+                ..Default::default()
+            },
+        )),
+    };
+    socket
+        .send(Message::Binary(filter.encode_to_vec().into()))
+        .await
+        .expect("send filter");
+
+    // フィルタの反映は非同期なので、絞り込まれたバッチが届くまで送り続ける
+    let mut filtered = None;
+    for _ in 0..50 {
+        state.event_tx.send(batch.clone()).expect("send batch");
+        let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+            .await
+            .expect("timed out waiting websocket message")
+            .expect("websocket stream ended")
+            .expect("websocket read error");
+        let envelope = read_envelope(next);
+        if envelope.packets.len() == 1 {
+            filtered = Some(envelope);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let envelope = filtered.expect("filter was not applied");
+    let packet = envelope.packets[0].packet.as_ref().expect("packet payload");
+    assert_eq!(packet.id, "pkt-fw-drop");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn websocket_closes_on_invalid_client_message() {
+    let state = Arc::new(AppState::new());
+    let (addr, server) = start_server(state.clone()).await;
+
+    let ws_url = format!("ws://{}/ws", addr);
+    let (mut socket, _response) = tokio_tungstenite::connect_async(ws_url)
+        .await
+        .expect("connect websocket");
+    send_batch_when_subscribed(
+        &state,
+        CapturedPacketEnvelope {
+            packets: vec![sample_captured_packet("pkt-delivered")],
+            epoch_offset_ms: 0.0,
+        },
+    )
+    .await;
+    next_message(&mut socket).await;
+
+    // 不正なフィルタを送ると、理由を付けて接続を閉じる
+    let invalid = ws_proto::pb::ClientMessage {
+        kind: Some(ws_proto::pb::client_message::Kind::SetFilter(
+            ws_proto::pb::SubscriptionFilter {
+                cidrs: vec!["not-a-cidr".to_string()],
+                ..Default::default()
+            },
+        )),
+    };
+    socket
+        .send(Message::Binary(invalid.encode_to_vec().into()))
+        .await
+        .expect("send invalid filter");
+
+    let close = loop {
+        if let Message::Close(frame) = next_message(&mut socket).await {
+            break frame.expect("close frame");
+        }
+    };
+    assert_eq!(
+        close.code,
+        tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode::Policy
+    );
+    assert!(close.reason.starts_with("invalid subscription filter"));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn websocket_sampling_reports_suppressed_packets() {
+    let state = Arc::new(AppState::new());
+    let app = Router::new()
+        .route(
+            "/ws",
+            get(ws::ws_handler).layer(axum::Extension(ws::WsConfig::default())),
+        )
+        .with_state(state.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("read local addr");
+
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve app");
+    });
+
+    // 不正な配信モードは接続時に拒否される
+    let invalid = tokio_tungstenite::connect_async(format!("ws://{}/ws?mode=sample", addr)).await;
+    assert!(invalid.is_err());
+
+    let ws_url = format!("ws://{}/ws?mode=dropsOnly&rate=3", addr);
+    let (mut socket, _response) = tokio_tungstenite::connect_async(ws_url)
+        .await
+        .expect("connect websocket");
+
+    let mut packets: Vec<CapturedPacket> = (0..6)
+        .map(|i| sample_captured_packet(&format!("pkt-delivered-{}", i)))
+        .collect();
+    let mut dropped = sample_captured_packet("pkt-nic-drop");
+    dropped.result = PacketResult::NicDrop;
+    packets.push(dropped);
+    let batch = CapturedPacketEnvelope {
+        packets,
+        epoch_offset_ms: 0.0,
+    };
+    send_batch_when_subscribed(&state, batch).await;
+
+    let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error");
+    let bytes = match next {
+        Message::Binary(bytes) => bytes,
+        other => panic!("expected websocket binary message, got {:?}", other),
+    };
+
+    let envelope = ws_proto::pb::PacketBatchEnvelope::decode(bytes).expect("decode protobuf");
+    assert!(envelope.sampled);
+    let ids: Vec<&str> = envelope
+        .packets
+        .iter()
+        .map(|p| p.packet.as_ref().expect("packet payload").id.as_str())
+        .collect();
+    assert_eq!(
+        ids,
+        vec!["pkt-delivered-0", "pkt-delivered-3", "pkt-nic-drop"]
+    );
+    let suppressed = envelope.suppressed.expect("suppressed counts");
+    assert_eq!(suppressed.delivered, 4);
+    assert_eq!(suppressed.nic_drop, 0);
+    assert_eq!(suppressed.fw_drop, 0);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn websocket_notifies_client_of_stream_gap() {
+    let state = Arc::new(AppState::new());
+    let app = Router::new()
+        .route(
+            "/ws",
+            get(ws::ws_handler).layer(axum::Extension(ws::WsConfig::default())),
+        )
+        .with_state(state.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("read local addr");
+
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve app");
+    });
+
+    let ws_url = format!("ws://{}/ws", addr);
+    let (mut socket, _response) = tokio_tungstenite::connect_async(ws_url)
+        .await
+        .expect("connect websocket");
+
+    let batch = CapturedPacketEnvelope {
+        packets: vec![
+            sample_captured_packet("pkt-gap-1"),
+            sample_captured_packet("pkt-gap-2"),
+        ],
+        epoch_offset_ms: 0.0,
+    };
+    send_batch_when_subscribed(&state, batch.clone()).await;
+
+    let read_envelope = |message: Message| match message {
+        Message::Binary(bytes) => {
+            ws_proto::pb::PacketBatchEnvelope::decode(bytes).expect("decode protobuf")
+        }
+        other => panic!("expected websocket binary message, got {:?}", other),
+    };
+    let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error");
+    assert!(read_envelope(next).gap.is_none());
+
+    // current_thread ランタイムでは送信中にサーバー側のタスクが動かないため、確実に取りこぼす
+    let overflow = scrop_capture::EVENT_CHANNEL_CAPACITY + 10;
+    for _ in 0..overflow {
+        state.event_tx.send(batch.clone()).expect("send batch");
+    }
+
+    let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error");
+    let envelope = read_envelope(next);
+    assert!(envelope.packets.is_empty());
+    let gap = envelope.gap.expect("stream gap");
+    assert_eq!(gap.skipped_batches, 10);
+    assert_eq!(gap.approx_packets, 20);
+
+    // 取りこぼし後も残りのバッチは届く
+    let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error");
+    assert_eq!(read_envelope(next).packets.len(), 2);
+
+    let clients = state.clients.snapshot();
+    assert_eq!(clients.len(), 1);
+    assert_eq!(clients[0].kind, "websocket");
+    assert_eq!(clients[0].lag_events, 1);
+    assert_eq!(clients[0].skipped_batches, 10);
+    assert_eq!(clients[0].approx_skipped_packets, 20);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn websocket_pushes_state_changes_and_stats() {
+    let state = Arc::new(AppState::new());
+    let app = Router::new()
+        .route(
+            "/ws",
+            get(ws::ws_handler).layer(axum::Extension(ws::WsConfig::default())),
+        )
+        .with_state(state.clone());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("read local addr");
+
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve app");
+    });
+
+    let ws_url = format!("ws://{}/ws", addr);
+    let (mut socket, _response) = tokio_tungstenite::connect_async(ws_url)
+        .await
+        .expect("connect websocket");
+
+    let failed = CaptureStateEvent {
+        kind: CaptureStateKind::Failed,
+        error: Some("ring buffer unavailable".to_string()),
+    };
+    let mut subscribed = false;
+    for _ in 0..30 {
+        if state.state_tx.send(failed.clone()).is_ok() {
+            subscribed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(subscribed, "timed out waiting for websocket subscriber");
+
+    let read_envelope = |message: Message| match message {
+        Message::Binary(bytes) => {
+            ws_proto::pb::PacketBatchEnvelope::decode(bytes).expect("decode protobuf")
+        }
+        other => panic!("expected websocket binary message, got {:?}", other),
+    };
+    let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error");
+    let envelope = read_envelope(next);
+    assert!(envelope.packets.is_empty());
+    match envelope.control {
+        Some(ws_proto::pb::packet_batch_envelope::Control::StateChange(change)) => {
+            assert_eq!(change.kind(), ws_proto::pb::StateChangeKind::Failed);
+            assert_eq!(change.error.as_deref(), Some("ring buffer unavailable"));
+        }
+        other => panic!("expected state change, got {:?}", other),
+    }
+
+    let publisher = state.spawn_status_publisher(Duration::from_millis(10));
+    let next = tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error");
+    match read_envelope(next).control {
+        Some(ws_proto::pb::packet_batch_envelope::Control::Stats(stats)) => {
+            assert!(!stats.is_capturing);
+            assert_eq!(stats.total_packets, 0);
+            assert!(!stats.mode.is_empty());
+        }
+        other => panic!("expected stats update, got {:?}", other),
+    }
+
+    publisher.abort();
+    server.abort();
+}
+
+async fn start_server(state: Arc<AppState>) -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+    start_server_with(state, ws::WsConfig::default()).await
+}
+
+async fn start_server_with(
+    state: Arc<AppState>,
+    config: ws::WsConfig,
+) -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+    let app = Router::new()
+        .route("/ws", get(ws::ws_handler).layer(axum::Extension(config)))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("read local addr");
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("serve app");
+    });
+    (addr, server)
+}
+
+type ClientSocket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn next_message(socket: &mut ClientSocket) -> Message {
+    tokio::time::timeout(Duration::from_secs(2), socket.next())
+        .await
+        .expect("timed out waiting websocket message")
+        .expect("websocket stream ended")
+        .expect("websocket read error")
+}
+
+async fn next_server_message(socket: &mut ClientSocket) -> ws_proto::pb::server_message::Kind {
+    match next_message(socket).await {
+        Message::Binary(bytes) => ws_proto::pb::ServerMessage::decode(bytes)
+            .expect("decode server message")
+            .kind
+            .expect("server message kind"),
+        other => panic!("expected websocket binary message, got {:?}", other),
+    }
+}
+
+async fn send_hello(socket: &mut ClientSocket, versions: Vec<u32>, schemas: Vec<u32>) {
+    send_client_hello(
+        socket,
+        ws_proto::pb::ClientHello {
+            protocol_versions: versions,
+            client_name: "contract-test".to_string(),
+            schema_versions: schemas,
+            ..Default::default()
+        },
+    )
+    .await;
+}
+
+async fn send_client_hello(socket: &mut ClientSocket, hello: ws_proto::pb::ClientHello) {
+    let hello = ws_proto::pb::ClientMessage {
+        kind: Some(ws_proto::pb::client_message::Kind::Hello(hello)),
+    };
+    socket
+        .send(Message::Binary(hello.encode_to_vec().into()))
+        .await
+        .expect("send hello");
+}
+
+/// 圧縮を合意した接続のフレームを、先頭 1 バイトの方式に従って展開する
+async fn next_compressed_message(
+    socket: &mut ClientSocket,
+) -> (
+    ws_proto::pb::Compression,
+    ws_proto::pb::server_message::Kind,
+) {
+    use std::io::Read as _;
+    use ws_proto::pb::Compression;
+
+    let frame = match next_message(socket).await {
+        Message::Binary(bytes) => bytes,
+        other => panic!("expected websocket binary message, got {:?}", other),
+    };
+    let codec = Compression::try_from(i32::from(frame[0])).expect("known compression tag");
+    let payload = match codec {
+        Compression::None => frame[1..].to_vec(),
+        Compression::Zstd => zstd::stream::decode_all(&frame[1..]).expect("decode zstd"),
+        Compression::Deflate => {
+            let mut payload = Vec::new();
+            flate2::read::DeflateDecoder::new(&frame[1..])
+                .read_to_end(&mut payload)
+                .expect("decode deflate");
+            payload
+        }
+    };
+    let message =
+        ws_proto::pb::ServerMessage::decode(payload.as_slice()).expect("decode server message");
+    (codec, message.kind.expect("server message kind"))
+}
+
+/// CompactPacketBatch をスキーマバージョン 2 のパケットに戻す（クライアント側の復号と同じ手順）
+fn expand_compact_batch(
+    batch: &ws_proto::pb::CompactPacketBatch,
+) -> Vec<ws_proto::pb::CapturedPacket> {
+    let string = |index: Option<u32>| index.map(|i| batch.strings[i as usize].clone());
+    let address = |value: u32, text: &Option<String>| {
+        text.clone()
+            .unwrap_or_else(|| std::net::Ipv4Addr::from(value).to_string())
+    };
+    let mut counter = batch.base_counter;
+    let mut capture_mono_ns = batch.base_capture_mono_ns;
+    batch
+        .packets
+        .iter()
+        .map(|packet| {
+            capture_mono_ns = capture_mono_ns.wrapping_add(packet.capture_mono_ns_delta as u64);
+            let id = match &packet.id {
+                Some(id) => id.clone(),
+                None => {
+                    counter = counter.wrapping_add(packet.counter_delta as u64);
+                    format!("pkt-{}-{}", batch.session_id, counter)
+                }
+            };
+            ws_proto::pb::CapturedPacket {
+                packet: Some(ws_proto::pb::AnimatingPacket {
+                    id,
+                    protocol: packet.protocol,
+                    size: packet.size,
+                    source: address(packet.source, &packet.source_text),
+                    src_port: packet.src_port,
+                    destination: address(packet.destination, &packet.destination_text),
+                    dest_port: packet.dest_port,
+                    target_port: packet.target_port,
+                    reason: string(packet.reason_ref),
+                    capture_mono_ns: capture_mono_ns as f64,
+                    interface: string(packet.interface_ref),
+                    netns: string(packet.netns_ref),
+                    source_meta: packet.source_meta.clone(),
+                    destination_meta: packet.destination_meta.clone(),
+                    process: packet.process.clone(),
+                    source_name: string(packet.source_name_ref),
+                    destination_name: string(packet.destination_name_ref),
+                    service: string(packet.service_ref),
+                    source_geo: packet.source_geo.clone(),
+                    destination_geo: packet.destination_geo.clone(),
+                }),
+                result: packet.result,
+            }
+        })
+        .collect()
+}
+
+fn interface_added(name: &str) -> InterfaceEvent {
+    InterfaceEvent {
+        kind: InterfaceEventKind::Added,
+        interface: name.to_string(),
+        previous_name: None,
+        error: None,
+        interfaces: vec!["eth0".to_string(), name.to_string()],
+        attached: vec![],
+    }
+}
+
+#[tokio::test]
+async fn hello_negotiates_multiplexed_protocol() {
+    use ws_proto::pb::server_message::Kind;
+
+    let state = Arc::new(AppState::new());
+    let (addr, server) = start_server(state.clone()).await;
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .expect("connect websocket");
+
+    // 未知の将来バージョンを含んでいても、共通の最新バージョンが選ばれる
+    send_hello(&mut socket, vec![ws_proto::PROTOCOL_VERSION, 99], vec![]).await;
+    match next_server_message(&mut socket).await {
+        Kind::Hello(hello) => {
+            assert_eq!(hello.protocol_version, ws_proto::PROTOCOL_VERSION);
+            assert_eq!(hello.schema_version, ws_proto::SCHEMA_VERSION);
+            assert!(!hello.server_version.is_empty());
+        }
+        other => panic!("expected hello, got {:?}", other),
+    }
+
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets: vec![sample_captured_packet("pkt-v2")],
+            epoch_offset_ms: 5.0,
+        })
+        .expect("send batch");
+    match next_server_message(&mut socket).await {
+        Kind::PacketBatch(envelope) => {
+            assert_eq!(envelope.schema_version, ws_proto::SCHEMA_VERSION);
+            assert_eq!(envelope.epoch_offset_ms, 5.0);
+            assert_eq!(envelope.packets.len(), 1);
+            assert!(envelope.gap.is_none());
+            assert!(envelope.control.is_none());
+        }
+        other => panic!("expected packet batch, got {:?}", other),
+    }
+
+    state
+        .state_tx
+        .send(CaptureStateEvent {
+            kind: CaptureStateKind::Started,
+            error: None,
+        })
+        .expect("send state event");
+    match next_server_message(&mut socket).await {
+        Kind::StateChange(change) => {
+            assert_eq!(change.kind(), ws_proto::pb::StateChangeKind::Started);
+        }
+        other => panic!("expected state change, got {:?}", other),
+    }
+
+    // バージョン 2 ではアタッチ以外のインターフェースの変化も届く
+    state
+        .interface_tx
+        .send(interface_added("veth1"))
+        .expect("send interface event");
+    match next_server_message(&mut socket).await {
+        Kind::InterfaceUpdate(update) => {
+            assert_eq!(update.kind(), ws_proto::pb::InterfaceEventKind::Added);
+            assert_eq!(update.interface, "veth1");
+            assert_eq!(update.interfaces, vec!["eth0", "veth1"]);
+        }
+        other => panic!("expected interface update, got {:?}", other),
+    }
+
+    state
+        .alert_tx
+        .send(Alert {
+            rule: "eth0-fw".to_string(),
+            expr: "fw drops on eth0 > 100/s".to_string(),
+            labels: [("interface".to_string(), "eth0".to_string())].into(),
+            state: AlertState::Firing,
+            value: 250.0,
+            active_since_ms: 1_000,
+            fired_at_ms: Some(1_000),
+            resolved_at_ms: None,
+        })
+        .expect("send alert");
+    match next_server_message(&mut socket).await {
+        Kind::Alert(alert) => {
+            assert_eq!(alert.rule, "eth0-fw");
+            assert_eq!(alert.state(), ws_proto::pb::AlertState::Firing);
+            assert_eq!(alert.labels["interface"], "eth0");
+            assert_eq!(alert.value, 250.0);
+            assert_eq!(alert.fired_at_ms, Some(1_000));
+        }
+        other => panic!("expected alert, got {:?}", other),
+    }
+
+    // 接続後の制御メッセージは引き続き使える
+    let filter = ws_proto::pb::ClientMessage {
+        kind: Some(ws_proto::pb::client_message::Kind::SetFilter(
+            ws_proto::pb::SubscriptionFilter {
+                results: vec![ws_proto::pb::PacketResult::NicDrop as i32],
+                ..Default::default()
+            },
+        )),
+    };
+    socket
+        .send(Message::Binary(filter.encode_to_vec().into()))
+        .await
+        .expect("send filter");
+    let mut dropped = sample_captured_packet("pkt-v2-drop");
+    dropped.result = PacketResult::NicDrop;
+    let batch = CapturedPacketEnvelope {
+        packets: vec![sample_captured_packet("pkt-v2-delivered"), dropped],
+        epoch_offset_ms: 0.0,
+    };
+    let mut filtered = false;
+    for _ in 0..50 {
+        state.event_tx.send(batch.clone()).expect("send batch");
+        match next_server_message(&mut socket).await {
+            Kind::PacketBatch(envelope) if envelope.packets.len() == 1 => {
+                let packet = envelope.packets[0].packet.as_ref().expect("packet payload");
+                assert_eq!(packet.id, "pkt-v2-drop");
+                filtered = true;
+                break;
+            }
+            Kind::PacketBatch(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            other => panic!("expected packet batch, got {:?}", other),
+        }
+    }
+    assert!(filtered, "filter was not applied");
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn hello_negotiates_compact_schema() {
+    use ws_proto::pb::server_message::Kind;
+
+    let state = Arc::new(AppState::new());
+    let (addr, server) = start_server(state.clone()).await;
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .expect("connect websocket");
+
+    send_hello(
+        &mut socket,
+        vec![ws_proto::PROTOCOL_VERSION],
+        vec![
+            ws_proto::SCHEMA_VERSION,
+            ws_proto::COMPACT_SCHEMA_VERSION,
+            99,
+        ],
+    )
+    .await;
+    match next_server_message(&mut socket).await {
+        Kind::Hello(hello) => {
+            assert_eq!(hello.protocol_version, ws_proto::PROTOCOL_VERSION);
+            assert_eq!(hello.schema_version, ws_proto::COMPACT_SCHEMA_VERSION);
+        }
+        other => panic!("expected hello, got {:?}", other),
+    }
+
+    // 連番・順不同の ID、別形式の ID、IPv6 アドレスを混ぜる
+    let mut packets: Vec<CapturedPacket> = [7, 8, 9, 5]
+        .iter()
+        .enumerate()
+        .map(|(i, counter)| {
+            let mut packet = sample_captured_packet(&format!("pkt-a1b2c3-{}", counter));
+            packet.packet.capture_mono_ns += i as u64 * 1_500;
+            packet
+        })
+        .collect();
+    packets[1].packet.capture_mono_ns -= 10_000;
+    packets[2].result = PacketResult::FwDrop;
+    packets[2].packet.reason = Some("policy".to_string());
+    packets[2].packet.service = Some("http".to_string());
+    let mut other = sample_captured_packet("flow-42");
+    other.packet.source = "fd00::1".to_string();
+    other.packet.interface = Some("veth0".to_string());
+    packets.push(other);
+    let batch = CapturedPacketEnvelope {
+        packets,
+        epoch_offset_ms: 12.5,
+    };
+    state.event_tx.send(batch.clone()).expect("send batch");
+
+    let compact = match next_server_message(&mut socket).await {
+        Kind::CompactBatch(compact) => compact,
+        other => panic!("expected compact batch, got {:?}", other),
+    };
+    assert_eq!(compact.session_id, "a1b2c3");
+    assert_eq!(compact.base_counter, 7);
+    assert_eq!(compact.epoch_offset_ms, 12.5);
+    // 繰り返し現れる文字列は 1 回だけ載る
+    assert_eq!(compact.strings.len(), 5);
+
+    let expected = match ws_proto::encode_outbound(
+        ws_proto::StreamVersion {
+            protocol: ws_proto::PROTOCOL_VERSION,
+            schema: ws_proto::SCHEMA_VERSION,
+        },
+        ws_proto::Outbound::Batch(ws_proto::OutboundBatch {
+            packets: batch.packets.as_slice().into(),
+            epoch_offset_ms: batch.epoch_offset_ms,
+            sampled: false,
+            suppressed: Default::default(),
+        }),
+    )
+    .map(|bytes| ws_proto::pb::ServerMessage::decode(bytes.as_slice()))
+    {
+        Some(Ok(ws_proto::pb::ServerMessage {
+            kind: Some(Kind::PacketBatch(envelope)),
+        })) => envelope,
+        other => panic!("expected v2 envelope, got {:?}", other),
+    };
+    assert_eq!(expand_compact_batch(&compact), expected.packets);
+    assert!(compact.encoded_len() < expected.encoded_len());
+
+    server.abort();
+}
+
+fn large_batch(count: usize) -> CapturedPacketEnvelope {
+    CapturedPacketEnvelope {
+        packets: (0..count)
+            .map(|i| sample_captured_packet(&format!("pkt-a1b2c3-{}", i)))
+            .collect(),
+        epoch_offset_ms: 0.0,
+    }
+}
+
+#[tokio::test]
+async fn hello_negotiates_zstd_frame_compression() {
+    use ws_proto::pb::server_message::Kind;
+    use ws_proto::pb::Compression;
+
+    let state = Arc::new(AppState::new());
+    let (addr, server) = start_server(state.clone()).await;
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .expect("connect websocket");
+
+    send_client_hello(
+        &mut socket,
+        ws_proto::pb::ClientHello {
+            protocol_versions: vec![ws_proto::PROTOCOL_VERSION],
+            compressions: vec![Compression::Zstd as i32, Compression::Deflate as i32],
+            // サーバーの上限を超えるレベルは上限に丸められる
+            compression_level: Some(19),
+            ..Default::default()
+        },
+    )
+    .await;
+    // Hello 自体は圧縮しない
+    match next_server_message(&mut socket).await {
+        Kind::Hello(hello) => {
+            assert_eq!(hello.compression(), Compression::Zstd);
+            assert_eq!(
+                hello.compression_level,
+                ws_compression::CompressionConfig::default().max_level
+            );
+        }
+        other => panic!("expected hello, got {:?}", other),
+    }
+
+    state.event_tx.send(large_batch(64)).expect("send batch");
+    match next_compressed_message(&mut socket).await {
+        (Compression::Zstd, Kind::PacketBatch(envelope)) => {
+            assert_eq!(envelope.packets.len(), 64);
+        }
+        other => panic!("expected zstd packet batch, got {:?}", other),
+    }
+
+    // 小さなフレームは圧縮しない
+    state
+        .state_tx
+        .send(CaptureStateEvent {
+            kind: CaptureStateKind::Stopped,
+            error: None,
+        })
+        .expect("send state event");
+    match next_compressed_message(&mut socket).await {
+        (Compression::None, Kind::StateChange(change)) => {
+            assert_eq!(change.kind(), ws_proto::pb::StateChangeKind::Stopped);
+        }
+        other => panic!("expected uncompressed state change, got {:?}", other),
+    }
+
+    let clients = state.clients.snapshot();
+    assert_eq!(clients.len(), 1);
+    assert!(clients[0].wire_bytes < clients[0].payload_bytes);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn compression_follows_server_allowed_codecs() {
+    use ws_proto::pb::server_message::Kind;
+    use ws_proto::pb::Compression;
+
+    let config = ws::WsConfig {
+        compression: ws_compression::CompressionConfig {
+            codecs: vec![Compression::Deflate],
+            ..Default::default()
+        },
+    };
+    let state = Arc::new(AppState::new());
+    let (addr, server) = start_server_with(state.clone(), config).await;
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .expect("connect websocket");
+
+    send_client_hello(
+        &mut socket,
+        ws_proto::pb::ClientHello {
+            protocol_versions: vec![ws_proto::PROTOCOL_VERSION],
+            compressions: vec![Compression::Zstd as i32, Compression::Deflate as i32],
+            ..Default::default()
+        },
+    )
+    .await;
+    match next_server_message(&mut socket).await {
+        Kind::Hello(hello) => assert_eq!(hello.compression(), Compression::Deflate),
+        other => panic!("expected hello, got {:?}", other),
+    }
+
+    state.event_tx.send(large_batch(64)).expect("send batch");
+    match next_compressed_message(&mut socket).await {
+        (Compression::Deflate, Kind::PacketBatch(envelope)) => {
+            assert_eq!(envelope.packets.len(), 64);
+        }
+        other => panic!("expected deflate packet batch, got {:?}", other),
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn hello_without_common_version_closes_connection() {
+    let state = Arc::new(AppState::new());
+    let (addr, server) = start_server(state).await;
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .expect("connect websocket");
+
+    send_hello(&mut socket, vec![99], vec![]).await;
+    match next_message(&mut socket).await {
+        Message::Close(Some(frame)) => {
+            assert_eq!(u16::from(frame.code), 1002);
+            assert_eq!(frame.reason.as_str(), "unsupported protocol version");
+        }
+        other => panic!("expected close frame, got {:?}", other),
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn client_without_hello_gets_legacy_envelopes() {
+    let state = Arc::new(AppState::new());
+    let (addr, server) = start_server(state.clone()).await;
+    let (mut socket, _response) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .expect("connect websocket");
+
+    // 旧クライアントに送れないインターフェースの変化は送られない
+    let mut subscribed = false;
+    for _ in 0..30 {
+        if state.interface_tx.send(interface_added("veth2")).is_ok() {
+            subscribed = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(subscribed, "timed out waiting for websocket subscriber");
+    state
+        .event_tx
+        .send(CapturedPacketEnvelope {
+            packets: vec![sample_captured_packet("pkt-legacy")],
+            epoch_offset_ms: 0.0,
+        })
+        .expect("send batch");
+
+    let bytes = match next_message(&mut socket).await {
+        Message::Binary(bytes) => bytes,
+        other => panic!("expected websocket binary message, got {:?}", other),
+    };
+    let envelope = ws_proto::pb::PacketBatchEnvelope::decode(bytes).expect("decode protobuf");
+    assert_eq!(envelope.schema_version, ws_proto::SCHEMA_VERSION);
+    assert_eq!(envelope.packets.len(), 1);
+    let packet = envelope.packets[0].packet.as_ref().expect("packet payload");
+    assert_eq!(packet.id, "pkt-legacy");
+
+    server.abort();
+}
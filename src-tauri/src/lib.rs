@@ -1,13 +1,15 @@
 use std::sync::Arc;
-use tauri::{Emitter, State};
+use std::time::Duration;
+use tauri::{Emitter, Manager, State};
 use tokio::task::JoinHandle;
 use tracing::Level;
 use tracing_subscriber::filter::{filter_fn, LevelFilter};
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{fmt, EnvFilter};
 
-use scrop_capture::types::CaptureStats;
-use scrop_capture::AppState as CaptureState;
+use scrop_capture::config::{self, CaptureConfig};
+use scrop_capture::types::{CaptureStats, NetnsInfo};
+use scrop_capture::{netns, AppState as CaptureState, STATUS_PUSH_INTERVAL_MS};
 
 pub struct AppState {
     inner: Arc<CaptureState>,
@@ -16,8 +18,16 @@ pub struct AppState {
 
 impl AppState {
     fn new() -> Self {
+        // デスクトップ版には設定ファイルがないため、旧来の環境変数だけを見る
+        let capture_config = CaptureConfig {
+            shadow_correlator: std::env::var(config::SHADOW_CORRELATOR_ENV)
+                .ok()
+                .and_then(|value| config::parse_flag(&value))
+                .unwrap_or(false),
+            ..CaptureConfig::default()
+        };
         Self {
-            inner: Arc::new(CaptureState::new()),
+            inner: Arc::new(CaptureState::with_config(&capture_config)),
             bridge_handle: tokio::sync::Mutex::new(None),
         }
     }
@@ -31,6 +41,44 @@ impl AppState {
 }
 
 const EVENT_CAPTURED_BATCH: &str = "packet:captured-batch";
+const EVENT_INTERFACE_CHANGED: &str = "interface:changed";
+const EVENT_CAPTURE_STATE_CHANGED: &str = "capture:state-changed";
+const EVENT_CAPTURE_STATS: &str = "capture:stats";
+
+/// 状態変化・インターフェースの変化と定期的な統計を、キャプチャの開始・停止に関係なく Tauri イベントとして送る
+fn spawn_status_bridge(app: tauri::AppHandle, inner: Arc<CaptureState>) {
+    let mut state_rx = inner.state_tx.subscribe();
+    let mut interface_rx = inner.interface_tx.subscribe();
+    let mut status_rx = inner.status_tx.subscribe();
+    tauri::async_runtime::spawn(async move {
+        inner.spawn_status_publisher(Duration::from_millis(STATUS_PUSH_INTERVAL_MS));
+        loop {
+            tokio::select! {
+                event = state_rx.recv() => match event {
+                    Ok(event) => {
+                        let _ = app.emit(EVENT_CAPTURE_STATE_CHANGED, &event);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                event = interface_rx.recv() => match event {
+                    Ok(event) => {
+                        let _ = app.emit(EVENT_INTERFACE_CHANGED, &event);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                snapshot = status_rx.recv() => match snapshot {
+                    Ok(snapshot) => {
+                        let _ = app.emit(EVENT_CAPTURE_STATS, &snapshot);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+    });
+}
 
 fn init_tracing() {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
@@ -109,7 +157,29 @@ async fn list_interfaces(state: State<'_, AppState>) -> Result<Vec<String>, Stri
 }
 
 #[tauri::command]
-async fn attach_interface(state: State<'_, AppState>, interface: String) -> Result<(), String> {
+async fn list_netns(state: State<'_, AppState>) -> Result<Vec<NetnsInfo>, String> {
+    let capture = state.inner.capture.lock().await;
+    Ok(capture.list_netns())
+}
+
+#[tauri::command]
+async fn list_netns_interfaces(
+    state: State<'_, AppState>,
+    netns: String,
+) -> Result<Vec<String>, String> {
+    let capture = state.inner.capture.lock().await;
+    capture
+        .list_netns_interfaces(&netns)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_interface(
+    state: State<'_, AppState>,
+    interface: String,
+    netns: Option<String>,
+) -> Result<(), String> {
+    let interface = netns::qualify_interface(netns.as_deref(), &interface);
     let capture = state.inner.capture.lock().await;
     capture
         .attach_interface(&interface)
@@ -118,7 +188,12 @@ async fn attach_interface(state: State<'_, AppState>, interface: String) -> Resu
 }
 
 #[tauri::command]
-async fn detach_interface(state: State<'_, AppState>, interface: String) -> Result<(), String> {
+async fn detach_interface(
+    state: State<'_, AppState>,
+    interface: String,
+    netns: Option<String>,
+) -> Result<(), String> {
+    let interface = netns::qualify_interface(netns.as_deref(), &interface);
     let capture = state.inner.capture.lock().await;
     capture
         .detach_interface(&interface)
@@ -126,6 +201,24 @@ async fn detach_interface(state: State<'_, AppState>, interface: String) -> Resu
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+async fn get_auto_attach_rules(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let capture = state.inner.capture.lock().await;
+    Ok(capture.auto_attach_rules())
+}
+
+#[tauri::command]
+async fn set_auto_attach_rules(
+    state: State<'_, AppState>,
+    rules: Vec<String>,
+) -> Result<(), String> {
+    let capture = state.inner.capture.lock().await;
+    capture
+        .set_auto_attach_rules(rules)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[derive(serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CaptureStatusResponse {
@@ -147,6 +240,11 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .manage(AppState::new())
+        .setup(|app| {
+            let inner = Arc::clone(&app.state::<AppState>().inner);
+            spawn_status_bridge(app.handle().clone(), inner);
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             start_capture,
             stop_capture,
@@ -154,7 +252,11 @@ pub fn run() {
             reset_capture,
             list_interfaces,
             attach_interface,
-            detach_interface
+            detach_interface,
+            list_netns,
+            list_netns_interfaces,
+            get_auto_attach_rules,
+            set_auto_attach_rules
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");